@@ -0,0 +1,246 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Benchmarks vote import into a single round with a large voter set, the
+//! import of duplicated votes, and the memory a round retains once all votes
+//! are imported, both with votes concentrated on a few blocks and spread
+//! thinly across many.
+//!
+//! Run with `cargo bench --bench round`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use finality_grandpa::{Chain, Error, Prevote, Precommit};
+use finality_grandpa::round::{Round, RoundParams};
+use finality_grandpa::voter_set::VoterSet;
+
+const VOTERS: u64 = 500;
+const MEMORY_VOTERS: u64 = 1000;
+const CHAIN_LENGTH: u64 = 200;
+const FORKS: u64 = 10;
+const SIBLINGS: u64 = 200;
+const ITERATIONS: u32 = 200;
+const DUPLICATES: u32 = 10_000;
+
+// tracks the number of bytes currently allocated.
+struct CountingAlloc;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+	unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+		ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+		unsafe { System.alloc(layout) }
+	}
+
+	unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+		ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+		unsafe { System.dealloc(ptr, layout) }
+	}
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+// blocks are numbered by hash: the canonical chain is `1..=CHAIN_LENGTH`, and
+// fork `f` branches off the canonical chain at block `f * 10`. above the tip
+// of the canonical chain are `SIBLINGS` competing blocks.
+struct BenchChain {
+	parents: HashMap<u64, (u64, u32)>,
+}
+
+impl BenchChain {
+	fn new() -> Self {
+		let mut parents = HashMap::new();
+		parents.insert(1, (0, 1));
+		for hash in 2..=CHAIN_LENGTH {
+			parents.insert(hash, (hash - 1, hash as u32));
+		}
+
+		for fork in 1..=FORKS {
+			let mut parent = fork * 10;
+			for i in 1..=10 {
+				let hash = CHAIN_LENGTH * fork * 100 + i;
+				parents.insert(hash, (parent, (fork * 10 + i) as u32));
+				parent = hash;
+			}
+		}
+
+		for sibling in 1..=SIBLINGS {
+			parents.insert(sibling_hash(sibling), (CHAIN_LENGTH, CHAIN_LENGTH as u32 + 1));
+		}
+
+		BenchChain { parents }
+	}
+
+	fn number(&self, hash: u64) -> u32 {
+		self.parents[&hash].1
+	}
+}
+
+impl Chain<u64, u32> for BenchChain {
+	fn ancestry(&self, base: u64, block: u64) -> Result<Vec<u64>, Error> {
+		let mut ancestry = Vec::new();
+		let mut current = block;
+		loop {
+			if current == base { break }
+			current = match self.parents.get(&current) {
+				Some(&(parent, _)) if parent != 0 => parent,
+				_ => return Err(Error::NotDescendent),
+			};
+			ancestry.push(current);
+		}
+		ancestry.pop();
+
+		Ok(ancestry)
+	}
+
+	fn best_chain_containing(&self, _base: u64) -> Option<(u64, u32)> {
+		None
+	}
+}
+
+fn sibling_hash(sibling: u64) -> u64 {
+	CHAIN_LENGTH * (FORKS + 1) * 100 + sibling
+}
+
+// a deterministic spread of targets: most voters are near the tip of the
+// canonical chain, some are on forks.
+fn target(chain: &BenchChain, voter: u64) -> (u64, u32) {
+	let hash = if voter.is_multiple_of(7) {
+		let fork = voter % FORKS + 1;
+		CHAIN_LENGTH * fork * 100 + voter % 10 + 1
+	} else {
+		CHAIN_LENGTH - voter % 20
+	};
+
+	(hash, chain.number(hash))
+}
+
+// targets spread evenly over the siblings, so every vote-node but the base
+// holds only a handful of votes.
+fn sibling_target(chain: &BenchChain, voter: u64) -> (u64, u32) {
+	let hash = sibling_hash(voter % SIBLINGS + 1);
+	(hash, chain.number(hash))
+}
+
+fn new_round(voters: &VoterSet<u64>) -> Round<u64, u64, u32, ()> {
+	Round::new(RoundParams {
+		round_number: 1,
+		set_id: 0,
+		voters: voters.clone(),
+		base: (1, 1),
+	})
+}
+
+fn run_round(chain: &BenchChain, voters: &VoterSet<u64>) -> (Duration, Duration) {
+	let mut round = new_round(voters);
+
+	let start = Instant::now();
+	for voter in 0..VOTERS {
+		let (hash, number) = target(chain, voter);
+		round.import_prevote(chain, Prevote::new(hash, number), voter, ()).unwrap();
+	}
+	let prevotes = start.elapsed();
+
+	let start = Instant::now();
+	for voter in 0..VOTERS {
+		let (hash, number) = target(chain, voter);
+		round.import_precommit(chain, Precommit::new(hash, number), voter, ()).unwrap();
+	}
+	let precommits = start.elapsed();
+
+	assert!(round.finalized().is_some());
+	(prevotes, precommits)
+}
+
+// the time taken to import a prevote, and then on average to import the same
+// prevote again.
+fn duplicates(chain: &BenchChain, voters: &VoterSet<u64>) -> (Duration, Duration) {
+	let mut round = new_round(voters);
+	let (hash, number) = target(chain, 1);
+
+	let start = Instant::now();
+	round.import_prevote(chain, Prevote::new(hash, number), 1, ()).unwrap();
+	let first = start.elapsed();
+
+	let start = Instant::now();
+	for _ in 0..DUPLICATES {
+		let result = round.import_prevote(chain, Prevote::new(hash, number), 1, ()).unwrap();
+		assert!(result.duplicated);
+	}
+
+	(first, start.elapsed() / DUPLICATES)
+}
+
+// bytes retained by a round after importing all votes for the given targets.
+// nobody equivocates.
+fn round_memory(
+	chain: &BenchChain,
+	voters: &VoterSet<u64>,
+	target: fn(&BenchChain, u64) -> (u64, u32),
+) -> usize {
+	let before = ALLOCATED.load(Ordering::Relaxed);
+
+	let mut round = new_round(voters);
+	for voter in 0..voters.len() as u64 {
+		let (hash, number) = target(chain, voter);
+		round.import_prevote(chain, Prevote::new(hash, number), voter, ()).unwrap();
+		round.import_precommit(chain, Precommit::new(hash, number), voter, ()).unwrap();
+	}
+
+	let retained = ALLOCATED.load(Ordering::Relaxed) - before;
+	drop(round);
+	retained
+}
+
+fn main() {
+	let chain = BenchChain::new();
+	let voters: VoterSet<u64> = VoterSet::new((0..VOTERS).map(|id| (id, 1))).unwrap();
+
+	let mut prevotes = Duration::default();
+	let mut precommits = Duration::default();
+	for _ in 0..ITERATIONS {
+		let (p, c) = run_round(&chain, &voters);
+		prevotes += p;
+		precommits += c;
+	}
+
+	println!(
+		"{} voters: {:?} per round importing prevotes, {:?} per round importing precommits",
+		VOTERS,
+		prevotes / ITERATIONS,
+		precommits / ITERATIONS,
+	);
+
+	let (first, duplicate) = duplicates(&chain, &voters);
+	println!(
+		"{} voters: {:?} importing a prevote, {:?} per duplicate of it",
+		VOTERS,
+		first,
+		duplicate,
+	);
+
+	let voters: VoterSet<u64> = VoterSet::new((0..MEMORY_VOTERS).map(|id| (id, 1))).unwrap();
+	println!(
+		"{} voters: {} bytes retained per round, {} with votes spread over {} blocks",
+		MEMORY_VOTERS,
+		round_memory(&chain, &voters, target),
+		round_memory(&chain, &voters, sibling_target),
+		SIBLINGS,
+	);
+}
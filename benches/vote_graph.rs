@@ -0,0 +1,148 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Benchmarks walking back a vote graph over a deep chain with sparse
+//! vote-nodes and many forks, from its head down to the base.
+//!
+//! Run with `cargo bench --bench vote_graph`.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use finality_grandpa::{Chain, Error};
+use finality_grandpa::vote_graph::VoteGraph;
+
+const CHAIN_LENGTH: u64 = 100_000;
+const VOTE_INTERVAL: u64 = 1_000;
+const FORK_LENGTH: u64 = 10;
+const ITERATIONS: u32 = 100;
+
+// blocks are numbered by hash: the canonical chain is `1..=CHAIN_LENGTH`, and
+// a fork of `FORK_LENGTH` blocks branches off every `VOTE_INTERVAL` blocks, in
+// the middle between two votes on the canonical chain.
+struct BenchChain {
+	parents: HashMap<u64, (u64, u32)>,
+}
+
+impl BenchChain {
+	fn new() -> Self {
+		let mut parents = HashMap::new();
+		parents.insert(1, (0, 1));
+		for hash in 2..=CHAIN_LENGTH {
+			parents.insert(hash, (hash - 1, hash as u32));
+		}
+
+		for fork in 1..CHAIN_LENGTH / VOTE_INTERVAL {
+			let mut parent = fork * VOTE_INTERVAL + VOTE_INTERVAL / 2;
+			for i in 1..=FORK_LENGTH {
+				parents.insert(fork_block(fork, i), (parent, (parent + 1) as u32));
+				parent = fork_block(fork, i);
+			}
+		}
+
+		BenchChain { parents }
+	}
+
+	fn number(&self, hash: u64) -> u32 {
+		self.parents[&hash].1
+	}
+}
+
+impl Chain<u64, u32> for BenchChain {
+	fn ancestry(&self, base: u64, block: u64) -> Result<Vec<u64>, Error> {
+		let mut ancestry = Vec::new();
+		let mut current = block;
+		loop {
+			if current == base { break }
+			current = match self.parents.get(&current) {
+				Some(&(parent, _)) if parent != 0 => parent,
+				_ => return Err(Error::NotDescendent),
+			};
+			ancestry.push(current);
+		}
+		ancestry.pop();
+
+		Ok(ancestry)
+	}
+
+	fn best_chain_containing(&self, _base: u64) -> Option<(u64, u32)> {
+		None
+	}
+}
+
+fn fork_block(fork: u64, i: u64) -> u64 {
+	CHAIN_LENGTH + fork * FORK_LENGTH + i
+}
+
+// one vote every `VOTE_INTERVAL` blocks on the canonical chain, and one at the
+// tip of every fork.
+fn vote_graph(chain: &BenchChain) -> VoteGraph<u64, u32, u64> {
+	let mut graph = VoteGraph::new(1, 1);
+	for hash in (VOTE_INTERVAL..=CHAIN_LENGTH).step_by(VOTE_INTERVAL as usize) {
+		graph.insert(hash, chain.number(hash), 1, chain).unwrap();
+
+		let fork = hash / VOTE_INTERVAL;
+		if fork < CHAIN_LENGTH / VOTE_INTERVAL {
+			let tip = fork_block(fork, FORK_LENGTH);
+			graph.insert(tip, chain.number(tip), 1, chain).unwrap();
+		}
+	}
+
+	graph
+}
+
+// the time taken to find the lowest vote-node as the ancestor of the head with
+// all votes,
+// and to walk back from the head to the base.
+fn walk_back(graph: &VoteGraph<u64, u32, u64>) -> (Duration, Duration) {
+	let total = 2 * (CHAIN_LENGTH / VOTE_INTERVAL) - 1;
+	let head = CHAIN_LENGTH - VOTE_INTERVAL / 2;
+
+	let start = Instant::now();
+	let found = graph.find_ancestor(head, head as u32, |&vote| vote >= total);
+	let find_ancestor = start.elapsed();
+	assert_eq!(found, Some((VOTE_INTERVAL, VOTE_INTERVAL as u32)));
+
+	let start = Instant::now();
+	let mut visited = 0;
+	graph.walk_back(head, head as u32, |_, _, _| {
+		visited += 1;
+		true
+	});
+	let walk_back = start.elapsed();
+	assert_eq!(visited, head);
+
+	(find_ancestor, walk_back)
+}
+
+fn main() {
+	let chain = BenchChain::new();
+	let graph = vote_graph(&chain);
+
+	let mut find_ancestor = Duration::default();
+	let mut walk_back_all = Duration::default();
+	for _ in 0..ITERATIONS {
+		let (f, w) = walk_back(&graph);
+		find_ancestor += f;
+		walk_back_all += w;
+	}
+
+	println!(
+		"{} blocks, a vote-node every {}: {:?} finding the ancestor of the head with all votes, {:?} walking back from the head",
+		CHAIN_LENGTH,
+		VOTE_INTERVAL / 2,
+		find_ancestor / ITERATIONS,
+		walk_back_all / ITERATIONS,
+	);
+}
@@ -0,0 +1,268 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Benchmarks a voter ingesting a burst of commit messages, as they trickle
+//! in one at a time or in batches with `CommunicationIn::Batch`, counting
+//! how often the voter is woken up to process them. The environment here
+//! does no work per wakeup, so the time taken mostly measures validation of
+//! the commits, which is the same either way.
+//!
+//! Run with `cargo bench --bench voter`.
+
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::prelude::*;
+
+use finality_grandpa::{
+	Chain, Commit, CompactCommit, Equivocation, Error, HistoricalVotes, Prevote, Precommit,
+	PrimaryPropose, SignedCommit, SignedMessage, SignedPrecommit,
+};
+use finality_grandpa::round::State as RoundState;
+use finality_grandpa::voter::{
+	Callback, CommunicationIn, Environment, RoundData, SystemClock, Voter,
+};
+use finality_grandpa::voter_set::VoterSet;
+
+const COMMITS: u32 = 10_000;
+const BATCH_SIZE: usize = 100;
+const VOTERS: u64 = 4;
+const ITERATIONS: u32 = 20;
+
+type In = CommunicationIn<u64, u32, u64, u64>;
+
+// a chain where block `n` has hash `n`, and the voters never vote in rounds
+// of their own. only commits finalize anything.
+struct BenchEnvironment {
+	clock: SystemClock,
+	finalized: AtomicU32,
+}
+
+impl Chain<u64, u32> for BenchEnvironment {
+	fn ancestry(&self, base: u64, block: u64) -> Result<Vec<u64>, Error> {
+		if block <= base {
+			return Err(Error::NotDescendent);
+		}
+
+		Ok((base + 1..block).rev().collect())
+	}
+
+	fn best_chain_containing(&self, base: u64) -> Option<(u64, u32)> {
+		Some((base, base as u32))
+	}
+}
+
+type Timer = future::Pending<Result<(), Error>>;
+
+impl Environment<u64, u32> for BenchEnvironment {
+	type Timer = Timer;
+	type Id = u64;
+	type Signature = u64;
+	type In = stream::Pending<Result<SignedMessage<u64, u32, u64, u64>, Error>>;
+	type Out = NullSink;
+	type Error = Error;
+	type Clock = SystemClock;
+
+	fn clock(&self) -> &SystemClock {
+		&self.clock
+	}
+
+	fn round_data(&self, _round: u64) -> RoundData<u64, Timer, Self::In, NullSink> {
+		RoundData {
+			voter_id: None,
+			prevote_timer: future::pending(),
+			precommit_timer: future::pending(),
+			incoming: stream::pending(),
+			outgoing: NullSink,
+		}
+	}
+
+	fn round_commit_timer(&self) -> Timer {
+		future::pending()
+	}
+
+	fn proposed(&self, _round: u64, _propose: PrimaryPropose<u64, u32>) -> Result<(), Error> {
+		Ok(())
+	}
+
+	fn prevoted(&self, _round: u64, _prevote: Prevote<u64, u32>) -> Result<(), Error> {
+		Ok(())
+	}
+
+	fn precommitted(&self, _round: u64, _precommit: Precommit<u64, u32>) -> Result<(), Error> {
+		Ok(())
+	}
+
+	fn completed(
+		&self,
+		_round: u64,
+		_state: RoundState<u64, u32>,
+		_base: (u64, u32),
+		_votes: &HistoricalVotes<u64, u32, u64, u64>,
+	) -> Result<(), Error> {
+		Ok(())
+	}
+
+	fn finalize_block(&self, _hash: u64, number: u32, _round: u64, _commit: Commit<u64, u32, u64, u64>) -> Result<(), Error> {
+		self.finalized.store(number, Ordering::Relaxed);
+		Ok(())
+	}
+
+	fn prevote_equivocation(&self, _round: u64, _equivocation: Equivocation<u64, Prevote<u64, u32>, u64>) {}
+
+	fn precommit_equivocation(&self, _round: u64, _equivocation: Equivocation<u64, Precommit<u64, u32>, u64>) {}
+
+	// commits are only sent unsigned here.
+	fn verify_commit_signature(&self, _round: u64, _commit: &SignedCommit<u64, u32, u64, u64>) -> bool {
+		false
+	}
+}
+
+// a sink discarding everything sent to it.
+struct NullSink;
+
+impl<T> Sink<T> for NullSink {
+	type Error = Error;
+
+	fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn start_send(self: Pin<&mut Self>, _item: T) -> Result<(), Error> {
+		Ok(())
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Error>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), Error>> {
+		Poll::Ready(Ok(()))
+	}
+}
+
+// yields a single message per wakeup of the task, as if each message arrived
+// from the network on its own.
+struct Trickle {
+	messages: VecDeque<In>,
+	yielded: bool,
+}
+
+impl Stream for Trickle {
+	type Item = Result<In, Error>;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+		if std::mem::replace(&mut self.yielded, false) {
+			// the next message arrives.
+			cx.waker().wake_by_ref();
+			return Poll::Pending;
+		}
+
+		match self.messages.pop_front() {
+			Some(message) => {
+				self.yielded = true;
+				Poll::Ready(Some(Ok(message)))
+			}
+			None => Poll::Pending,
+		}
+	}
+}
+
+impl Unpin for Trickle {}
+
+// commits finalizing blocks `2..=COMMITS + 1` in order, all in round 0.
+fn commits() -> Vec<In> {
+	(2..COMMITS + 2).map(|number| {
+		let commit = Commit {
+			target_hash: number as u64,
+			target_number: number,
+			precommits: (0..VOTERS).map(|id| SignedPrecommit {
+				precommit: Precommit::new(number as u64, number),
+				signature: id,
+				id,
+			}).collect(),
+			set_id: 0,
+		};
+
+		CommunicationIn::Commit(0, CompactCommit::from(commit), Callback::Blank)
+	}).collect()
+}
+
+// run a voter until all commits are imported, returning how often it was
+// polled and how long that took.
+fn run(messages: Vec<In>) -> (usize, Duration) {
+	let env = Arc::new(BenchEnvironment { clock: SystemClock, finalized: AtomicU32::new(1) });
+	let voters = VoterSet::new((0..VOTERS).map(|id| (id, 1))).unwrap();
+
+	let mut voter = Voter::new(
+		env.clone(),
+		voters,
+		(Trickle { messages: messages.into(), yielded: false }, NullSink),
+		0,
+		RoundState::genesis((1, 1)),
+		(1, 1),
+	);
+
+	let mut polls = 0;
+	let start = Instant::now();
+	futures::executor::block_on(future::poll_fn(|cx| {
+		polls += 1;
+		if let Poll::Ready(result) = voter.poll_unpin(cx) {
+			panic!("voter stopped: {:?}", result);
+		}
+
+		if env.finalized.load(Ordering::Relaxed) == COMMITS + 1 {
+			Poll::Ready(())
+		} else {
+			Poll::Pending
+		}
+	}));
+
+	(polls, start.elapsed())
+}
+
+fn batched(mut commits: Vec<In>) -> Vec<In> {
+	let mut batches = Vec::new();
+	while !commits.is_empty() {
+		let rest = commits.split_off(BATCH_SIZE.min(commits.len()));
+		batches.push(CommunicationIn::Batch(std::mem::replace(&mut commits, rest)));
+	}
+
+	batches
+}
+
+fn main() {
+	let mut single = (0, Duration::default());
+	let mut batches = (0, Duration::default());
+	for _ in 0..ITERATIONS {
+		let (polls, elapsed) = run(commits());
+		single = (polls, single.1 + elapsed);
+
+		let (polls, elapsed) = run(batched(commits()));
+		batches = (polls, batches.1 + elapsed);
+	}
+
+	println!("{} commits one by one: {} wakeups, {:?}", COMMITS, single.0, single.1 / ITERATIONS);
+	println!(
+		"{} commits in batches of {}: {} wakeups, {:?}",
+		COMMITS,
+		BATCH_SIZE,
+		batches.0,
+		batches.1 / ITERATIONS,
+	);
+}
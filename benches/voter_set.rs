@@ -0,0 +1,58 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Benchmarks constructing large voter sets with `VoterSet::new` against
+//! `VoterSet::from_sorted_unchecked`, from voters in canonical order.
+//!
+//! Run with `cargo bench --bench voter_set`.
+
+use std::time::{Duration, Instant};
+
+use finality_grandpa::voter_set::VoterSet;
+
+const VOTERS: u64 = 10_000;
+const ITERATIONS: u32 = 200;
+
+// time constructing a set with the given constructor, which gets a fresh
+// copy of the voters each time.
+fn construct<F>(voters: &[(u64, u64)], f: F) -> Duration where
+	F: Fn(Vec<(u64, u64)>) -> VoterSet<u64>,
+{
+	let mut elapsed = Duration::default();
+	for _ in 0..ITERATIONS {
+		let voters = voters.to_vec();
+
+		let start = Instant::now();
+		let set = f(voters);
+		elapsed += start.elapsed();
+
+		assert_eq!(set.len(), VOTERS as usize);
+	}
+
+	elapsed / ITERATIONS
+}
+
+fn main() {
+	let voters: Vec<_> = (0..VOTERS).map(|id| (id, 1)).collect();
+
+	let new = construct(&voters, |voters| VoterSet::new(voters).unwrap());
+	let unchecked = construct(&voters, VoterSet::from_sorted_unchecked);
+
+	println!(
+		"{} voters: {:?} per set with `new`, {:?} per set with `from_sorted_unchecked`",
+		VOTERS,
+		new,
+		unchecked,
+	);
+}
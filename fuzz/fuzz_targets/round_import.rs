@@ -0,0 +1,27 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Imports arbitrary sequences of votes into a round, on arbitrary chains and
+//! bases, checking the invariants of the round after every vote.
+//!
+//! Run with `cargo fuzz run round_import` from the crate root.
+
+#![no_main]
+
+use finality_grandpa::testing::arbitrary::Scenario;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+	Scenario::decode(data).run();
+});
@@ -0,0 +1,86 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Commit validation without the standard library, as in a wasm runtime.
+//!
+//! Build for a target without `std` (e.g. `thumbv7em-none-eabi`) to check
+//! that the crate doesn't depend on it, and run the tests on the host to
+//! check that validation works.
+
+#![cfg_attr(not(test), no_std)]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use finality_grandpa::voter_set::VoterSet;
+use finality_grandpa::{validate_commit, Chain, Commit, Error, Precommit, SignedPrecommit};
+
+/// A chain without forks, where the hash of a block is its number.
+pub struct LinearChain {
+	/// The number of the best block.
+	pub best: u32,
+}
+
+impl Chain<u32, u32> for LinearChain {
+	fn ancestry(&self, base: u32, block: u32) -> Result<Vec<u32>, Error> {
+		if block <= base || block > self.best {
+			return Err(Error::NotDescendent);
+		}
+
+		Ok((base + 1..block).rev().collect())
+	}
+
+	fn best_chain_containing(&self, base: u32) -> Option<(u32, u32)> {
+		if base <= self.best { Some((self.best, self.best)) } else { None }
+	}
+}
+
+/// Whether a commit for `target`, with a precommit for it by each of the
+/// given voters, finalizes it in a set of voters with the given weights.
+pub fn finalizes(chain: &LinearChain, weights: &[u64], voters: &[u32], target: u32) -> bool {
+	let voter_set = match VoterSet::new(weights.iter().cloned().enumerate().map(|(id, weight)| (id as u32, weight))) {
+		Ok(voter_set) => voter_set,
+		Err(_) => return false,
+	};
+
+	let commit = Commit {
+		target_hash: target,
+		target_number: target,
+		precommits: voters.iter().map(|id| SignedPrecommit {
+			precommit: Precommit::new(target, target),
+			signature: *id,
+			id: *id,
+		}).collect(),
+		set_id: 0,
+	};
+
+	validate_commit(&commit, &voter_set, chain)
+		.map(|result| result.finalized_target().is_some())
+		.unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn commit_with_a_supermajority_finalizes() {
+		let chain = LinearChain { best: 10 };
+		let weights = [1, 1, 1, 1];
+
+		assert!(finalizes(&chain, &weights, &[0, 1, 2], 5));
+		assert!(!finalizes(&chain, &weights, &[0, 1], 5));
+		assert!(!finalizes(&chain, &weights, &[0, 1, 4], 5));
+	}
+}
@@ -26,7 +26,8 @@
 //! for those in precommits.
 //!
 //! Bitfields on regular vote-nodes will tend to be live, but the equivocating
-//! bitfield will be mostly empty.
+//! bitfield will be mostly empty. Live bitfields with few bits set only keep
+//! the indices of those bits.
 
 #[cfg(feature = "std")]
 use parking_lot::RwLock;
@@ -39,7 +40,7 @@ use crate::voter_set::VoterInfo;
 
 /// Errors that can occur when using the equivocation weighting tools.
 #[derive(Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "std", derive(Debug))]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
 pub enum Error {
 	/// Attempted to index bitfield past its length.
 	IndexOutOfBounds(usize, usize),
@@ -63,37 +64,42 @@ impl std::fmt::Display for Error {
 impl std::error::Error for Error {}
 
 /// Bitfield for tracking voters who have equivocated.
-#[derive(Eq, PartialEq, Clone)]
-#[cfg_attr(feature = "std", derive(Debug))]
+#[derive(Eq, PartialEq, Clone, Default)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
 pub enum Bitfield {
 	/// Blank bitfield,
+	#[default]
 	Blank,
 	/// Live bitfield,
 	Live(LiveBitfield),
 }
 
-impl Default for Bitfield {
-	fn default() -> Self {
-		Bitfield::Blank
-	}
-}
-
 impl Bitfield {
 	/// Combine two bitfields. Fails if they have conflicting shared data
 	/// (i.e. they come from different contexts).
 	pub fn merge(&self, other: &Self) -> Result<Self, Error> {
 		match (self, other) {
-			(&Bitfield::Blank, &Bitfield::Blank) => Ok(Bitfield::Blank),
-			(&Bitfield::Live(ref live), &Bitfield::Blank) | (&Bitfield::Blank, &Bitfield::Live(ref live))
+			(Bitfield::Blank, Bitfield::Blank) => Ok(Bitfield::Blank),
+			(Bitfield::Live(live), Bitfield::Blank) | (Bitfield::Blank, Bitfield::Live(live))
 				=> Ok(Bitfield::Live(live.clone())),
-			(&Bitfield::Live(ref a), &Bitfield::Live(ref b)) => {
-				if a.bits.len() == b.bits.len() {
-					let bits = a.bits.iter().zip(&b.bits).map(|(a, b)| a | b).collect();
-					Ok(Bitfield::Live(LiveBitfield { bits }))
-				} else {
+			(Bitfield::Live(a), Bitfield::Live(b)) => {
+				if a.n_words != b.n_words {
 					// we can't merge two bitfields with different lengths.
-					Err(Error::LengthMismatch(a.bits.len(), b.bits.len()))
+					return Err(Error::LengthMismatch(a.n_words, b.n_words));
 				}
+
+				let bits = match (&a.bits, &b.bits) {
+					(Bits::Sparse(a), Bits::Sparse(b)) => Bits::Sparse(merge_sorted(a, b)),
+					(Bits::Packed(packed), other) | (other, Bits::Packed(packed)) => {
+						let mut packed = packed.clone();
+						other.or_into(&mut packed);
+						Bits::Packed(packed)
+					}
+				};
+
+				let mut live = LiveBitfield { n_words: a.n_words, bits };
+				live.pack_if_dense();
+				Ok(Bitfield::Live(live))
 			}
 		}
 	}
@@ -101,15 +107,22 @@ impl Bitfield {
 	/// Find overlap weight (prevote, precommit) between this bitfield and another.
 	pub fn overlap(&self, other: &Self) -> Result<Self, Error> {
 		match (self, other) {
-			(&Bitfield::Live(ref a), &Bitfield::Live(ref b)) => {
-				if a.bits.len() == b.bits.len() {
-					Ok(Bitfield::Live(LiveBitfield {
-						bits: a.bits.iter().zip(&b.bits).map(|(a, b)| a & b).collect(),
-					}))
-				} else {
+			(Bitfield::Live(a), Bitfield::Live(b)) => {
+				if a.n_words != b.n_words {
 					// we can't find overlap of two bitfields with different lengths.
-					Err(Error::LengthMismatch(a.bits.len(), b.bits.len()))
+					return Err(Error::LengthMismatch(a.n_words, b.n_words));
 				}
+
+				let bits = match (&a.bits, &b.bits) {
+					(Bits::Sparse(indices), other) | (other, Bits::Sparse(indices)) => {
+						// the overlap has at most as many bits set as the sparse side.
+						Bits::Sparse(indices.iter().cloned().filter(|&idx| other.is_set(idx as usize)).collect())
+					}
+					(Bits::Packed(a), Bits::Packed(b))
+						=> Bits::Packed(a.iter().zip(b).map(|(a, b)| a & b).collect()),
+				};
+
+				Ok(Bitfield::Live(LiveBitfield { n_words: a.n_words, bits }))
 			}
 			_ => Ok(Bitfield::Blank)
 		}
@@ -120,10 +133,34 @@ impl Bitfield {
 	pub fn total_weight<F: Fn(usize) -> u64>(&self, lookup: F) -> (u64, u64) {
 		match *self {
 			Bitfield::Blank => (0, 0),
-			Bitfield::Live(ref live) => total_weight(live.bits.iter().cloned(), lookup),
+			Bitfield::Live(LiveBitfield { bits: Bits::Packed(ref words), .. })
+				=> total_weight(words.iter().cloned(), lookup),
+			Bitfield::Live(LiveBitfield { bits: Bits::Sparse(ref indices), .. }) => {
+				indices.iter().fold((0u64, 0u64), |(prevote, precommit), &idx| {
+					let weight = lookup(idx as usize / 2);
+					if idx % 2 == 0 {
+						(prevote.saturating_add(weight), precommit)
+					} else {
+						(prevote, precommit.saturating_add(weight))
+					}
+				})
+			}
 		}
 	}
 
+	/// Find the indices of all voters with at least one bit set, in ascending order.
+	pub fn voter_indices(&self) -> Vec<usize> {
+		let live = match *self {
+			Bitfield::Blank => return Vec::new(),
+			Bitfield::Live(ref live) => live,
+		};
+
+		// a voter's prevote and precommit bits are adjacent.
+		let mut indices: Vec<usize> = live.set_bits().map(|bit| bit / 2).collect();
+		indices.dedup();
+		indices
+	}
+
 	/// Set a bit in the bitfield.
 	fn set_bit(&mut self, bit: usize, n_voters: usize) -> Result<(), Error> {
 		let mut live = match std::mem::replace(self, Bitfield::Blank) {
@@ -138,37 +175,144 @@ impl Bitfield {
 }
 
 /// Live bitfield instance.
-#[derive(Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "std", derive(Debug))]
+///
+/// While few bits are set, only their indices are kept. Once those would take
+/// more space than the packed bits, they are packed into words. Vote-nodes far
+/// from the base hold only a handful of votes, so they don't cost memory
+/// proportional to the size of the voter set.
+#[derive(Clone)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
 pub struct LiveBitfield {
-	bits: Vec<u64>,
+	// number of words needed to pack all bits.
+	n_words: usize,
+	bits: Bits,
+}
+
+#[derive(Clone)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+enum Bits {
+	// indices of the set bits, in ascending order.
+	Sparse(Vec<u32>),
+	// all bits, set starting from the left of each word.
+	Packed(Vec<u64>),
+}
+
+impl Bits {
+	fn is_set(&self, bit_idx: usize) -> bool {
+		match *self {
+			Bits::Sparse(ref indices) => indices.binary_search(&(bit_idx as u32)).is_ok(),
+			Bits::Packed(ref words) => words[bit_idx / 64] & (1 << (63 - bit_idx % 64)) != 0,
+		}
+	}
+
+	// set all bits of `self` in the given packed words.
+	fn or_into(&self, words: &mut [u64]) {
+		match *self {
+			Bits::Sparse(ref indices) => for &idx in indices {
+				let idx = idx as usize;
+				words[idx / 64] |= 1 << (63 - idx % 64);
+			},
+			Bits::Packed(ref packed) => for (word, other) in words.iter_mut().zip(packed) {
+				*word |= other;
+			},
+		}
+	}
 }
 
 impl LiveBitfield {
 	fn with_voters(n_voters: usize) -> Self {
 		let n_bits = n_voters * 2;
-		let n_words = (n_bits + 63) / 64;
+		let n_words = n_bits.div_ceil(64);
+
+		// bit indices must fit in a `u32` to be kept sparsely.
+		let bits = if n_words * 64 <= u32::MAX as usize {
+			Bits::Sparse(Vec::new())
+		} else {
+			Bits::Packed(vec![0; n_words])
+		};
 
-		LiveBitfield { bits: vec![0; n_words] }
+		LiveBitfield { n_words, bits }
 	}
 
 	fn set_bit(&mut self, bit_idx: usize, n_voters: usize) -> Result<(), Error> {
-		let word_off = bit_idx / 64;
-		let bit_off = bit_idx % 64;
-
-		// If this isn't `Some`, something has gone really wrong.
-		if let Some(word) = self.bits.get_mut(word_off) {
-			// set bit starting from left.
-			*word |= 1 << (63 - bit_off);
-			Ok(())
-		} else {
-			Err(Error::IndexOutOfBounds(bit_idx / 2, n_voters))
+		// If this is out of range, something has gone really wrong.
+		if bit_idx >= self.n_words * 64 {
+			return Err(Error::IndexOutOfBounds(bit_idx / 2, n_voters));
+		}
+
+		match self.bits {
+			Bits::Sparse(ref mut indices) => {
+				if let Err(pos) = indices.binary_search(&(bit_idx as u32)) {
+					indices.insert(pos, bit_idx as u32);
+				}
+			}
+			Bits::Packed(ref mut words) => {
+				// set bit starting from left.
+				words[bit_idx / 64] |= 1 << (63 - bit_idx % 64);
+			}
+		}
+
+		self.pack_if_dense();
+		Ok(())
+	}
+
+	// iterate the indices of all set bits, in ascending order.
+	fn set_bits(&self) -> impl Iterator<Item=usize> + '_ {
+		let (sparse, packed) = match self.bits {
+			Bits::Sparse(ref indices) => (Some(indices.iter().map(|&idx| idx as usize)), None),
+			Bits::Packed(ref words) => (None, Some(words.iter().enumerate().flat_map(|(word_idx, &word)| {
+				(0..64).filter(move |bit| word & (1 << (63 - bit)) != 0).map(move |bit| word_idx * 64 + bit)
+			}))),
+		};
+
+		sparse.into_iter().flatten().chain(packed.into_iter().flatten())
+	}
+
+	// switch to packed words once the indices would take more space.
+	fn pack_if_dense(&mut self) {
+		if let Bits::Sparse(ref indices) = self.bits {
+			if indices.len() * 4 > self.n_words * 8 {
+				let mut words = vec![0; self.n_words];
+				self.bits.or_into(&mut words);
+				self.bits = Bits::Packed(words);
+			}
 		}
 	}
 }
 
+// bitfields with the same bits set are equal, however they are kept.
+impl PartialEq for LiveBitfield {
+	fn eq(&self, other: &Self) -> bool {
+		self.n_words == other.n_words && self.set_bits().eq(other.set_bits())
+	}
+}
+
+impl Eq for LiveBitfield {}
+
+// merge two ascending lists of bit indices, without duplicates.
+fn merge_sorted(a: &[u32], b: &[u32]) -> Vec<u32> {
+	let mut merged = Vec::with_capacity(a.len() + b.len());
+	let (mut a, mut b) = (a.iter().peekable(), b.iter().peekable());
+	loop {
+		let next = match (a.peek(), b.peek()) {
+			(Some(&&x), Some(&&y)) => {
+				if x <= y { a.next(); }
+				if y <= x { b.next(); }
+				x.min(y)
+			}
+			(Some(&&x), None) => { a.next(); x }
+			(None, Some(&&y)) => { b.next(); y }
+			(None, None) => break,
+		};
+
+		merged.push(next);
+	}
+
+	merged
+}
+
 // find total weight of the given iterable of bits. assumes that there are enough
-// voters in the given context to correspond to all bits.
+// voters in the given context to correspond to all bits. sums saturate at `u64::MAX`.
 fn total_weight<Iter, Lookup>(iterable: Iter, lookup: Lookup) -> (u64, u64) where
 	Iter: IntoIterator<Item=u64>,
 	Lookup: Fn(usize) -> u64,
@@ -177,7 +321,7 @@ fn total_weight<Iter, Lookup>(iterable: Iter, lookup: Lookup) -> (u64, u64) wher
 		val_idx: usize,
 		prevote: u64,
 		precommit: u64,
-	};
+	}
 
 	let state = State {
 		val_idx: 0,
@@ -191,12 +335,12 @@ fn total_weight<Iter, Lookup>(iterable: Iter, lookup: Lookup) -> (u64, u64) wher
 
 			// prevote bit is set
 			if word & (1 << 63) == (1 << 63) {
-				state.prevote += lookup(state.val_idx + i);
+				state.prevote = state.prevote.saturating_add(lookup(state.val_idx + i));
 			}
 
 			// precommit bit is set
 			if word & (1 << 62) == (1 << 62) {
-				state.precommit += lookup(state.val_idx + i);
+				state.precommit = state.precommit.saturating_add(lookup(state.val_idx + i));
 			}
 
 			word <<= 2;
@@ -246,12 +390,12 @@ impl Context {
 	}
 
 	/// Get a reference to the equivocators bitfield.
-	pub fn equivocators(&self) -> parking_lot::RwLockReadGuard<Bitfield> {
+	pub fn equivocators(&self) -> parking_lot::RwLockReadGuard<'_, Bitfield> {
 		self.equivocators.read()
 	}
 
 	/// Get a mutable reference to the equivocators bitfield.
-	pub fn equivocators_mut(&mut self) -> parking_lot::RwLockWriteGuard<Bitfield> {
+	pub fn equivocators_mut(&mut self) -> parking_lot::RwLockWriteGuard<'_, Bitfield> {
 		self.equivocators.write()
 	}
 }
@@ -329,35 +473,35 @@ mod tests {
 		let mut a = Bitfield::Live(LiveBitfield::with_voters(10));
 		let mut b = Bitfield::Live(LiveBitfield::with_voters(10));
 
-		let v: VoterSet<usize> = [
+		let v: VoterSet<usize> = VoterSet::new([
 			(1, 5),
 			(4, 1),
 			(3, 9),
 			(5, 7),
 			(9, 9),
 			(2, 7),
-		].iter().cloned().collect();
+		].iter().cloned()).unwrap();
 
-		a.set_bit(to_prevote(v.info(&1).unwrap().canon_idx()), 10).unwrap(); // prevote 1
-		a.set_bit(to_precommit(v.info(&2).unwrap().canon_idx()), 10).unwrap(); // precommit 2
+		a.set_bit(to_prevote(v.get(&1).unwrap().canon_idx()), 10).unwrap(); // prevote 1
+		a.set_bit(to_precommit(v.get(&2).unwrap().canon_idx()), 10).unwrap(); // precommit 2
 
-		b.set_bit(to_prevote(v.info(&3).unwrap().canon_idx()), 10).unwrap(); // prevote 3
-		b.set_bit(to_precommit(v.info(&3).unwrap().canon_idx()), 10).unwrap(); // precommit 3
+		b.set_bit(to_prevote(v.get(&3).unwrap().canon_idx()), 10).unwrap(); // prevote 3
+		b.set_bit(to_precommit(v.get(&3).unwrap().canon_idx()), 10).unwrap(); // precommit 3
 
 		let c = a.merge(&b).unwrap();
-		assert_eq!(c.total_weight(|i| v.weight_by_index(i).unwrap()), (14, 16));
+		assert_eq!(c.total_weight(|i| v.nth(i).unwrap().1.weight()), (14, 16));
 	}
 
 	#[test]
 	fn set_first_and_last_bits() {
-		let v: VoterSet<usize> = (0..32).map(|i| (i, (i + 1) as u64)).collect();
+		let v: VoterSet<usize> = VoterSet::new((0..32).map(|i| (i, (i + 1) as u64))).unwrap();
 
 		let mut live_bitfield = Bitfield::Live(LiveBitfield::with_voters(32));
 
 		live_bitfield.set_bit(0, 32).unwrap();
 		live_bitfield.set_bit(63, 32).unwrap();
 
-		assert_eq!(live_bitfield.total_weight(|i| v.weight_by_index(i).unwrap()), (1, 32));
+		assert_eq!(live_bitfield.total_weight(|i| v.nth(i).unwrap().1.weight()), (1, 32));
 	}
 
 	#[test]
@@ -365,31 +509,175 @@ mod tests {
 		let mut a = Bitfield::Live(LiveBitfield::with_voters(10));
 		let mut b = Bitfield::Live(LiveBitfield::with_voters(10));
 
-		let v: VoterSet<usize> = [
+		let v: VoterSet<usize> = VoterSet::new([
 			(1, 5),
 			(4, 1),
 			(3, 9),
 			(5, 7),
 			(9, 9),
 			(2, 7),
-		].iter().cloned().collect();
+		].iter().cloned()).unwrap();
 
-		a.set_bit(to_prevote(v.info(&1).unwrap().canon_idx()), 10).unwrap(); // prevote 1
-		a.set_bit(to_precommit(v.info(&2).unwrap().canon_idx()), 10).unwrap(); // precommit 2
-		a.set_bit(to_prevote(v.info(&3).unwrap().canon_idx()), 10).unwrap(); // prevote 3
+		a.set_bit(to_prevote(v.get(&1).unwrap().canon_idx()), 10).unwrap(); // prevote 1
+		a.set_bit(to_precommit(v.get(&2).unwrap().canon_idx()), 10).unwrap(); // precommit 2
+		a.set_bit(to_prevote(v.get(&3).unwrap().canon_idx()), 10).unwrap(); // prevote 3
 
-		b.set_bit(to_prevote(v.info(&1).unwrap().canon_idx()), 10).unwrap(); // prevote 1
-		b.set_bit(to_precommit(v.info(&2).unwrap().canon_idx()), 10).unwrap(); // precommit 2
-		b.set_bit(to_precommit(v.info(&3).unwrap().canon_idx()), 10).unwrap(); // precommit 3
+		b.set_bit(to_prevote(v.get(&1).unwrap().canon_idx()), 10).unwrap(); // prevote 1
+		b.set_bit(to_precommit(v.get(&2).unwrap().canon_idx()), 10).unwrap(); // precommit 2
+		b.set_bit(to_precommit(v.get(&3).unwrap().canon_idx()), 10).unwrap(); // precommit 3
 
-		assert_eq!(a.total_weight(|i| v.weight_by_index(i).unwrap()), (14, 7));
-		assert_eq!(b.total_weight(|i| v.weight_by_index(i).unwrap()), (5, 16));
+		assert_eq!(a.total_weight(|i| v.nth(i).unwrap().1.weight()), (14, 7));
+		assert_eq!(b.total_weight(|i| v.nth(i).unwrap().1.weight()), (5, 16));
 
 		let mut c = Bitfield::Live(LiveBitfield::with_voters(10));
 
-		c.set_bit(to_prevote(v.info(&1).unwrap().canon_idx()), 10).unwrap(); // prevote 1
-		c.set_bit(to_precommit(v.info(&2).unwrap().canon_idx()), 10).unwrap(); // precommit 2
+		c.set_bit(to_prevote(v.get(&1).unwrap().canon_idx()), 10).unwrap(); // prevote 1
+		c.set_bit(to_precommit(v.get(&2).unwrap().canon_idx()), 10).unwrap(); // precommit 2
 
 		assert_eq!(a.overlap(&b).unwrap(), c);
 	}
+
+	#[test]
+	fn voter_indices_of_set_bits() {
+		assert!(Bitfield::Blank.voter_indices().is_empty());
+
+		let mut bitfield = Bitfield::Live(LiveBitfield::with_voters(40));
+		assert!(bitfield.voter_indices().is_empty());
+
+		bitfield.set_bit(to_prevote(0), 40).unwrap();
+		bitfield.set_bit(to_precommit(5), 40).unwrap();
+		bitfield.set_bit(to_prevote(31), 40).unwrap();
+		bitfield.set_bit(to_precommit(31), 40).unwrap();
+		bitfield.set_bit(to_precommit(39), 40).unwrap();
+
+		assert_eq!(bitfield.voter_indices(), vec![0, 5, 31, 39]);
+	}
+
+	// read a bitfield back into a naive model with one `bool` per bit.
+	fn to_model(bitfield: &Bitfield, n_voters: usize) -> Vec<bool> {
+		let mut model = vec![false; n_voters * 2];
+		if let Bitfield::Live(ref live) = *bitfield {
+			for bit in live.set_bits() {
+				model[bit] = true;
+			}
+		}
+
+		model
+	}
+
+	#[test]
+	fn matches_naive_model() {
+		use rand::{Rng, SeedableRng, rngs::StdRng};
+
+		let mut rng = StdRng::seed_from_u64(42);
+
+		for _ in 0..1000 {
+			let n_voters = rng.gen_range(1, 300);
+			let v: VoterSet<usize> = VoterSet::new((0..n_voters).map(|i| (i, rng.gen_range(1, 1000)))).unwrap();
+
+			// a random bitfield along with its model. the density varies
+			// between a handful of bits and most of them.
+			let random_bitfield = |rng: &mut StdRng| {
+				let mut bitfield = Bitfield::Blank;
+				let mut model = vec![false; n_voters * 2];
+
+				let max_set = if rng.gen() { 4 } else { n_voters * 2 };
+				for _ in 0..rng.gen_range(0, max_set + 1) {
+					let bit = rng.gen_range(0, n_voters * 2);
+					bitfield.set_bit(bit, n_voters).unwrap();
+					model[bit] = true;
+				}
+
+				(bitfield, model)
+			};
+
+			let (a, a_model) = random_bitfield(&mut rng);
+			let (b, b_model) = random_bitfield(&mut rng);
+
+			let model_weight = |model: &[bool]| model.iter().enumerate()
+				.filter(|&(_, &set)| set)
+				.fold((0, 0), |(prevote, precommit), (bit, _)| {
+					let weight = v.nth(bit / 2).unwrap().1.weight();
+					if bit % 2 == 0 { (prevote + weight, precommit) } else { (prevote, precommit + weight) }
+				});
+
+			let model_voters = |model: &[bool]| (0..n_voters)
+				.filter(|&i| model[i * 2] || model[i * 2 + 1])
+				.collect::<Vec<_>>();
+
+			let lookup = |i| v.nth(i).unwrap().1.weight();
+
+			assert_eq!(to_model(&a, n_voters), a_model);
+			assert_eq!(a.total_weight(lookup), model_weight(&a_model));
+			assert_eq!(a.voter_indices(), model_voters(&a_model));
+
+			let merged = a.merge(&b).unwrap();
+			let merged_model: Vec<_> = a_model.iter().zip(&b_model).map(|(a, b)| *a || *b).collect();
+			assert_eq!(to_model(&merged, n_voters), merged_model);
+			assert_eq!(merged.total_weight(lookup), model_weight(&merged_model));
+			assert_eq!(merged, b.merge(&a).unwrap());
+
+			let overlap = a.overlap(&b).unwrap();
+			let overlap_model: Vec<_> = a_model.iter().zip(&b_model).map(|(a, b)| *a && *b).collect();
+			assert_eq!(to_model(&overlap, n_voters), overlap_model);
+			assert_eq!(overlap.total_weight(lookup), model_weight(&overlap_model));
+			assert_eq!(overlap, b.overlap(&a).unwrap());
+
+			// the same bits set in a different order are equal.
+			let mut rebuilt = Bitfield::Blank;
+			for bit in (0..n_voters * 2).rev().filter(|&bit| merged_model[bit]) {
+				rebuilt.set_bit(bit, n_voters).unwrap();
+			}
+			if rebuilt != Bitfield::Blank {
+				assert_eq!(rebuilt, merged);
+			}
+		}
+	}
+
+	#[test]
+	fn set_bit_is_bounded_by_words() {
+		let mut bitfield = LiveBitfield::with_voters(1000);
+		assert_eq!(bitfield.n_words, 32);
+
+		// the last word has room for bits beyond the voter set.
+		bitfield.set_bit(2047, 1000).unwrap();
+		assert_eq!(bitfield.set_bits().collect::<Vec<_>>(), vec![2047]);
+
+		assert_eq!(bitfield.set_bit(2048, 1000), Err(Error::IndexOutOfBounds(1024, 1000)));
+	}
+
+	#[test]
+	fn few_bits_are_kept_sparsely() {
+		let is_sparse = |bitfield: &Bitfield|
+			matches!(*bitfield, Bitfield::Live(LiveBitfield { bits: Bits::Sparse(_), .. }));
+
+		// 32 words hold the bits of 1000 voters, as much space as 64 indices.
+		let mut sparse = Bitfield::Blank;
+		for voter in 0..64 {
+			sparse.set_bit(to_prevote(voter * 10), 1000).unwrap();
+		}
+		assert!(is_sparse(&sparse));
+
+		let mut packed = sparse.clone();
+		packed.set_bit(to_precommit(999), 1000).unwrap();
+		assert!(!is_sparse(&packed));
+
+		// merging into a dense bitfield packs it, overlapping keeps the
+		// sparse side.
+		let mut few = Bitfield::Blank;
+		few.set_bit(to_prevote(10), 1000).unwrap();
+		few.set_bit(to_precommit(10), 1000).unwrap();
+
+		let merged = few.merge(&packed).unwrap();
+		assert!(!is_sparse(&merged));
+		assert_eq!(merged.voter_indices().len(), 65);
+
+		let overlap = packed.overlap(&few).unwrap();
+		assert!(is_sparse(&overlap));
+		assert_eq!(overlap.voter_indices(), vec![10]);
+
+		let merged = sparse.merge(&few).unwrap();
+		assert!(!is_sparse(&merged));
+		assert_eq!(merged.voter_indices(), sparse.voter_indices());
+	}
 }
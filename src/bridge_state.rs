@@ -15,15 +15,24 @@
 //! Bridging round state between rounds.
 
 use crate::round::State as RoundState;
-use futures::task;
-use parking_lot::{RwLock, RwLockReadGuard};
+use futures::{task, Stream};
+use parking_lot::{Mutex, RwLock, RwLockReadGuard};
+use std::pin::Pin;
 use std::sync::Arc;
-use std::task::Context;
+use std::task::{Context, Poll};
 
 // round state bridged across rounds.
 struct Bridged<H, N> {
 	inner: RwLock<RoundState<H, N>>,
 	waker: task::AtomicWaker,
+	watch: Mutex<Watch>,
+}
+
+// bookkeeping for streams watching the bridged state.
+struct Watch {
+	version: u64, // bumped on every update.
+	closed: bool, // whether the prior view was dropped.
+	wakers: Vec<Arc<task::AtomicWaker>>,
 }
 
 impl<H, N> Bridged<H, N> {
@@ -31,6 +40,17 @@ impl<H, N> Bridged<H, N> {
 		Bridged {
 			inner,
 			waker: task::AtomicWaker::new(),
+			watch: Mutex::new(Watch { version: 0, closed: false, wakers: Vec::new() }),
+		}
+	}
+}
+
+impl Watch {
+	fn wake_all(&mut self) {
+		// wakers of dropped streams are only referenced from here.
+		self.wakers.retain(|waker| Arc::strong_count(waker) > 1);
+		for waker in &self.wakers {
+			waker.wake();
 		}
 	}
 }
@@ -41,22 +61,85 @@ pub(crate) struct PriorView<H, N>(Arc<Bridged<H, N>>);
 impl<H, N> PriorView<H, N> {
 	/// Push an update to the latter view.
 	pub(crate) fn update(&self, new: RoundState<H, N>) {
-		*self.0.inner.write() = new;
+		{
+			let mut watch = self.0.watch.lock();
+			*self.0.inner.write() = new;
+			watch.version += 1;
+			watch.wake_all();
+		}
+
 		self.0.waker.wake();
 	}
 }
 
+impl<H, N> Drop for PriorView<H, N> {
+	fn drop(&mut self) {
+		let mut watch = self.0.watch.lock();
+		watch.closed = true;
+		watch.wake_all();
+	}
+}
+
 /// A latter view of a round-state.
 pub(crate) struct LatterView<H, N>(Arc<Bridged<H, N>>);
 
 impl<H, N> LatterView<H, N> {
 	/// Fetch a handle to the last round-state.
-	pub(crate) fn get(&self, cx: &mut Context) -> RwLockReadGuard<RoundState<H, N>> {
+	pub(crate) fn get(&self, cx: &mut Context) -> RwLockReadGuard<'_, RoundState<H, N>> {
 		self.0.waker.register(cx.waker());
 		self.0.inner.read()
 	}
+
+	/// Get a stream of the bridged round-state.
+	pub(crate) fn watch(&self) -> RoundStateStream<H, N> {
+		let waker = Arc::new(task::AtomicWaker::new());
+		self.0.watch.lock().wakers.push(waker.clone());
+
+		RoundStateStream {
+			bridged: self.0.clone(),
+			waker,
+			seen: None,
+		}
+	}
+}
+
+/// A stream of the state of a round, as seen by the round built on top of it.
+///
+/// Yields the current state when first polled and then again whenever it
+/// changes. Updates made between two polls are coalesced, so only the latest
+/// state is yielded. Ends once the round is dropped.
+pub struct RoundStateStream<H, N> {
+	bridged: Arc<Bridged<H, N>>,
+	waker: Arc<task::AtomicWaker>,
+	seen: Option<u64>,
+}
+
+impl<H: Clone, N: Clone> Stream for RoundStateStream<H, N> {
+	type Item = RoundState<H, N>;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<RoundState<H, N>>> {
+		self.waker.register(cx.waker());
+
+		let watch = self.bridged.watch.lock();
+		if self.seen != Some(watch.version) {
+			let state = self.bridged.inner.read().clone();
+			let version = watch.version;
+			drop(watch);
+
+			self.seen = Some(version);
+			return Poll::Ready(Some(state));
+		}
+
+		if watch.closed {
+			Poll::Ready(None)
+		} else {
+			Poll::Pending
+		}
+	}
 }
 
+impl<H, N> Unpin for RoundStateStream<H, N> {}
+
 /// Constructs two views of a bridged round-state.
 ///
 /// The prior view is held by a round which produces the state and pushes updates to a latter view.
@@ -73,7 +156,6 @@ pub(crate) fn bridge_state<H, N>(initial: RoundState<H, N>) -> (PriorView<H, N>,
 
 #[cfg(test)]
 mod tests {
-	use futures::prelude::*;
 	use std::{sync::Barrier, task::Poll};
 	use super::*;
 
@@ -110,4 +192,87 @@ mod tests {
 		barrier.wait();
 		futures::executor::block_on(waits_for_finality);
 	}
+
+	#[test]
+	fn watching_state() {
+		use futures::StreamExt;
+
+		let initial = RoundState {
+			prevote_ghost: None,
+			finalized: None,
+			estimate: None,
+			completable: false,
+		};
+
+		let (prior, latter) = bridge_state(initial.clone());
+		let mut states = latter.watch();
+
+		let with_estimate = RoundState {
+			prevote_ghost: Some(("5", 5)),
+			finalized: None,
+			estimate: Some(("3", 3)),
+			completable: true,
+		};
+
+		let with_finalized = RoundState {
+			finalized: Some(("3", 3)),
+			..with_estimate.clone()
+		};
+
+		futures::executor::block_on(async move {
+			// the current state comes first.
+			assert_eq!(states.next().await, Some(initial));
+
+			prior.update(with_estimate.clone());
+			assert_eq!(states.next().await, Some(with_estimate.clone()));
+
+			// intermediate updates are coalesced.
+			prior.update(with_estimate);
+			prior.update(with_finalized.clone());
+			assert_eq!(states.next().await, Some(with_finalized));
+
+			// the stream ends once the round is gone.
+			drop(prior);
+			assert_eq!(states.next().await, None);
+		});
+	}
+
+	#[test]
+	fn watching_state_wakes_pending_stream() {
+		use futures::StreamExt;
+
+		let initial = RoundState {
+			prevote_ghost: None,
+			finalized: None,
+			estimate: None,
+			completable: false,
+		};
+
+		let (prior, latter) = bridge_state(initial);
+		let mut states = latter.watch();
+		drop(latter);
+
+		let barrier = Arc::new(Barrier::new(2));
+		let barrier_other = barrier.clone();
+		let handle = ::std::thread::spawn(move || {
+			barrier_other.wait();
+			prior.update(RoundState {
+				prevote_ghost: Some(("5", 5)),
+				finalized: Some(("1", 1)),
+				estimate: Some(("3", 3)),
+				completable: true,
+			});
+		});
+
+		futures::executor::block_on(async move {
+			let _ = states.next().await;
+			barrier.wait();
+
+			// woken by the update, then by the prior view being dropped.
+			assert_eq!(states.next().await.unwrap().finalized, Some(("1", 1)));
+			assert_eq!(states.next().await, None);
+		});
+
+		handle.join().unwrap();
+	}
 }
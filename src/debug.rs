@@ -0,0 +1,199 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! `Debug` output of signature-bearing messages.
+//!
+//! Signatures are noisy in logs, so only the start of their `Debug` output
+//! is printed by default. The full output is available through the
+//! `verbose_debug` method of each of these messages.
+
+use core::fmt;
+
+use crate::{Commit, SignedMessage, SignedPrecommit, SignedPrevote};
+
+// how many characters of the `Debug` output of a signature are printed.
+const SIGNATURE_CHARS: usize = 12;
+
+// prints the start of the `Debug` output of a signature.
+struct Redacted<'a, S>(&'a S);
+
+impl<S: fmt::Debug> fmt::Debug for Redacted<'_, S> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let mut truncate = Truncate { inner: f, remaining: SIGNATURE_CHARS, truncated: false };
+		fmt::write(&mut truncate, format_args!("{:?}", self.0))?;
+
+		if truncate.truncated {
+			f.write_str("..")?;
+		}
+
+		Ok(())
+	}
+}
+
+// writes through at most `remaining` characters, and drops the rest.
+struct Truncate<'a, 'b> {
+	inner: &'a mut fmt::Formatter<'b>,
+	remaining: usize,
+	truncated: bool,
+}
+
+impl fmt::Write for Truncate<'_, '_> {
+	fn write_str(&mut self, s: &str) -> fmt::Result {
+		match s.char_indices().nth(self.remaining) {
+			Some((end, _)) => {
+				self.remaining = 0;
+				self.truncated = true;
+				self.inner.write_str(&s[..end])
+			}
+			None => {
+				self.remaining -= s.chars().count();
+				self.inner.write_str(s)
+			}
+		}
+	}
+}
+
+/// Prints the full `Debug` output of a message, including its signatures.
+///
+/// Returned by e.g. `SignedPrecommit::verbose_debug`.
+pub struct VerboseDebug<'a, T>(&'a T);
+
+macro_rules! signed_vote_debug {
+	($name:ident, $vote:ident) => {
+		impl<H, N, S, Id> $name<H, N, S, Id> {
+			/// Print the full `Debug` output, instead of only the start of the
+			/// signature.
+			pub fn verbose_debug(&self) -> VerboseDebug<'_, Self> {
+				VerboseDebug(self)
+			}
+
+			fn fmt_with(&self, f: &mut fmt::Formatter, signature: &dyn fmt::Debug) -> fmt::Result
+				where H: fmt::Debug, N: fmt::Debug, Id: fmt::Debug,
+			{
+				f.debug_struct(stringify!($name))
+					.field(stringify!($vote), &self.$vote)
+					.field("signature", signature)
+					.field("id", &self.id)
+					.finish()
+			}
+		}
+
+		impl<H: fmt::Debug, N: fmt::Debug, S: fmt::Debug, Id: fmt::Debug> fmt::Debug for $name<H, N, S, Id> {
+			fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+				self.fmt_with(f, &Redacted(&self.signature))
+			}
+		}
+
+		impl<H: fmt::Debug, N: fmt::Debug, S: fmt::Debug, Id: fmt::Debug> fmt::Debug
+			for VerboseDebug<'_, $name<H, N, S, Id>>
+		{
+			fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+				self.0.fmt_with(f, &self.0.signature)
+			}
+		}
+	}
+}
+
+signed_vote_debug!(SignedMessage, message);
+signed_vote_debug!(SignedPrevote, prevote);
+signed_vote_debug!(SignedPrecommit, precommit);
+
+impl<H, N, S, Id> Commit<H, N, S, Id> {
+	/// Print the full `Debug` output, instead of only the start of the
+	/// signatures of the precommits.
+	pub fn verbose_debug(&self) -> VerboseDebug<'_, Self> {
+		VerboseDebug(self)
+	}
+
+	fn fmt_with(&self, f: &mut fmt::Formatter, precommits: &dyn fmt::Debug) -> fmt::Result
+		where H: fmt::Debug, N: fmt::Debug,
+	{
+		f.debug_struct("Commit")
+			.field("target_hash", &self.target_hash)
+			.field("target_number", &self.target_number)
+			.field("precommits", precommits)
+			.field("set_id", &self.set_id)
+			.finish()
+	}
+}
+
+impl<H: fmt::Debug, N: fmt::Debug, S: fmt::Debug, Id: fmt::Debug> fmt::Debug for Commit<H, N, S, Id> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		self.fmt_with(f, &self.precommits)
+	}
+}
+
+impl<H: fmt::Debug, N: fmt::Debug, S: fmt::Debug, Id: fmt::Debug> fmt::Debug for VerboseDebug<'_, Commit<H, N, S, Id>> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let precommits = VerbosePrecommits(&self.0.precommits);
+		self.0.fmt_with(f, &precommits)
+	}
+}
+
+// the precommits of a commit, each printed in full.
+struct VerbosePrecommits<'a, H, N, S, Id>(&'a [SignedPrecommit<H, N, S, Id>]);
+
+impl<H: fmt::Debug, N: fmt::Debug, S: fmt::Debug, Id: fmt::Debug> fmt::Debug for VerbosePrecommits<'_, H, N, S, Id> {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.debug_list().entries(self.0.iter().map(SignedPrecommit::verbose_debug)).finish()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{Commit, Message, Precommit, Prevote, SignedMessage, SignedPrecommit};
+	use crate::std::vec::Vec;
+
+	fn signature() -> Vec<u8> {
+		(0..64).collect()
+	}
+
+	#[test]
+	fn signatures_are_truncated_by_default() {
+		let full_signature = format!("{:?}", signature());
+
+		let precommit = SignedPrecommit {
+			precommit: Precommit::new("B", 3),
+			signature: signature(),
+			id: 7,
+		};
+
+		let debug = format!("{:?}", precommit);
+		assert!(!debug.contains(&full_signature));
+		assert!(debug.contains("signature: [0, 1, 2, 3,.."));
+		assert!(debug.contains("target_hash: \"B\""));
+		assert!(debug.contains("id: 7"));
+		assert!(format!("{:?}", precommit.verbose_debug()).contains(&full_signature));
+
+		let message = SignedMessage {
+			message: Message::Prevote(Prevote::new("B", 3)),
+			signature: signature(),
+			id: 7,
+		};
+
+		assert!(!format!("{:?}", message).contains(&full_signature));
+		assert!(format!("{:?}", message.verbose_debug()).contains(&full_signature));
+
+		let commit = Commit { target_hash: "B", target_number: 3, precommits: vec![precommit], set_id: 0 };
+
+		assert!(!format!("{:?}", commit).contains(&full_signature));
+		assert!(format!("{:?}", commit.verbose_debug()).contains(&full_signature));
+	}
+
+	#[test]
+	fn short_signatures_are_printed_in_full() {
+		let precommit = SignedPrecommit { precommit: Precommit::new("B", 3), signature: 42u64, id: 7 };
+		assert_eq!(format!("{:?}", precommit), format!("{:?}", precommit.verbose_debug()));
+	}
+}
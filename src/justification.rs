@@ -0,0 +1,306 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Self-contained proofs of finality.
+//!
+//! A `Justification` bundles a commit with the ancestry linking the target
+//! of each of its precommits to the commit target, so that it can be checked
+//! without access to a `Chain`, e.g. by light clients.
+
+use crate::std::{self, collections::HashSet, hash::Hash, vec::Vec};
+
+use crate::voter_set::VoterSet;
+use crate::{validate_commit_stateless, BlockNumberOps, Chain, Commit, CompactCommit, Error, MalformedCommit};
+
+#[cfg(feature = "derive-codec")]
+use crate::{localized_payload, Message, MessageVerifier};
+
+#[cfg(feature = "derive-codec")]
+use parity_scale_codec::{Encode, Decode};
+
+#[cfg(feature = "derive-serde")]
+use serde::{Deserialize, Serialize};
+
+/// A commit along with the ancestry proving that the target of each of its
+/// precommits is on the same chain as the commit target.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+#[cfg_attr(feature = "derive-serde", derive(Serialize, Deserialize))]
+pub struct Justification<H, N, S, Id> {
+	/// The round the commit was made in.
+	pub round: u64,
+	/// The commit being justified.
+	pub commit: Commit<H, N, S, Id>,
+	/// Pairs of a block hash and the hash of its parent, covering the blocks
+	/// between the commit target and the target of each precommit.
+	pub ancestry: Vec<(H, H)>,
+}
+
+/// Ways in which a `Justification` can be invalid.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+pub enum BadJustification {
+	/// The commit is structurally invalid.
+	Malformed(MalformedCommit),
+	/// A precommit has a bad signature.
+	BadSignature,
+	/// The ancestry doesn't link a precommit target to the commit target.
+	MissingAncestry,
+	/// The precommits don't have enough weight to finalize the commit target.
+	NotFinal,
+}
+
+#[cfg(feature = "std")]
+impl std::fmt::Display for BadJustification {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match *self {
+			BadJustification::Malformed(ref e) => write!(f, "Justification has a malformed commit: {}", e),
+			BadJustification::BadSignature => write!(f, "Justification has a precommit with a bad signature"),
+			BadJustification::MissingAncestry => write!(f, "Justification is missing ancestry of a precommit target"),
+			BadJustification::NotFinal => write!(f, "Justification doesn't have enough precommit weight"),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for BadJustification {}
+
+impl<H, N, S, Id> Justification<H, N, S, Id> where
+	H: Hash + Clone + Eq + Ord + std::fmt::Debug,
+	N: Copy + BlockNumberOps + std::fmt::Debug,
+	S: Clone + Eq,
+	Id: Hash + Clone + Eq + Ord + std::fmt::Debug,
+{
+	/// Create a justification for a commit made in the given round, taking
+	/// the ancestry of the precommit targets from the chain. Every block is
+	/// included once, no matter how many precommits it is an ancestor of.
+	pub fn from_commit<C: Chain<H, N>>(round: u64, commit: Commit<H, N, S, Id>, chain: &C) -> Result<Self, Error> {
+		let mut seen = HashSet::new();
+		let mut ancestry = Vec::new();
+
+		for signed in &commit.precommits {
+			let target = &signed.precommit.target_hash;
+			if *target == commit.target_hash { continue }
+
+			// nil precommits are on ancestors of the commit target.
+			let (base, block) = if signed.precommit.target_number < commit.target_number {
+				(target.clone(), commit.target_hash.clone())
+			} else {
+				(commit.target_hash.clone(), target.clone())
+			};
+
+			// pair every block from `block` down to the child of `base` with
+			// its parent.
+			let blocks = chain.ancestry_inclusive(base, block)?;
+			for pair in blocks.windows(2) {
+				// the paths of nil precommits all start at the commit target, so
+				// a block being included already says nothing about the blocks
+				// below it.
+				if seen.insert(pair[0].clone()) {
+					ancestry.push((pair[0].clone(), pair[1].clone()));
+				}
+			}
+		}
+
+		Ok(Justification { round, commit, ancestry })
+	}
+
+	/// Check the weight and ancestry of the precommits, returning the
+	/// finalized block. Voters may equivocate once, as in
+	/// `CompactCommit::validate_structure_allowing_equivocations`.
+	///
+	/// Only the embedded ancestry is used, and signatures are not checked,
+	/// see `verify` for that.
+	pub fn validate(&self, voters: &VoterSet<Id>) -> Result<(H, N), BadJustification> {
+		CompactCommit::from(self.commit.clone())
+			.validate_structure_allowing_equivocations(voters)
+			.map_err(BadJustification::Malformed)?;
+
+		let ancestry_proof = self.ancestry.iter().cloned().collect();
+		let result = validate_commit_stateless(&self.commit, voters, &ancestry_proof)
+			.map_err(|_| BadJustification::MissingAncestry)?;
+
+		match result.finalized_target() {
+			Some(finalized) => Ok(finalized.clone()),
+			None => Err(BadJustification::NotFinal),
+		}
+	}
+}
+
+#[cfg(feature = "derive-codec")]
+impl<H, N, S, Id> Justification<H, N, S, Id> where
+	H: Hash + Clone + Eq + Ord + std::fmt::Debug + Encode,
+	N: Copy + BlockNumberOps + std::fmt::Debug + Encode,
+	S: Clone + Eq,
+	Id: Hash + Clone + Eq + Ord + std::fmt::Debug,
+{
+	/// Check the signatures on the precommits, as cast in the round of the
+	/// justification, and then `validate` it.
+	pub fn verify<V: MessageVerifier<Id, S>>(
+		&self,
+		voters: &VoterSet<Id>,
+		verifier: &V,
+	) -> Result<(H, N), BadJustification> {
+		for signed in &self.commit.precommits {
+			let message = Message::Precommit(signed.precommit.clone());
+			let payload = localized_payload(self.round, self.commit.set_id, &message);
+			if !verifier.verify(&signed.id, &signed.signature, &payload) {
+				return Err(BadJustification::BadSignature);
+			}
+		}
+
+		self.validate(voters)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Precommit, SignedPrecommit};
+	use crate::testing::chain::{GENESIS_HASH, DummyChain};
+
+	fn commit(precommits: &[(&'static str, u32, u32)]) -> Commit<&'static str, u32, u32, u32> {
+		Commit {
+			target_hash: "C",
+			target_number: 4,
+			precommits: precommits.iter().map(|&(hash, number, id)| SignedPrecommit {
+				precommit: Precommit::new(hash, number),
+				signature: id,
+				id,
+			}).collect(),
+			set_id: 0,
+		}
+	}
+
+	fn chain() -> DummyChain {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+		chain.push_blocks("C", &["D2"]);
+		chain
+	}
+
+	#[test]
+	fn valid_justification_is_accepted() {
+		let voters = VoterSet::new((1..5).map(|i| (i, 1))).unwrap();
+		let commit = commit(&[("C", 4, 1), ("E", 6, 2), ("D", 5, 3), ("D2", 5, 4)]);
+
+		let justification = Justification::from_commit(1, commit, &chain()).unwrap();
+
+		// the blocks on the way to "E" are only included once.
+		assert_eq!(justification.ancestry, vec![("E", "D"), ("D", "C"), ("D2", "C")]);
+		assert_eq!(justification.validate(&voters), Ok(("C", 4)));
+	}
+
+	#[test]
+	fn nil_precommits_are_linked_upwards() {
+		let voters = VoterSet::new((1..5).map(|i| (i, 1))).unwrap();
+		let commit = commit(&[("C", 4, 1), ("D", 5, 2), ("E", 6, 3), ("A", 2, 4)]);
+
+		let justification = Justification::from_commit(1, commit, &chain()).unwrap();
+		assert_eq!(justification.validate(&voters), Ok(("C", 4)));
+		assert!(justification.ancestry.contains(&("B", "A")));
+	}
+
+	#[test]
+	fn nested_nil_precommits_are_all_linked() {
+		let voters = VoterSet::new(vec![(1, 3), (2, 1), (3, 1), (4, 3)]).unwrap();
+		let commit = commit(&[("C", 4, 1), ("B", 3, 2), ("A", 2, 3), ("E", 6, 4)]);
+
+		let justification = Justification::from_commit(1, commit, &chain()).unwrap();
+		assert_eq!(justification.ancestry, vec![("C", "B"), ("B", "A"), ("E", "D"), ("D", "C")]);
+		assert_eq!(justification.validate(&voters), Ok(("C", 4)));
+	}
+
+	#[test]
+	fn missing_or_tampered_ancestry_is_rejected() {
+		let voters = VoterSet::new((1..5).map(|i| (i, 1))).unwrap();
+		let commit = commit(&[("C", 4, 1), ("E", 6, 2), ("D2", 5, 3)]);
+		let justification = Justification::from_commit(1, commit, &chain()).unwrap();
+		assert_eq!(justification.validate(&voters), Ok(("C", 4)));
+
+		// a link missing.
+		let mut missing = justification.clone();
+		missing.ancestry.retain(|link| *link != ("D", "C"));
+		assert_eq!(missing.validate(&voters), Err(BadJustification::MissingAncestry));
+
+		// a link skipping a block, which doesn't match the numbers.
+		let mut tampered = justification.clone();
+		tampered.ancestry.retain(|link| *link != ("D", "C"));
+		tampered.ancestry.retain(|link| *link != ("E", "D"));
+		tampered.ancestry.push(("E", "C"));
+		assert_eq!(tampered.validate(&voters), Err(BadJustification::MissingAncestry));
+
+		// a link to a block off the target's chain.
+		let mut tampered = justification.clone();
+		tampered.ancestry.retain(|link| *link != ("D2", "C"));
+		tampered.ancestry.push(("D2", "B"));
+		assert_eq!(tampered.validate(&voters), Err(BadJustification::MissingAncestry));
+
+		// a cycle.
+		let mut tampered = justification;
+		tampered.ancestry.retain(|link| *link != ("D", "C"));
+		tampered.ancestry.push(("D", "E"));
+		assert_eq!(tampered.validate(&voters), Err(BadJustification::MissingAncestry));
+	}
+
+	#[test]
+	fn weight_and_duplicates_are_checked() {
+		let voters = VoterSet::new((1..5).map(|i| (i, 1))).unwrap();
+
+		let justification = Justification::from_commit(1, commit(&[("C", 4, 1), ("D", 5, 2)]), &chain()).unwrap();
+		assert_eq!(justification.validate(&voters), Err(BadJustification::NotFinal));
+
+		let duplicated = commit(&[("C", 4, 1), ("D", 5, 2), ("D", 5, 2)]);
+		let justification = Justification::from_commit(1, duplicated, &chain()).unwrap();
+		assert_eq!(
+			justification.validate(&voters),
+			Err(BadJustification::Malformed(MalformedCommit::DuplicateVoter)),
+		);
+	}
+
+	#[cfg(feature = "derive-codec")]
+	#[test]
+	fn signatures_are_verified() {
+		// signatures are the signer's id along with the payload.
+		struct Verifier;
+		impl MessageVerifier<u32, (u32, Vec<u8>)> for Verifier {
+			fn verify(&self, id: &u32, signature: &(u32, Vec<u8>), payload: &[u8]) -> bool {
+				signature.0 == *id && signature.1 == payload
+			}
+		}
+
+		let voters = VoterSet::new((1..4).map(|i| (i, 1))).unwrap();
+		let sign = |hash, number, id| {
+			let precommit = Precommit::new(hash, number);
+			let payload = localized_payload(2, 0, &Message::Precommit(precommit.clone()));
+			SignedPrecommit { precommit, signature: (id, payload), id }
+		};
+
+		let commit = Commit {
+			target_hash: "C",
+			target_number: 4,
+			precommits: vec![sign("C", 4, 1), sign("D", 5, 2), sign("E", 6, 3)],
+			set_id: 0,
+		};
+
+		let justification = Justification::from_commit(2, commit, &chain()).unwrap();
+		assert_eq!(justification.verify(&voters, &Verifier), Ok(("C", 4)));
+
+		// signed for another round.
+		let mut other_round = justification;
+		other_round.round = 3;
+		assert_eq!(other_round.verify(&voters, &Verifier), Err(BadJustification::BadSignature));
+	}
+}
@@ -26,6 +26,9 @@
 //! The work for actually casting votes is done in the `voter` module.
 
 #![cfg_attr(not(feature = "std"), no_std)]
+// error types only implement `Debug` under `std`, so `Result::expect` isn't
+// available everywhere.
+#![allow(clippy::ok_expect)]
 
 #[cfg(not(feature = "std"))]
 #[macro_use]
@@ -56,10 +59,17 @@ mod std {
 #[cfg(feature = "std")]
 extern crate std;
 
-use crate::std::vec::Vec;
+use crate::std::{collections::HashMap, vec::Vec};
 
 pub mod bitfield;
 
+#[cfg(any(feature = "std", test))]
+mod debug;
+#[cfg(any(feature = "std", test))]
+pub use debug::VerboseDebug;
+
+pub mod justification;
+
 pub mod round;
 use round::ImportResult;
 
@@ -74,16 +84,21 @@ pub mod voter;
 #[cfg(feature = "std")]
 mod bridge_state;
 
-#[cfg(test)]
-mod testing;
+#[cfg(any(test, feature = "test-helpers"))]
+#[doc(hidden)]
+pub mod testing;
 
 #[cfg(feature = "derive-codec")]
 use parity_scale_codec::{Encode, Decode};
 
+#[cfg(feature = "derive-serde")]
+use serde::{Deserialize, Serialize};
+
 /// A prevote for a block and its ancestors.
 #[derive(Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "std", derive(Debug))]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
 #[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+#[cfg_attr(feature = "derive-serde", derive(Serialize, Deserialize))]
 pub struct Prevote<H, N> {
 	/// The target block's hash.
 	pub target_hash: H,
@@ -99,8 +114,9 @@ impl<H, N> Prevote<H, N> {
 
 /// A precommit for a block and its ancestors.
 #[derive(Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "std", derive(Debug))]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
 #[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+#[cfg_attr(feature = "derive-serde", derive(Serialize, Deserialize))]
 pub struct Precommit<H, N> {
 	/// The target block's hash.
 	pub target_hash: H,
@@ -112,12 +128,24 @@ impl<H, N> Precommit<H, N> {
 	pub fn new(target_hash: H, target_number: N) -> Self {
 		Precommit { target_hash, target_number }
 	}
+
+	/// A nil precommit in a round with the given base, i.e. a precommit for
+	/// the base itself. Voters cast it when they can't precommit for anything
+	/// else, to not be mistaken for being offline.
+	///
+	/// Nil precommits count towards a round being completable, but not towards
+	/// the weight of any block above the base. In commits, precommits for ancestors of the
+	/// commit target are treated as nil and ignored.
+	pub fn nil(base: (H, N)) -> Self {
+		Precommit { target_hash: base.0, target_number: base.1 }
+	}
 }
 
 /// A primary proposed block, this is a broadcast of the last round's estimate.
 #[derive(Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "std", derive(Debug))]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
 #[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+#[cfg_attr(feature = "derive-serde", derive(Serialize, Deserialize))]
 pub struct PrimaryPropose<H, N> {
 	/// The target block's hash.
 	pub target_hash: H,
@@ -131,36 +159,149 @@ impl<H, N> PrimaryPropose<H, N> {
 	}
 }
 
+/// Errors of GRANDPA, e.g. on importing votes or validating messages.
+///
+/// Environments are expected to wrap these in their own error type, by way
+/// of `From<Error>`. Matching on all variants exhaustively breaks whenever
+/// variants are added, so matches should have a fallback arm. As `Error` is
+/// also converted from `MalformedCommit`, error types inferred through `From`,
+/// e.g. of `SinkExt::with`, may need to be annotated.
 #[derive(Clone, PartialEq)]
-#[cfg_attr(feature = "std", derive(Debug))]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
 pub enum Error {
+	/// A block isn't a descendent of the base it's expected to build on.
 	NotDescendent,
+	/// Block number arithmetic overflowed, e.g. for a block claimed to be
+	/// below its ancestor.
+	BlockNumberOverflow,
+	/// The ancestry of a block is longer than allowed.
+	AncestryTooLong,
+	/// A vote from a voter who isn't in the voter set.
+	UnknownVoter,
+	/// A block below the last finalized block was reported as finalized.
+	FinalityReverted,
+	/// A block at or below the base of a vote graph, which can't be in the
+	/// subtree of blocks built on it.
+	BlockNotInSubtree,
+	/// The weight of voters behind some messages is below the threshold.
+	ThresholdNotReached {
+		/// The weight of the voters.
+		got: u64,
+		/// The threshold weight of the voter set.
+		needed: u64,
+	},
+	/// A catch-up was rejected.
+	BadCatchUp(BadCatchUpReason),
+	/// A commit is malformed.
+	BadCommit(MalformedCommit),
+	/// A sink doesn't accept messages anymore.
+	SinkClosed,
+	/// A message is for a different voter set than expected.
+	WrongSetId {
+		/// The id of the voter set expected.
+		expected: u64,
+		/// The id of the voter set of the message.
+		got: u64,
+	},
 }
 
-#[cfg(feature = "std")]
-impl std::fmt::Display for Error {
-	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+impl core::fmt::Display for Error {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
 		match *self {
 			Error::NotDescendent => write!(f, "Block not descendent of base"),
+			Error::BlockNumberOverflow => write!(f, "Block number arithmetic overflowed"),
+			Error::AncestryTooLong => write!(f, "Ancestry of block exceeds maximum length"),
+			Error::UnknownVoter => write!(f, "Vote from a voter outside the voter set"),
+			Error::FinalityReverted => write!(f, "Finalized block below the last finalized block"),
+			Error::BlockNotInSubtree => write!(f, "Block not in the subtree of the base"),
+			Error::ThresholdNotReached { got, needed } =>
+				write!(f, "Voter weight {} is below the threshold of {}", got, needed),
+			Error::BadCatchUp(ref reason) => write!(f, "Bad catch-up: {}", reason),
+			Error::BadCommit(ref malformed) => write!(f, "Bad commit: {}", malformed),
+			Error::SinkClosed => write!(f, "Sink closed"),
+			Error::WrongSetId { expected, got } =>
+				write!(f, "Message for voter set {}, expected set {}", got, expected),
 		}
 	}
 }
 
 #[cfg(feature = "std")]
 impl std::error::Error for Error {
-	fn description(&self) -> &str {
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
 		match *self {
-			Error::NotDescendent => "Block not descendent of base",
+			Error::BadCommit(ref malformed) => Some(malformed),
+			_ => None,
 		}
 	}
 }
 
+impl From<MalformedCommit> for Error {
+	fn from(malformed: MalformedCommit) -> Self {
+		Error::BadCommit(malformed)
+	}
+}
+
+/// Why a catch-up was rejected, besides invalid votes.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+pub enum BadCatchUpReason {
+	/// The catch-up is for a round we have reached already.
+	Stale,
+	/// The catch-up is for a different voter set.
+	WrongSetId,
+	/// The votes of the catch-up don't make its round completable.
+	NotCompletable,
+}
+
+impl core::fmt::Display for BadCatchUpReason {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		match *self {
+			BadCatchUpReason::Stale => write!(f, "Catch-up for a round reached already"),
+			BadCatchUpReason::WrongSetId => write!(f, "Catch-up for a different voter set"),
+			BadCatchUpReason::NotCompletable => write!(f, "Catch-up doesn't complete its round"),
+		}
+	}
+}
+
+/// Ways in which the structure of a `CompactCommit` can be invalid.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+pub enum MalformedCommit {
+	/// The number of precommits differs from the number of signatures.
+	LengthMismatch,
+	/// A precommit is by a voter who isn't in the voter set.
+	UnknownVoter,
+	/// A voter has more precommits than allowed.
+	DuplicateVoter,
+}
+
+impl core::fmt::Display for MalformedCommit {
+	fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+		match *self {
+			MalformedCommit::LengthMismatch => write!(f, "Commit has a different number of precommits and signatures"),
+			MalformedCommit::UnknownVoter => write!(f, "Commit has a precommit by a voter not in the set"),
+			MalformedCommit::DuplicateVoter => write!(f, "Commit has too many precommits by the same voter"),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MalformedCommit {}
+
 /// Arithmetic necessary for a block number.
+///
+/// Block numbers may come from untrusted votes, so arithmetic on them
+/// should use `checked_add` and `checked_sub` where overflow is possible.
 pub trait BlockNumberOps:
 	std::fmt::Debug +
 	std::cmp::Ord +
 	std::ops::Add<Output=Self> +
 	std::ops::Sub<Output=Self> +
+	num::CheckedAdd +
+	num::CheckedSub +
 	num::One +
 	num::Zero +
 	num::AsPrimitive<usize>
@@ -171,6 +312,8 @@ impl<T> BlockNumberOps for T where
 	T: std::cmp::Ord,
 	T: std::ops::Add<Output=Self>,
 	T: std::ops::Sub<Output=Self>,
+	T: num::CheckedAdd,
+	T: num::CheckedSub,
 	T: num::One,
 	T: num::Zero,
 	T: num::AsPrimitive<usize>,
@@ -184,29 +327,99 @@ pub trait Chain<H: Eq, N: Copy + BlockNumberOps> {
 	/// If the block is not a descendent of `base`, returns an error.
 	fn ancestry(&self, base: H, block: H) -> Result<Vec<H>, Error>;
 
-	/// Return the hash of the best block whose chain contains the given block hash,
-	/// even if that block is `base` itself.
+	/// Return the hash and number of the best block whose chain contains the
+	/// given block hash, i.e. the block to prevote for when building on `base`.
+	/// This may be `base` itself, and needn't be the highest descendent of it,
+	/// e.g. if the fork choice rule prefers a shorter fork.
 	///
-	/// If `base` is unknown, return `None`.
+	/// If `base` is unknown, e.g. after a reorg or before it's imported, return
+	/// `None`. Voters wait for the block to become known again before they
+	/// prevote then, so there is no default returning `None`.
+	///
+	/// Voters look this up through `Environment::best_chain`, which can answer
+	/// asynchronously.
 	fn best_chain_containing(&self, base: H) -> Option<(H, N)>;
 
 	/// Returns true if `block` is a descendent of or equal to the given `base`.
 	fn is_equal_or_descendent_of(&self, base: H, block: H) -> bool {
 		if base == block { return true; }
 
-		// TODO: currently this function always succeeds since the only error
-		// variant is `Error::NotDescendent`, this may change in the future as
-		// other errors (e.g. IO) are not being exposed.
-		match self.ancestry(base, block) {
-			Ok(_) => true,
-			Err(Error::NotDescendent) => false,
+		// TODO: currently this function always succeeds since errors other than
+		// `Error::NotDescendent` are treated the same way, this may change in the
+		// future as other errors (e.g. IO) are not being exposed.
+		self.ancestry(base, block).is_ok()
+	}
+
+	/// Like `ancestry`, but fails with `Error::AncestryTooLong` if it's longer
+	/// than `max_len` blocks.
+	///
+	/// By default the whole ancestry is fetched before checking its length.
+	/// Implementations should stop walking it once the bound is exceeded, so
+	/// that the bound limits the work done for blocks far above `base`.
+	fn ancestry_bounded(&self, base: H, block: H, max_len: usize) -> Result<Vec<H>, Error> {
+		let ancestry = self.ancestry(base, block)?;
+		if ancestry.len() > max_len {
+			return Err(Error::AncestryTooLong);
+		}
+
+		Ok(ancestry)
+	}
+
+	/// Get the ancestry of a block down to and including the base hash, in
+	/// reverse order starting with `block` itself. If `block` is `base`, it's
+	/// the only block returned.
+	///
+	/// If the block is not a descendent of `base`, returns an error.
+	fn ancestry_inclusive(&self, base: H, block: H) -> Result<Vec<H>, Error> where H: Clone {
+		let mut blocks = Vec::new();
+		if base == block {
+			blocks.push(block);
+			return Ok(blocks);
 		}
+
+		let ancestry = self.ancestry(base.clone(), block.clone())?;
+		blocks.reserve(ancestry.len() + 2);
+		blocks.push(block);
+		blocks.extend(ancestry);
+		blocks.push(base);
+
+		Ok(blocks)
+	}
+
+	/// Return the highest block which both `a` and `b` are equal to or
+	/// descendents of, given the hash and number of a `base` which they both
+	/// descend from. This may be `base` itself, or either of the blocks.
+	///
+	/// If either block is not a descendent of `base`, returns an error.
+	fn common_ancestor(&self, base: (H, N), a: H, b: H) -> Result<(H, N), Error> where H: Clone {
+		let (mut common, mut number) = base;
+		let a = self.ancestry_inclusive(common.clone(), a)?;
+		let b = self.ancestry_inclusive(common.clone(), b)?;
+
+		// both ancestries end in the base, so they're walked up from there
+		// until they fork.
+		for (a, b) in a.into_iter().rev().zip(b.into_iter().rev()).skip(1) {
+			if a != b { break }
+
+			number = number.checked_add(&N::one()).ok_or(Error::BlockNumberOverflow)?;
+			common = a;
+		}
+
+		Ok((common, number))
+	}
+
+	/// Return the last finalized block, if known. Vote graphs stop walking the
+	/// ancestry of blocks voted on at this block, if it's between their base
+	/// and the block, instead of walking back to the base every time. By
+	/// default `None` is returned, and ancestries are walked to the base.
+	fn last_finalized(&self) -> Option<(H, N)> {
+		None
 	}
 }
 
 /// An equivocation (double-vote) in a given round.
 #[derive(Clone, PartialEq)]
-#[cfg_attr(feature = "std", derive(Debug))]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
 #[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
 pub struct Equivocation<Id, V, S> {
 	/// The round number equivocated in.
@@ -221,8 +434,9 @@ pub struct Equivocation<Id, V, S> {
 
 /// A protocol message or vote.
 #[derive(Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "std", derive(Debug))]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
 #[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+#[cfg_attr(feature = "derive-serde", derive(Serialize, Deserialize))]
 pub enum Message<H, N> {
 	/// A prevote message.
 	#[cfg_attr(feature = "derive-codec", codec(index = "0"))]
@@ -248,8 +462,8 @@ impl<H, N: Copy> Message<H, N> {
 
 /// A signed message.
 #[derive(Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "std", derive(Debug))]
 #[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+#[cfg_attr(feature = "derive-serde", derive(Serialize, Deserialize))]
 pub struct SignedMessage<H, N, S, Id> {
 	/// The internal message which has been signed.
 	pub message: Message<H, N>,
@@ -266,10 +480,40 @@ impl<H, N: Copy, S, Id> SignedMessage<H, N, S, Id> {
 	}
 }
 
+#[cfg(feature = "derive-codec")]
+impl<H: Encode, N: Encode, S, Id> SignedMessage<H, N, S, Id> {
+	/// Check the signature on the message, as cast in the given round of the
+	/// voter set with the given id. The signed payload is built with
+	/// `localized_payload`.
+	pub fn verify<V: MessageVerifier<Id, S>>(&self, verifier: &V, round: u64, set_id: u64) -> bool {
+		let payload = localized_payload(round, set_id, &self.message);
+		verifier.verify(&self.id, &self.signature, &payload)
+	}
+}
+
+/// Checks signatures of voters over message payloads.
+pub trait MessageVerifier<Id, S> {
+	/// Whether `signature` is a valid signature by `id` over `payload`.
+	fn verify(&self, id: &Id, signature: &S, payload: &[u8]) -> bool;
+}
+
+/// The payload that is signed for a message cast in the given round of the
+/// voter set with the given id. Messages must be signed over this payload to
+/// pass `SignedMessage::verify`.
+///
+/// The payload is the SCALE encoding of the tuple `(message, round, set_id)`,
+/// i.e. the encoded message followed by the round and the set id as
+/// little-endian `u64`s. Binding both means a vote signed in one round or
+/// voter set can't be replayed in another.
+#[cfg(feature = "derive-codec")]
+pub fn localized_payload<H: Encode, N: Encode>(round: u64, set_id: u64, message: &Message<H, N>) -> Vec<u8> {
+	(message, round, set_id).encode()
+}
+
 /// A commit message which is an aggregate of precommits.
 #[derive(Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "std", derive(Debug))]
 #[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+#[cfg_attr(feature = "derive-serde", derive(Serialize, Deserialize))]
 pub struct Commit<H, N, S, Id> {
 	/// The target block's hash.
 	pub target_hash: H,
@@ -277,12 +521,90 @@ pub struct Commit<H, N, S, Id> {
 	pub target_number: N,
 	/// Precommits for target block or any block after it that justify this commit.
 	pub precommits: Vec<SignedPrecommit<H, N, S, Id>>,
+	/// The id of the voter set the precommits were cast in.
+	pub set_id: u64,
+}
+
+impl<H: Clone + Eq, N: Copy + BlockNumberOps, S, Id> Commit<H, N, S, Id> {
+	/// Check that the target of every precommit is equal to or a descendent
+	/// of the commit target, except for precommits on lower blocks, which must
+	/// be ancestors of it (these are nil precommits, see `Precommit::nil`).
+	///
+	/// This is only a structural check, which is cheaper than validating the
+	/// commit fully and can be used to reject bad commits early. Signatures
+	/// and weights are not checked.
+	///
+	/// Since the base of the commit's round isn't known here, precommits for
+	/// any ancestor of the commit target are accepted as nil. Use
+	/// `verify_ancestry_in_round` where the base is known.
+	pub fn verify_ancestry<C: Chain<H, N>>(&self, chain: &C) -> Result<(), Error> {
+		self.verify_ancestry_above(chain, None)
+	}
+
+	/// Like `verify_ancestry`, for a commit of a round with the given base:
+	/// nil precommits must also be for the base or a block above it.
+	pub fn verify_ancestry_in_round<C: Chain<H, N>>(&self, chain: &C, base: (H, N)) -> Result<(), Error> {
+		self.verify_ancestry_above(chain, Some(&base))
+	}
+
+	fn verify_ancestry_above<C: Chain<H, N>>(&self, chain: &C, base: Option<&(H, N)>) -> Result<(), Error> {
+		let all_on_chain = self.precommits.iter().all(|signed| {
+			let precommit = &signed.precommit;
+			if precommit.target_number < self.target_number {
+				let above_base = base.is_none_or(|(base_hash, base_number)| {
+					precommit.target_number >= *base_number
+						&& chain.is_equal_or_descendent_of(base_hash.clone(), precommit.target_hash.clone())
+				});
+
+				above_base && chain.is_equal_or_descendent_of(precommit.target_hash.clone(), self.target_hash.clone())
+			} else {
+				chain.is_equal_or_descendent_of(self.target_hash.clone(), precommit.target_hash.clone())
+			}
+		});
+
+		if all_on_chain { Ok(()) } else { Err(Error::NotDescendent) }
+	}
+}
+
+/// A commit message with a signature over it by the voter who broadcast it,
+/// e.g. to authenticate the origin of commits on the network.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+#[cfg_attr(feature = "derive-serde", derive(Serialize, Deserialize))]
+pub struct SignedCommit<H, N, S, Id> {
+	/// The commit which has been signed.
+	pub commit: Commit<H, N, S, Id>,
+	/// The signature on the commit.
+	pub signature: S,
+	/// The Id of the signer.
+	pub id: Id,
+}
+
+#[cfg(feature = "derive-codec")]
+impl<H: Encode, N: Encode, S: Encode, Id: Encode> SignedCommit<H, N, S, Id> {
+	/// Check the signature on the commit, as made for the given round. The
+	/// signed payload is built with `localized_commit_payload`.
+	pub fn verify<V: MessageVerifier<Id, S>>(&self, verifier: &V, round: u64) -> bool {
+		let payload = localized_commit_payload(round, &self.commit);
+		verifier.verify(&self.id, &self.signature, &payload)
+	}
+}
+
+/// The payload that is signed for a commit made for the given round. Commits
+/// must be signed over this payload to pass `SignedCommit::verify`.
+#[cfg(feature = "derive-codec")]
+pub fn localized_commit_payload<H: Encode, N: Encode, S: Encode, Id: Encode>(
+	round: u64,
+	commit: &Commit<H, N, S, Id>,
+) -> Vec<u8> {
+	(commit, round).encode()
 }
 
 /// A signed prevote message.
 #[derive(Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "std", derive(Debug))]
 #[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+#[cfg_attr(feature = "derive-serde", derive(Serialize, Deserialize))]
 pub struct SignedPrevote<H, N, S, Id> {
 	/// The prevote message which has been signed.
 	pub prevote: Prevote<H, N>,
@@ -294,8 +616,8 @@ pub struct SignedPrevote<H, N, S, Id> {
 
 /// A signed precommit message.
 #[derive(Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "std", derive(Debug))]
 #[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+#[cfg_attr(feature = "derive-serde", derive(Serialize, Deserialize))]
 pub struct SignedPrecommit<H, N, S, Id> {
 	/// The precommit message which has been signed.
 	pub precommit: Precommit<H, N>,
@@ -307,8 +629,9 @@ pub struct SignedPrecommit<H, N, S, Id> {
 
 /// A commit message with compact representation of authentication data.
 #[derive(Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "std", derive(Debug))]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
 #[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+#[cfg_attr(feature = "derive-serde", derive(Serialize, Deserialize))]
 pub struct CompactCommit<H, N, S, Id> {
 	/// The target block's hash.
 	pub target_hash: H,
@@ -318,6 +641,8 @@ pub struct CompactCommit<H, N, S, Id> {
 	pub precommits: Vec<Precommit<H, N>>,
 	/// Authentication data for the commit.
 	pub auth_data: MultiAuthData<S, Id>,
+	/// The id of the voter set the precommits were cast in.
+	pub set_id: u64,
 }
 
 /// A catch-up message, which is an aggregate of prevotes and precommits necessary
@@ -326,8 +651,9 @@ pub struct CompactCommit<H, N, S, Id> {
 /// This message contains a "base", which is a block all of the vote-targets are
 /// a descendent of.
 #[derive(Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "std", derive(Debug))]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
 #[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+#[cfg_attr(feature = "derive-serde", derive(Serialize, Deserialize))]
 pub struct CatchUp<H, N, S, Id> {
 	/// Round number.
 	pub round_number: u64,
@@ -339,6 +665,8 @@ pub struct CatchUp<H, N, S, Id> {
 	pub base_hash: H,
 	/// The base number. See struct docs.
 	pub base_number: N,
+	/// The id of the voter set the votes were cast in.
+	pub set_id: u64,
 }
 
 /// Authentication data for a set of many messages, currently a set of precommit signatures but
@@ -351,9 +679,10 @@ impl<H, N, S, Id> From<CompactCommit<H, N, S, Id>> for Commit<H, N, S, Id> {
 			target_hash: commit.target_hash,
 			target_number: commit.target_number,
 			precommits: commit.precommits.into_iter()
-				.zip(commit.auth_data.into_iter())
+				.zip(commit.auth_data)
 				.map(|(precommit, (signature, id))| SignedPrecommit { precommit, signature, id })
-				.collect()
+				.collect(),
+			set_id: commit.set_id,
 		}
 	}
 }
@@ -365,18 +694,60 @@ impl<H: Clone, N: Clone, S, Id> From<Commit<H, N, S, Id>> for CompactCommit<H, N
 			target_number: commit.target_number,
 			precommits: commit.precommits.iter().map(|signed| signed.precommit.clone()).collect(),
 			auth_data: commit.precommits.into_iter().map(|signed| (signed.signature, signed.id)).collect(),
+			set_id: commit.set_id,
 		}
 	}
 }
 
+impl<H: Eq, N: Eq, S, Id: std::hash::Hash + Eq + Clone + Ord> CompactCommit<H, N, S, Id> {
+	/// Check that there is a signature for every precommit, and that every
+	/// precommit is by a voter in the given set, each of whom precommitted at
+	/// most once.
+	///
+	/// This is only a structural check, which is cheaper than verifying the
+	/// signatures and can be used to reject malformed commits from the network
+	/// before expanding them into a `Commit`.
+	pub fn validate_structure(&self, voters: &VoterSet<Id>) -> Result<(), MalformedCommit> {
+		self.check_structure(voters, false)
+	}
+
+	/// Like `validate_structure`, but voters may have two different
+	/// precommits, i.e. have equivocated.
+	pub fn validate_structure_allowing_equivocations(&self, voters: &VoterSet<Id>) -> Result<(), MalformedCommit> {
+		self.check_structure(voters, true)
+	}
+
+	fn check_structure(&self, voters: &VoterSet<Id>, allow_equivocations: bool) -> Result<(), MalformedCommit> {
+		if self.precommits.len() != self.auth_data.len() {
+			return Err(MalformedCommit::LengthMismatch);
+		}
+
+		// the first precommit of each voter by canonical index, and whether
+		// they equivocated.
+		let mut seen = vec![None; voters.len()];
+		for (precommit, (_, id)) in self.precommits.iter().zip(&self.auth_data) {
+			let idx = voters.get(id).ok_or(MalformedCommit::UnknownVoter)?.canon_idx();
+			seen[idx] = match seen[idx] {
+				None => Some((precommit, false)),
+				Some((first, false)) if allow_equivocations && first != precommit => Some((first, true)),
+				Some(_) => return Err(MalformedCommit::DuplicateVoter),
+			};
+		}
+
+		Ok(())
+	}
+}
+
 /// Struct returned from `validate_commit` function with information
 /// about the validation result.
 pub struct CommitValidationResult<H, N> {
 	ghost: Option<(H, N)>,
+	finalized_target: Option<(H, N)>,
 	num_precommits: usize,
 	num_duplicated_precommits: usize,
 	num_equivocations: usize,
 	num_invalid_voters: usize,
+	cause: Option<Error>,
 }
 
 impl<H, N> CommitValidationResult<H, N> {
@@ -387,6 +758,30 @@ impl<H, N> CommitValidationResult<H, N> {
 		self.ghost.as_ref()
 	}
 
+	/// Returns the block finalized by the commit, i.e. the commit target, if
+	/// the commit is valid.
+	pub fn finalized_target(&self) -> Option<&(H, N)> {
+		self.finalized_target.as_ref()
+	}
+
+	/// Whether the commit is valid and finalizes a block strictly higher than
+	/// the given (currently finalized) block number.
+	pub fn advances_beyond(&self, current: N) -> bool where N: PartialOrd {
+		self.finalized_target.as_ref().is_some_and(|(_, number)| *number > current)
+	}
+
+	/// How many blocks above the given (currently finalized) block number the
+	/// commit finalizes, or zero if it doesn't advance beyond it. `None` if
+	/// the commit is invalid.
+	///
+	/// Large gaps mean that finality was skipped ahead without intermediate
+	/// commits, and callers may want to fetch the blocks in between first.
+	pub fn gap_from_previous(&self, current: N) -> Option<N> where N: BlockNumberOps + Copy {
+		self.finalized_target.as_ref().map(|&(_, number)| {
+			if number > current { number - current } else { N::zero() }
+		})
+	}
+
 	/// Returns the number of precommits in the commit.
 	pub fn num_precommits(&self) -> usize {
 		self.num_precommits
@@ -406,16 +801,26 @@ impl<H, N> CommitValidationResult<H, N> {
 	pub fn num_invalid_voters(&self) -> usize {
 		self.num_invalid_voters
 	}
+
+	/// Why the commit is invalid, e.g. `Error::NotDescendent` for precommits
+	/// which aren't on the chain of the target, or `Error::ThresholdNotReached`
+	/// if they don't weigh enough. `None` if the commit is valid, or if an
+	/// aggregated commit's signature doesn't verify.
+	pub fn cause(&self) -> Option<&Error> {
+		self.cause.as_ref()
+	}
 }
 
 impl<H, N> Default for CommitValidationResult<H, N> {
 	fn default() -> Self {
 		CommitValidationResult {
 			ghost: None,
+			finalized_target: None,
 			num_precommits: 0,
 			num_duplicated_precommits: 0,
 			num_equivocations: 0,
 			num_invalid_voters: 0,
+			cause: None,
 		}
 	}
 }
@@ -428,6 +833,13 @@ impl<H, N> Default for CommitValidationResult<H, N> {
 ///
 /// Duplicate votes or votes from voters not in the voter-set will be ignored, but it is recommended
 /// for the caller of this function to remove those at signature-verification time.
+///
+/// Precommits for any ancestor of the commit target are ignored as nil, see
+/// `Commit::verify_ancestry`. Use `validate_round_commit` where the base of
+/// the commit's round is known.
+///
+/// The set id of the commit isn't checked, use `validate_set_commit` where
+/// the voter set may be stale.
 pub fn validate_commit<H, N, S, I, C: Chain<H, N>>(
 	commit: &Commit<H, N, S, I>,
 	voters: &VoterSet<I>,
@@ -439,20 +851,78 @@ pub fn validate_commit<H, N, S, I, C: Chain<H, N>>(
 	I: Clone + std::hash::Hash + Eq + std::fmt::Debug,
 	S: Clone + Eq,
 {
-	let mut validation_result = CommitValidationResult::default();
-	validation_result.num_precommits = commit.precommits.len();
-
-	// check that all precommits are for blocks higher than the target
-	// commit block, and that they're its descendents
-	let all_precommits_higher_than_target = commit.precommits.iter().all(|signed| {
-		signed.precommit.target_number >= commit.target_number &&
-			chain.is_equal_or_descendent_of(
-				commit.target_hash.clone(),
-				signed.precommit.target_hash.clone(),
-			)
-	});
+	validate_commit_above(commit, voters, chain, None)
+}
+
+/// Validates a GRANDPA commit message like `validate_commit`, if it's for
+/// the voter set with the given id. Fails with `Error::WrongSetId` otherwise,
+/// as precommits cast in one set must never be counted in another.
+pub fn validate_set_commit<H, N, S, I, C: Chain<H, N>>(
+	commit: &Commit<H, N, S, I>,
+	set_id: u64,
+	voters: &VoterSet<I>,
+	chain: &C,
+) -> Result<CommitValidationResult<H, N>, crate::Error>
+	where
+	H: std::hash::Hash + Clone + Eq + Ord + std::fmt::Debug,
+	N: Copy + BlockNumberOps + std::fmt::Debug,
+	I: Clone + std::hash::Hash + Eq + std::fmt::Debug,
+	S: Clone + Eq,
+{
+	check_set_id(commit, set_id)?;
+	validate_commit_above(commit, voters, chain, None)
+}
+
+/// Validates a GRANDPA commit message of a round with the given base, in the
+/// voter set with the given id, like `validate_set_commit`. Nil precommits
+/// must also be for the base or a block above it, otherwise the commit is
+/// invalid.
+pub fn validate_round_commit<H, N, S, I, C: Chain<H, N>>(
+	commit: &Commit<H, N, S, I>,
+	set_id: u64,
+	voters: &VoterSet<I>,
+	chain: &C,
+	base: (H, N),
+) -> Result<CommitValidationResult<H, N>, crate::Error>
+	where
+	H: std::hash::Hash + Clone + Eq + Ord + std::fmt::Debug,
+	N: Copy + BlockNumberOps + std::fmt::Debug,
+	I: Clone + std::hash::Hash + Eq + std::fmt::Debug,
+	S: Clone + Eq,
+{
+	check_set_id(commit, set_id)?;
+	validate_commit_above(commit, voters, chain, Some(&base))
+}
+
+fn check_set_id<H, N, S, I>(commit: &Commit<H, N, S, I>, set_id: u64) -> Result<(), crate::Error> {
+	if commit.set_id == set_id {
+		Ok(())
+	} else {
+		Err(crate::Error::WrongSetId { expected: set_id, got: commit.set_id })
+	}
+}
 
-	if !all_precommits_higher_than_target {
+fn validate_commit_above<H, N, S, I, C: Chain<H, N>>(
+	commit: &Commit<H, N, S, I>,
+	voters: &VoterSet<I>,
+	chain: &C,
+	base: Option<&(H, N)>,
+) -> Result<CommitValidationResult<H, N>, crate::Error>
+	where
+	H: std::hash::Hash + Clone + Eq + Ord + std::fmt::Debug,
+	N: Copy + BlockNumberOps + std::fmt::Debug,
+	I: Clone + std::hash::Hash + Eq + std::fmt::Debug,
+	S: Clone + Eq,
+{
+	let mut validation_result = CommitValidationResult {
+		num_precommits: commit.precommits.len(),
+		..Default::default()
+	};
+
+	// check that all precommits are for descendents of the target commit
+	// block, or are nil precommits for one of its ancestors.
+	if let Err(e) = commit.verify_ancestry_above(chain, base) {
+		validation_result.cause = Some(e);
 		return Ok(validation_result);
 	}
 
@@ -462,26 +932,45 @@ pub fn validate_commit<H, N, S, I, C: Chain<H, N>>(
 	// using the commit target as a base.
 	let mut round = round::Round::new(round::RoundParams {
 		round_number: 0, // doesn't matter here.
+		set_id: commit.set_id,
 		voters: voters.clone(),
 		base: (commit.target_hash.clone(), commit.target_number),
 	});
 
 	for SignedPrecommit { precommit, id, signature } in &commit.precommits {
-		match round.import_precommit(chain, precommit.clone(), id.clone(), signature.clone())? {
+		if !voters.contains(id) {
+			validation_result.num_invalid_voters += 1;
+			continue;
+		}
+
+		// nil precommits don't count towards any block.
+		if precommit.target_number < commit.target_number {
+			continue;
+		}
+
+		// precommits which can't be imported, e.g. with a number not matching
+		// their block, make the commit invalid.
+		let import_result = match round.import_precommit(chain, precommit.clone(), id.clone(), signature.clone()) {
+			Ok(import_result) => import_result,
+			Err(e) => {
+				validation_result.cause = Some(e);
+				return Ok(validation_result);
+			}
+		};
+
+		match import_result {
 			ImportResult { equivocation: Some(_), .. } => {
 				validation_result.num_equivocations += 1;
 				// allow only one equivocation per voter, as extras are redundant.
 				if !equivocated.insert(id) {
+					validation_result.cause = Some(MalformedCommit::DuplicateVoter.into());
 					return Ok(validation_result)
 				}
 			},
-			ImportResult { duplicated, valid_voter, .. } => {
+			ImportResult { duplicated, .. } => {
 				if duplicated {
 					validation_result.num_duplicated_precommits += 1;
 				}
-				if !valid_voter {
-					validation_result.num_invalid_voters += 1;
-				}
 			}
 		}
 	}
@@ -489,10 +978,253 @@ pub fn validate_commit<H, N, S, I, C: Chain<H, N>>(
 	// if a ghost is found then it must be equal or higher than the commit
 	// target, otherwise the commit is invalid
 	validation_result.ghost = round.precommit_ghost();
+	if validation_result.ghost.is_some() {
+		validation_result.finalized_target = Some((commit.target_hash.clone(), commit.target_number));
+	} else {
+		// all precommits counted are for the target or its descendents, so
+		// there is a ghost once they reach the threshold.
+		validation_result.cause = Some(Error::ThresholdNotReached {
+			got: round.vote_weight().1,
+			needed: round.threshold(),
+		});
+	}
+
 	Ok(validation_result)
 }
 
+/// Validates a GRANDPA commit message like `validate_commit`, but without a
+/// `Chain`, e.g. in light clients.
+///
+/// Ancestry is instead taken from the given proof, which maps block hashes to
+/// the hashes of their parents and must link the target of each precommit to
+/// the commit target, with block numbers matching the number of links. If it
+/// doesn't, or contains a cycle on the way, `Error::NotDescendent` is returned.
+pub fn validate_commit_stateless<H, N, S, I>(
+	commit: &Commit<H, N, S, I>,
+	voters: &VoterSet<I>,
+	ancestry_proof: &HashMap<H, H>,
+) -> Result<CommitValidationResult<H, N>, crate::Error>
+	where
+	H: std::hash::Hash + Clone + Eq + Ord + std::fmt::Debug,
+	N: Copy + BlockNumberOps + std::fmt::Debug,
+	I: Clone + std::hash::Hash + Eq + std::fmt::Debug,
+	S: Clone + Eq,
+{
+	let chain = AncestryProof { parents: ancestry_proof };
+	let target = (commit.target_hash.clone(), commit.target_number);
+
+	// the numbers of precommit targets must match the proof too, as votes
+	// are applied to the vote graph by number.
+	for signed in &commit.precommits {
+		let precommit = (signed.precommit.target_hash.clone(), signed.precommit.target_number);
+		let (base, block) = if precommit.1 < target.1 {
+			(precommit, target.clone())
+		} else {
+			(target.clone(), precommit)
+		};
+
+		let depth = Chain::<H, N>::ancestry_inclusive(&chain, base.0, block.0)?.len() - 1;
+		if (block.1 - base.1).as_() != depth {
+			return Err(crate::Error::NotDescendent);
+		}
+	}
+
+	validate_commit(commit, voters, &chain)
+}
+
+// a chain made up of only the blocks in an ancestry proof.
+struct AncestryProof<'a, H> {
+	parents: &'a HashMap<H, H>,
+}
+
+impl<H: std::hash::Hash + Clone + Eq, N: Copy + BlockNumberOps> Chain<H, N> for AncestryProof<'_, H> {
+	fn ancestry(&self, base: H, block: H) -> Result<Vec<H>, Error> {
+		let mut ancestry = Vec::new();
+		let mut current = block;
+
+		loop {
+			let parent = self.parents.get(&current).ok_or(Error::NotDescendent)?;
+			if *parent == base { return Ok(ancestry) }
+
+			// every link is walked at most once, unless there is a cycle.
+			if ancestry.len() == self.parents.len() {
+				return Err(Error::NotDescendent);
+			}
+
+			ancestry.push(parent.clone());
+			current = parent.clone();
+		}
+	}
+
+	fn best_chain_containing(&self, _base: H) -> Option<(H, N)> {
+		None
+	}
+}
+
+/// A commit message with a single aggregate signature over the precommits of
+/// many voters, e.g. for threshold or aggregate signature schemes.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+#[cfg_attr(feature = "derive-serde", derive(Serialize, Deserialize))]
+pub struct AggregatedCommit<H, N, S> {
+	/// The target block's hash.
+	pub target_hash: H,
+	/// The target block's number.
+	pub target_number: N,
+	/// The precommit of each signer, in the order of the signers.
+	pub precommits: Vec<Precommit<H, N>>,
+	/// Bitfield of the signers by their index in the voter set, least
+	/// significant bit first.
+	pub signers: Vec<u8>,
+	/// The aggregate signature of all signers on their precommits.
+	pub signature: S,
+	/// The id of the voter set the precommits were cast in.
+	pub set_id: u64,
+}
+
+impl<H, N, S> AggregatedCommit<H, N, S> {
+	/// The indices of the signers in the voter set, in ascending order.
+	pub fn signer_indices(&self) -> impl Iterator<Item = usize> + '_ {
+		self.signers.iter().enumerate().flat_map(|(byte_idx, byte)| {
+			(0..8).filter(move |bit| byte & (1 << bit) != 0).map(move |bit| byte_idx * 8 + bit)
+		})
+	}
+}
+
+/// Checks aggregate signatures of voters over their precommits.
+pub trait AggregateVerifier<H, N, Id, S> {
+	/// Whether `signature` is a valid aggregate of the signatures of each of
+	/// the given voters on their precommit, as cast in the given round of the
+	/// voter set with the given id.
+	fn verify_aggregate(
+		&self,
+		precommits: &[(Id, Precommit<H, N>)],
+		signature: &S,
+		round: u64,
+		set_id: u64,
+	) -> bool;
+}
+
+/// Validates an aggregated commit made in the given round, like
+/// `validate_commit`.
+///
+/// Unlike with `validate_commit`, the signature is checked here, as it can't
+/// be checked for any precommit on its own. Commits with signers that aren't
+/// in the voter set, or that don't have a precommit for each signer, are
+/// invalid.
+pub fn validate_aggregated_commit<H, N, S, I, C, V>(
+	commit: &AggregatedCommit<H, N, S>,
+	round: u64,
+	voters: &VoterSet<I>,
+	chain: &C,
+	verifier: &V,
+) -> Result<CommitValidationResult<H, N>, crate::Error>
+	where
+	H: std::hash::Hash + Clone + Eq + Ord + std::fmt::Debug,
+	N: Copy + BlockNumberOps + std::fmt::Debug,
+	I: Clone + std::hash::Hash + Eq + std::fmt::Debug,
+	C: Chain<H, N>,
+	V: AggregateVerifier<H, N, I, S>,
+{
+	let invalid = |num_invalid_voters, cause| Ok(CommitValidationResult {
+		num_precommits: commit.precommits.len(),
+		num_invalid_voters,
+		cause,
+		..Default::default()
+	});
+
+	let signers = commit.signer_indices()
+		.map(|idx| voters.nth(idx).map(|(id, _)| id.clone()))
+		.collect::<Option<Vec<_>>>();
+
+	let signers = match signers {
+		Some(signers) => signers,
+		None => return invalid(1, Some(MalformedCommit::UnknownVoter.into())),
+	};
+
+	if signers.len() != commit.precommits.len() {
+		return invalid(0, Some(MalformedCommit::LengthMismatch.into()));
+	}
+
+	let precommits = signers.into_iter().zip(commit.precommits.iter().cloned()).collect::<Vec<_>>();
+	if !verifier.verify_aggregate(&precommits, &commit.signature, round, commit.set_id) {
+		return invalid(0, None);
+	}
+
+	// the signature has been checked, so the weight of the precommits can
+	// be counted as for any other commit.
+	let commit = Commit {
+		target_hash: commit.target_hash.clone(),
+		target_number: commit.target_number,
+		precommits: precommits.into_iter()
+			.map(|(id, precommit)| SignedPrecommit { precommit, signature: (), id })
+			.collect(),
+		set_id: commit.set_id,
+	};
+
+	validate_commit(&commit, voters, chain)
+}
+
+/// Merge the precommits of two commits on the same target, e.g. when neither
+/// of them reaches the threshold on its own. Only the first precommit of each
+/// voter is kept, so duplicate and conflicting precommits are dropped, and so
+/// are precommits of voters which aren't in the set.
+///
+/// Returns the merged commit if the weight of its voters reaches the threshold
+/// of the set, and `None` otherwise, or if the commits are for different
+/// targets or voter sets. Neither signatures nor the ancestry of precommits
+/// are checked, so the merged commit should be validated like any other.
+pub fn merge_commits<H, N, S, Id>(
+	a: Commit<H, N, S, Id>,
+	b: Commit<H, N, S, Id>,
+	voters: &VoterSet<Id>,
+) -> Option<Commit<H, N, S, Id>> where
+	H: Eq,
+	N: Eq,
+	Id: std::hash::Hash + Eq + Clone,
+{
+	if a.target_hash != b.target_hash || a.target_number != b.target_number || a.set_id != b.set_id {
+		return None;
+	}
+
+	let mut seen = crate::std::collections::HashSet::new();
+	let mut weight = 0u64;
+	let precommits: Vec<_> = a.precommits.into_iter()
+		.chain(b.precommits)
+		.filter(|signed| match voters.get(&signed.id) {
+			Some(info) if seen.insert(signed.id.clone()) => {
+				weight = weight.saturating_add(info.weight());
+				true
+			}
+			_ => false,
+		})
+		.collect();
+
+	if weight < voters.threshold() {
+		return None;
+	}
+
+	Some(Commit {
+		target_hash: a.target_hash,
+		target_number: a.target_number,
+		precommits,
+		set_id: a.set_id,
+	})
+}
+
 /// Get the threshold weight given the total voting weight.
+///
+/// This is `total_weight - faulty`, where `faulty = (total_weight - 1) / 3` is
+/// the maximum weight of faulty voters that can be tolerated, so that any two
+/// sets of voters reaching the threshold overlap in more than `faulty` weight.
+/// Equivocators are among the faulty voters: their votes count for every
+/// block, so once they weigh more than `faulty`, the threshold can be reached
+/// on conflicting blocks. See also `VoterSet::faulty_weight`.
+///
+/// This doesn't apply to a set with a single voter, whose threshold is all of
+/// its weight: for a single voter of weight 10 this gives 7, while rounds
+/// wait for 10. Prefer `VoterSet::threshold`, which rounds use.
 pub fn threshold(total_weight: u64) -> u64 {
 	let faulty = total_weight.saturating_sub(1) / 3;
 	total_weight - faulty
@@ -519,12 +1251,18 @@ pub fn process_commit_validation_result<H, N>(
 
 /// Historical votes seen in a round.
 #[derive(Default, Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "std", derive(Debug))]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
 #[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
 pub struct HistoricalVotes<H, N, S, Id> {
 	seen: Vec<SignedMessage<H, N, S, Id>>,
 	prevote_idx: Option<u64>,
 	precommit_idx: Option<u64>,
+	// the votes we cast are kept locally, and not encoded along with the
+	// messages seen, so that the encoding stays as it was.
+	#[cfg_attr(feature = "derive-codec", codec(skip))]
+	prevoted: Option<Prevote<H, N>>,
+	#[cfg_attr(feature = "derive-codec", codec(skip))]
+	precommitted: Option<Precommit<H, N>>,
 }
 
 impl<H, N, S, Id> HistoricalVotes<H, N, S, Id> {
@@ -534,6 +1272,8 @@ impl<H, N, S, Id> HistoricalVotes<H, N, S, Id> {
 			seen: Vec::new(),
 			prevote_idx: None,
 			precommit_idx: None,
+			prevoted: None,
+			precommitted: None,
 		}
 	}
 
@@ -547,6 +1287,8 @@ impl<H, N, S, Id> HistoricalVotes<H, N, S, Id> {
 			seen,
 			prevote_idx,
 			precommit_idx,
+			prevoted: None,
+			precommitted: None,
 		}
 	}
 
@@ -581,11 +1323,55 @@ impl<H, N, S, Id> HistoricalVotes<H, N, S, Id> {
 	pub fn set_precommited_idx(&mut self) {
 		self.precommit_idx = Some(self.seen.len() as u64)
 	}
+
+	/// Record the prevote we cast, before it's sent, along with the number of
+	/// messages seen before it. It's only among the messages seen once it
+	/// comes back signed.
+	pub fn record_prevote(&mut self, prevote: Prevote<H, N>) {
+		self.set_prevoted_idx();
+		self.prevoted = Some(prevote);
+	}
+
+	/// Record the precommit we cast, before it's sent, along with the number
+	/// of messages seen before it.
+	pub fn record_precommit(&mut self, precommit: Precommit<H, N>) {
+		self.set_precommited_idx();
+		self.precommitted = Some(precommit);
+	}
+
+	/// Return the prevote we cast, if any, even if it wasn't sent. It isn't
+	/// encoded, so it's `None` once decoded.
+	pub fn prevoted(&self) -> Option<&Prevote<H, N>> {
+		self.prevoted.as_ref()
+	}
+
+	/// Return the precommit we cast, if any, even if it wasn't sent. It isn't
+	/// encoded, so it's `None` once decoded.
+	pub fn precommitted(&self) -> Option<&Precommit<H, N>> {
+		self.precommitted.as_ref()
+	}
 }
 
 #[cfg(test)]
 mod tests {
-	use super::threshold;
+	use super::*;
+	use crate::testing::chain::{GENESIS_HASH, DummyChain};
+
+	fn commit_for(
+		target: (&'static str, u32),
+		precommits: &[(&'static str, u32, u32)],
+	) -> Commit<&'static str, u32, u32, u32> {
+		Commit {
+			target_hash: target.0,
+			target_number: target.1,
+			precommits: precommits.iter().map(|&(hash, number, id)| SignedPrecommit {
+				precommit: Precommit::new(hash, number),
+				signature: id,
+				id,
+			}).collect(),
+			set_id: 0,
+		}
+	}
 
 	#[test]
 	fn threshold_is_right() {
@@ -601,6 +1387,545 @@ mod tests {
 		assert_eq!(threshold(103), 69);
 	}
 
+	#[test]
+	fn commit_advances_beyond_current_finality() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+
+		let voters: VoterSet<u32> = VoterSet::new([(1, 1), (2, 1), (3, 1)].iter().cloned()).unwrap();
+		let commit = commit_for(("C", 4), &[("C", 4, 1), ("D", 5, 2), ("E", 6, 3)]);
+
+		let result = validate_commit(&commit, &voters, &chain).unwrap();
+		assert_eq!(result.finalized_target(), Some(&("C", 4)));
+
+		// above, equal to and below the commit target.
+		assert!(result.advances_beyond(3));
+		assert!(!result.advances_beyond(4));
+		assert!(!result.advances_beyond(5));
+
+		assert_eq!(result.gap_from_previous(1), Some(3));
+		assert_eq!(result.gap_from_previous(4), Some(0));
+		assert_eq!(result.gap_from_previous(5), Some(0));
+	}
+
+	#[test]
+	fn invalid_commit_does_not_advance() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+
+		let voters: VoterSet<u32> = VoterSet::new([(1, 1), (2, 1), (3, 1)].iter().cloned()).unwrap();
+
+		// only two out of three voters precommitted, not enough to finalize.
+		let commit = commit_for(("C", 4), &[("C", 4, 1), ("D", 5, 2)]);
+
+		let result = validate_commit(&commit, &voters, &chain).unwrap();
+		assert!(result.ghost().is_none());
+		assert!(result.finalized_target().is_none());
+		assert!(!result.advances_beyond(0));
+		assert_eq!(result.gap_from_previous(0), None);
+	}
+
+	#[test]
+	fn sub_threshold_commits_merge_into_a_finalizing_one() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+
+		let voters: VoterSet<u32> = VoterSet::new([(1, 1), (2, 1), (3, 1), (4, 1)].iter().cloned()).unwrap();
+
+		// two voters each, one of them in both commits and changing its mind
+		// in the second, and one which isn't a voter.
+		let a = commit_for(("C", 4), &[("C", 4, 1), ("D", 5, 2)]);
+		let b = commit_for(("C", 4), &[("E", 6, 2), ("D", 5, 3), ("D", 5, 9)]);
+
+		assert!(validate_commit(&a, &voters, &chain).unwrap().ghost().is_none());
+		assert!(validate_commit(&b, &voters, &chain).unwrap().ghost().is_none());
+
+		let merged = merge_commits(a.clone(), b.clone(), &voters).unwrap();
+		assert_eq!(merged, commit_for(("C", 4), &[("C", 4, 1), ("D", 5, 2), ("D", 5, 3)]));
+
+		let result = validate_commit(&merged, &voters, &chain).unwrap();
+		assert_eq!(result.ghost(), Some(&("C", 4)));
+		assert_eq!(result.num_duplicated_precommits(), 0);
+
+		// not enough voters together.
+		let c = commit_for(("C", 4), &[("C", 4, 1), ("D", 5, 9)]);
+		assert_eq!(merge_commits(a.clone(), c, &voters), None);
+
+		// commits on different targets.
+		let d = commit_for(("D", 5), &[("D", 5, 3), ("D", 5, 4)]);
+		assert_eq!(merge_commits(a, d, &voters), None);
+	}
+
+	#[test]
+	fn commit_validation_fails_with_the_cause() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+
+		let voters: VoterSet<u32> = VoterSet::new([(1, 1), (2, 1), (3, 1)].iter().cloned()).unwrap();
+
+		// a precommit for a descendent of the target, claimed at its number,
+		// makes the commit invalid.
+		let commit = commit_for(("C", 4), &[("C", 4, 1), ("D", 4, 2), ("D", 5, 3)]);
+		let result = validate_commit(&commit, &voters, &chain).unwrap();
+		assert_eq!(result.ghost(), None);
+		assert_eq!(result.cause(), Some(&Error::BlockNotInSubtree));
+
+		// so does a precommit off the chain of the target.
+		chain.push_blocks("A", &["B2", "C2"]);
+		let commit = commit_for(("C", 4), &[("C", 4, 1), ("C2", 4, 2), ("C", 4, 3)]);
+		let result = validate_commit(&commit, &voters, &chain).unwrap();
+		assert_eq!(result.ghost(), None);
+		assert_eq!(result.cause(), Some(&Error::NotDescendent));
+
+		// and precommits which don't weigh enough.
+		let commit = commit_for(("C", 4), &[("C", 4, 1)]);
+		let result = validate_commit(&commit, &voters, &chain).unwrap();
+		assert_eq!(result.ghost(), None);
+		assert_eq!(result.cause(), Some(&Error::ThresholdNotReached { got: 1, needed: 3 }));
+
+		// an ancestry proof which doesn't link a precommit to the target.
+		let commit = commit_for(("C", 4), &[("C", 4, 1), ("D", 5, 2), ("E", 6, 3)]);
+		let proof = [("D", "C")].iter().cloned().collect();
+		assert_eq!(validate_commit_stateless(&commit, &voters, &proof).err(), Some(Error::NotDescendent));
+
+		// with the full proof it's valid.
+		let proof = [("D", "C"), ("E", "D")].iter().cloned().collect();
+		assert_eq!(validate_commit_stateless(&commit, &voters, &proof).unwrap().ghost(), Some(&("C", 4)));
+	}
+
+	#[test]
+	fn commits_with_precommits_which_cant_be_imported_are_invalid() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E", "F"]);
+
+		let voters: VoterSet<u32> = VoterSet::new([(1, 1), (2, 1), (3, 1)].iter().cloned()).unwrap();
+
+		// "F" is claimed below "E", which is in its ancestry, so the offset
+		// between them overflows.
+		let commit = commit_for(("C", 4), &[("E", 6, 1), ("F", 5, 2), ("C", 4, 3)]);
+		let result = validate_commit(&commit, &voters, &chain).unwrap();
+		assert_eq!(result.ghost(), None);
+		assert_eq!(result.finalized_target(), None);
+		assert_eq!(result.cause(), Some(&Error::BlockNumberOverflow));
+
+		// a well-formed commit is still valid.
+		let commit = commit_for(("C", 4), &[("C", 4, 1), ("C", 4, 2), ("D", 5, 3)]);
+		let result = validate_commit(&commit, &voters, &chain).unwrap();
+		assert_eq!(result.ghost(), Some(&("C", 4)));
+		assert_eq!(result.cause(), None);
+	}
+
+	#[test]
+	fn errors_convert_and_display() {
+		#[cfg(not(feature = "std"))]
+		use alloc::string::ToString;
+
+		assert_eq!(Error::from(MalformedCommit::DuplicateVoter), Error::BadCommit(MalformedCommit::DuplicateVoter));
+		assert_eq!(
+			Error::ThresholdNotReached { got: 2, needed: 3 }.to_string(),
+			"Voter weight 2 is below the threshold of 3",
+		);
+		assert_eq!(
+			Error::BadCatchUp(BadCatchUpReason::Stale).to_string(),
+			"Bad catch-up: Catch-up for a round reached already",
+		);
+		assert_eq!(
+			Error::WrongSetId { expected: 2, got: 1 }.to_string(),
+			"Message for voter set 1, expected set 2",
+		);
+	}
+
+	#[test]
+	fn chain_ancestry_helpers() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+		chain.push_blocks("C", &["D2", "E2"]);
+		chain.push_blocks("B", &["C3"]);
+
+		// identical blocks.
+		assert!(chain.is_equal_or_descendent_of("C", "C"));
+		assert_eq!(chain.ancestry_inclusive("C", "C"), Ok(vec!["C"]));
+		assert_eq!(chain.common_ancestor(("A", 2), "C", "C"), Ok(("C", 4)));
+
+		// a block and its ancestors.
+		assert!(chain.is_equal_or_descendent_of("B", "E2"));
+		assert!(!chain.is_equal_or_descendent_of("E2", "B"));
+		assert_eq!(chain.ancestry_inclusive("B", "E2"), Ok(vec!["E2", "D2", "C", "B"]));
+		assert_eq!(chain.common_ancestor(("A", 2), "B", "E2"), Ok(("B", 3)));
+		assert_eq!(chain.common_ancestor(("A", 2), "E", "A"), Ok(("A", 2)));
+
+		// forks.
+		assert!(!chain.is_equal_or_descendent_of("C3", "E"));
+		assert_eq!(chain.ancestry_inclusive("C3", "E"), Err(Error::NotDescendent));
+		assert_eq!(chain.common_ancestor(("A", 2), "E", "E2"), Ok(("C", 4)));
+		assert_eq!(chain.common_ancestor((GENESIS_HASH, 1), "E2", "C3"), Ok(("B", 3)));
+
+		// blocks which aren't both on the chain of the base.
+		assert_eq!(chain.common_ancestor(("C", 4), "E", "C3"), Err(Error::NotDescendent));
+		assert_eq!(chain.common_ancestor(("C", 4), "Z", "E"), Err(Error::NotDescendent));
+		assert_eq!(chain.common_ancestor(("D", 5), "E", "E2"), Err(Error::NotDescendent));
+	}
+
+	#[test]
+	fn commit_ancestry_is_verified() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+		chain.push_blocks("C", &["D2", "E2"]);
+		chain.push_blocks("B", &["C3"]);
+
+		// precommits on the target and on descendents of it on any fork.
+		let commit = commit_for(("C", 4), &[("C", 4, 1), ("E", 6, 2), ("E2", 6, 3)]);
+		assert_eq!(commit.verify_ancestry(&chain), Ok(()));
+
+		// a precommit on a sibling of the target.
+		let commit = commit_for(("C", 4), &[("C", 4, 1), ("E", 6, 2), ("C3", 4, 3)]);
+		assert_eq!(commit.verify_ancestry(&chain), Err(Error::NotDescendent));
+
+		// a precommit on an ancestor of the target is nil.
+		let commit = commit_for(("C", 4), &[("B", 3, 1), (GENESIS_HASH, 1, 2)]);
+		assert_eq!(commit.verify_ancestry(&chain), Ok(()));
+
+		// but lower precommits must still be on the same chain.
+		let commit = commit_for(("D", 5), &[("C3", 4, 1)]);
+		assert_eq!(commit.verify_ancestry(&chain), Err(Error::NotDescendent));
+
+		// the weight of the precommits doesn't matter, and neither do
+		// validation failures which aren't structural.
+		let voters: VoterSet<u32> = VoterSet::new([(1, 1), (2, 1), (3, 1)].iter().cloned()).unwrap();
+		let commit = commit_for(("C", 4), &[("D", 5, 1), ("D", 5, 7)]);
+		assert_eq!(commit.verify_ancestry(&chain), Ok(()));
+		assert!(validate_commit(&commit, &voters, &chain).unwrap().ghost().is_none());
+	}
+
+	#[test]
+	fn commit_validation_ignores_nil_precommits() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+		chain.push_blocks("B", &["C3"]);
+
+		let voters: VoterSet<u32> = VoterSet::new((1..5).map(|i| (i, 1))).unwrap();
+
+		// three out of four voters precommitted for descendents of the target
+		// and the last one precommitted nil.
+		let nil = Precommit::nil((GENESIS_HASH, 1));
+		let commit = commit_for(
+			("C", 4),
+			&[("C", 4, 1), ("D", 5, 2), ("E", 6, 3), (nil.target_hash, nil.target_number, 4)],
+		);
+
+		let result = validate_commit(&commit, &voters, &chain).unwrap();
+		assert_eq!(result.finalized_target(), Some(&("C", 4)));
+		assert_eq!(result.num_precommits(), 4);
+
+		// the nil precommit doesn't count towards the target.
+		let commit = commit_for(("C", 4), &[("C", 4, 1), ("D", 5, 2), ("B", 3, 3), (GENESIS_HASH, 1, 4)]);
+		let result = validate_commit(&commit, &voters, &chain).unwrap();
+		assert!(result.ghost().is_none());
+
+		// lower precommits off the target's chain make the commit invalid.
+		let commit = commit_for(("C", 4), &[("C", 4, 1), ("D", 5, 2), ("E", 6, 3), ("C3", 4, 4)]);
+		assert!(validate_commit(&commit, &voters, &chain).unwrap().ghost().is_none());
+		let commit = commit_for(("D", 5), &[("D", 5, 1), ("D", 5, 2), ("E", 6, 3), ("C3", 4, 4)]);
+		assert!(validate_commit(&commit, &voters, &chain).unwrap().ghost().is_none());
+	}
+
+	#[test]
+	fn nil_precommits_must_be_at_or_above_the_round_base() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+
+		let voters: VoterSet<u32> = VoterSet::new((1..5).map(|i| (i, 1))).unwrap();
+		let base = ("B", 3);
+
+		// nil precommits for the base, or between it and the target, are fine.
+		let commit = commit_for(("D", 5), &[("D", 5, 1), ("E", 6, 2), ("D", 5, 3), ("B", 3, 4)]);
+		assert_eq!(commit.verify_ancestry_in_round(&chain, base), Ok(()));
+		let result = validate_round_commit(&commit, 0, &voters, &chain, base).unwrap();
+		assert_eq!(result.finalized_target(), Some(&("D", 5)));
+
+		let commit = commit_for(("D", 5), &[("D", 5, 1), ("E", 6, 2), ("D", 5, 3), ("C", 4, 4)]);
+		assert_eq!(commit.verify_ancestry_in_round(&chain, base), Ok(()));
+
+		// below the base they aren't, although they are ancestors of the target.
+		let commit = commit_for(("D", 5), &[("D", 5, 1), ("E", 6, 2), ("D", 5, 3), ("A", 2, 4)]);
+		assert_eq!(commit.verify_ancestry(&chain), Ok(()));
+		assert_eq!(commit.verify_ancestry_in_round(&chain, base), Err(Error::NotDescendent));
+		assert!(validate_commit(&commit, &voters, &chain).unwrap().ghost().is_some());
+		assert!(validate_round_commit(&commit, 0, &voters, &chain, base).unwrap().ghost().is_none());
+	}
+
+	#[test]
+	fn commits_of_other_sets_are_rejected() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+
+		let voters: VoterSet<u32> = VoterSet::new([(1, 1), (2, 1), (3, 1)].iter().cloned()).unwrap();
+
+		let mut commit = commit_for(("C", 4), &[("C", 4, 1), ("C", 4, 2), ("D", 5, 3)]);
+		commit.set_id = 1;
+		assert_eq!(validate_set_commit(&commit, 1, &voters, &chain).unwrap().ghost(), Some(&("C", 4)));
+
+		// the same commit is valid without the check, but not in another set.
+		assert_eq!(validate_commit(&commit, &voters, &chain).unwrap().ghost(), Some(&("C", 4)));
+		assert_eq!(
+			validate_set_commit(&commit, 2, &voters, &chain).err(),
+			Some(Error::WrongSetId { expected: 2, got: 1 }),
+		);
+		assert_eq!(
+			validate_round_commit(&commit, 0, &voters, &chain, ("B", 3)).err(),
+			Some(Error::WrongSetId { expected: 0, got: 1 }),
+		);
+	}
+
+	#[test]
+	fn compact_commit_structure_is_validated() {
+		let voters: VoterSet<u32> = VoterSet::new((1..5).map(|i| (i, 1))).unwrap();
+		let compact = |precommits: &[(&'static str, u32, u32)]| CompactCommit::from(commit_for(("C", 4), precommits));
+
+		let commit = compact(&[("C", 4, 1), ("D", 5, 2), ("C", 4, 4)]);
+		assert_eq!(commit.validate_structure(&voters), Ok(()));
+
+		// a voter not in the set.
+		let commit = compact(&[("C", 4, 1), ("D", 5, 2), ("C", 4, 5)]);
+		assert_eq!(commit.validate_structure(&voters), Err(MalformedCommit::UnknownVoter));
+
+		// a signature missing.
+		let mut commit = compact(&[("C", 4, 1), ("D", 5, 2)]);
+		commit.auth_data.pop();
+		assert_eq!(commit.validate_structure(&voters), Err(MalformedCommit::LengthMismatch));
+	}
+
+	#[test]
+	fn stateless_commit_validation_walks_the_proof() {
+		let voters: VoterSet<u32> = VoterSet::new((1..5).map(|i| (i, 1))).unwrap();
+		let commit = commit_for(("C", 4), &[("C", 4, 1), ("E", 6, 2), ("D", 5, 3), ("B", 3, 4)]);
+
+		let proof = |links: &[(&'static str, &'static str)]| links.iter().cloned().collect::<HashMap<_, _>>();
+
+		let valid = proof(&[("E", "D"), ("D", "C"), ("C", "B")]);
+		let result = validate_commit_stateless(&commit, &voters, &valid).unwrap();
+		assert_eq!(result.finalized_target(), Some(&("C", 4)));
+
+		// the same as with the full chain.
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+		assert_eq!(validate_commit(&commit, &voters, &chain).unwrap().finalized_target(), Some(&("C", 4)));
+
+		// a gap between "E" and the target.
+		let gap = proof(&[("E", "D"), ("C", "B")]);
+		assert_eq!(validate_commit_stateless(&commit, &voters, &gap).err(), Some(Error::NotDescendent));
+
+		// a gap which skips a block, so the numbers don't match.
+		let skip = proof(&[("E", "C"), ("D", "C"), ("C", "B")]);
+		assert_eq!(validate_commit_stateless(&commit, &voters, &skip).err(), Some(Error::NotDescendent));
+
+		// a cycle which never reaches the target.
+		let cycle = proof(&[("E", "D"), ("D", "E"), ("C", "B")]);
+		assert_eq!(validate_commit_stateless(&commit, &voters, &cycle).err(), Some(Error::NotDescendent));
+
+		// a valid proof with too little weight.
+		let commit = commit_for(("C", 4), &[("C", 4, 1), ("E", 6, 2)]);
+		assert!(validate_commit_stateless(&commit, &voters, &valid).unwrap().ghost().is_none());
+	}
+
+	#[test]
+	fn aggregated_commits_are_validated() {
+		// the aggregate "signature" is a checksum over signers, targets and round.
+		struct Verifier;
+		impl AggregateVerifier<&'static str, u32, u32, u64> for Verifier {
+			fn verify_aggregate(
+				&self,
+				precommits: &[(u32, Precommit<&'static str, u32>)],
+				signature: &u64,
+				round: u64,
+				_set_id: u64,
+			) -> bool {
+				*signature == aggregate(precommits.iter().map(|(id, p)| (*id, p.target_number)), round)
+			}
+		}
+
+		fn aggregate(signed: impl Iterator<Item = (u32, u32)>, round: u64) -> u64 {
+			signed.map(|(id, number)| (id as u64) * 100 + number as u64).sum::<u64>() * round
+		}
+
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+
+		let voters: VoterSet<u32> = VoterSet::new((1..5).map(|i| (i, 1))).unwrap();
+		let signer_ids = |bits: u8| (0..4).filter(move |i| bits & (1 << i) != 0).map(|i| *voters.nth(i).unwrap().0);
+
+		let commit = AggregatedCommit {
+			target_hash: "C",
+			target_number: 4,
+			precommits: vec![Precommit::new("C", 4), Precommit::new("D", 5), Precommit::new("E", 6)],
+			signers: vec![0b1011],
+			signature: aggregate(signer_ids(0b1011).zip(vec![4, 5, 6]), 2),
+			set_id: 0,
+		};
+
+		assert_eq!(commit.signer_indices().collect::<Vec<_>>(), vec![0, 1, 3]);
+		let result = validate_aggregated_commit(&commit, 2, &voters, &chain, &Verifier).unwrap();
+		assert_eq!(result.finalized_target(), Some(&("C", 4)));
+
+		// a tampered precommit doesn't match the signature.
+		let mut tampered = commit.clone();
+		tampered.precommits[1] = Precommit::new("E", 6);
+		let result = validate_aggregated_commit(&tampered, 2, &voters, &chain, &Verifier).unwrap();
+		assert!(result.ghost().is_none());
+
+		// and neither do other signers or rounds.
+		let mut tampered = commit.clone();
+		tampered.signers = vec![0b1101];
+		assert!(validate_aggregated_commit(&tampered, 2, &voters, &chain, &Verifier).unwrap().ghost().is_none());
+		assert!(validate_aggregated_commit(&commit, 3, &voters, &chain, &Verifier).unwrap().ghost().is_none());
+
+		// signers which aren't voters.
+		let mut tampered = commit.clone();
+		tampered.signers = vec![0b1_0011];
+		let result = validate_aggregated_commit(&tampered, 2, &voters, &chain, &Verifier).unwrap();
+		assert_eq!(result.num_invalid_voters(), 1);
+		assert!(result.ghost().is_none());
+
+		// validly signed, but by too few voters.
+		let commit = AggregatedCommit {
+			precommits: vec![Precommit::new("C", 4), Precommit::new("D", 5)],
+			signers: vec![0b0011],
+			signature: aggregate(signer_ids(0b0011).zip(vec![4, 5]), 2),
+			..commit
+		};
+		assert!(validate_aggregated_commit(&commit, 2, &voters, &chain, &Verifier).unwrap().ghost().is_none());
+	}
+
+	#[test]
+	fn compact_commit_duplicate_voters_are_rejected() {
+		let voters: VoterSet<u32> = VoterSet::new((1..5).map(|i| (i, 1))).unwrap();
+		let compact = |precommits: &[(&'static str, u32, u32)]| CompactCommit::from(commit_for(("C", 4), precommits));
+
+		// the same precommit twice is never allowed.
+		let commit = compact(&[("C", 4, 1), ("D", 5, 2), ("C", 4, 1)]);
+		assert_eq!(commit.validate_structure(&voters), Err(MalformedCommit::DuplicateVoter));
+		assert_eq!(commit.validate_structure_allowing_equivocations(&voters), Err(MalformedCommit::DuplicateVoter));
+
+		// an equivocation is only allowed if asked for, and only once.
+		let commit = compact(&[("C", 4, 1), ("D", 5, 2), ("D", 5, 1)]);
+		assert_eq!(commit.validate_structure(&voters), Err(MalformedCommit::DuplicateVoter));
+		assert_eq!(commit.validate_structure_allowing_equivocations(&voters), Ok(()));
+
+		let commit = compact(&[("C", 4, 1), ("D", 5, 1), ("E", 6, 1)]);
+		assert_eq!(commit.validate_structure_allowing_equivocations(&voters), Err(MalformedCommit::DuplicateVoter));
+	}
+
+	#[cfg(feature = "derive-codec")]
+	#[test]
+	fn message_payload_binds_round_and_set_id() {
+		let message = Message::Precommit(Precommit::new(b"C".to_vec(), 4u32));
+		let payload = localized_payload(3, 1, &message);
+
+		assert_ne!(payload, localized_payload(4, 1, &message));
+		assert_ne!(payload, localized_payload(3, 2, &message));
+		assert_ne!(payload, localized_payload(1, 3, &message));
+
+		// the encoded message, then the round and set id.
+		let mut expected = message.encode();
+		expected.extend_from_slice(&3u64.to_le_bytes());
+		expected.extend_from_slice(&1u64.to_le_bytes());
+		assert_eq!(payload, expected);
+	}
+
+	#[cfg(feature = "derive-codec")]
+	#[test]
+	fn signed_messages_are_verified() {
+		// signatures are the signer's id along with the payload.
+		struct Verifier;
+		impl MessageVerifier<u32, (u32, Vec<u8>)> for Verifier {
+			fn verify(&self, id: &u32, signature: &(u32, Vec<u8>), payload: &[u8]) -> bool {
+				signature.0 == *id && signature.1 == payload
+			}
+		}
+
+		let message = Message::Precommit(Precommit::new(b"C".to_vec(), 4u32));
+		let signed = SignedMessage {
+			signature: (7, localized_payload(3, 1, &message)),
+			message,
+			id: 7,
+		};
+
+		assert!(signed.verify(&Verifier, 3, 1));
+
+		// signed for another round or voter set.
+		assert!(!signed.verify(&Verifier, 4, 1));
+		assert!(!signed.verify(&Verifier, 3, 2));
+
+		// tampered with message or signer.
+		let mut tampered = signed.clone();
+		tampered.message = Message::Precommit(Precommit::new(b"D".to_vec(), 5));
+		assert!(!tampered.verify(&Verifier, 3, 1));
+
+		let mut tampered = signed.clone();
+		tampered.message = Message::Prevote(Prevote::new(b"C".to_vec(), 4));
+		assert!(!tampered.verify(&Verifier, 3, 1));
+
+		let mut tampered = signed;
+		tampered.id = 8;
+		assert!(!tampered.verify(&Verifier, 3, 1));
+	}
+
+	#[cfg(feature = "derive-codec")]
+	#[test]
+	fn signed_commits_are_verified() {
+		use parity_scale_codec::{Encode, Decode};
+
+		struct Verifier;
+		impl MessageVerifier<u32, (u32, Vec<u8>)> for Verifier {
+			fn verify(&self, id: &u32, signature: &(u32, Vec<u8>), payload: &[u8]) -> bool {
+				signature.0 == *id && signature.1 == payload
+			}
+		}
+
+		let commit = Commit {
+			target_hash: b"C".to_vec(),
+			target_number: 4u32,
+			precommits: vec![SignedPrecommit {
+				precommit: Precommit::new(b"C".to_vec(), 4),
+				signature: (1, Vec::new()),
+				id: 1,
+			}],
+			set_id: 1,
+		};
+		let signed = SignedCommit {
+			signature: (7, localized_commit_payload(3, &commit)),
+			commit,
+			id: 7,
+		};
+
+		assert!(signed.verify(&Verifier, 3));
+
+		// the envelope survives a round-trip through the codec.
+		let decoded = SignedCommit::decode(&mut &signed.encode()[..]).unwrap();
+		assert_eq!(decoded, signed);
+		assert!(decoded.verify(&Verifier, 3));
+
+		// signed for another round.
+		assert!(!signed.verify(&Verifier, 4));
+
+		// tampered with commit, signature or signer.
+		let mut tampered = signed.clone();
+		tampered.commit.target_number = 5;
+		assert!(!tampered.verify(&Verifier, 3));
+
+		let mut tampered = signed.clone();
+		tampered.commit.set_id = 2;
+		assert!(!tampered.verify(&Verifier, 3));
+
+		let mut tampered = signed.clone();
+		tampered.signature.1.push(0);
+		assert!(!tampered.verify(&Verifier, 3));
+
+		let mut tampered = signed;
+		tampered.id = 8;
+		assert!(!tampered.verify(&Verifier, 3));
+	}
+
 	#[cfg(feature = "derive-codec")]
 	#[test]
 	fn codec_was_derived() {
@@ -619,4 +1944,102 @@ mod tests {
 		let signed2 = crate::SignedMessage::decode(&mut &encoded[..]).unwrap();
 		assert_eq!(signed, signed2);
 	}
+
+	// hex of the encoding of the given value, with SCALE codec.
+	#[cfg(feature = "derive-codec")]
+	fn encoded_hex<T: parity_scale_codec::Encode>(value: &T) -> String {
+		value.encode().iter().map(|byte| format!("{:02x}", byte)).collect()
+	}
+
+	#[cfg(feature = "derive-codec")]
+	#[test]
+	fn wire_format_is_pinned() {
+		use parity_scale_codec::{Decode, EncodeLike};
+
+		// the value encodes to the fixture, and decodes back from it.
+		fn check<T: Decode + EncodeLike + PartialEq + core::fmt::Debug>(value: T, fixture: &str) {
+			assert_eq!(encoded_hex(&value), fixture);
+
+			let bytes = value.encode();
+			assert_eq!(T::decode(&mut &bytes[..]), Ok(value));
+		}
+
+		let prevote = Prevote::new([0xaau8, 0xbb], 5u32);
+		let precommit = Precommit::new([0xccu8, 0xdd], 6u32);
+		let propose = PrimaryPropose::new([0xeeu8, 0xff], 7u32);
+		let signed_precommit = SignedPrecommit { precommit: precommit.clone(), signature: 0x11u8, id: 0x22u8 };
+
+		check(prevote.clone(), "aabb05000000");
+		check(precommit.clone(), "ccdd06000000");
+		check(propose.clone(), "eeff07000000");
+
+		// variant indices of messages are pinned.
+		check(Message::Prevote(prevote.clone()), "00aabb05000000");
+		check(Message::Precommit(precommit.clone()), "01ccdd06000000");
+		check(Message::PrimaryPropose(propose), "02eeff07000000");
+
+		check(SignedMessage { message: Message::Prevote(prevote.clone()), signature: 0x11u8, id: 0x22u8 }, "00aabb050000001122");
+		check(SignedPrevote { prevote, signature: 0x11u8, id: 0x22u8 }, "aabb050000001122");
+		check(signed_precommit.clone(), "ccdd060000001122");
+
+		let commit = Commit { target_hash: [0xccu8, 0xdd], target_number: 6u32, precommits: vec![signed_precommit.clone()], set_id: 3 };
+		check(commit.clone(), "ccdd0600000004ccdd0600000011220300000000000000");
+		check(SignedCommit { commit: commit.clone(), signature: 0x33u8, id: 0x44u8 }, "ccdd0600000004ccdd06000000112203000000000000003344");
+		check(CompactCommit::from(commit), "ccdd0600000004ccdd060000000411220300000000000000");
+
+		check(CatchUp {
+			round_number: 9,
+			prevotes: vec![SignedPrevote { prevote: Prevote::new([0xccu8, 0xdd], 6u32), signature: 0x11u8, id: 0x22u8 }],
+			precommits: vec![signed_precommit],
+			base_hash: [0xaau8, 0xbb],
+			base_number: 5u32,
+			set_id: 3,
+		}, "090000000000000004ccdd06000000112204ccdd060000001122aabb050000000300000000000000");
+	}
+
+	#[cfg(feature = "derive-codec")]
+	#[test]
+	fn historical_votes_encode_without_recorded_votes() {
+		use parity_scale_codec::{Encode, Decode};
+
+		let seen = vec![SignedMessage { message: Message::Prevote(Prevote::new([0xaau8, 0xbb], 5u32)), signature: 0x11u8, id: 0x22u8 }];
+		let mut votes = HistoricalVotes::new_with(seen.clone(), None, None);
+		votes.record_prevote(Prevote::new([0xccu8, 0xdd], 6u32));
+		votes.record_precommit(Precommit::new([0xccu8, 0xdd], 6u32));
+
+		let encoded = votes.encode();
+		assert_eq!(encoded, (seen.clone(), Some(1u64), Some(1u64)).encode());
+		assert_eq!(
+			HistoricalVotes::decode(&mut &encoded[..]),
+			Ok(HistoricalVotes::new_with(seen, Some(1), Some(1))),
+		);
+	}
+
+	#[cfg(feature = "derive-serde")]
+	#[test]
+	fn commit_serde_round_trip() {
+		let commit = Commit {
+			target_hash: "B".to_string(),
+			target_number: 3u32,
+			precommits: vec![
+				SignedPrecommit { precommit: Precommit::new("B".to_string(), 3), signature: vec![1u8, 2], id: 5u32 },
+				SignedPrecommit { precommit: Precommit::new("C".to_string(), 4), signature: vec![3u8, 4], id: 6u32 },
+			],
+			set_id: 1,
+		};
+
+		let json = serde_json::to_string(&commit).unwrap();
+		assert_eq!(
+			json,
+			"{\"target_hash\":\"B\",\"target_number\":3,\"precommits\":[\
+				{\"precommit\":{\"target_hash\":\"B\",\"target_number\":3},\"signature\":[1,2],\"id\":5},\
+				{\"precommit\":{\"target_hash\":\"C\",\"target_number\":4},\"signature\":[3,4],\"id\":6}\
+			],\"set_id\":1}",
+		);
+		assert_eq!(serde_json::from_str::<Commit<String, u32, Vec<u8>, u32>>(&json).unwrap(), commit);
+
+		let compact = CompactCommit::from(commit);
+		let json = serde_json::to_string(&compact).unwrap();
+		assert_eq!(serde_json::from_str::<CompactCommit<String, u32, Vec<u8>, u32>>(&json).unwrap(), compact);
+	}
 }
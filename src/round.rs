@@ -16,29 +16,30 @@
 
 #[cfg(feature = "derive-codec")]
 use parity_scale_codec::{Encode, Decode};
+#[cfg(feature = "derive-serde")]
+use serde::{Deserialize, Serialize};
 
 use crate::bitfield::{Context as BitfieldContext, Bitfield};
 use crate::std::{
-	self, collections::hash_map::{HashMap, Entry}, hash::Hash, fmt, ops::AddAssign, vec::Vec,
+	self, collections::{HashSet, hash_map::{HashMap, Entry}}, hash::Hash, fmt, ops::AddAssign, vec::Vec,
 };
 use crate::vote_graph::VoteGraph;
-use crate::voter_set::VoterSet;
+use crate::voter_set::{VoterSet, VoterInfo};
 
-use super::{Equivocation, Prevote, Precommit, Chain, BlockNumberOps, HistoricalVotes, Message, SignedMessage};
-
-#[derive(Eq, PartialEq)]
-#[cfg_attr(feature = "std", derive(Hash))]
-struct Address;
+use super::{
+	Equivocation, Prevote, Precommit, Chain, BlockNumberOps, HistoricalVotes, Message, SignedMessage,
+	SignedPrevote, SignedPrecommit,
+};
 
 #[derive(PartialEq, Eq)]
-#[cfg_attr(feature = "std", derive(Debug))]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
 struct TotalWeight {
 	prevote: u64,
 	precommit: u64,
 }
 
 #[derive(Clone)]
-#[cfg_attr(feature = "std", derive(Debug))]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
 struct VoteWeight {
 	bitfield: Bitfield,
 }
@@ -67,7 +68,7 @@ impl VoteWeight {
 		// the unwrap-or is defensive only: there should be registered weights for
 		// all known indices.
 		let (prevote, precommit) = with_equivocators
-			.total_weight(|idx| voter_set.weight_by_index(idx).unwrap_or(0));
+			.total_weight(|idx| voter_set.nth(idx).map_or(0, |(_, info)| info.weight()));
 
 		TotalWeight { prevote, precommit }
 	}
@@ -132,11 +133,11 @@ impl<Id: Hash + Eq + Clone, Vote: Clone + Eq, Signature: Clone + Eq> VoteTracker
 	// since this struct doesn't track the round-number of votes, that must be set
 	// by the caller.
 	fn add_vote(&mut self, id: Id, vote: Vote, signature: Signature, weight: u64)
-		-> AddVoteResult<Vote, Signature>
+		-> AddVoteResult<'_, Vote, Signature>
 	{
 		match self.votes.entry(id) {
 			Entry::Vacant(vacant) => {
-				self.current_weight += weight;
+				self.current_weight = self.current_weight.saturating_add(weight);
 				let multiplicity = vacant.insert(VoteMultiplicity::Single(vote, signature));
 
 				AddVoteResult {
@@ -170,6 +171,11 @@ impl<Id: Hash + Eq + Clone, Vote: Clone + Eq, Signature: Clone + Eq> VoteTracker
 		}
 	}
 
+	// whether the given vote of the voter was tracked already.
+	fn contains(&self, id: &Id, vote: &Vote, signature: &Signature) -> bool {
+		self.votes.get(id).is_some_and(|multiplicity| multiplicity.contains(vote, signature))
+	}
+
 	// Returns all imported votes.
 	fn votes(&self) -> Vec<(Id, Vote, Signature)> {
 		let mut votes = Vec::new();
@@ -188,12 +194,21 @@ impl<Id: Hash + Eq + Clone, Vote: Clone + Eq, Signature: Clone + Eq> VoteTracker
 
 		votes
 	}
+
+	// the latest vote of each voter, i.e. the second one for equivocators.
+	fn latest_votes(&self) -> impl Iterator<Item=(&Id, &Vote)> {
+		self.votes.iter().map(|(id, vote)| match vote {
+			VoteMultiplicity::Single(v, _) => (id, v),
+			VoteMultiplicity::Equivocated(_, (v, _)) => (id, v),
+		})
+	}
 }
 
 /// State of the round.
 #[derive(PartialEq, Clone)]
-#[cfg_attr(feature = "std", derive(Debug))]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
 #[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+#[cfg_attr(feature = "derive-serde", derive(Serialize, Deserialize))]
 pub struct State<H, N> {
 	/// The prevote-GHOST block.
 	pub prevote_ghost: Option<(H, N)>,
@@ -217,10 +232,95 @@ impl<H: Clone, N: Clone> State<H, N> {
 	}
 }
 
+/// Why a round isn't completable yet, see `Round::completability_blockers`.
+#[derive(PartialEq, Clone)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+pub struct CompletabilityBlockers<Id, H, N> {
+	/// The prevote weight missing to reach the threshold, zero once reached.
+	pub missing_prevote_weight: u64,
+	/// The precommit weight missing to reach the threshold, zero once reached.
+	/// If both are zero, the round waits for precommits to rule out blocks
+	/// above the estimate, accounting for possible equivocations.
+	pub missing_precommit_weight: u64,
+	/// The blocks the round-estimate could still settle on: the current
+	/// estimate, and the prevote-GHOST if it's higher. Empty until there's a
+	/// prevote-GHOST.
+	pub estimate_candidates: Vec<(H, N)>,
+	/// The voters who haven't prevoted yet, in canonical order.
+	pub missing_prevoters: Vec<Id>,
+	/// The voters who haven't precommitted yet, in canonical order.
+	pub missing_precommitters: Vec<Id>,
+}
+
+/// A compact summary of the votes seen in a round, e.g. to ask a peer only for
+/// the votes we're missing, see `Round::votes_not_in`. There are four bits per
+/// voter, by canonical index: whether a prevote was seen, and whether the voter
+/// equivocated in prevotes, and the same for precommits.
+#[derive(PartialEq, Eq, Clone)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+#[cfg_attr(feature = "derive-serde", derive(Serialize, Deserialize))]
+pub struct VoteSummary {
+	bits: Vec<u8>,
+}
+
+const PREVOTE_SEEN: u8 = 1;
+const PREVOTE_EQUIVOCATED: u8 = 1 << 1;
+const PRECOMMIT_SEEN: u8 = 1 << 2;
+const PRECOMMIT_EQUIVOCATED: u8 = 1 << 3;
+
+impl VoteSummary {
+	fn new(n_voters: usize) -> Self {
+		VoteSummary { bits: vec![0; n_voters.div_ceil(2)] }
+	}
+
+	// two voters per byte, the first one in the low nibble.
+	fn set(&mut self, idx: usize, flag: u8) {
+		self.bits[idx / 2] |= flag << (4 * (idx % 2));
+	}
+
+	// voters beyond the summary, e.g. of a different voter set, have no bits.
+	fn get(&self, idx: usize, flag: u8) -> bool {
+		self.bits.get(idx / 2).is_some_and(|byte| byte >> (4 * (idx % 2)) & flag != 0)
+	}
+
+	/// Whether a prevote of the voter with the given canonical index was seen.
+	pub fn has_prevote(&self, idx: usize) -> bool {
+		self.get(idx, PREVOTE_SEEN)
+	}
+
+	/// Whether the voter with the given canonical index was seen equivocating
+	/// in prevotes.
+	pub fn prevote_equivocated(&self, idx: usize) -> bool {
+		self.get(idx, PREVOTE_EQUIVOCATED)
+	}
+
+	/// Whether a precommit of the voter with the given canonical index was seen.
+	pub fn has_precommit(&self, idx: usize) -> bool {
+		self.get(idx, PRECOMMIT_SEEN)
+	}
+
+	/// Whether the voter with the given canonical index was seen equivocating
+	/// in precommits.
+	pub fn precommit_equivocated(&self, idx: usize) -> bool {
+		self.get(idx, PRECOMMIT_EQUIVOCATED)
+	}
+}
+
+// the direction in which importing a vote may move the round-estimate.
+enum EstimateMove {
+	// prevotes: to the estimate or one of its descendents.
+	Up,
+	// precommits: to the estimate or one of its ancestors.
+	Down,
+}
+
 /// Parameters for starting a round.
 pub struct RoundParams<Id: Hash + Eq, H, N> {
 	/// The round number for votes.
 	pub round_number: u64,
+	/// The id of the voter set the round's votes are cast in.
+	pub set_id: u64,
 	/// Actors and weights in the round.
 	pub voters: VoterSet<Id>,
 	/// The base block to build on.
@@ -234,24 +334,34 @@ pub struct Round<Id: Hash + Eq, H: Hash + Eq, N, Signature> {
 	precommit: VoteTracker<Id, Precommit<H, N>, Signature>, // tracks precommits
 	historical_votes: HistoricalVotes<H, N, Signature, Id>,
 	round_number: u64,
+	set_id: u64,
 	voters: VoterSet<Id>,
 	total_weight: u64,
 	bitfield_context: BitfieldContext,
 	prevote_ghost: Option<(H, N)>, // current memoized prevote-GHOST block
+	prevote_ghost_stale: bool, // whether prevotes were imported since computing it
 	precommit_ghost: Option<(H, N)>, // current memoized precommit-GHOST block
 	finalized: Option<(H, N)>, // best finalized block in this round.
 	estimate: Option<(H, N)>, // current memoized round-estimate
 	completable: bool, // whether the round is completable
+	strict_base: bool, // whether votes for the base are rejected
 }
 
 /// Result of importing a Prevote or Precommit.
-pub(crate) struct ImportResult<Id, P, Signature> {
+pub struct ImportResult<Id, P, Signature> {
 	/// Indicates if the voter is part of the voter set.
-	pub(crate) valid_voter: bool,
+	pub valid_voter: bool,
 	/// Indicates if the vote is duplicated.
-	pub(crate) duplicated: bool,
+	pub duplicated: bool,
 	/// An equivocation proof, if the vote is an equivocation.
-	pub(crate) equivocation: Option<Equivocation<Id, P, Signature>>,
+	pub equivocation: Option<Equivocation<Id, P, Signature>>,
+	/// Indicates if the vote is for exactly the round base, and wasn't counted
+	/// since the round is strict about the base (see `Round::set_strict_base`).
+	pub vote_for_base: bool,
+	/// Indicates if the vote moved the round-estimate against the votes
+	/// imported, which is a bug. The vote is counted, but the estimate and
+	/// completability are kept as they were before it.
+	pub inconsistent_estimate: bool,
 }
 
 impl<Id, P, Signature> Default for ImportResult<Id, P, Signature> {
@@ -260,6 +370,8 @@ impl<Id, P, Signature> Default for ImportResult<Id, P, Signature> {
 			valid_voter: false,
 			duplicated: false,
 			equivocation: None,
+			vote_for_base: false,
+			inconsistent_estimate: false,
 		}
 	}
 }
@@ -279,6 +391,7 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 
 		Round {
 			round_number: round_params.round_number,
+			set_id: round_params.set_id,
 			total_weight,
 			voters: round_params.voters,
 			graph: VoteGraph::new(base_hash, base_number),
@@ -287,11 +400,45 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 			historical_votes: HistoricalVotes::new(),
 			bitfield_context: BitfieldContext::new(n_validators),
 			prevote_ghost: None,
+			prevote_ghost_stale: false,
 			precommit_ghost: None,
 			finalized: None,
 			estimate: None,
 			completable: false,
+			strict_base: false,
+		}
+	}
+
+	/// Reconstruct a round from its parameters and previously captured votes,
+	/// e.g. when restoring a snapshot. The signatures are kept as they are, and
+	/// not checked. Prevotes are imported before precommits, and the state is
+	/// recomputed from them.
+	///
+	/// Fails with `Error::UnknownVoter` if any vote is from outside the voter
+	/// set, or with the error of importing a vote.
+	pub fn from_parts<C: Chain<H, N>>(
+		round_params: RoundParams<Id, H, N>,
+		prevotes: Vec<SignedPrevote<H, N, Signature, Id>>,
+		precommits: Vec<SignedPrecommit<H, N, Signature, Id>>,
+		chain: &C,
+	) -> Result<Self, crate::Error> {
+		let voters = &round_params.voters;
+		let members = prevotes.iter().map(|vote| &vote.id)
+			.chain(precommits.iter().map(|vote| &vote.id))
+			.all(|id| voters.contains(id));
+		if !members {
+			return Err(crate::Error::UnknownVoter);
 		}
+
+		let mut round = Round::new(round_params);
+		for vote in prevotes {
+			round.import_prevote(chain, vote.prevote, vote.id, vote.signature)?;
+		}
+		for vote in precommits {
+			round.import_precommit(chain, vote.precommit, vote.id, vote.signature)?;
+		}
+
+		Ok(round)
 	}
 
 	/// Return the round number.
@@ -299,12 +446,35 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 		self.round_number
 	}
 
-	#[allow(unused)]
+	/// Return the id of the voter set the round's votes are cast in.
+	pub fn set_id(&self) -> u64 {
+		self.set_id
+	}
+
+	/// Set whether votes for exactly the base block are left out of the round
+	/// instead of being counted, which is reported in their `ImportResult`.
+	/// Votes imported before aren't affected. Defaults to `false`.
+	pub fn set_strict_base(&mut self, strict_base: bool) {
+		self.strict_base = strict_base;
+	}
+
+	/// Set the maximum length of the ancestry fetched from the chain for a
+	/// vote, see `VoteGraph::set_max_ancestry_len`. Votes exceeding it fail to
+	/// import with `Error::AncestryTooLong`.
+	pub fn set_max_ancestry_len(&mut self, max_ancestry_len: usize) {
+		self.graph.set_max_ancestry_len(max_ancestry_len);
+	}
+
+	fn is_base(&self, hash: &H, number: N) -> bool {
+		let (base_hash, base_number) = self.graph.base();
+		number == base_number && *hash == base_hash
+	}
+
 	/// Import a prevote. Returns an equivocation proof, if the vote is an equivocation,
 	/// and a bool indicating if the vote is duplicated (see `ImportResult`).
 	///
 	/// Ignores duplicate prevotes (not equivocations).
-	pub(crate) fn import_prevote<C: Chain<H, N>>(
+	pub fn import_prevote<C: Chain<H, N>>(
 		&mut self,
 		chain: &C,
 		vote: Prevote<H, N>,
@@ -313,13 +483,41 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 	) -> Result<ImportResult<Id, Prevote<H, N>, Signature>, crate::Error> {
 		let mut import_result = ImportResult::default();
 
-		let info = match self.voters.info(&signer) {
+		let info = match self.voters.get(&signer) {
 			Some(info) => info,
 			None => return Ok(import_result),
 		};
 		import_result.valid_voter = true;
+
+		if self.strict_base && self.is_base(&vote.target_hash, vote.target_number) {
+			import_result.vote_for_base = true;
+			return Ok(import_result);
+		}
 		let weight = info.weight();
 
+		// whether this vote could move the prevote-GHOST. only blocks whose
+		// cumulative weight changed need to be re-evaluated, and a single vote only
+		// changes weights on the path from its target back to the base.
+		let mut ghost_may_move = true;
+
+		// the first vote of a voter is added to the graph before it's tracked,
+		// so that it doesn't count at all if its target can't be inserted, e.g.
+		// since it isn't a descendent of the base.
+		if !self.prevote.votes.contains_key(&signer) {
+			let vote_weight = VoteWeight {
+				bitfield: self.bitfield_context.prevote_bitfield(info)
+					.ok()
+					.expect("info is instantiated from same voter set as context; qed"),
+			};
+
+			self.graph.insert(
+				vote.target_hash.clone(),
+				vote.target_number,
+				vote_weight,
+				chain,
+			)?;
+		}
+
 		let equivocation = {
 			let multiplicity = match self.prevote.add_vote(signer.clone(), vote.clone(), signature.clone(), weight) {
 				AddVoteResult { multiplicity: Some(m), .. } => m,
@@ -332,18 +530,14 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 
 			match multiplicity {
 				VoteMultiplicity::Single(single_vote, _) => {
-					let vote_weight = VoteWeight {
-						bitfield: self.bitfield_context.prevote_bitfield(info)
-							.ok()
-							.expect("info is instantiated from same voter set as context; qed"),
-					};
-
-					self.graph.insert(
-						single_vote.target_hash.clone(),
-						single_vote.target_number,
-						vote_weight,
-						chain,
-					)?;
+					// weight only moves the GHOST forward if it lands strictly
+					// below the current GHOST.
+					if let Some((ref g_hash, g_num)) = self.prevote_ghost {
+						ghost_may_move = self.graph.is_descendent_of(
+							&single_vote.target_hash,
+							(g_hash, g_num),
+						);
+					}
 
 					// Push the vote into HistoricalVotes.
 					let message = Message::Prevote(vote);
@@ -373,27 +567,48 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 			}
 		};
 
-		// update prevote-GHOST
-		let threshold = self.threshold();
-		if self.prevote.current_weight >= threshold {
-			let equivocators = self.bitfield_context.equivocators();
-
-			self.prevote_ghost = self.graph.find_ghost(
-				self.prevote_ghost.take(),
-				|v| v.total_weight(&equivocators, &self.voters).prevote >= threshold,
-			);
+		if ghost_may_move {
+			self.prevote_ghost_stale = true;
 		}
 
+		let prior = (self.estimate.clone(), self.completable);
 		self.update();
+		import_result.inconsistent_estimate = !self.check_estimate(prior, EstimateMove::Up);
+
 		import_result.equivocation = equivocation;
 		Ok(import_result)
 	}
 
+	// re-evaluate the prevote-GHOST, starting from the current one.
+	fn update_prevote_ghost(&mut self) {
+		let threshold = self.threshold();
+		if self.prevote.current_weight < threshold { return }
+
+		let equivocators = &self.bitfield_context.equivocators();
+
+		self.prevote_ghost = self.graph.find_ghost(
+			self.prevote_ghost.take(),
+			|v| v.total_weight(equivocators, &self.voters).prevote >= threshold,
+		);
+	}
+
+	// recompute the prevote-GHOST and all derived state from scratch.
+	#[cfg(test)]
+	fn recompute_from_scratch(&mut self) {
+		self.prevote_ghost = None;
+		self.prevote_ghost_stale = true;
+		self.update();
+	}
+
 	/// Import a precommit. Returns an equivocation proof, if the vote is an
 	/// equivocation, and a bool indicating if the vote is duplicated (see `ImportResult`).
 	///
+	/// Precommits for the round base are nil (see `Precommit::nil`): their
+	/// weight counts towards completing the round, but not towards any block
+	/// above the base.
+	///
 	/// Ignores duplicate precommits (not equivocations).
-	pub(crate) fn import_precommit<C: Chain<H, N>>(
+	pub fn import_precommit<C: Chain<H, N>>(
 		&mut self,
 		chain: &C,
 		vote: Precommit<H, N>,
@@ -402,13 +617,36 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 	) -> Result<ImportResult<Id, Precommit<H, N>, Signature>, crate::Error> {
 		let mut import_result = ImportResult::default();
 
-		let info = match self.voters.info(&signer) {
+		let info = match self.voters.get(&signer) {
 			Some(info) => info,
 			None => return Ok(import_result),
 		};
 		import_result.valid_voter = true;
+
+		if self.strict_base && self.is_base(&vote.target_hash, vote.target_number) {
+			import_result.vote_for_base = true;
+			return Ok(import_result);
+		}
 		let weight = info.weight();
 
+		// the first vote of a voter is added to the graph before it's tracked,
+		// so that it doesn't count at all if its target can't be inserted, e.g.
+		// since it isn't a descendent of the base.
+		if !self.precommit.votes.contains_key(&signer) {
+			let vote_weight = VoteWeight {
+				bitfield: self.bitfield_context.precommit_bitfield(info)
+					.ok()
+					.expect("info is instantiated from same voter set as context; qed"),
+			};
+
+			self.graph.insert(
+				vote.target_hash.clone(),
+				vote.target_number,
+				vote_weight,
+				chain,
+			)?;
+		}
+
 		let equivocation = {
 			let multiplicity = match self.precommit.add_vote(signer.clone(), vote.clone(), signature.clone(), weight) {
 				AddVoteResult { multiplicity: Some(m), .. } => m,
@@ -420,20 +658,7 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 			let round_number = self.round_number;
 
 			match multiplicity {
-				VoteMultiplicity::Single(single_vote, _) => {
-					let vote_weight = VoteWeight {
-						bitfield: self.bitfield_context.precommit_bitfield(info)
-							.ok()
-							.expect("info is instantiated from same voter set as context; qed"),
-					};
-
-					self.graph.insert(
-						single_vote.target_hash.clone(),
-						single_vote.target_number,
-						vote_weight,
-						chain,
-					)?;
-
+				VoteMultiplicity::Single(..) => {
 					let message = Message::Precommit(vote);
 					let signed_message = SignedMessage { id: signer, signature, message };
 					self.historical_votes.push_vote(signed_message);
@@ -461,7 +686,10 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 			}
 		};
 
+		let prior = (self.estimate.clone(), self.completable);
 		self.update();
+		import_result.inconsistent_estimate = !self.check_estimate(prior, EstimateMove::Down);
+
 		import_result.equivocation = equivocation;
 		Ok(import_result)
 	}
@@ -481,18 +709,24 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 		// update precommit-GHOST
 		let threshold = self.threshold();
 		if self.precommit.current_weight >= threshold {
-			let equivocators = self.bitfield_context.equivocators();
+			let equivocators = &self.bitfield_context.equivocators();
 
 			self.precommit_ghost = self.graph.find_ghost(
 				self.precommit_ghost.take(),
-				|v| v.total_weight(&equivocators, &self.voters).precommit >= threshold,
+				|v| v.total_weight(equivocators, &self.voters).precommit >= threshold,
 			);
 		}
 
 		self.precommit_ghost.clone()
 	}
 
-	/// Returns an iterator of all precommits targeting the finalized hash.
+	/// Returns an iterator of all precommits targeting the finalized hash, in
+	/// canonical order of the voters.
+	///
+	/// The two precommits of an equivocator are ordered by their target, lowest
+	/// first, and only precommits for the same target are in the order they
+	/// were imported. So rounds which imported the same precommits yield the
+	/// same ones in the same order, regardless of the order they arrived in.
 	///
 	/// Only returns `None` if no block has been finalized in this round.
 	pub fn finalizing_precommits<'a, C: 'a + Chain<H, N>>(&'a mut self, chain: &'a C)
@@ -501,6 +735,7 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 		struct YieldVotes<'b, V: 'b, S: 'b> {
 			yielded: usize,
 			multiplicity: &'b VoteMultiplicity<V, S>,
+			swapped: bool, // whether to yield the votes of an equivocation in reverse.
 		}
 
 		impl<'b, V: 'b + Clone, S: 'b + Clone> Iterator for YieldVotes<'b, V, S> {
@@ -517,9 +752,10 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 						}
 					}
 					VoteMultiplicity::Equivocated(ref a, ref b) => {
+						let (first, second) = if self.swapped { (b, a) } else { (a, b) };
 						let res = match self.yielded {
-							0 => Some(a.clone()),
-							1 => Some(b.clone()),
+							0 => Some(first.clone()),
+							1 => Some(second.clone()),
 							_ => None,
 						};
 
@@ -531,7 +767,9 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 		}
 
 		let (f_hash, _f_num) = self.finalized.clone()?;
-		let find_valid_precommits = self.precommit.votes.iter()
+		let votes = &self.precommit.votes;
+		let find_valid_precommits = self.voters.iter()
+			.filter_map(move |(_, id, _)| votes.get(id).map(|multiplicity| (id, multiplicity)))
 			.filter(move |&(_id, multiplicity)| {
 				if let VoteMultiplicity::Single(ref v, _) = *multiplicity {
 					// if there is a single vote from this voter, we only include it
@@ -543,7 +781,12 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 				}
 			})
 			.flat_map(|(id, multiplicity)| {
-				let yield_votes = YieldVotes { yielded: 0, multiplicity };
+				let swapped = match *multiplicity {
+					VoteMultiplicity::Equivocated((ref a, _), (ref b, _)) =>
+						(b.target_number, &b.target_hash) < (a.target_number, &a.target_hash),
+					VoteMultiplicity::Single(..) => false,
+				};
+				let yield_votes = YieldVotes { yielded: 0, multiplicity, swapped };
 
 				yield_votes.map(move |(v, s)| crate::SignedPrecommit {
 					precommit: v,
@@ -555,21 +798,91 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 		Some(find_valid_precommits)
 	}
 
+	/// Returns a minimal set of precommits whose weight meets the threshold
+	/// for the block finalized in this round, e.g. to reward the voters who
+	/// finalized it. They're taken from `historical_votes` in import order,
+	/// preferring earlier precommits, and those which aren't needed to meet
+	/// the threshold are left out again, latest first. So no precommit can be
+	/// left out of the result without falling below the threshold, and rounds
+	/// which imported the same precommits in the same order yield the same
+	/// ones.
+	///
+	/// Equivocators count for every block, but are only included for a
+	/// precommit on the finalized block or one of its descendents, unless the
+	/// threshold can't be met otherwise. Then both their precommits are.
+	///
+	/// Only returns `None` if no block has been finalized in this round.
+	pub fn finalization_evidence(&self) -> Option<Vec<SignedPrecommit<H, N, Signature, Id>>> {
+		let (f_hash, f_num) = self.finalized.clone()?;
+		let threshold = self.threshold();
+
+		let precommits = || self.historical_votes.seen().iter().enumerate().filter_map(|(index, signed)| {
+			match signed.message {
+				Message::Precommit(ref precommit) => Some((index, SignedPrecommit {
+					precommit: precommit.clone(),
+					signature: signed.signature.clone(),
+					id: signed.id.clone(),
+				})),
+				_ => None,
+			}
+		});
+		let weight_of = |id: &Id| self.voters.get(id).map_or(0, |info| info.weight());
+
+		// the precommits of each included voter, by the index of the first one.
+		let mut included = Vec::new();
+		let mut seen_voters = HashSet::new();
+		let mut weight = 0;
+
+		for (index, signed) in precommits() {
+			if weight >= threshold { break }
+
+			let supports = signed.precommit.target_hash == f_hash
+				|| self.graph.is_descendent_of(&signed.precommit.target_hash, (&f_hash, f_num));
+			if supports && seen_voters.insert(signed.id.clone()) {
+				let voter_weight = weight_of(&signed.id);
+				weight += voter_weight;
+				included.push((index, voter_weight, vec![signed]));
+			}
+		}
+
+		for (index, signed) in precommits() {
+			if weight >= threshold { break }
+
+			let equivocated = matches!(self.precommit.votes.get(&signed.id), Some(VoteMultiplicity::Equivocated(..)));
+			if !equivocated || !seen_voters.insert(signed.id.clone()) { continue }
+
+			let both = precommits().map(|(_, signed)| signed).filter(|other| other.id == signed.id).collect();
+			let voter_weight = weight_of(&signed.id);
+			weight += voter_weight;
+			included.push((index, voter_weight, both));
+		}
+
+		included.sort_by_key(|&(index, _, _)| index);
+		for i in (0..included.len()).rev() {
+			if weight - included[i].1 >= threshold {
+				weight -= included[i].1;
+				included.remove(i);
+			}
+		}
+
+		Some(included.into_iter().flat_map(|(_, _, precommits)| precommits).collect())
+	}
+
 	// update the round-estimate and whether the round is completable.
 	fn update(&mut self) {
 		let threshold = self.threshold();
 		if self.prevote.current_weight < threshold { return }
 
+		let (g_hash, g_num) = match self.prevote_ghost() {
+			None => return,
+			Some(x) => x,
+		};
+
 		let remaining_commit_votes = self.total_weight - self.precommit.current_weight;
 		let equivocators = &self.bitfield_context.equivocators();
 
 		let voters = &self.voters;
 
-		let (g_hash, g_num) = match self.prevote_ghost.clone() {
-			None => return,
-			Some(x) => x,
-		};
-
 		// anything new finalized? finalized blocks are those which have both
 		// 2/3+ prevote and precommit weight.
 		let threshold = self.threshold();
@@ -595,7 +908,7 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 			// we have already seen, because we are assuming any votes we
 			// haven't seen will target this block.
 			let current_equivocations = equivocators
-				.total_weight(|idx| self.voters.weight_by_index(idx).unwrap_or(0))
+				.total_weight(|idx| self.voters.nth(idx).map_or(0, |(_, info)| info.weight()))
 				.1;
 
 			let additional_equiv = tolerated_equivocations.saturating_sub(current_equivocations);
@@ -632,7 +945,9 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 		// could ever have enough precommits.
 		//
 		// the round-estimate is the highest block in the chain with head
-		// `prevote_ghost` that could have supermajority-commits.
+		// `prevote_ghost` that could have supermajority-commits. nil precommits
+		// only ever add weight to the base, so they can make the round
+		// completable at a lower estimate but never raise it.
 		if self.precommit.current_weight >= threshold {
 			self.estimate = self.graph.find_ancestor(
 				g_hash.clone(),
@@ -644,22 +959,81 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 			return;
 		}
 
-		self.completable = self.estimate.clone().map_or(false, |(b_hash, b_num)| {
+		self.completable = self.estimate.clone().is_some_and(|(b_hash, b_num)| {
 			b_hash != g_hash || {
 				// round-estimate is the same as the prevote-ghost.
 				// this round is still completable if no further blocks
 				// could have commit-supermajority.
 				self.graph.find_ghost(Some((b_hash, b_num)), possible_to_precommit)
-					.map_or(true, |x| x == (g_hash, g_num))
+					.is_none_or(|x| x == (g_hash, g_num))
 			}
 		})
 	}
 
+	// check that the estimate only moved the way the votes imported can move
+	// it: prevotes can only raise the prevote-GHOST and with it the estimate,
+	// while precommits can only rule out blocks, moving the estimate back to
+	// an ancestor. moving back on precommits is expected, e.g. once
+	// equivocations show that the block can't have been finalized anymore.
+	//
+	// this only holds while the equivocations are within the tolerated
+	// faulty weight. beyond that, nothing can be said about the round.
+	// otherwise it's a bug, in which case the prior estimate and whether the
+	// round was completable with it are kept, as the ones consistent with the
+	// votes imported before. returns whether the estimate is consistent.
+	fn check_estimate(&mut self, prior: (Option<(H, N)>, bool), allowed: EstimateMove) -> bool {
+		let tolerated_equivocations = self.total_weight - self.threshold();
+		let (prevote_equivocations, precommit_equivocations) = self.equivocation_weight();
+		if prevote_equivocations.max(precommit_equivocations) > tolerated_equivocations {
+			return true;
+		}
+
+		let (prior, prior_completable) = prior;
+
+		let consistent = match (&prior, &self.estimate) {
+			(None, _) => true,
+			(Some(_), None) => false,
+			(Some(prior), Some(estimate)) if prior == estimate => true,
+			(Some(prior), Some(estimate)) => match allowed {
+				EstimateMove::Up => self.graph.is_equal_or_descendent_of(estimate.clone(), (&prior.0, prior.1)),
+				EstimateMove::Down => self.graph.is_equal_or_descendent_of(prior.clone(), (&estimate.0, estimate.1)),
+			},
+		};
+
+		if !consistent {
+			self.estimate = prior;
+			self.completable = prior_completable;
+		}
+
+		consistent
+	}
+
+	/// Fetch the prevote-GHOST block: the highest block with supermajority
+	/// support in prevotes, which precommits should be cast for. Unlike the
+	/// estimate, this doesn't take precommits into account.
+	///
+	/// The GHOST is cached, and only recomputed if prevotes which may move it
+	/// were imported since the last call.
+	pub fn prevote_ghost(&mut self) -> Option<(H, N)> {
+		if self.prevote_ghost_stale {
+			self.prevote_ghost_stale = false;
+			self.update_prevote_ghost();
+		}
+
+		self.prevote_ghost.clone()
+	}
+
 	/// Fetch the "round-estimate": the best block which might have been finalized
 	/// in this round.
 	///
 	/// Returns `None` when new new blocks could have been finalized in this round,
 	/// according to our estimate.
+	///
+	/// The estimate stays on one chain: importing prevotes can only move it to a
+	/// descendent, and importing precommits only back to an ancestor, as long as
+	/// equivocations are within the tolerated faulty weight. Imports which would
+	/// move it otherwise leave it as it was, and are flagged with
+	/// `ImportResult::inconsistent_estimate`.
 	pub fn estimate(&self) -> Option<&(H, N)> {
 		self.estimate.as_ref()
 	}
@@ -669,6 +1043,31 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 		self.finalized.as_ref()
 	}
 
+	/// Returns the blocks with a supermajority of precommits on the chain of the
+	/// finalized block, from the base up to the finalized block.
+	///
+	/// Every one of them meets the finality condition, and the finalized block is
+	/// the highest. Empty if no block has been finalized in this round.
+	pub fn finalizable_targets(&self) -> impl Iterator<Item = (H, N)> {
+		let mut targets = Vec::new();
+
+		if let Some((ref hash, ref number)) = self.finalized {
+			let threshold = self.threshold();
+			let equivocators = &self.bitfield_context.equivocators();
+
+			self.graph.walk_back(hash.clone(), *number, |hash, number, vote| {
+				let finalizable = vote.total_weight(equivocators, &self.voters).precommit >= threshold;
+				if finalizable {
+					targets.push((hash.clone(), number));
+				}
+
+				finalizable
+			});
+		}
+
+		targets.into_iter().rev()
+	}
+
 	/// Returns `true` when the round is completable.
 	///
 	/// This is the case when the round-estimate is an ancestor of the prevote-ghost head,
@@ -678,7 +1077,36 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 		self.completable
 	}
 
-	// Threshold number of weight for supermajority.
+	/// Report why the round isn't completable yet, or `None` if it is.
+	pub fn completability_blockers(&self) -> Option<CompletabilityBlockers<Id, H, N>> {
+		if self.completable { return None }
+
+		let threshold = self.threshold();
+		let estimate_candidates = self.estimate.iter()
+			.chain(self.prevote_ghost.iter().filter(|ghost| Some(*ghost) != self.estimate.as_ref()))
+			.cloned()
+			.collect();
+
+		let missing = |voted: &dyn Fn(&Id) -> bool| self.voters.iter()
+			.filter(|(_, id, _)| !voted(id))
+			.map(|(_, id, _)| id.clone())
+			.collect();
+
+		Some(CompletabilityBlockers {
+			missing_prevote_weight: threshold.saturating_sub(self.prevote.current_weight),
+			missing_precommit_weight: threshold.saturating_sub(self.precommit.current_weight),
+			estimate_candidates,
+			missing_prevoters: missing(&|id| self.has_prevoted(id)),
+			missing_precommitters: missing(&|id| self.has_precommitted(id)),
+		})
+	}
+
+	/// Return the threshold weight for supermajority, as used for the GHOST
+	/// and finality checks of this round.
+	///
+	/// This is `total_weight - faulty`, where `faulty = (total_weight - 1) / 3`
+	/// is the maximum weight of faulty voters that can be tolerated. With a
+	/// single voter no faults are tolerated, and this is the voter's weight.
 	pub fn threshold(&self) -> u64 {
 		self.voters.threshold()
 	}
@@ -694,8 +1122,47 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 	}
 
 	/// Return the primary voter of the round.
-	pub fn primary_voter(&self) -> &(Id, u64) {
-		self.voters.voter_by_index(self.round_number as usize % self.voters.len())
+	pub fn primary_voter(&self) -> (&Id, &VoterInfo) {
+		self.voters.primary(self.round_number)
+	}
+
+	/// Return the total weight of the voters who have voted in this round,
+	/// as `(prevote, precommit)`. Equivocators are counted once.
+	pub fn vote_weight(&self) -> (u64, u64) {
+		(self.prevote.current_weight, self.precommit.current_weight)
+	}
+
+	/// Return the total weight of the voters who precommitted for exactly the
+	/// given block, not counting precommits for its descendents. Equivocators
+	/// are counted once.
+	pub fn precommit_weight_on(&self, hash: &H, number: N) -> u64 {
+		let targets = |p: &Precommit<H, N>| p.target_number == number && &p.target_hash == hash;
+
+		self.precommit.votes.iter()
+			.filter(|(_, vote)| match vote {
+				VoteMultiplicity::Single(v, _) => targets(v),
+				VoteMultiplicity::Equivocated((v1, _), (v2, _)) => targets(v1) || targets(v2),
+			})
+			.filter_map(|(id, _)| self.voters.get(id))
+			.map(|info| info.weight())
+			.sum()
+	}
+
+	/// Return the total weight of the voters who have equivocated in this
+	/// round, as `(prevote, precommit)`.
+	pub fn equivocation_weight(&self) -> (u64, u64) {
+		self.bitfield_context.equivocators()
+			.total_weight(|idx| self.voters.nth(idx).map_or(0, |(_, info)| info.weight()))
+	}
+
+	/// Return the voters who have equivocated in this round, in prevotes or
+	/// precommits, in canonical order.
+	pub fn equivocators(&self) -> Vec<Id> {
+		self.bitfield_context.equivocators()
+			.voter_indices()
+			.into_iter()
+			.filter_map(|idx| self.voters.nth(idx).map(|(id, _)| id.clone()))
+			.collect()
 	}
 
 	/// Return all imported prevotes.
@@ -708,6 +1175,45 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 		self.precommit.votes()
 	}
 
+	/// Return the latest imported prevote of each voter, e.g. to analyze how
+	/// voters agree. For equivocators, this is the second prevote we imported,
+	/// further ones aren't stored. The order is arbitrary.
+	pub fn latest_prevotes(&self) -> impl Iterator<Item=(&Id, &Prevote<H, N>)> {
+		self.prevote.latest_votes()
+	}
+
+	/// Return the latest imported precommit of each voter, like
+	/// `latest_prevotes`.
+	pub fn latest_precommits(&self) -> impl Iterator<Item=(&Id, &Precommit<H, N>)> {
+		self.precommit.latest_votes()
+	}
+
+	/// Whether exactly this prevote of the given voter, with the same signature,
+	/// was imported already. This only takes a lookup of the voter, e.g. to
+	/// drop votes received repeatedly through gossip before doing any further
+	/// work on them. Other prevotes of the voter aren't duplicates, so that
+	/// equivocations are still detected on import.
+	pub fn is_duplicate_prevote(&self, id: &Id, prevote: &Prevote<H, N>, signature: &Signature) -> bool {
+		self.prevote.contains(id, prevote, signature)
+	}
+
+	/// Whether exactly this precommit of the given voter, with the same
+	/// signature, was imported already, like `is_duplicate_prevote`.
+	pub fn is_duplicate_precommit(&self, id: &Id, precommit: &Precommit<H, N>, signature: &Signature) -> bool {
+		self.precommit.contains(id, precommit, signature)
+	}
+
+	/// Whether a prevote of the given voter was imported, e.g. to check that
+	/// we don't prevote twice in the round.
+	pub fn has_prevoted(&self, id: &Id) -> bool {
+		self.prevote.votes.contains_key(id)
+	}
+
+	/// Whether a precommit of the given voter was imported.
+	pub fn has_precommitted(&self, id: &Id) -> bool {
+		self.precommit.votes.contains_key(id)
+	}
+
 	/// Return all votes for the round (prevotes and precommits), sorted by
 	/// imported order and indicating the indices where we voted. At most two
 	/// prevotes and two precommits per voter are present, further equivocations
@@ -716,6 +1222,62 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 		&self.historical_votes
 	}
 
+	/// Summarize which voters we have seen prevotes and precommits of, and
+	/// which of them equivocated, to be sent to peers for reconciling votes.
+	pub fn vote_summary(&self) -> VoteSummary {
+		let mut summary = VoteSummary::new(self.voters.len());
+		for (id, multiplicity) in &self.prevote.votes {
+			let idx = self.canon_idx(id);
+			summary.set(idx, PREVOTE_SEEN);
+			if let VoteMultiplicity::Equivocated(..) = multiplicity {
+				summary.set(idx, PREVOTE_EQUIVOCATED);
+			}
+		}
+		for (id, multiplicity) in &self.precommit.votes {
+			let idx = self.canon_idx(id);
+			summary.set(idx, PRECOMMIT_SEEN);
+			if let VoteMultiplicity::Equivocated(..) = multiplicity {
+				summary.set(idx, PRECOMMIT_EQUIVOCATED);
+			}
+		}
+
+		summary
+	}
+
+	/// The votes a peer is missing, given the summary of its votes, in the
+	/// order we imported them. These are all our votes of voters it has seen
+	/// no vote of, and those of equivocators it hasn't seen equivocating, as
+	/// it can't be told which of their votes it has. Duplicates among them
+	/// are ignored on import. Equivocations where each side saw a different
+	/// single vote of the voter can't be told from the summary, and aren't
+	/// reconciled.
+	pub fn votes_not_in(&self, summary: &VoteSummary) -> Vec<SignedMessage<H, N, Signature, Id>> {
+		self.historical_votes.seen().iter()
+			.filter(|signed| {
+				let idx = self.canon_idx(&signed.id);
+				match signed.message {
+					Message::Prevote(_) => !summary.has_prevote(idx) || (
+						!summary.prevote_equivocated(idx) &&
+							matches!(self.prevote.votes.get(&signed.id), Some(VoteMultiplicity::Equivocated(..)))
+					),
+					Message::Precommit(_) => !summary.has_precommit(idx) || (
+						!summary.precommit_equivocated(idx) &&
+							matches!(self.precommit.votes.get(&signed.id), Some(VoteMultiplicity::Equivocated(..)))
+					),
+					Message::PrimaryPropose(_) => false,
+				}
+			})
+			.cloned()
+			.collect()
+	}
+
+	// the canonical index of a voter we imported votes of.
+	fn canon_idx(&self, id: &Id) -> usize {
+		self.voters.get(id)
+			.expect("votes are only imported from voters in the set; qed")
+			.canon_idx()
+	}
+
 	/// Set the number of prevotes and precommits received at the moment of prevoting.
 	/// It should be called inmediatly after prevoting.
 	pub fn set_prevoted_index(&mut self) {
@@ -728,6 +1290,20 @@ impl<Id, H, N, Signature> Round<Id, H, N, Signature> where
 		self.historical_votes.set_precommited_idx()
 	}
 
+	/// Record our own prevote as cast, along with the number of prevotes and
+	/// precommits received at the moment of prevoting. It should be called
+	/// before the prevote is sent, see `HistoricalVotes::record_prevote`.
+	pub fn record_prevote(&mut self, prevote: Prevote<H, N>) {
+		self.historical_votes.record_prevote(prevote)
+	}
+
+	/// Record our own precommit as cast, along with the number of prevotes and
+	/// precommits received at the moment of precommitting. It should be called
+	/// before the precommit is sent.
+	pub fn record_precommit(&mut self, precommit: Precommit<H, N>) {
+		self.historical_votes.record_precommit(precommit)
+	}
+
 	/// Get the number of prevotes and precommits received at the moment of prevoting.
 	/// Returns None if the prevote wasn't realized.
 	pub fn prevoted_index(&self) -> Option<u64> {
@@ -747,11 +1323,11 @@ mod tests {
 	use crate::testing::chain::{GENESIS_HASH, DummyChain};
 
 	fn voters() -> VoterSet<&'static str> {
-		[
+		VoterSet::new([
 			("Alice", 4),
 			("Bob", 7),
 			("Eve", 3),
-		].iter().cloned().collect()
+		].iter().cloned()).unwrap()
 	}
 
 	#[derive(PartialEq, Eq, Hash, Clone, Debug)]
@@ -766,6 +1342,7 @@ mod tests {
 
 		let mut round = Round::new(RoundParams {
 			round_number: 1,
+			set_id: 0,
 			voters: voters(),
 			base: ("C", 4),
 		});
@@ -799,6 +1376,118 @@ mod tests {
 		assert_eq!(round.estimate(), Some(&("E", 6)));
 	}
 
+	#[test]
+	fn prevote_ghost_is_exposed_apart_from_estimate() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			set_id: 0,
+			voters: voters(),
+			base: (GENESIS_HASH, 1),
+		});
+
+		for voter in &["Alice", "Bob", "Eve"] {
+			round.import_prevote(&chain, Prevote::new("E", 6), voter, Signature(voter)).unwrap();
+		}
+
+		assert_eq!(round.prevote_ghost(), Some(("E", 6)));
+		assert_eq!(round.state().prevote_ghost, Some(("E", 6)));
+
+		// once precommits settle on "C", the estimate falls behind the ghost.
+		round.import_precommit(&chain, Precommit::new("C", 4), "Alice", Signature("Alice")).unwrap();
+		round.import_precommit(&chain, Precommit::new("C", 4), "Bob", Signature("Bob")).unwrap();
+
+		let state = round.state();
+		assert_eq!(state.prevote_ghost, Some(("E", 6)));
+		assert_eq!(state.estimate, Some(("C", 4)));
+		assert_eq!(state.finalized, Some(("C", 4)));
+		assert!(state.completable);
+	}
+
+	#[test]
+	fn nil_precommits_make_round_completable() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+
+		let new_round = || {
+			let mut round = Round::new(RoundParams {
+				round_number: 1,
+				set_id: 0,
+				voters: voters(),
+				base: (GENESIS_HASH, 1),
+			});
+
+			for &voter in &["Alice", "Bob", "Eve"] {
+				round.import_prevote(&chain, Prevote::new("E", 6), voter, Signature(voter)).unwrap();
+			}
+
+			round
+		};
+
+		let mut round = new_round();
+		round.import_precommit(&chain, Precommit::new("C", 4), "Bob", Signature("Bob")).unwrap();
+
+		// not enough precommits to tell anything yet.
+		assert_eq!(round.estimate(), Some(&("E", 6)));
+		assert!(!round.completable());
+
+		// Eve can't precommit anything but nil. Alice's weight isn't enough to
+		// get anything past "C" finalized anymore.
+		round.import_precommit(&chain, Precommit::nil((GENESIS_HASH, 1)), "Eve", Signature("Eve")).unwrap();
+		assert_eq!(round.estimate(), Some(&("C", 4)));
+		assert!(round.completable());
+
+		// nothing beyond the (already final) base is finalized.
+		assert_eq!(round.finalized(), Some(&(GENESIS_HASH, 1)));
+
+		// switching from nil to a block is an equivocation.
+		let result = round.import_precommit(&chain, Precommit::new("C", 4), "Eve", Signature("Eve")).unwrap();
+		assert!(result.equivocation.is_some());
+
+		// with enough nil precommits, nothing past the base could be finalized.
+		let mut round = new_round();
+		round.import_precommit(&chain, Precommit::nil((GENESIS_HASH, 1)), "Bob", Signature("Bob")).unwrap();
+		round.import_precommit(&chain, Precommit::nil((GENESIS_HASH, 1)), "Eve", Signature("Eve")).unwrap();
+		assert_eq!(round.estimate(), Some(&(GENESIS_HASH, 1)));
+		assert!(round.completable());
+		assert_eq!(round.finalized(), Some(&(GENESIS_HASH, 1)));
+		assert_eq!(round.precommits().len(), 2);
+	}
+
+	#[test]
+	fn single_voter_finalizes_with_one_vote_each() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			set_id: 0,
+			voters: VoterSet::new(std::iter::once(("Alice", 5))).unwrap(),
+			base: (GENESIS_HASH, 1),
+		});
+
+		assert_eq!(round.threshold(), 5);
+		assert!(!round.completable());
+
+		round.import_prevote(&chain, Prevote::new("E", 6), "Alice", Signature("Alice")).unwrap();
+		assert_eq!(round.state().prevote_ghost, Some(("E", 6)));
+		assert_eq!(round.estimate(), Some(&("E", 6)));
+		assert!(!round.completable());
+		assert_eq!(round.finalized(), None);
+
+		round.import_precommit(&chain, Precommit::new("D", 5), "Alice", Signature("Alice")).unwrap();
+		assert_eq!(round.estimate(), Some(&("D", 5)));
+		assert_eq!(round.finalized(), Some(&("D", 5)));
+		assert!(round.completable());
+
+		// any equivocation by the single voter exceeds the tolerated weight.
+		round.import_precommit(&chain, Precommit::new("E", 6), "Alice", Signature("Alice-1")).unwrap();
+		assert_eq!(round.equivocation_weight().1, 5);
+		assert!(round.equivocation_weight().1 > round.voters().total_weight() - round.threshold());
+	}
+
 	#[test]
 	fn finalization() {
 		let mut chain = DummyChain::new();
@@ -808,6 +1497,7 @@ mod tests {
 
 		let mut round = Round::new(RoundParams {
 			round_number: 1,
+			set_id: 0,
 			voters: voters(),
 			base: ("C", 4),
 		});
@@ -873,6 +1563,7 @@ mod tests {
 
 		let mut round = Round::new(RoundParams {
 			round_number: 1,
+			set_id: 0,
 			voters: voters(),
 			base: ("C", 4),
 		});
@@ -919,51 +1610,323 @@ mod tests {
 	}
 
 	#[test]
-	fn vote_weight_discounts_equivocators() {
-		let v: VoterSet<_> = [
-			(1, 1),
-			(2, 2),
-			(3, 3),
-			(4, 4),
-			(5, 5),
-		].iter().cloned().collect();
-
-		let ctx = BitfieldContext::new(5);
+	fn threshold_matches_formula() {
+		let weight_sets: &[&[u64]] = &[
+			&[1],
+			&[5],
+			&[1, 1],
+			&[1, 1, 1],
+			&[1, 1, 1, 1],
+			&[4, 7, 3],
+			&[1, 2, 3, 4, 5, 6, 7],
+			&[100, 1, 1],
+			&[10; 10],
+			&[u64::MAX / 4, u64::MAX / 4],
+		];
+
+		for weights in weight_sets {
+			let voters: VoterSet<usize> = VoterSet::new(weights.iter().cloned().enumerate()).unwrap();
+			let total_weight: u64 = weights.iter().sum();
+
+			let round = Round::<_, &'static str, u32, Signature>::new(RoundParams {
+				round_number: 1,
+				set_id: 0,
+				voters,
+				base: (GENESIS_HASH, 1),
+			});
 
-		let equivocators = {
-			let equiv_a = ctx.prevote_bitfield(v.info(&1).unwrap()).unwrap();
-			let equiv_b = ctx.prevote_bitfield(v.info(&5).unwrap()).unwrap();
+			let faulty = if weights.len() == 1 { 0 } else { (total_weight - 1) / 3 };
+			assert_eq!(round.threshold(), total_weight - faulty, "weights: {:?}", weights);
 
-			equiv_a.merge(&equiv_b).unwrap()
-		};
+			// more than 2/3 of the total weight, and any two supermajorities
+			// overlap by more than the faulty weight.
+			assert!(3 * (round.threshold() as u128) > 2 * (total_weight as u128));
+			assert!(2 * round.threshold() - total_weight > faulty);
+		}
+	}
 
-		let votes = {
-			let vote_a = ctx.prevote_bitfield(v.info(&1).unwrap()).unwrap();
-			let vote_b = ctx.prevote_bitfield(v.info(&2).unwrap()).unwrap();
-			let vote_c = ctx.prevote_bitfield(v.info(&3).unwrap()).unwrap();
+	#[test]
+	fn equivocation_weight_by_phase() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+		chain.push_blocks("A", &["D"]);
 
-			vote_a.merge(&vote_b).unwrap().merge(&vote_c).unwrap()
-		};
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			set_id: 0,
+			voters: voters(),
+			base: (GENESIS_HASH, 1),
+		});
 
-		let weight = VoteWeight { bitfield: votes };
-		let vote_weight = weight.total_weight(&equivocators, &v);
+		assert_eq!(round.equivocation_weight(), (0, 0));
+		assert!(round.equivocators().is_empty());
 
-		// counts the prevotes from 2, 3, and the equivocations from 1, 5 without
-		// double-counting 1
-		assert_eq!(vote_weight, TotalWeight { prevote: 1 + 5 + 2 + 3, precommit: 0 });
+		round.import_prevote(&chain, Prevote::new("B", 3), "Eve", Signature("Eve-1")).unwrap();
+		round.import_prevote(&chain, Prevote::new("D", 3), "Eve", Signature("Eve-2")).unwrap();
+		round.import_precommit(&chain, Precommit::new("B", 3), "Alice", Signature("Alice-1")).unwrap();
+		round.import_precommit(&chain, Precommit::new("D", 3), "Alice", Signature("Alice-2")).unwrap();
+		round.import_precommit(&chain, Precommit::new("B", 3), "Bob", Signature("Bob-1")).unwrap();
 
-		let votes = weight.bitfield.merge(&ctx.prevote_bitfield(v.info(&5).unwrap()).unwrap()).unwrap();
+		assert_eq!(round.equivocation_weight(), (3, 4));
 
-		let weight = VoteWeight { bitfield: votes };
-		let vote_weight = weight.total_weight(&equivocators, &v);
+		// canonical order is by id.
+		assert_eq!(round.equivocators(), vec!["Alice", "Eve"]);
 
+		// further equivocations don't count twice.
+		round.import_prevote(&chain, Prevote::new("C", 4), "Eve", Signature("Eve-3")).unwrap();
+		round.import_precommit(&chain, Precommit::new("B", 3), "Eve", Signature("Eve-4")).unwrap();
+		round.import_precommit(&chain, Precommit::new("D", 3), "Eve", Signature("Eve-5")).unwrap();
 
-		// adding an extra vote by 5 doesn't increase the count.
-		assert_eq!(vote_weight, TotalWeight { prevote: 1 + 5 + 2 + 3, precommit: 0 });
+		assert_eq!(round.equivocation_weight(), (3, 4 + 3));
+		assert_eq!(round.equivocators(), vec!["Alice", "Eve"]);
 	}
 
 	#[test]
-	fn historical_votes_works() {
+	fn precommit_weight_on_exact_target() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+		chain.push_blocks("A", &["D"]);
+
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			set_id: 0,
+			voters: voters(),
+			base: (GENESIS_HASH, 1),
+		});
+
+		round.import_precommit(&chain, Precommit::new("B", 3), "Alice", Signature("Alice")).unwrap();
+		round.import_precommit(&chain, Precommit::new("C", 4), "Bob", Signature("Bob")).unwrap();
+
+		// precommits for descendents don't count.
+		assert_eq!(round.precommit_weight_on(&"B", 3), 4);
+		assert_eq!(round.precommit_weight_on(&"C", 4), 7);
+		assert_eq!(round.precommit_weight_on(&"A", 2), 0);
+
+		// an equivocator counts once if either precommit is for the block.
+		round.import_precommit(&chain, Precommit::new("B", 3), "Eve", Signature("Eve-1")).unwrap();
+		round.import_precommit(&chain, Precommit::new("D", 3), "Eve", Signature("Eve-2")).unwrap();
+
+		assert_eq!(round.precommit_weight_on(&"B", 3), 4 + 3);
+		assert_eq!(round.precommit_weight_on(&"D", 3), 3);
+	}
+
+	#[test]
+	fn vote_weight_discounts_equivocators() {
+		let v: VoterSet<_> = VoterSet::new([
+			(1, 1),
+			(2, 2),
+			(3, 3),
+			(4, 4),
+			(5, 5),
+		].iter().cloned()).unwrap();
+
+		let ctx = BitfieldContext::new(5);
+
+		let equivocators = {
+			let equiv_a = ctx.prevote_bitfield(v.get(&1).unwrap()).unwrap();
+			let equiv_b = ctx.prevote_bitfield(v.get(&5).unwrap()).unwrap();
+
+			equiv_a.merge(&equiv_b).unwrap()
+		};
+
+		let votes = {
+			let vote_a = ctx.prevote_bitfield(v.get(&1).unwrap()).unwrap();
+			let vote_b = ctx.prevote_bitfield(v.get(&2).unwrap()).unwrap();
+			let vote_c = ctx.prevote_bitfield(v.get(&3).unwrap()).unwrap();
+
+			vote_a.merge(&vote_b).unwrap().merge(&vote_c).unwrap()
+		};
+
+		let weight = VoteWeight { bitfield: votes };
+		let vote_weight = weight.total_weight(&equivocators, &v);
+
+		// counts the prevotes from 2, 3, and the equivocations from 1, 5 without
+		// double-counting 1
+		assert_eq!(vote_weight, TotalWeight { prevote: 1 + 5 + 2 + 3, precommit: 0 });
+
+		let votes = weight.bitfield.merge(&ctx.prevote_bitfield(v.get(&5).unwrap()).unwrap()).unwrap();
+
+		let weight = VoteWeight { bitfield: votes };
+		let vote_weight = weight.total_weight(&equivocators, &v);
+
+
+		// adding an extra vote by 5 doesn't increase the count.
+		assert_eq!(vote_weight, TotalWeight { prevote: 1 + 5 + 2 + 3, precommit: 0 });
+	}
+
+	#[test]
+	fn vote_weight_at_maximum_total_weight() {
+		// voter sets with a larger total weight are rejected.
+		let v: VoterSet<_> = VoterSet::new([
+			(1, u64::MAX / 2),
+			(2, u64::MAX / 2 - 4),
+			(3, 5),
+		].iter().cloned()).unwrap();
+
+		assert_eq!(v.total_weight(), u64::MAX);
+
+		let ctx = BitfieldContext::new(3);
+		let votes = [1, 2, 3].iter().fold(Bitfield::Blank, |votes, id| {
+			let vote = ctx.precommit_bitfield(v.get(id).unwrap()).unwrap();
+			votes.merge(&vote).unwrap()
+		});
+
+		let weight = VoteWeight { bitfield: votes };
+		assert_eq!(
+			weight.total_weight(&Bitfield::Blank, &v),
+			TotalWeight { prevote: 0, precommit: u64::MAX },
+		);
+
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A"]);
+
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			set_id: 0,
+			voters: v,
+			base: (GENESIS_HASH, 1),
+		});
+
+		for id in &[1, 2, 3] {
+			round.import_prevote(&chain, Prevote::new("A", 2), *id, Signature("sig")).unwrap();
+		}
+
+		assert_eq!(round.prevote.current_weight, u64::MAX);
+		assert_eq!(round.state().prevote_ghost, Some(("A", 2)));
+	}
+
+	#[test]
+	fn extreme_block_numbers_do_not_panic() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+		chain.push_blocks("A", &["B2"]);
+
+		let numbers = [0, 1, 2, u32::MAX - 1, u32::MAX];
+		let targets = [GENESIS_HASH, "A", "B", "C", "B2"];
+
+		for &first in &numbers[1..] {
+			let mut round = Round::new(RoundParams {
+				round_number: 1,
+				set_id: 0,
+				voters: voters(),
+				base: (GENESIS_HASH, 0),
+			});
+
+			round.import_prevote(&chain, Prevote::new("A", first), "Alice", Signature("Alice")).unwrap();
+
+			for (i, &target) in targets.iter().enumerate() {
+				for &number in &numbers {
+					for &voter in &["Bob", "Eve"] {
+						let prevote = round.import_prevote(
+							&chain,
+							Prevote::new(target, number),
+							voter,
+							Signature(voter),
+						);
+						let precommit = round.import_precommit(
+							&chain,
+							Precommit::new(target, number),
+							voter,
+							Signature(voter),
+						);
+
+						for err in prevote.err().into_iter().chain(precommit.err()) {
+							assert_eq!(err, crate::Error::BlockNumberOverflow);
+							// only descendents of "A" can be claimed below it.
+							assert!(i >= 2);
+						}
+					}
+				}
+			}
+
+			round.precommit_ghost();
+		}
+
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			set_id: 0,
+			voters: voters(),
+			base: (GENESIS_HASH, 0),
+		});
+
+		round.import_prevote(&chain, Prevote::new("A", u32::MAX - 1), "Alice", Signature("Alice")).unwrap();
+		assert_eq!(
+			round.import_prevote(&chain, Prevote::new("B", u32::MAX - 1), "Bob", Signature("Bob")).err(),
+			Some(crate::Error::BlockNumberOverflow),
+		);
+		assert!(round.import_prevote(&chain, Prevote::new("B", u32::MAX), "Eve", Signature("Eve")).is_ok());
+	}
+
+	#[test]
+	fn made_up_block_numbers_do_not_panic() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D"]);
+
+		let voters = VoterSet::new([("Alice", 1), ("Bob", 1), ("Eve", 1)].iter().cloned()).unwrap();
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			set_id: 0,
+			voters,
+			base: (GENESIS_HASH, 1),
+		});
+
+		// "D" is block 5, but everyone claims it at 100.
+		for &voter in &["Alice", "Bob", "Eve"] {
+			round.import_prevote(&chain, Prevote::new("D", 100), voter, Signature(voter)).unwrap();
+		}
+		round.import_precommit(&chain, Precommit::new("D", 100), "Alice", Signature("Alice")).unwrap();
+		round.import_precommit(&chain, Precommit::new("D", 100), "Bob", Signature("Bob")).unwrap();
+		// the blocks below "D" can't be told by number, so whatever comes of
+		// it, it doesn't panic.
+		let _ = round.import_precommit(&chain, Precommit::new("C", 4), "Eve", Signature("Eve"));
+		let _ = round.precommit_ghost();
+		assert_eq!(round.state().prevote_ghost, Some(("D", 100)));
+	}
+
+	#[test]
+	fn tracks_who_has_voted() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			set_id: 0,
+			voters: voters(),
+			base: ("C", 4),
+		});
+
+		assert!(!round.has_prevoted(&"Alice"));
+		round.import_prevote(&chain, Prevote::new("E", 6), "Alice", Signature("Alice")).unwrap();
+		assert!(round.has_prevoted(&"Alice"));
+		assert!(!round.has_precommitted(&"Alice"));
+
+		round.import_precommit(&chain, Precommit::new("D", 5), "Alice", Signature("Alice")).unwrap();
+		assert!(round.has_precommitted(&"Alice"));
+		assert!(!round.has_prevoted(&"Bob"));
+
+		// votes replayed from the history of the round count as well.
+		let mut replayed = Round::new(RoundParams {
+			round_number: 1,
+			set_id: 0,
+			voters: voters(),
+			base: ("C", 4),
+		});
+
+		for SignedMessage { message, signature, id } in round.historical_votes().seen().clone() {
+			match message {
+				Message::Prevote(prevote) => { replayed.import_prevote(&chain, prevote, id, signature).unwrap(); }
+				Message::Precommit(precommit) => { replayed.import_precommit(&chain, precommit, id, signature).unwrap(); }
+				Message::PrimaryPropose(_) => {}
+			}
+		}
+
+		assert!(replayed.has_prevoted(&"Alice"));
+		assert!(replayed.has_precommitted(&"Alice"));
+		assert!(!replayed.has_prevoted(&"Bob"));
+	}
+
+	#[test]
+	fn historical_votes_works() {
 		let mut chain = DummyChain::new();
 		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E", "F"]);
 		chain.push_blocks("E", &["EA", "EB", "EC", "ED"]);
@@ -971,6 +1934,7 @@ mod tests {
 
 		let mut round = Round::new(RoundParams {
 			round_number: 1,
+			set_id: 0,
 			voters: voters(),
 			base: ("C", 4),
 		});
@@ -1042,4 +2006,781 @@ mod tests {
 			Some(4),
 		));
 	}
+
+	#[test]
+	fn incremental_state_matches_full_recompute() {
+		use rand::{Rng, SeedableRng, rngs::StdRng};
+		#[cfg(not(feature = "std"))]
+		use alloc::boxed::Box;
+		use std::collections::HashSet;
+
+		const N_BLOCKS: usize = 32;
+
+		let names: Vec<&'static str> = (0..N_BLOCKS)
+			.map(|i| &*Box::leak(format!("B{}", i).into_boxed_str()))
+			.collect();
+
+		let mut rng = StdRng::seed_from_u64(0x6a09_e667);
+
+		for _ in 0..2000 {
+			// random tree of blocks on top of genesis.
+			let mut chain = DummyChain::new();
+			let mut blocks = vec![GENESIS_HASH];
+			for name in &names {
+				let parent = blocks[rng.gen_range(0, blocks.len())];
+				chain.push_blocks(parent, &[name]);
+				blocks.push(name);
+			}
+
+			let voters: VoterSet<u32> = VoterSet::new((0..rng.gen_range(1, 10u32))
+				.map(|id| (id, rng.gen_range(1, 10)))
+				).unwrap();
+			let n_voters = voters.len() as u32;
+
+			// the GHOST is only well-defined when equivocators stay within the
+			// tolerated faulty weight, so only a subset of voters may equivocate.
+			let mut faulty_budget = voters.total_weight() - voters.threshold();
+			let faulty: HashSet<u32> = (0..n_voters).filter(|id| {
+				let weight = voters.get(id).unwrap().weight();
+				if weight <= faulty_budget && rng.gen() {
+					faulty_budget -= weight;
+					true
+				} else {
+					false
+				}
+			}).collect();
+			let mut honest_votes = HashMap::new();
+
+			let mut round = Round::new(RoundParams {
+				round_number: 1,
+				set_id: 0,
+				voters,
+				base: (GENESIS_HASH, 1),
+			});
+
+			for _ in 0..rng.gen_range(1, 40) {
+				let voter = rng.gen_range(0, n_voters);
+				let is_prevote: bool = rng.gen();
+
+				let mut target = blocks[rng.gen_range(0, blocks.len())];
+				if !faulty.contains(&voter) {
+					target = *honest_votes.entry((voter, is_prevote)).or_insert(target);
+				}
+				let target_number = chain.number(target);
+
+				if is_prevote {
+					round.import_prevote(
+						&chain,
+						Prevote::new(target, target_number),
+						voter,
+						Signature("sig"),
+					).unwrap();
+				} else {
+					round.import_precommit(
+						&chain,
+						Precommit::new(target, target_number),
+						voter,
+						Signature("sig"),
+					).unwrap();
+				}
+
+				let incremental = round.state();
+				round.recompute_from_scratch();
+				assert_eq!(incremental, round.state());
+			}
+		}
+	}
+
+	#[test]
+	fn arbitrary_vote_sequences_keep_invariants() {
+		use crate::testing::arbitrary::Scenario;
+		use rand::{Rng, SeedableRng, rngs::StdRng};
+
+		let mut rng = StdRng::seed_from_u64(0xbb67_ae85);
+
+		for _ in 0..2000 {
+			let data: Vec<u8> = (0..rng.gen_range(0, 300)).map(|_| rng.gen()).collect();
+			Scenario::decode(&data).run();
+		}
+	}
+
+	#[test]
+	fn round_state_matches_naive_model() {
+		use crate::testing::arbitrary::{Scenario, Vote};
+		use crate::testing::model::NaiveRound;
+		use rand::{Rng, SeedableRng, rngs::StdRng};
+
+		// bounded for CI, set `GRANDPA_MODEL_CASES` to run more locally.
+		#[cfg(feature = "std")]
+		let cases = std::env::var("GRANDPA_MODEL_CASES").ok()
+			.and_then(|cases| cases.parse().ok())
+			.unwrap_or(500);
+		#[cfg(not(feature = "std"))]
+		let cases = 500;
+
+		let mut rng = StdRng::seed_from_u64(0x3c6e_f372);
+
+		for _ in 0..cases {
+			let data: Vec<u8> = (0..rng.gen_range(0, 300)).map(|_| rng.gen()).collect();
+			let scenario = Scenario::decode(&data);
+			let chain = &scenario.chain;
+
+			// the model finds blocks by hash, so it only agrees on the chain's
+			// numbers.
+			if scenario.made_up_numbers { continue }
+
+			let mut round = scenario.round();
+			let mut model = NaiveRound::new(scenario.voters.clone(), scenario.base);
+
+			for (i, (id, vote)) in scenario.votes.iter().enumerate() {
+				let (imported, modelled) = match vote {
+					Vote::Prevote(prevote) => (
+						round.import_prevote(chain, prevote.clone(), *id, *id).is_ok(),
+						model.import_prevote(chain, prevote.clone(), *id),
+					),
+					Vote::Precommit(precommit) => (
+						round.import_precommit(chain, precommit.clone(), *id, *id).is_ok(),
+						model.import_precommit(chain, precommit.clone(), *id),
+					),
+				};
+
+				assert_eq!(imported, modelled, "vote {} of {:?} imported differently", i, data);
+				assert_eq!(round.state(), model.state(chain), "state after vote {} of {:?} differs", i, data);
+			}
+		}
+	}
+
+	#[test]
+	fn finalizable_targets_are_the_chain_up_to_finalized() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E", "F"]);
+		chain.push_blocks("E", &["EA", "EB", "EC", "ED"]);
+		chain.push_blocks("F", &["FA", "FB", "FC"]);
+
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			set_id: 0,
+			voters: voters(),
+			base: ("C", 4),
+		});
+
+		for &(voter, target) in &[("Alice", ("FC", 10)), ("Bob", ("ED", 10)), ("Eve", ("EA", 7))] {
+			round.import_prevote(&chain, Prevote::new(target.0, target.1), voter, Signature(voter)).unwrap();
+		}
+
+		round.import_precommit(&chain, Precommit::new("FC", 10), "Alice", Signature("Alice")).unwrap();
+		assert_eq!(round.finalizable_targets().count(), 0);
+
+		round.import_precommit(&chain, Precommit::new("ED", 10), "Bob", Signature("Bob")).unwrap();
+		assert_eq!(round.finalized(), Some(&("E", 6)));
+		assert_eq!(
+			round.finalizable_targets().collect::<Vec<_>>(),
+			vec![("C", 4), ("D", 5), ("E", 6)],
+		);
+
+		// precommits on the other fork only add to the support of "E" and its
+		// ancestors.
+		round.import_precommit(&chain, Precommit::new("FA", 8), "Eve", Signature("Eve")).unwrap();
+		assert_eq!(round.finalized(), Some(&("E", 6)));
+		assert_eq!(
+			round.finalizable_targets().collect::<Vec<_>>(),
+			vec![("C", 4), ("D", 5), ("E", 6)],
+		);
+	}
+
+	#[cfg(feature = "derive-serde")]
+	#[test]
+	fn state_serde_round_trip() {
+		let state = State {
+			prevote_ghost: Some(("B".to_string(), 3u32)),
+			finalized: Some(("A".to_string(), 2)),
+			estimate: None,
+			completable: false,
+		};
+
+		let json = serde_json::to_string(&state).unwrap();
+		assert_eq!(
+			json,
+			r#"{"prevote_ghost":["B",3],"finalized":["A",2],"estimate":null,"completable":false}"#,
+		);
+		assert_eq!(serde_json::from_str::<State<String, u32>>(&json).unwrap(), state);
+	}
+
+	#[test]
+	fn commits_are_independent_of_import_order() {
+		use crate::{Commit, CompactCommit};
+
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+		chain.push_blocks("E", &["EA", "EB"]);
+
+		let prevotes = [("Alice", ("E", 6)), ("Bob", ("EA", 7)), ("Eve", ("E", 6))];
+		let precommits = [
+			("Alice", ("E", 6), "Alice"),
+			("Bob", ("EA", 7), "Bob"),
+			("Eve", ("EB", 8), "Eve-1"),
+			("Eve", ("E", 6), "Eve-2"),
+		];
+
+		// drive a round with the votes in the given order, and assemble its commit.
+		let commit = |order: &[usize]| {
+			let mut round = Round::new(RoundParams {
+				round_number: 1,
+				set_id: 0,
+				voters: voters(),
+				base: ("C", 4),
+			});
+
+			for &i in order.iter().filter(|&&i| i < prevotes.len()) {
+				let (id, (hash, number)) = prevotes[i];
+				round.import_prevote(&chain, Prevote::new(hash, number), id, Signature(id)).unwrap();
+			}
+
+			for &i in order {
+				let (id, (hash, number), signature) = precommits[i];
+				round.import_precommit(&chain, Precommit::new(hash, number), id, Signature(signature)).unwrap();
+			}
+
+			let (target_hash, target_number) = round.finalized().cloned().unwrap();
+			let precommits = round.finalizing_precommits(&chain).unwrap().collect();
+			CompactCommit::from(Commit { target_hash, target_number, precommits, set_id: 0 })
+		};
+
+		let first = commit(&[0, 1, 2, 3]);
+		let second = commit(&[3, 2, 1, 0]);
+		assert_eq!(first, second);
+
+		// precommits are by voter, and the equivocator's by target.
+		let order: Vec<_> = first.precommits.iter().map(|p| p.target_hash).collect();
+		assert_eq!(order, vec!["E", "EA", "E", "EB"]);
+		let signers: Vec<_> = first.auth_data.iter().map(|(signature, id)| (signature.0, *id)).collect();
+		assert_eq!(signers, vec![("Alice", "Alice"), ("Bob", "Bob"), ("Eve-2", "Eve"), ("Eve-1", "Eve")]);
+	}
+
+	#[test]
+	fn votes_off_the_base_do_not_count() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B"]);
+		chain.push_blocks(GENESIS_HASH, &["X"]);
+
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			set_id: 0,
+			voters: voters(),
+			base: ("A", 2),
+		});
+
+		assert!(round.import_prevote(&chain, Prevote::new("X", 2), "Bob", Signature("Bob")).is_err());
+		assert!(round.import_precommit(&chain, Precommit::new("X", 2), "Bob", Signature("Bob")).is_err());
+		assert_eq!(round.vote_weight(), (0, 0));
+		assert!(!round.has_prevoted(&"Bob"));
+
+		// the voter can still cast valid votes.
+		round.import_prevote(&chain, Prevote::new("B", 3), "Bob", Signature("Bob")).unwrap();
+		round.import_precommit(&chain, Precommit::new("B", 3), "Bob", Signature("Bob")).unwrap();
+		assert_eq!(round.vote_weight(), (7, 7));
+		assert!(round.equivocators().is_empty());
+	}
+
+	#[test]
+	fn round_is_reconstructed_from_its_votes() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+		chain.push_blocks("C", &["D2"]);
+
+		let params = || RoundParams {
+			round_number: 1,
+			set_id: 0,
+			voters: voters(),
+			base: ("C", 4),
+		};
+
+		let mut round = Round::new(params());
+		round.import_prevote(&chain, Prevote::new("E", 6), "Alice", Signature("Alice")).unwrap();
+		round.import_prevote(&chain, Prevote::new("D", 5), "Bob", Signature("Bob")).unwrap();
+		round.import_prevote(&chain, Prevote::new("D2", 5), "Eve", Signature("Eve")).unwrap();
+		round.import_prevote(&chain, Prevote::new("E", 6), "Eve", Signature("Eve-2")).unwrap();
+		round.import_precommit(&chain, Precommit::new("D", 5), "Alice", Signature("Alice")).unwrap();
+		round.import_precommit(&chain, Precommit::new("D", 5), "Bob", Signature("Bob")).unwrap();
+
+		let prevotes = round.prevotes().into_iter()
+			.map(|(id, prevote, signature)| SignedPrevote { prevote, signature, id })
+			.collect::<Vec<_>>();
+		let precommits = round.precommits().into_iter()
+			.map(|(id, precommit, signature)| SignedPrecommit { precommit, signature, id })
+			.collect::<Vec<_>>();
+
+		let restored = Round::from_parts(params(), prevotes.clone(), precommits.clone(), &chain).unwrap();
+		assert_eq!(restored.state(), round.state());
+		assert_eq!(restored.state().finalized, Some(("D", 5)));
+		assert_eq!(restored.equivocators(), vec!["Eve"]);
+
+		// votes from outside the voter set are rejected.
+		let mut outsider = precommits;
+		outsider.push(SignedPrecommit { precommit: Precommit::new("D", 5), signature: Signature("Mallory"), id: "Mallory" });
+		assert!(matches!(
+			Round::from_parts(params(), prevotes, outsider, &chain),
+			Err(crate::Error::UnknownVoter),
+		));
+	}
+
+	#[test]
+	fn finalization_evidence_is_minimal_and_deterministic() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+
+		// threshold of 10, out of 14.
+		let run = |precommits: &[(&'static str, u32, &'static str)]| {
+			let mut round = Round::new(RoundParams {
+				round_number: 1,
+				set_id: 0,
+				voters: voters(),
+				base: ("C", 4),
+			});
+
+			for &id in &["Alice", "Bob", "Eve"] {
+				round.import_prevote(&chain, Prevote::new("E", 6), id, Signature(id)).unwrap();
+			}
+			assert!(round.finalization_evidence().is_none());
+
+			for &(hash, number, id) in precommits {
+				round.import_precommit(&chain, Precommit::new(hash, number), id, Signature(id)).unwrap();
+			}
+			round.finalization_evidence().unwrap().into_iter().map(|signed| signed.id).collect::<Vec<_>>()
+		};
+
+		// Alice isn't needed once Bob's precommit is in, and the rest meets the
+		// threshold exactly.
+		let order = [("D", 5, "Eve"), ("E", 6, "Alice"), ("D", 5, "Bob")];
+		assert_eq!(run(&order), vec!["Eve", "Bob"]);
+		assert_eq!(run(&order), run(&order));
+
+		// the earliest precommits meeting the threshold are preferred.
+		assert_eq!(run(&[("D", 5, "Bob"), ("E", 6, "Alice"), ("D", 5, "Eve")]), vec!["Bob", "Alice"]);
+	}
+
+	#[test]
+	fn completability_blockers_of_round_missing_a_precommit() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			set_id: 0,
+			voters: voters(),
+			base: ("C", 4),
+		});
+
+		let blockers = round.completability_blockers().unwrap();
+		assert_eq!(blockers.missing_prevote_weight, 10);
+		assert!(blockers.estimate_candidates.is_empty());
+		assert_eq!(blockers.missing_prevoters, vec!["Alice", "Bob", "Eve"]);
+
+		for &id in &["Alice", "Bob", "Eve"] {
+			round.import_prevote(&chain, Prevote::new("E", 6), id, Signature(id)).unwrap();
+		}
+
+		// without Bob, the precommits of Alice and Eve fall short of the
+		// threshold of 10.
+		for &id in &["Alice", "Eve"] {
+			round.import_precommit(&chain, Precommit::new("E", 6), id, Signature(id)).unwrap();
+		}
+
+		assert_eq!(round.completability_blockers(), Some(CompletabilityBlockers {
+			missing_prevote_weight: 0,
+			missing_precommit_weight: 3,
+			estimate_candidates: vec![("E", 6)],
+			missing_prevoters: vec![],
+			missing_precommitters: vec!["Bob"],
+		}));
+
+		round.import_precommit(&chain, Precommit::new("E", 6), "Bob", Signature("Bob")).unwrap();
+		assert!(round.completable());
+		assert_eq!(round.completability_blockers(), None);
+	}
+
+	#[test]
+	fn finalization_evidence_includes_equivocations_when_needed() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+		chain.push_blocks("C", &["D2"]);
+		chain.push_blocks("C", &["D3"]);
+
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			set_id: 0,
+			voters: voters(),
+			base: ("C", 4),
+		});
+
+		for &id in &["Alice", "Bob", "Eve"] {
+			round.import_prevote(&chain, Prevote::new("E", 6), id, Signature(id)).unwrap();
+		}
+
+		// Bob's weight counts for "E", but neither of his precommits is on it.
+		round.import_precommit(&chain, Precommit::new("E", 6), "Alice", Signature("Alice")).unwrap();
+		round.import_precommit(&chain, Precommit::new("D", 5), "Eve", Signature("Eve")).unwrap();
+		round.import_precommit(&chain, Precommit::new("D2", 5), "Bob", Signature("Bob")).unwrap();
+		round.import_precommit(&chain, Precommit::new("D3", 5), "Bob", Signature("Bob-2")).unwrap();
+		assert_eq!(round.finalized(), Some(&("E", 6)));
+
+		let evidence = round.finalization_evidence().unwrap();
+		let targets = evidence.iter().map(|signed| (signed.id, signed.precommit.target_hash)).collect::<Vec<_>>();
+		assert_eq!(targets, vec![("Alice", "E"), ("Bob", "D2"), ("Bob", "D3")]);
+	}
+
+	#[test]
+	fn strict_base_rejects_votes_for_the_base() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+
+		let new_round = |strict_base| {
+			let mut round = Round::new(RoundParams {
+				round_number: 1,
+				set_id: 0,
+				voters: voters(),
+				base: ("C", 4),
+			});
+			round.set_strict_base(strict_base);
+			round
+		};
+
+		// by default, votes for the base are counted like any other.
+		let mut round = new_round(false);
+		let result = round.import_prevote(&chain, Prevote::new("C", 4), "Alice", Signature("Alice")).unwrap();
+		assert!(result.valid_voter && !result.vote_for_base);
+		let result = round.import_precommit(&chain, Precommit::new("C", 4), "Alice", Signature("Alice")).unwrap();
+		assert!(result.valid_voter && !result.vote_for_base);
+		assert_eq!(round.historical_votes().seen().len(), 2);
+
+		// in strict mode they are reported and not recorded, while votes for
+		// blocks above the base are still accepted.
+		let mut round = new_round(true);
+		let result = round.import_prevote(&chain, Prevote::new("C", 4), "Alice", Signature("Alice")).unwrap();
+		assert!(result.valid_voter && result.vote_for_base);
+		let result = round.import_precommit(&chain, Precommit::new("C", 4), "Alice", Signature("Alice")).unwrap();
+		assert!(result.valid_voter && result.vote_for_base);
+		assert!(round.historical_votes().seen().is_empty());
+		assert_eq!(round.vote_weight(), (0, 0));
+
+		round.import_prevote(&chain, Prevote::new("E", 6), "Alice", Signature("Alice")).unwrap();
+		round.import_precommit(&chain, Precommit::new("D", 5), "Alice", Signature("Alice")).unwrap();
+		assert_eq!(round.historical_votes().seen().len(), 2);
+	}
+
+	#[test]
+	fn votes_with_over_long_ancestry_are_rejected() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			set_id: 0,
+			voters: voters(),
+			base: ("A", 2),
+		});
+		round.set_max_ancestry_len(2);
+
+		// the ancestry of "D" down to the base is two blocks, that of "E" three.
+		round.import_prevote(&chain, Prevote::new("D", 5), "Alice", Signature("Alice")).unwrap();
+		match round.import_prevote(&chain, Prevote::new("E", 6), "Bob", Signature("Bob")) {
+			Err(crate::Error::AncestryTooLong) => {},
+			other => panic!("unexpected import result: {:?}", other.map(|_| ())),
+		}
+		assert_eq!(round.historical_votes().seen().len(), 1);
+	}
+
+	#[test]
+	fn latest_votes_of_each_voter() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+		chain.push_blocks("C", &["D2"]);
+
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			set_id: 0,
+			voters: voters(),
+			base: ("C", 4),
+		});
+
+		round.import_prevote(&chain, Prevote::new("E", 6), "Alice", Signature("Alice")).unwrap();
+		round.import_prevote(&chain, Prevote::new("D", 5), "Bob", Signature("Bob")).unwrap();
+		round.import_precommit(&chain, Precommit::new("D", 5), "Alice", Signature("Alice")).unwrap();
+
+		// Eve equivocates in prevotes, only her latest prevote is exposed.
+		round.import_prevote(&chain, Prevote::new("D", 5), "Eve", Signature("Eve-1")).unwrap();
+		round.import_prevote(&chain, Prevote::new("D2", 5), "Eve", Signature("Eve-2")).unwrap();
+
+		let mut prevotes = round.latest_prevotes()
+			.map(|(id, prevote)| (*id, prevote.clone()))
+			.collect::<Vec<_>>();
+		prevotes.sort_by_key(|(id, _)| *id);
+		assert_eq!(prevotes, vec![
+			("Alice", Prevote::new("E", 6)),
+			("Bob", Prevote::new("D", 5)),
+			("Eve", Prevote::new("D2", 5)),
+		]);
+
+		let precommits = round.latest_precommits()
+			.map(|(id, precommit)| (*id, precommit.clone()))
+			.collect::<Vec<_>>();
+		assert_eq!(precommits, vec![("Alice", Precommit::new("D", 5))]);
+	}
+
+	#[test]
+	fn duplicates_are_detected_without_hiding_equivocations() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+		chain.push_blocks("C", &["D2"]);
+
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			set_id: 0,
+			voters: voters(),
+			base: ("C", 4),
+		});
+
+		let prevote = Prevote::new("E", 6);
+		assert!(!round.is_duplicate_prevote(&"Alice", &prevote, &Signature("Alice")));
+		round.import_prevote(&chain, prevote.clone(), "Alice", Signature("Alice")).unwrap();
+
+		// the same vote again is a duplicate, and doesn't count twice.
+		assert!(round.is_duplicate_prevote(&"Alice", &prevote, &Signature("Alice")));
+		let result = round.import_prevote(&chain, prevote.clone(), "Alice", Signature("Alice")).unwrap();
+		assert!(result.duplicated);
+		assert!(result.equivocation.is_none());
+		assert_eq!(round.vote_weight(), (4, 0));
+
+		// a prevote for another target, or only with another signature, isn't.
+		let other = Prevote::new("D2", 5);
+		assert!(!round.is_duplicate_prevote(&"Alice", &other, &Signature("Alice")));
+		assert!(!round.is_duplicate_prevote(&"Alice", &prevote, &Signature("Alice-2")));
+		assert!(!round.is_duplicate_precommit(&"Alice", &Precommit::new("E", 6), &Signature("Alice")));
+
+		let result = round.import_prevote(&chain, other.clone(), "Alice", Signature("Alice")).unwrap();
+		assert!(!result.duplicated);
+		assert!(result.equivocation.is_some());
+
+		// both votes of the equivocator are duplicates from now on.
+		assert!(round.is_duplicate_prevote(&"Alice", &prevote, &Signature("Alice")));
+		assert!(round.is_duplicate_prevote(&"Alice", &other, &Signature("Alice")));
+	}
+
+	#[test]
+	fn rounds_reconcile_votes_through_summaries() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+		chain.push_blocks("C", &["D2"]);
+
+		let new_round = || Round::new(RoundParams {
+			round_number: 1,
+			set_id: 0,
+			voters: voters(),
+			base: (GENESIS_HASH, 1),
+		});
+
+		let import = |round: &mut Round<_, _, _, _>, votes: Vec<SignedMessage<_, _, _, _>>| {
+			for SignedMessage { message, signature, id } in votes {
+				match message {
+					Message::Prevote(prevote) => { round.import_prevote(&chain, prevote, id, signature).unwrap(); }
+					Message::Precommit(precommit) => { round.import_precommit(&chain, precommit, id, signature).unwrap(); }
+					Message::PrimaryPropose(_) => unreachable!("rounds only hold votes; qed"),
+				}
+			}
+		};
+
+		let vote = |message, id| SignedMessage { message, signature: Signature(id), id };
+
+		// each round saw half of the votes, one of them Eve's equivocation.
+		let mut a = new_round();
+		import(&mut a, vec![
+			vote(Message::Prevote(Prevote::new("E", 6)), "Alice"),
+			vote(Message::Prevote(Prevote::new("E", 6)), "Eve"),
+			vote(Message::Prevote(Prevote::new("D2", 5)), "Eve"),
+			vote(Message::Precommit(Precommit::new("D", 5)), "Bob"),
+		]);
+
+		let mut b = new_round();
+		import(&mut b, vec![
+			vote(Message::Prevote(Prevote::new("D", 5)), "Bob"),
+			vote(Message::Precommit(Precommit::new("D", 5)), "Alice"),
+			vote(Message::Precommit(Precommit::new("C", 4)), "Eve"),
+		]);
+
+		// neither half is enough to finalize anything.
+		assert_eq!(a.state().finalized, None);
+		assert_eq!(b.state().finalized, None);
+
+		let summary_a = a.vote_summary();
+		let summary_b = b.vote_summary();
+		assert!(summary_a.has_prevote(0) && !summary_a.has_precommit(0));
+		assert!(summary_a.prevote_equivocated(2));
+		assert!(!summary_b.has_prevote(2) && summary_b.has_precommit(2));
+
+		let missing_in_b = a.votes_not_in(&summary_b);
+		let missing_in_a = b.votes_not_in(&summary_a);
+		assert_eq!(missing_in_b.len(), 4);
+		assert_eq!(missing_in_a.len(), 3);
+
+		import(&mut a, missing_in_a);
+		import(&mut b, missing_in_b);
+
+		assert_eq!(a.state(), b.state());
+		assert_eq!(a.state().finalized, Some(("D", 5)));
+		assert_eq!(a.vote_summary(), b.vote_summary());
+		assert!(a.vote_summary().prevote_equivocated(2));
+		assert!(!a.vote_summary().precommit_equivocated(2));
+
+		// nothing is missing anymore.
+		assert!(a.votes_not_in(&b.vote_summary()).is_empty());
+		assert!(b.votes_not_in(&a.vote_summary()).is_empty());
+
+		// a peer which saw one of Eve's prevotes, but not her equivocation,
+		// is sent both of them.
+		let mut c = new_round();
+		import(&mut c, vec![vote(Message::Prevote(Prevote::new("E", 6)), "Eve")]);
+		let missing_in_c = a.votes_not_in(&c.vote_summary());
+		assert_eq!(missing_in_c.iter().filter(|signed| signed.id == "Eve" && matches!(signed.message, Message::Prevote(_))).count(), 2);
+	}
+
+	// a chain counting the ancestry lookups made through it.
+	struct CountingChain {
+		inner: DummyChain,
+		ancestry_calls: core::cell::Cell<usize>,
+	}
+
+	impl Chain<&'static str, u32> for CountingChain {
+		fn ancestry(&self, base: &'static str, block: &'static str) -> Result<Vec<&'static str>, crate::Error> {
+			self.ancestry_calls.set(self.ancestry_calls.get() + 1);
+			self.inner.ancestry(base, block)
+		}
+
+		fn best_chain_containing(&self, base: &'static str) -> Option<(&'static str, u32)> {
+			self.inner.best_chain_containing(base)
+		}
+	}
+
+	#[test]
+	fn prevote_ghost_is_only_recomputed_after_imports() {
+		let mut inner = DummyChain::new();
+		inner.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+		let chain = CountingChain { inner, ancestry_calls: Default::default() };
+
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			set_id: 0,
+			voters: voters(),
+			base: (GENESIS_HASH, 1),
+		});
+
+		round.import_prevote(&chain, Prevote::new("E", 6), "Alice", Signature("Alice")).unwrap();
+		round.import_prevote(&chain, Prevote::new("D", 5), "Bob", Signature("Bob")).unwrap();
+		let ancestry_calls = chain.ancestry_calls.get();
+		assert!(ancestry_calls > 0);
+
+		// asking repeatedly returns the cached GHOST.
+		assert_eq!(round.prevote_ghost(), Some(("D", 5)));
+		assert_eq!(round.prevote_ghost(), Some(("D", 5)));
+		assert!(!round.prevote_ghost_stale);
+
+		// duplicates and precommits don't make it stale.
+		round.import_prevote(&chain, Prevote::new("D", 5), "Bob", Signature("Bob")).unwrap();
+		round.import_precommit(&chain, Precommit::new("D", 5), "Alice", Signature("Alice")).unwrap();
+		assert!(!round.prevote_ghost_stale);
+		assert_eq!(round.prevote_ghost(), Some(("D", 5)));
+		assert_eq!(chain.ancestry_calls.get(), ancestry_calls);
+	}
+
+	// seven voters of weight one, two of them equivocating: Eve in prevotes and
+	// Ferdie in precommits. the prevotes are on "F", and the precommits are
+	// enough to finalize "C" and estimate "D".
+	fn equivocating_round() -> (DummyChain, Round<&'static str, &'static str, u32, Signature>) {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E", "F"]);
+		chain.push_blocks("C", &["D2", "E2"]);
+
+		let voters = ["Alice", "Bob", "Charlie", "Dave", "Eve", "Ferdie", "George"];
+		let mut round = Round::new(RoundParams {
+			round_number: 1,
+			set_id: 0,
+			voters: VoterSet::new(voters.iter().map(|&id| (id, 1))).unwrap(),
+			base: (GENESIS_HASH, 1),
+		});
+
+		let prevotes = [("Alice", "F"), ("Bob", "F"), ("Charlie", "F"), ("Dave", "F"), ("Eve", "F"), ("Eve", "E2")];
+		for &(id, target) in &prevotes {
+			round.import_prevote(&chain, Prevote::new(target, chain.number(target)), id, Signature(id)).unwrap();
+		}
+		assert_eq!(round.estimate(), Some(&("F", 7)));
+
+		let precommits = [("Alice", "F"), ("Bob", "D"), ("Charlie", "D"), ("Ferdie", "D"), ("Ferdie", "E2"), ("Dave", "C")];
+		for &(id, target) in &precommits {
+			round.import_precommit(&chain, Precommit::new(target, chain.number(target)), id, Signature(id)).unwrap();
+		}
+		assert_eq!(round.estimate(), Some(&("F", 7)));
+		assert_eq!(round.finalized(), Some(&("C", 4)));
+
+		// the last precommit rules out "E" and "F": even with Eve and another
+		// equivocation they can't get supermajority precommits anymore.
+		round.import_precommit(&chain, Precommit::new("C", 4), "George", Signature("George")).unwrap();
+		assert_eq!(round.estimate(), Some(&("D", 5)));
+		assert_eq!(round.equivocation_weight(), (1, 1));
+
+		(chain, round)
+	}
+
+	#[test]
+	fn late_prevotes_do_not_move_the_estimate_back() {
+		let (chain, mut round) = equivocating_round();
+
+		// late prevotes, one on the fork and one on the prevote-GHOST, don't
+		// move the estimate, which only precommits can lower.
+		round.import_prevote(&chain, Prevote::new("E2", 6), "George", Signature("George")).unwrap();
+		assert_eq!(round.estimate(), Some(&("D", 5)));
+		round.import_prevote(&chain, Prevote::new("F", 7), "Ferdie", Signature("Ferdie")).unwrap();
+		assert_eq!(round.estimate(), Some(&("D", 5)));
+		assert_eq!(round.prevote_ghost(), Some(("F", 7)));
+
+		// nor do late precommits move it up again.
+		round.import_precommit(&chain, Precommit::new("F", 7), "Eve", Signature("Eve")).unwrap();
+		assert_eq!(round.estimate(), Some(&("D", 5)));
+		assert!(round.completable());
+	}
+
+	#[test]
+	fn estimate_moving_against_the_votes_is_flagged() {
+		// pretend the estimate was "F" before a prevote moved it down to "D".
+		let (chain, mut round) = equivocating_round();
+		round.estimate = Some(("F", 7));
+		let result = round.import_prevote(&chain, Prevote::new("F", 7), "Ferdie", Signature("Ferdie")).unwrap();
+		assert!(result.inconsistent_estimate);
+		assert_eq!(round.estimate(), Some(&("F", 7)));
+
+		// the vote is counted all the same.
+		assert!(round.has_prevoted(&"Ferdie"));
+
+		// pretend the estimate was "C" before a precommit moved it up to "D".
+		let (chain, mut round) = equivocating_round();
+		round.estimate = Some(("C", 4));
+		let result = round.import_precommit(&chain, Precommit::new("F", 7), "Eve", Signature("Eve")).unwrap();
+		assert!(result.inconsistent_estimate);
+		assert_eq!(round.estimate(), Some(&("C", 4)));
+
+		// or on another fork.
+		let (chain, mut round) = equivocating_round();
+		round.estimate = Some(("E2", 6));
+		let result = round.import_precommit(&chain, Precommit::new("C", 4), "Eve", Signature("Eve")).unwrap();
+		assert!(result.inconsistent_estimate);
+		assert_eq!(round.estimate(), Some(&("E2", 6)));
+
+		// equivocations are still reported along with it.
+		let (chain, mut round) = equivocating_round();
+		round.estimate = Some(("C", 4));
+		round.completable = false;
+		let result = round.import_precommit(&chain, Precommit::new("F", 7), "George", Signature("George")).unwrap();
+		assert!(result.inconsistent_estimate);
+		assert!(result.equivocation.is_some());
+		assert_eq!(round.estimate(), Some(&("C", 4)));
+		assert!(!round.completable());
+	}
 }
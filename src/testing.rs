@@ -16,7 +16,7 @@
 
 pub mod chain {
 	use crate::{Chain, Error};
-	use crate::std::{collections::HashMap, vec::Vec};
+	use crate::std::{collections::{HashMap, HashSet}, vec::Vec};
 
 	pub const GENESIS_HASH: &str = "genesis";
 	const NULL_HASH: &str = "NULL";
@@ -30,6 +30,8 @@ pub mod chain {
 		inner: HashMap<&'static str, BlockRecord>,
 		leaves: Vec<&'static str>,
 		finalized: (&'static str, u32),
+		best: Option<&'static str>, // preferred over the longest chain.
+		unsynced: HashSet<&'static str>,
 	}
 
 	impl DummyChain {
@@ -41,6 +43,8 @@ pub mod chain {
 				inner,
 				leaves: vec![GENESIS_HASH],
 				finalized: (GENESIS_HASH, 1),
+				best: None,
+				unsynced: HashSet::new(),
 			}
 		}
 
@@ -62,7 +66,11 @@ pub mod chain {
 				parent = descendent;
 			}
 
-			let new_leaf = blocks.last().unwrap();
+			self.insert_leaf(blocks.last().unwrap());
+		}
+
+		// keep leaves in descending order of their numbers.
+		fn insert_leaf(&mut self, new_leaf: &'static str) {
 			let new_leaf_number = self.inner.get(new_leaf).unwrap().number;
 
 			let insertion_index = self.leaves.binary_search_by(
@@ -72,12 +80,73 @@ pub mod chain {
 			self.leaves.insert(insertion_index, new_leaf);
 		}
 
+		/// Make the chain ending in the given block the best one containing
+		/// any of its ancestors, regardless of its length, e.g. to simulate a
+		/// reorg.
+		pub fn set_best_chain(&mut self, best: &'static str) {
+			assert!(self.inner.contains_key(best), "the best chain must be known");
+			self.best = Some(best);
+		}
+
+		/// Prune the fork ending in the given leaf, back to where it branches
+		/// off another fork or the last finalized block. Pruned blocks are
+		/// unknown afterwards, as if they were never imported.
+		pub fn prune_fork(&mut self, leaf: &'static str) {
+			let pos = self.leaves.iter().position(|x| x == &leaf).expect("only forks are pruned, by their leaf");
+			self.leaves.remove(pos);
+
+			let mut block = leaf;
+			loop {
+				assert!(block != self.finalized.0, "finalized blocks are never pruned");
+
+				let parent = self.inner.remove(block).unwrap().parent;
+				self.unsynced.remove(block);
+				if self.best == Some(block) {
+					self.best = None;
+				}
+
+				if self.inner.values().any(|record| record.parent == parent) { break }
+				if parent == self.finalized.0 {
+					self.insert_leaf(parent);
+					break
+				}
+
+				block = parent;
+			}
+		}
+
+		/// Treat the given block as not synced yet: ancestry queries involving
+		/// it fail until it's marked as synced.
+		pub fn mark_unsynced(&mut self, block: &'static str) {
+			self.unsynced.insert(block);
+		}
+
+		/// Answer ancestry queries involving the given block again.
+		pub fn mark_synced(&mut self, block: &'static str) {
+			self.unsynced.remove(block);
+		}
+
+		pub fn contains(&self, hash: &'static str) -> bool {
+			self.inner.contains_key(hash)
+		}
+
 		pub fn number(&self, hash: &'static str) -> u32 {
 			self.inner.get(hash).unwrap().number
 		}
 
+		/// The ancestor of the given block with the given number, or the block
+		/// itself.
+		pub fn ancestor_at(&self, mut hash: &'static str, number: u32) -> Option<(&'static str, u32)> {
+			loop {
+				let record = self.inner.get(hash)?;
+				if record.number == number { return Some((hash, number)) }
+				if record.number < number { return None }
+				hash = record.parent;
+			}
+		}
+
 		pub fn last_finalized(&self) -> (&'static str, u32) {
-			self.finalized.clone()
+			self.finalized
 		}
 
 		pub fn set_last_finalized(&mut self, last_finalized: (&'static str, u32)) {
@@ -85,11 +154,22 @@ pub mod chain {
 		}
 	}
 
+	impl Default for DummyChain {
+		fn default() -> Self {
+			DummyChain::new()
+		}
+	}
+
 	impl Chain<&'static str, u32> for DummyChain {
-		fn ancestry(&self, base: &'static str, mut block: &'static str) -> Result<Vec<&'static str>, Error> {
+		fn ancestry(&self, base: &'static str, block: &'static str) -> Result<Vec<&'static str>, Error> {
+			self.ancestry_bounded(base, block, usize::MAX)
+		}
+
+		fn ancestry_bounded(&self, base: &'static str, mut block: &'static str, max_len: usize) -> Result<Vec<&'static str>, Error> {
 			let mut ancestry = Vec::new();
 
 			loop {
+				if self.unsynced.contains(block) { return Err(Error::NotDescendent) }
 				match self.inner.get(block) {
 					None => return Err(Error::NotDescendent),
 					Some(record) => { block = record.parent; }
@@ -97,6 +177,7 @@ pub mod chain {
 
 				if block == NULL_HASH { return Err(Error::NotDescendent) }
 				if block == base { break }
+				if ancestry.len() == max_len { return Err(Error::AncestryTooLong) }
 
 				ancestry.push(block);
 			}
@@ -107,6 +188,12 @@ pub mod chain {
 		fn best_chain_containing(&self, base: &'static str) -> Option<(&'static str, u32)> {
 			let base_number = self.inner.get(base)?.number;
 
+			if let Some(best) = self.best {
+				if best == base || self.ancestry(base, best).is_ok() {
+					return Some((best, self.inner.get(best).unwrap().number));
+				}
+			}
+
 			for leaf in &self.leaves {
 				// leaves are in descending order.
 				let leaf_number = self.inner.get(leaf).unwrap().number;
@@ -116,7 +203,7 @@ pub mod chain {
 					return Some((leaf, leaf_number))
 				}
 
-				if let Ok(_) = self.ancestry(base, leaf) {
+				if self.ancestry(base, leaf).is_ok() {
 					return Some((leaf, leaf_number));
 				}
 			}
@@ -126,20 +213,547 @@ pub mod chain {
 	}
 }
 
+pub mod arbitrary {
+	//! Decoding of arbitrary bytes into a round of voting, for fuzzing and
+	//! property tests of the `Round` state machine.
+	//!
+	//! Any input decodes to some scenario, so that fuzzers don't waste time on
+	//! inputs which would be rejected. Inputs which run out early are padded
+	//! with zeroes.
+
+	use super::chain::{DummyChain, GENESIS_HASH};
+	use crate::round::{Round, RoundParams};
+	use crate::voter_set::VoterSet;
+	use crate::{Chain, Prevote, Precommit};
+	use crate::std::{collections::HashMap, vec::Vec};
+
+	// the blocks which can be built on top of genesis, in order.
+	const BLOCKS: [&str; 26] = [
+		"A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L", "M",
+		"N", "O", "P", "Q", "R", "S", "T", "U", "V", "W", "X", "Y", "Z",
+	];
+	const MAX_VOTERS: usize = 10;
+	const MAX_WEIGHT: usize = 4;
+	const MAX_VOTES: usize = 256;
+
+	struct Input<'a>(&'a [u8]);
+
+	impl<'a> Input<'a> {
+		fn is_empty(&self) -> bool {
+			self.0.is_empty()
+		}
+
+		fn byte(&mut self) -> u8 {
+			match self.0.split_first() {
+				Some((byte, rest)) => {
+					self.0 = rest;
+					*byte
+				}
+				None => 0,
+			}
+		}
+
+		// a number in `0..n`.
+		fn below(&mut self, n: usize) -> usize {
+			self.byte() as usize % n
+		}
+	}
+
+	/// A vote to import into the round.
+	#[derive(Clone)]
+	#[cfg_attr(any(feature = "std", test), derive(Debug))]
+	pub enum Vote {
+		/// A prevote.
+		Prevote(Prevote<&'static str, u32>),
+		/// A precommit.
+		Precommit(Precommit<&'static str, u32>),
+	}
+
+	/// A chain with forks, a voter set and base of a round on it, and votes to
+	/// import into the round in order. Votes may be for blocks which aren't
+	/// descendents of the base, by voters who aren't in the set, or be
+	/// equivocations of voters within the tolerated faulty weight. In some
+	/// scenarios, votes may also claim numbers for blocks which don't match
+	/// the chain.
+	pub struct Scenario {
+		/// The chain the votes are on.
+		pub chain: DummyChain,
+		/// The voters of the round.
+		pub voters: VoterSet<u32>,
+		/// The base of the round.
+		pub base: (&'static str, u32),
+		/// The votes to import, with the id of the voter.
+		pub votes: Vec<(u32, Vote)>,
+		/// Whether votes may be for made-up block numbers.
+		pub made_up_numbers: bool,
+	}
+
+	impl Scenario {
+		/// Decode a scenario from the given bytes.
+		pub fn decode(data: &[u8]) -> Self {
+			let mut input = Input(data);
+
+			// every block is built on genesis or an earlier block.
+			let mut chain = DummyChain::new();
+			let mut blocks = vec![GENESIS_HASH];
+			for block in &BLOCKS[..input.below(BLOCKS.len() + 1)] {
+				let parent = blocks[input.below(blocks.len())];
+				chain.push_blocks(parent, &[block]);
+				blocks.push(block);
+			}
+
+			let base = blocks[input.below(blocks.len())];
+			let base = (base, chain.number(base));
+
+			let num_voters = 1 + input.below(MAX_VOTERS);
+			let voters = (0..num_voters as u32).map(|id| (id, 1 + input.below(MAX_WEIGHT) as u64));
+			let voters = VoterSet::new(voters).ok().expect("voters are distinct and have non-zero weight; qed");
+
+			// the invariants only hold while equivocators stay within the
+			// tolerated faulty weight, so only some voters may equivocate.
+			let mut faulty_budget = voters.faulty_weight();
+			let faulty: Vec<bool> = voters.iter().map(|(_, _, weight)| {
+				let faulty = weight <= faulty_budget && input.byte() % 2 == 1;
+				if faulty {
+					faulty_budget -= weight;
+				}
+				faulty
+			}).collect();
+
+			let made_up_numbers = input.below(4) == 0;
+
+			// the first target of each honest voter, by phase.
+			let mut honest_targets = HashMap::new();
+
+			let mut votes = Vec::new();
+			while !input.is_empty() && votes.len() < MAX_VOTES {
+				// one more than the voters, for votes by an unknown voter.
+				let id = input.below(num_voters + 1) as u32;
+				let precommit = input.byte() % 2 == 1;
+				let target = blocks[input.below(blocks.len())];
+				let number = if made_up_numbers && input.below(2) == 0 {
+					input.byte() as u32
+				} else {
+					chain.number(target)
+				};
+				let mut target = (target, number);
+				if !faulty.get(id as usize).cloned().unwrap_or(true) {
+					target = *honest_targets.entry((id, precommit)).or_insert(target);
+				}
+
+				let vote = if precommit {
+					Vote::Precommit(Precommit::new(target.0, target.1))
+				} else {
+					Vote::Prevote(Prevote::new(target.0, target.1))
+				};
+				votes.push((id, vote));
+			}
+
+			Scenario { chain, voters, base, votes, made_up_numbers }
+		}
+
+		/// A round on the voters and base of the scenario, without any votes.
+		pub fn round(&self) -> Round<u32, &'static str, u32, u32> {
+			Round::new(RoundParams {
+				round_number: 1,
+				set_id: 0,
+				voters: self.voters.clone(),
+				base: self.base,
+			})
+		}
+
+		/// Import all votes into a new round, checking its invariants after
+		/// every vote. Panics if any of them is violated. With made-up block
+		/// numbers, blocks can't be told apart by number, so only importing
+		/// is checked not to panic.
+		pub fn run(&self) -> Round<u32, &'static str, u32, u32> {
+			let mut round = self.round();
+			let mut completable = false;
+
+			for (id, vote) in &self.votes {
+				let prior_estimate = round.estimate().cloned();
+
+				// votes for blocks which aren't descendents of the base fail.
+				let inconsistent = match vote {
+					Vote::Prevote(prevote) => round.import_prevote(&self.chain, prevote.clone(), *id, *id)
+						.is_ok_and(|res| res.inconsistent_estimate),
+					Vote::Precommit(precommit) => round.import_precommit(&self.chain, precommit.clone(), *id, *id)
+						.is_ok_and(|res| res.inconsistent_estimate),
+				};
+				if self.made_up_numbers { continue }
+
+				assert!(!inconsistent, "round estimate is inconsistent");
+
+				self.check(&round, completable);
+				self.check_estimate_moved(prior_estimate, round.estimate(), vote);
+				completable = round.completable();
+			}
+
+			round
+		}
+
+		fn check(&self, round: &Round<u32, &'static str, u32, u32>, was_completable: bool) {
+			let total_weight = self.voters.total_weight();
+			let (prevote_weight, precommit_weight) = round.vote_weight();
+			assert!(prevote_weight <= total_weight, "prevote weight exceeds total weight");
+			assert!(precommit_weight <= total_weight, "precommit weight exceeds total weight");
+
+			let (prevote_equivocations, precommit_equivocations) = round.equivocation_weight();
+			assert!(prevote_equivocations <= prevote_weight, "equivocators outweigh prevoters");
+			assert!(precommit_equivocations <= precommit_weight, "equivocators outweigh precommitters");
+
+			if let (Some(estimate), Some(ghost)) = (round.estimate(), round.state().prevote_ghost) {
+				assert!(
+					self.chain.is_equal_or_descendent_of(estimate.0, ghost.0),
+					"estimate is not an ancestor of the prevote ghost",
+				);
+			}
+
+			if let Some(finalized) = round.finalized() {
+				let estimate = round.estimate().expect("a round with a finalized block has an estimate");
+				assert!(finalized.1 <= estimate.1, "finalized block is above the estimate");
+			}
+
+			assert!(!was_completable || round.completable(), "round is no longer completable");
+		}
+
+		// prevotes may only move the estimate up its chain, and precommits
+		// only back down it.
+		fn check_estimate_moved(&self, prior: Option<(&'static str, u32)>, estimate: Option<&(&'static str, u32)>, vote: &Vote) {
+			let prior = match prior {
+				None => return,
+				Some(prior) => prior,
+			};
+			let estimate = estimate.expect("a round with an estimate keeps one");
+
+			match vote {
+				Vote::Prevote(_) => assert!(
+					self.chain.is_equal_or_descendent_of(prior.0, estimate.0),
+					"estimate moved down on a prevote",
+				),
+				Vote::Precommit(_) => assert!(
+					self.chain.is_equal_or_descendent_of(estimate.0, prior.0),
+					"estimate moved up on a precommit",
+				),
+			}
+		}
+	}
+}
+
+pub mod model {
+	//! A slow but obviously correct model of a round of voting, which
+	//! recomputes its state from scratch by brute force over all blocks and
+	//! votes, for differential tests of the `Round` state machine.
+
+	use super::chain::DummyChain;
+	use crate::round::State;
+	use crate::voter_set::VoterSet;
+	use crate::{Chain, Prevote, Precommit};
+	use crate::std::{collections::HashMap, vec::Vec};
+
+	// the distinct targets of a voter's votes in one phase: at most two are
+	// kept, and two of them make the voter an equivocator.
+	type Votes = HashMap<u32, Vec<(&'static str, u32)>>;
+
+	/// A round of voting on a `DummyChain`, with the same vote import and
+	/// equivocation rules as `Round`.
+	pub struct NaiveRound {
+		voters: VoterSet<u32>,
+		base: (&'static str, u32),
+		prevotes: Votes,
+		precommits: Votes,
+	}
+
+	impl NaiveRound {
+		/// Create a round with the given voters and base, without any votes.
+		pub fn new(voters: VoterSet<u32>, base: (&'static str, u32)) -> Self {
+			NaiveRound { voters, base, prevotes: Votes::new(), precommits: Votes::new() }
+		}
+
+		/// Import a prevote. Returns false if it was rejected for being for a
+		/// block which isn't a descendent of the base.
+		pub fn import_prevote(&mut self, chain: &DummyChain, vote: Prevote<&'static str, u32>, id: u32) -> bool {
+			let base = self.base;
+			Self::import(&self.voters, base, &mut self.prevotes, chain, (vote.target_hash, vote.target_number), id)
+		}
+
+		/// Import a precommit. Returns false if it was rejected for being for a
+		/// block which isn't a descendent of the base.
+		pub fn import_precommit(&mut self, chain: &DummyChain, vote: Precommit<&'static str, u32>, id: u32) -> bool {
+			let base = self.base;
+			Self::import(&self.voters, base, &mut self.precommits, chain, (vote.target_hash, vote.target_number), id)
+		}
+
+		fn import(
+			voters: &VoterSet<u32>,
+			base: (&'static str, u32),
+			votes: &mut Votes,
+			chain: &DummyChain,
+			target: (&'static str, u32),
+			id: u32,
+		) -> bool {
+			if !voters.contains(&id) { return true }
+
+			// only the first vote of a voter has to be on the base, later ones
+			// just make it an equivocator.
+			match votes.get_mut(&id) {
+				None if !chain.is_equal_or_descendent_of(base.0, target.0) => return false,
+				None => { votes.insert(id, vec![target]); }
+				Some(targets) if targets.len() == 1 && targets[0] != target => targets.push(target),
+				Some(_) => {}
+			}
+
+			true
+		}
+
+		/// The state of the round, recomputed from all votes imported so far.
+		pub fn state(&self, chain: &DummyChain) -> State<&'static str, u32> {
+			let threshold = self.voters.threshold();
+			let blocks = self.blocks(chain);
+
+			let mut state = State {
+				prevote_ghost: None,
+				finalized: None,
+				estimate: None,
+				completable: false,
+			};
+
+			if self.weight(&self.prevotes) < threshold { return state }
+
+			// the highest block with a supermajority of prevotes. there can't
+			// be two of them at the same height while equivocators stay within
+			// the tolerated faulty weight.
+			let ghost = blocks.iter()
+				.filter(|block| self.weight_for(chain, &self.prevotes, **block) >= threshold)
+				.max_by_key(|block| block.1)
+				.cloned()
+				.expect("the base has all prevotes; qed");
+
+			state.prevote_ghost = Some(ghost);
+			state.estimate = Some(ghost);
+
+			let precommitted = self.weight(&self.precommits);
+			if precommitted < threshold { return state }
+
+			// a block could still get a supermajority of precommits if all
+			// remaining voters precommit for it, and enough of those who
+			// precommitted elsewhere equivocate.
+			let equivocated = self.equivocated_weight(&self.precommits);
+			let remaining = self.voters.total_weight() - precommitted;
+			let equivocations_left = self.voters.faulty_weight().saturating_sub(equivocated);
+			let possible = |block: (&'static str, u32)| {
+				let precommitted_for = self.weight_for(chain, &self.precommits, block);
+				let elsewhere = precommitted - precommitted_for;
+				precommitted_for + remaining + elsewhere.min(equivocations_left) >= threshold
+			};
+
+			let ghost_and_ancestors = blocks.iter()
+				.filter(|block| chain.is_equal_or_descendent_of(block.0, ghost.0))
+				.cloned()
+				.collect::<Vec<_>>();
+
+			state.finalized = ghost_and_ancestors.iter()
+				.filter(|block| self.weight_for(chain, &self.precommits, **block) >= threshold)
+				.max_by_key(|block| block.1)
+				.cloned();
+
+			state.estimate = ghost_and_ancestors.iter()
+				.filter(|block| possible(**block))
+				.max_by_key(|block| block.1)
+				.cloned();
+
+			state.completable = match state.estimate {
+				None => false,
+				Some(estimate) if estimate != ghost => true,
+				Some(_) => !blocks.iter().any(|block| {
+					*block != ghost && chain.is_equal_or_descendent_of(ghost.0, block.0) && possible(*block)
+				}),
+			};
+
+			state
+		}
+
+		// the base, all targets, and all blocks between them. any other block
+		// has no votes, and so can't be the ghost, estimate or finalized.
+		fn blocks(&self, chain: &DummyChain) -> Vec<(&'static str, u32)> {
+			let mut blocks = vec![self.base];
+			let targets = self.prevotes.values().chain(self.precommits.values()).flatten();
+			for target in targets {
+				let ancestry = match chain.ancestry(self.base.0, target.0) {
+					Ok(ancestry) => ancestry,
+					Err(_) => continue,
+				};
+
+				blocks.push(*target);
+				for (i, hash) in ancestry.into_iter().enumerate() {
+					blocks.push((hash, target.1 - 1 - i as u32));
+				}
+			}
+
+			blocks.sort();
+			blocks.dedup();
+			blocks
+		}
+
+		fn weight(&self, votes: &Votes) -> u64 {
+			votes.keys().map(|id| self.voter_weight(id)).sum()
+		}
+
+		fn equivocated_weight(&self, votes: &Votes) -> u64 {
+			votes.iter()
+				.filter(|(_, targets)| targets.len() > 1)
+				.map(|(id, _)| self.voter_weight(id))
+				.sum()
+		}
+
+		// equivocators count towards every block.
+		fn weight_for(&self, chain: &DummyChain, votes: &Votes, block: (&'static str, u32)) -> u64 {
+			votes.iter()
+				.filter(|(_, targets)| targets.len() > 1 || chain.is_equal_or_descendent_of(block.0, targets[0].0))
+				.map(|(id, _)| self.voter_weight(id))
+				.sum()
+		}
+
+		fn voter_weight(&self, id: &u32) -> u64 {
+			self.voters.get(id).map(|info| info.weight()).unwrap_or(0)
+		}
+	}
+}
+
 #[cfg(feature = "std")]
+pub mod clock {
+	use crate::voter::Clock;
+	use futures::prelude::*;
+	#[cfg(not(target_arch = "wasm32"))]
+	use futures_timer::Delay;
+	use parking_lot::Mutex;
+	use std::pin::Pin;
+	use std::sync::Arc;
+	use std::task::{Context, Poll, Waker};
+	use std::time::{Duration, Instant};
+
+	/// A clock measuring the time since it was created. It runs in real time,
+	/// unless it's manual, in which case it's only ever advanced explicitly.
+	/// There's no system time on wasm, so clocks are always manual there.
+	#[derive(Clone)]
+	pub struct TestClock {
+		epoch: Option<Instant>, // if running in real time.
+		manual: Option<Arc<Mutex<ManualTime>>>,
+	}
+
+	#[derive(Default)]
+	struct ManualTime {
+		now: Duration,
+		wakers: Vec<Waker>, // of pending timers.
+	}
+
+	impl TestClock {
+		#[cfg(not(target_arch = "wasm32"))]
+		pub fn new() -> Self {
+			TestClock { epoch: Some(Instant::now()), manual: None }
+		}
+
+		#[cfg(target_arch = "wasm32")]
+		pub fn new() -> Self {
+			TestClock::manual()
+		}
+
+		pub fn manual() -> Self {
+			TestClock { epoch: None, manual: Some(Default::default()) }
+		}
+
+		fn epoch(&self) -> Instant {
+			self.epoch.expect("clocks which aren't manual have an epoch; qed")
+		}
+
+		/// Advance a manual clock by the given duration, waking any pending
+		/// timers.
+		pub fn advance(&self, duration: Duration) {
+			let manual = self.manual.as_ref().expect("only manual clocks are advanced; qed");
+
+			let wakers = {
+				let mut manual = manual.lock();
+				manual.now += duration;
+				std::mem::take(&mut manual.wakers)
+			};
+
+			for waker in wakers {
+				waker.wake();
+			}
+		}
+	}
+
+	impl Default for TestClock {
+		fn default() -> Self {
+			TestClock::new()
+		}
+	}
+
+	impl Clock for TestClock {
+		type Instant = Duration;
+		type Timer = Pin<Box<dyn Future<Output=()> + Send + 'static>>;
+
+		fn now(&self) -> Duration {
+			match self.manual {
+				Some(ref manual) => manual.lock().now,
+				None => self.epoch().elapsed(),
+			}
+		}
+
+		fn timer_at(&self, at: Duration) -> Self::Timer {
+			match self.manual {
+				Some(ref manual) => Box::pin(ManualTimer { at, manual: manual.clone() }),
+				#[cfg(not(target_arch = "wasm32"))]
+				None => Box::pin(Delay::new_at(self.epoch() + at).map(|_| ())),
+				#[cfg(target_arch = "wasm32")]
+				None => unreachable!("clocks are always manual on wasm; qed"),
+			}
+		}
+	}
+
+	struct ManualTimer {
+		at: Duration,
+		manual: Arc<Mutex<ManualTime>>,
+	}
+
+	impl Future for ManualTimer {
+		type Output = ();
+
+		fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+			let mut manual = self.manual.lock();
+			if manual.now >= self.at {
+				Poll::Ready(())
+			} else {
+				manual.wakers.push(cx.waker().clone());
+				Poll::Pending
+			}
+		}
+	}
+}
+
+#[cfg(all(test, feature = "std"))]
 pub mod environment {
+	use super::clock::TestClock;
 	use super::chain::*;
 	use crate::round::State as RoundState;
-	use crate::voter::{RoundData, CommunicationIn, CommunicationOut, Callback};
-	use crate::{Chain, Commit, Error, Equivocation, Message, Prevote, Precommit, PrimaryPropose, SignedMessage, HistoricalVotes};
+	use crate::voter::{
+		BestChainIssue, Clock, RoundData, CommunicationIn, CommunicationOut, Callback, Phase, RoundStateStream,
+		TooManyEquivocations,
+	};
+	use crate::{
+		Chain, Commit, Error, Equivocation, Message, Prevote, Precommit, PrimaryPropose, SignedCommit,
+		SignedMessage, HistoricalVotes,
+	};
 	use futures::prelude::*;
+	use futures::future::BoxFuture;
 	use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
-	use futures_timer::Delay;
 	use parking_lot::Mutex;
-	use std::collections::HashMap;
+	use rand::{Rng, SeedableRng, rngs::StdRng};
+	use std::collections::{BTreeMap, HashSet};
+	use std::ops::Range;
 	use std::pin::Pin;
 	use std::sync::Arc;
-	use std::task::{Context, Poll};
+	use std::task::{Context, Poll, Waker};
 	use std::time::Duration;
 
 	#[derive(Hash, Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd)]
@@ -148,13 +762,64 @@ pub mod environment {
 	#[derive(Debug, Clone, PartialEq, Eq)]
 	pub struct Signature(pub u32);
 
+	/// A report of stalled or resumed finality, with the number of rounds
+	/// without progress and the last finalized block number.
+	#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+	pub enum StallReport {
+		Stalled(u64, u32),
+		Resumed(u64, u32),
+	}
+
+	/// A finalized block along with the round and commit that finalized it.
+	pub type FinalizedNotification = (&'static str, u32, Commit<&'static str, u32, Signature, Id>);
+
+	/// A commit which wasn't sent, along with its round.
+	pub type CommitNotSent = (u64, Commit<&'static str, u32, Signature, Id>);
+
 	pub struct Environment {
 		chain: Mutex<DummyChain>,
 		local_id: Id,
 		network: Network,
-		listeners: Mutex<Vec<UnboundedSender<(&'static str, u32, Commit<&'static str, u32, Signature, Id>)>>>,
+		listeners: Mutex<Vec<UnboundedSender<FinalizedNotification>>>,
+		allow_equivocations: bool,
+		equivocations: Mutex<Vec<(u64, Id)>>,
+		too_many_equivocations: Mutex<Vec<TooManyEquivocations<Id>>>,
+		prior_round_states: Mutex<Vec<(u64, RoundStateStream<&'static str, u32>)>>,
+		commit_delay: Option<Duration>,
+		failing_commit_timers: Vec<u64>,
+		timer_requests: Mutex<Vec<(u64, Phase)>>,
+		primary_proposal: Option<Box<PrimaryProposalFn>>,
+		vote_restriction: Option<Box<VoteRestrictionFn>>,
+		proposals: Mutex<Vec<(u64, PrimaryPropose<&'static str, u32>)>>,
+		prevotes: Mutex<Vec<(u64, Prevote<&'static str, u32>)>>,
+		precommits: Mutex<Vec<(u64, Precommit<&'static str, u32>)>>,
+		best_chain: Mutex<Option<Option<(&'static str, u32)>>>,
+		best_chain_issues: Mutex<Vec<(u64, BestChainIssue<&'static str, u32>)>>,
+		global_out_errors: Mutex<Vec<Error>>,
+		commits_not_sent: Mutex<Vec<CommitNotSent>>,
+		stall_reports: Mutex<Vec<StallReport>>,
+		catch_up_requests: Mutex<Vec<u64>>,
+		set_id: u64,
+		best_chain_lookup: BestChainLookup,
+		clock: TestClock,
+		sign_commits: bool,
+		report_last_finalized: bool,
+	}
+
+	// how lookups of the best chain are answered.
+	#[derive(Clone, Copy)]
+	enum BestChainLookup {
+		Immediate,
+		Delayed(Duration),
+		Never,
 	}
 
+	type PrimaryProposalFn = dyn Fn(PrimaryPropose<&'static str, u32>) -> Option<PrimaryPropose<&'static str, u32>>
+		+ Send + Sync;
+
+	type VoteRestrictionFn = dyn Fn(&DummyChain, Phase, (&'static str, u32), (&'static str, u32))
+		-> Option<(&'static str, u32)> + Send + Sync;
+
 	impl Environment {
 		pub fn new(network: Network, local_id: Id) -> Self {
 			Environment {
@@ -162,16 +827,183 @@ pub mod environment {
 				local_id,
 				network,
 				listeners: Mutex::new(Vec::new()),
+				allow_equivocations: false,
+				equivocations: Mutex::new(Vec::new()),
+				too_many_equivocations: Mutex::new(Vec::new()),
+				prior_round_states: Mutex::new(Vec::new()),
+				commit_delay: None,
+				failing_commit_timers: Vec::new(),
+				timer_requests: Mutex::new(Vec::new()),
+				primary_proposal: None,
+				vote_restriction: None,
+				proposals: Mutex::new(Vec::new()),
+				prevotes: Mutex::new(Vec::new()),
+				precommits: Mutex::new(Vec::new()),
+				best_chain: Mutex::new(None),
+				best_chain_issues: Mutex::new(Vec::new()),
+				global_out_errors: Mutex::new(Vec::new()),
+				commits_not_sent: Mutex::new(Vec::new()),
+				stall_reports: Mutex::new(Vec::new()),
+				catch_up_requests: Mutex::new(Vec::new()),
+				set_id: 0,
+				best_chain_lookup: BestChainLookup::Immediate,
+				clock: TestClock::new(),
+				sign_commits: false,
+				report_last_finalized: false,
 			}
 		}
 
+		/// Time the voter and all timers with the given clock, instead of
+		/// one running in real time.
+		pub fn with_clock(mut self, clock: TestClock) -> Self {
+			self.clock = clock;
+			self
+		}
+
+		/// Sign outgoing commits with the local id, and check that incoming
+		/// ones are signed by their sender.
+		pub fn with_commit_signing(mut self) -> Self {
+			self.sign_commits = true;
+			self
+		}
+
+		/// Vote in the voter set with the given id.
+		pub fn with_set_id(mut self, set_id: u64) -> Self {
+			self.set_id = set_id;
+			self
+		}
+
+		/// Answer lookups of the best chain only after the given delay.
+		pub fn with_best_chain_delay(mut self, delay: Duration) -> Self {
+			self.best_chain_lookup = BestChainLookup::Delayed(delay);
+			self
+		}
+
+		/// Never answer lookups of the best chain.
+		pub fn with_unresolved_best_chain(mut self) -> Self {
+			self.best_chain_lookup = BestChainLookup::Never;
+			self
+		}
+
+		/// Use a fixed delay for commit timers instead of a random one.
+		pub fn with_commit_delay(mut self, commit_delay: Duration) -> Self {
+			self.commit_delay = Some(commit_delay);
+			self
+		}
+
+		/// Fail the commit timer of the given round.
+		pub fn with_failing_commit_timer(mut self, round: u64) -> Self {
+			self.failing_commit_timers.push(round);
+			self
+		}
+
+		/// Decide on primary proposals with the given function, instead of
+		/// proposing the last round estimate.
+		pub fn with_primary_proposal<F>(mut self, f: F) -> Self where
+			F: Fn(PrimaryPropose<&'static str, u32>) -> Option<PrimaryPropose<&'static str, u32>>
+				+ Send + Sync + 'static,
+		{
+			self.primary_proposal = Some(Box::new(f));
+			self
+		}
+
+		/// Restrict vote targets with the given function, given the chain,
+		/// the phase, the base and the current target of the vote.
+		pub fn with_vote_restriction<F>(mut self, f: F) -> Self where
+			F: Fn(&DummyChain, Phase, (&'static str, u32), (&'static str, u32)) -> Option<(&'static str, u32)>
+				+ Send + Sync + 'static,
+		{
+			self.vote_restriction = Some(Box::new(f));
+			self
+		}
+
+		/// Report the last finalized block of the chain through
+		/// `Chain::last_finalized`.
+		pub fn reporting_last_finalized(mut self) -> Self {
+			self.report_last_finalized = true;
+			self
+		}
+
+		/// Don't panic when encountering equivocations, but record them.
+		pub fn allowing_equivocations(mut self) -> Self {
+			self.allow_equivocations = true;
+			self
+		}
+
+		/// Take the equivocations reported so far, by round and equivocator,
+		/// in prevotes and precommits alike.
+		pub fn take_equivocations(&self) -> Vec<(u64, Id)> {
+			std::mem::take(&mut *self.equivocations.lock())
+		}
+
+		/// All reports of too many equivocations received so far.
+		pub fn equivocation_reports(&self) -> Vec<TooManyEquivocations<Id>> {
+			self.too_many_equivocations.lock().clone()
+		}
+
+		/// Take the streams of prior round states handed out so far, along with
+		/// the number of the round that was started.
+		pub fn take_prior_round_states(&self) -> Vec<(u64, RoundStateStream<&'static str, u32>)> {
+			std::mem::take(&mut *self.prior_round_states.lock())
+		}
+
+		/// Take the round timers requested so far, in order.
+		pub fn take_timer_requests(&self) -> Vec<(u64, Phase)> {
+			std::mem::take(&mut *self.timer_requests.lock())
+		}
+
+		/// Take the primary proposals made so far, along with their round.
+		pub fn take_proposals(&self) -> Vec<(u64, PrimaryPropose<&'static str, u32>)> {
+			std::mem::take(&mut *self.proposals.lock())
+		}
+
+		/// Take the prevotes cast so far, along with their round.
+		pub fn take_prevotes(&self) -> Vec<(u64, Prevote<&'static str, u32>)> {
+			std::mem::take(&mut *self.prevotes.lock())
+		}
+
+		/// Take the precommits cast so far, along with their round.
+		pub fn take_precommits(&self) -> Vec<(u64, Precommit<&'static str, u32>)> {
+			std::mem::take(&mut *self.precommits.lock())
+		}
+
+		/// Answer all queries for the best chain with the given block, e.g. to
+		/// simulate a reorg the voter doesn't know about. `None` clears it.
+		pub fn set_best_chain(&self, best_chain: Option<Option<(&'static str, u32)>>) {
+			*self.best_chain.lock() = best_chain;
+		}
+
+		/// Take the issues with the best chain reported so far, along with
+		/// their round.
+		pub fn take_best_chain_issues(&self) -> Vec<(u64, BestChainIssue<&'static str, u32>)> {
+			std::mem::take(&mut *self.best_chain_issues.lock())
+		}
+
+		pub fn take_global_out_errors(&self) -> Vec<Error> {
+			std::mem::take(&mut *self.global_out_errors.lock())
+		}
+
+		/// Take the commits reported as not sent so far, along with their round.
+		pub fn take_commits_not_sent(&self) -> Vec<CommitNotSent> {
+			std::mem::take(&mut *self.commits_not_sent.lock())
+		}
+
+		pub fn take_stall_reports(&self) -> Vec<StallReport> {
+			std::mem::take(&mut *self.stall_reports.lock())
+		}
+
+		/// Take the rounds a catch-up was requested to so far.
+		pub fn take_catch_up_requests(&self) -> Vec<u64> {
+			std::mem::take(&mut *self.catch_up_requests.lock())
+		}
+
 		pub fn with_chain<F, U>(&self, f: F) -> U where F: FnOnce(&mut DummyChain) -> U {
 			let mut chain = self.chain.lock();
-			f(&mut *chain)
+			f(&mut chain)
 		}
 
 		/// Stream of finalized blocks.
-		pub fn finalized_stream(&self) -> UnboundedReceiver<(&'static str, u32, Commit<&'static str, u32, Signature, Id>)> {
+		pub fn finalized_stream(&self) -> UnboundedReceiver<FinalizedNotification> {
 			let (tx, rx) = mpsc::unbounded();
 			self.listeners.lock().push(tx);
 			rx
@@ -184,7 +1016,18 @@ pub mod environment {
 		}
 
 		fn best_chain_containing(&self, base: &'static str) -> Option<(&'static str, u32)> {
-			self.chain.lock().best_chain_containing(base)
+			match *self.best_chain.lock() {
+				Some(best_chain) => best_chain,
+				None => self.chain.lock().best_chain_containing(base),
+			}
+		}
+
+		fn last_finalized(&self) -> Option<(&'static str, u32)> {
+			if self.report_last_finalized {
+				Some(self.chain.lock().last_finalized())
+			} else {
+				None
+			}
 		}
 	}
 
@@ -195,6 +1038,50 @@ pub mod environment {
 		type In = Pin<Box<dyn Stream<Item=Result<SignedMessage<&'static str, u32, Signature, Id>,Error>> + Send + 'static>>;
 		type Out = Pin<Box<dyn Sink<Message<&'static str, u32>,Error=Error> + Send + 'static>>;
 		type Error = Error;
+		type Clock = TestClock;
+
+		fn clock(&self) -> &TestClock {
+			&self.clock
+		}
+
+		fn best_chain(&self, base: &'static str) -> Option<BoxFuture<'static, Result<Option<(&'static str, u32)>, Error>>> {
+			let best_chain = self.best_chain_containing(base);
+			match self.best_chain_lookup {
+				BestChainLookup::Immediate => None,
+				BestChainLookup::Delayed(delay) => Some(Box::pin(self.clock.timer(delay).map(move |_| Ok(best_chain)))),
+				BestChainLookup::Never => Some(Box::pin(future::pending())),
+			}
+		}
+
+		fn set_id(&self) -> u64 {
+			self.set_id
+		}
+
+		#[allow(clippy::type_complexity)]
+		fn sign_commit(
+			&self,
+			_round: u64,
+			commit: &Commit<&'static str, u32, Signature, Id>,
+		) -> Option<BoxFuture<'static, Result<SignedCommit<&'static str, u32, Signature, Id>, Error>>> {
+			if !self.sign_commits {
+				return None;
+			}
+
+			let signed = SignedCommit {
+				commit: commit.clone(),
+				signature: Signature(self.local_id.0),
+				id: self.local_id,
+			};
+			Some(Box::pin(future::ready(Ok(signed))))
+		}
+
+		fn verify_commit_signature(
+			&self,
+			_round: u64,
+			commit: &SignedCommit<&'static str, u32, Signature, Id>,
+		) -> bool {
+			self.sign_commits && commit.signature.0 == commit.id.0
+		}
 
 		fn round_data(&self, round: u64) -> RoundData<Self::Id, Self::Timer, Self::In, Self::Out> {
 			const GOSSIP_DURATION: Duration = Duration::from_millis(500);
@@ -202,10 +1089,8 @@ pub mod environment {
 			let (incoming, outgoing) = self.network.make_round_comms(round, self.local_id);
 			RoundData {
 				voter_id: Some(self.local_id),
-				prevote_timer: Box::pin(Delay::new(GOSSIP_DURATION)
-										.map_err(|_| panic!("Timer failed"))),
-				precommit_timer: Box::pin(Delay::new(GOSSIP_DURATION + GOSSIP_DURATION)
-										  .map_err(|_| panic!("Timer failed"))),
+				prevote_timer: Box::pin(self.clock.timer(GOSSIP_DURATION).map(Ok)),
+				precommit_timer: Box::pin(self.clock.timer(GOSSIP_DURATION + GOSSIP_DURATION).map(Ok)),
 				incoming: Box::pin(incoming),
 				outgoing: Box::pin(outgoing),
 			}
@@ -216,10 +1101,21 @@ pub mod environment {
 
 			const COMMIT_DELAY_MILLIS: u64 = 100;
 
-			let delay = Duration::from_millis(
-				rand::thread_rng().gen_range(0, COMMIT_DELAY_MILLIS));
+			let delay = self.commit_delay.unwrap_or_else(|| Duration::from_millis(
+				rand::thread_rng().gen_range(0, COMMIT_DELAY_MILLIS)));
 
-			Box::pin(Delay::new(delay).map_err(|_| panic!("Timer failed")))
+			Box::pin(self.clock.timer(delay).map(Ok))
+		}
+
+		fn round_timer(&self, round: u64, phase: Phase) -> Option<Self::Timer> {
+			self.timer_requests.lock().push((round, phase));
+
+			if phase == Phase::Commit && self.failing_commit_timers.contains(&round) {
+				// any error will do.
+				return Some(Box::pin(future::err(Error::NotDescendent)));
+			}
+
+			None
 		}
 
 		fn completed(
@@ -236,7 +1132,7 @@ pub mod environment {
 			let mut chain = self.chain.lock();
 
 			let last_finalized = chain.last_finalized();
-			if number as u32 <= last_finalized.1 {
+			if number <= last_finalized.1 {
 				panic!("Attempted to finalize backwards")
 			}
 
@@ -251,33 +1147,203 @@ pub mod environment {
 			Ok(())
 		}
 
-		fn proposed(&self, _round: u64, _propose: PrimaryPropose<&'static str, u32>) -> Result<(), Self::Error> {
+		fn primary_proposal(
+			&self,
+			_round: u64,
+			estimate: PrimaryPropose<&'static str, u32>,
+		) -> Result<Option<PrimaryPropose<&'static str, u32>>, Self::Error> {
+			Ok(match self.primary_proposal {
+				Some(ref f) => f(estimate),
+				None => Some(estimate),
+			})
+		}
+
+		#[allow(clippy::type_complexity)]
+		fn restrict_vote(
+			&self,
+			_round: u64,
+			phase: Phase,
+			base: (&'static str, u32),
+			_best_target: (&'static str, u32),
+			current_target: (&'static str, u32),
+		) -> Option<BoxFuture<'static, Result<Option<(&'static str, u32)>, Error>>> {
+			let f = self.vote_restriction.as_ref()?;
+			let restricted = f(&self.chain.lock(), phase, base, current_target);
+			Some(Box::pin(future::ready(Ok(restricted))))
+		}
+
+		fn proposed(&self, round: u64, propose: PrimaryPropose<&'static str, u32>) -> Result<(), Self::Error> {
+			self.proposals.lock().push((round, propose));
 			Ok(())
 		}
 
-		fn prevoted(&self, _round: u64, _prevote: Prevote<&'static str, u32>) -> Result<(), Self::Error> {
+		fn prevoted(&self, round: u64, prevote: Prevote<&'static str, u32>) -> Result<(), Self::Error> {
+			self.prevotes.lock().push((round, prevote));
 			Ok(())
 		}
 
-		fn precommitted(&self, _round: u64, _precommit: Precommit<&'static str, u32>) -> Result<(), Self::Error> {
+		fn best_chain_issue(&self, round: u64, issue: BestChainIssue<&'static str, u32>) {
+			self.best_chain_issues.lock().push((round, issue));
+		}
+
+		fn precommitted(&self, round: u64, precommit: Precommit<&'static str, u32>) -> Result<(), Self::Error> {
+			self.precommits.lock().push((round, precommit));
 			Ok(())
 		}
 
+		fn global_out_error(&self, error: Error) {
+			self.global_out_errors.lock().push(error);
+		}
+
+		fn commit_not_sent(&self, round: u64, commit: Commit<&'static str, u32, Signature, Id>) {
+			self.commits_not_sent.lock().push((round, commit));
+		}
+
+		fn finality_stalled(&self, rounds_without_progress: u64, last_finalized: u32) {
+			self.stall_reports.lock().push(StallReport::Stalled(rounds_without_progress, last_finalized));
+		}
+
+		fn finality_resumed(&self, rounds_without_progress: u64, finalized: u32) {
+			self.stall_reports.lock().push(StallReport::Resumed(rounds_without_progress, finalized));
+		}
+
+		fn block_available(&self, hash: &'static str) -> bool {
+			self.chain.lock().contains(hash)
+		}
+
+		fn request_catch_up(&self, round: u64) {
+			self.catch_up_requests.lock().push(round);
+		}
+
 		fn prevote_equivocation(&self, round: u64, equivocation: Equivocation<Id, Prevote<&'static str, u32>, Signature>) {
-			panic!("Encountered equivocation in round {}: {:?}", round, equivocation);
+			if !self.allow_equivocations {
+				panic!("Encountered equivocation in round {}: {:?}", round, equivocation);
+			}
+
+			self.equivocations.lock().push((round, equivocation.identity));
 		}
 
 		fn precommit_equivocation(&self, round: u64, equivocation: Equivocation<Id, Precommit<&'static str, u32>, Signature>) {
-			panic!("Encountered equivocation in round {}: {:?}", round, equivocation);
+			if !self.allow_equivocations {
+				panic!("Encountered equivocation in round {}: {:?}", round, equivocation);
+			}
+
+			self.equivocations.lock().push((round, equivocation.identity));
+		}
+
+		fn too_many_equivocations(&self, event: TooManyEquivocations<Id>) -> Result<(), Error> {
+			self.too_many_equivocations.lock().push(event);
+			Ok(())
+		}
+
+		fn prior_round_state(&self, round: u64, states: RoundStateStream<&'static str, u32>) {
+			self.prior_round_states.lock().push((round, states));
+		}
+	}
+
+	/// Conditions of the test network. By default, every message is delivered
+	/// to every peer right away.
+	#[derive(Clone)]
+	pub struct NetworkConfig {
+		/// The range of delays of messages between two peers. Messages sent
+		/// to oneself are delivered right away.
+		pub latency: Range<Duration>,
+		/// The probability of a message between two peers getting lost.
+		pub drop_rate: f64,
+		/// Groups of peers which are cut off from each other. Peers which are
+		/// in no group form a group of their own.
+		pub partitions: Vec<HashSet<Id>>,
+		/// The seed of the delays and losses, so that they're the same in
+		/// every run.
+		pub seed: u64,
+	}
+
+	impl Default for NetworkConfig {
+		fn default() -> Self {
+			NetworkConfig {
+				latency: Duration::default()..Duration::default(),
+				drop_rate: 0.0,
+				partitions: Vec::new(),
+				seed: 0,
+			}
+		}
+	}
+
+	// the conditions of the network, shared by all its broadcasts.
+	struct Conditions {
+		config: NetworkConfig,
+		rng: StdRng,
+		clock: TestClock,
+		routing: Option<Waker>,
+		global_out_failing: bool, // whether sending global messages fails.
+	}
+
+	// how a message from one peer reaches another.
+	enum Delivery {
+		Now,
+		At(Duration),
+		Withheld, // until the peers are connected again.
+		Lost,
+	}
+
+	impl Conditions {
+		// whether two peers are in the same partition. peers without an id,
+		// i.e. observers, are connected to everyone.
+		fn connected(&self, from: Option<Id>, to: Option<Id>) -> bool {
+			match (from, to) {
+				(Some(from), Some(to)) => {
+					let partition = |id| self.config.partitions.iter().position(|peers| peers.contains(&id));
+					partition(from) == partition(to)
+				}
+				_ => true,
+			}
+		}
+
+		fn delivery(&mut self, from: Option<Id>, to: Option<Id>) -> Delivery {
+			if from.is_some() && from == to { return Delivery::Now }
+			if !self.connected(from, to) { return Delivery::Withheld }
+
+			if self.config.drop_rate > 0.0 && self.rng.gen::<f64>() < self.config.drop_rate {
+				return Delivery::Lost;
+			}
+
+			let Range { start, end } = self.config.latency.clone();
+			let latency = if end > start {
+				start + Duration::from_micros(self.rng.gen_range(0, (end - start).as_micros() as u64))
+			} else {
+				start
+			};
+
+			if latency == Duration::default() {
+				Delivery::Now
+			} else {
+				Delivery::At(self.clock.now() + latency)
+			}
+		}
+
+		fn wake_routing(&mut self) {
+			if let Some(waker) = self.routing.take() {
+				waker.wake();
+			}
 		}
 	}
 
+	type Timer = <TestClock as Clock>::Timer;
+
 	// p2p network data for a round.
 	struct BroadcastNetwork<M> {
-		receiver: UnboundedReceiver<M>,
-		raw_sender: UnboundedSender<M>,
-		senders: Vec<UnboundedSender<M>>,
-		history: Vec<M>,
+		receiver: UnboundedReceiver<(Option<Id>, M)>,
+		raw_sender: UnboundedSender<(Option<Id>, M)>,
+		peers: Vec<(Option<Id>, UnboundedSender<M>)>,
+		history: Vec<(Option<Id>, M)>,
+		// messages on their way, along with their sender, the time they
+		// arrive and the peer they arrive at.
+		delayed: Vec<(Option<Id>, Duration, usize, M)>,
+		// messages held back by a partition, along with their sender and the
+		// peer they're for.
+		withheld: Vec<(Option<Id>, usize, M)>,
+		// fires when the next delayed message arrives.
+		timer: Option<Timer>,
 	}
 
 	impl<M: Clone> BroadcastNetwork<M> {
@@ -286,49 +1352,103 @@ pub mod environment {
 			BroadcastNetwork {
 				receiver: rx,
 				raw_sender: tx,
-				senders: Vec::new(),
+				peers: Vec::new(),
 				history: Vec::new(),
+				delayed: Vec::new(),
+				withheld: Vec::new(),
+				timer: None,
 			}
 		}
 
 		pub fn send_message(&self, message: M) {
-			let _ = self.raw_sender.unbounded_send(message);
+			let _ = self.raw_sender.unbounded_send((None, message));
 		}
 
 		// add a node to the network for a round.
-		fn add_node<N, F: Fn(N) -> M>(&mut self, f: F) -> (
+		fn add_node<N, F: Fn(N) -> M>(&mut self, conditions: &mut Conditions, id: Option<Id>, f: F) -> (
 			impl Stream<Item=Result<M,Error>>,
 			impl Sink<N,Error=Error>
 		) {
 			let (tx, rx) = mpsc::unbounded();
 			let messages_out = self.raw_sender.clone()
-				.sink_map_err(|e| panic!("Error sending messages: {:?}", e))
-				.with(move |message| future::ready(Ok(f(message))));
+				.sink_map_err(|e| -> Error { panic!("Error sending messages: {:?}", e) })
+				.with(move |message| future::ready(Ok((id, f(message)))));
+
+			self.peers.push((id, tx));
 
 			// get history to the node.
-			for prior_message in self.history.iter().cloned() {
-				let _ = tx.unbounded_send(prior_message);
+			let peer = self.peers.len() - 1;
+			for (from, prior_message) in self.history.clone() {
+				self.send_to(conditions, from, peer, prior_message);
 			}
 
-			self.senders.push(tx);
-
 			(rx.map(Ok), messages_out)
 		}
 
+		fn send_to(&mut self, conditions: &mut Conditions, from: Option<Id>, peer: usize, message: M) {
+			match conditions.delivery(from, self.peers[peer].0) {
+				Delivery::Now => { let _ = self.peers[peer].1.unbounded_send(message); }
+				Delivery::At(at) => self.delayed.push((from, at, peer, message)),
+				Delivery::Withheld => self.withheld.push((from, peer, message)),
+				Delivery::Lost => {}
+			}
+		}
+
 		// do routing work
-		fn route(&mut self, cx: &mut Context) -> Poll<()> {
+		fn route(&mut self, conditions: &mut Conditions, cx: &mut Context) -> Poll<()> {
+			// messages held back by a partition which healed since.
+			let (connected, withheld) = std::mem::take(&mut self.withheld).into_iter()
+				.partition::<Vec<_>, _>(|(from, peer, _)| conditions.connected(*from, self.peers[*peer].0));
+			self.withheld = withheld;
+			for (from, peer, message) in connected {
+				self.send_to(conditions, from, peer, message);
+			}
+
 			loop {
 				match Stream::poll_next(Pin::new(&mut self.receiver), cx) {
-					Poll::Pending => return Poll::Pending,
+					Poll::Pending => break,
 					Poll::Ready(None) => return Poll::Ready(()),
-					Poll::Ready(Some(item)) => {
-						self.history.push(item.clone());
-						for sender in &self.senders {
-							let _ = sender.unbounded_send(item.clone());
+					Poll::Ready(Some((from, message))) => {
+						self.history.push((from, message.clone()));
+						for peer in 0..self.peers.len() {
+							self.send_to(conditions, from, peer, message.clone());
 						}
 					}
 				}
 			}
+
+			self.deliver_delayed(conditions, cx);
+			Poll::Pending
+		}
+
+		// deliver the delayed messages which arrived by now, in order of
+		// arrival, and wait for the next one.
+		fn deliver_delayed(&mut self, conditions: &mut Conditions, cx: &mut Context) {
+			loop {
+				let now = conditions.clock.now();
+				let (mut arrived, delayed) = std::mem::take(&mut self.delayed).into_iter()
+					.partition::<Vec<_>, _>(|(_, at, _, _)| *at <= now);
+				self.delayed = delayed;
+
+				arrived.sort_by_key(|(_, at, _, _)| *at);
+				for (_, _, peer, message) in arrived {
+					let _ = self.peers[peer].1.unbounded_send(message);
+				}
+
+				let next = match self.delayed.iter().map(|(_, at, _, _)| *at).min() {
+					Some(next) => next,
+					None => {
+						self.timer = None;
+						return;
+					}
+				};
+
+				let mut timer = conditions.clock.timer_at(next);
+				if Future::poll(timer.as_mut(), cx).is_pending() {
+					self.timer = Some(timer);
+					return;
+				}
+			}
 		}
 	}
 
@@ -336,11 +1456,25 @@ pub mod environment {
 	/// Give the network future to node environments and spawn the routing task
 	/// to run.
 	pub fn make_network() -> (Network, NetworkRouting) {
+		make_network_with(NetworkConfig::default(), TestClock::new())
+	}
+
+	/// Make a test network with the given conditions, timing the delays of
+	/// messages with the given clock.
+	pub fn make_network_with(config: NetworkConfig, clock: TestClock) -> (Network, NetworkRouting) {
 		let global_messages = Arc::new(Mutex::new(GlobalMessageNetwork::new()));
-		let rounds = Arc::new(Mutex::new(HashMap::new()));
+		let rounds = Arc::new(Mutex::new(BTreeMap::new()));
+		let conditions = Arc::new(Mutex::new(Conditions {
+			rng: StdRng::seed_from_u64(config.seed),
+			config,
+			clock,
+			routing: None,
+			global_out_failing: false,
+		}));
+
 		(
-			Network { global_messages: global_messages.clone(), rounds: rounds.clone() },
-			NetworkRouting { global_messages, rounds }
+			Network { global_messages: global_messages.clone(), rounds: rounds.clone(), conditions: conditions.clone() },
+			NetworkRouting { global_messages, rounds, conditions }
 		)
 	}
 
@@ -350,59 +1484,157 @@ pub mod environment {
 	/// A test network. Instantiate this with `make_network`,
 	#[derive(Clone)]
 	pub struct Network {
-		rounds: Arc<Mutex<HashMap<u64, RoundNetwork>>>,
+		rounds: Arc<Mutex<BTreeMap<u64, RoundNetwork>>>,
 		global_messages: Arc<Mutex<GlobalMessageNetwork>>,
+		conditions: Arc<Mutex<Conditions>>,
 	}
 
 	impl Network {
+		#[allow(clippy::type_complexity)]
 		pub fn make_round_comms(&self, round_number: u64, node_id: Id) -> (
 			impl Stream<Item=Result<SignedMessage<&'static str, u32, Signature, Id>,Error>>,
 			impl Sink<Message<&'static str, u32>,Error=Error>
 		) {
 			let mut rounds = self.rounds.lock();
+			let mut conditions = self.conditions.lock();
+
+			// the routing task doesn't know of new rounds until it's polled.
 			rounds.entry(round_number)
-				.or_insert_with(RoundNetwork::new)
-				.add_node(move |message| SignedMessage {
+				.or_insert_with(|| {
+					conditions.wake_routing();
+					RoundNetwork::new()
+				})
+				.add_node(&mut conditions, Some(node_id), move |message| SignedMessage {
 					message,
 					signature: Signature(node_id.0),
 					id: node_id,
 				})
 		}
 
+		/// Make global comms for an observer, which is never partitioned off.
+		#[allow(clippy::type_complexity)]
 		pub fn make_global_comms(&self) -> (
 			impl Stream<Item=Result<CommunicationIn<&'static str, u32, Signature, Id>,Error>>,
 			impl Sink<CommunicationOut<&'static str, u32, Signature, Id>,Error=Error>
+		) {
+			self.make_global_comms_with(None)
+		}
+
+		/// Make global comms for the given voter.
+		#[allow(clippy::type_complexity)]
+		pub fn make_global_comms_for(&self, node_id: Id) -> (
+			impl Stream<Item=Result<CommunicationIn<&'static str, u32, Signature, Id>,Error>>,
+			impl Sink<CommunicationOut<&'static str, u32, Signature, Id>,Error=Error>
+		) {
+			self.make_global_comms_with(Some(node_id))
+		}
+
+		#[allow(clippy::type_complexity)]
+		fn make_global_comms_with(&self, node_id: Option<Id>) -> (
+			impl Stream<Item=Result<CommunicationIn<&'static str, u32, Signature, Id>,Error>>,
+			impl Sink<CommunicationOut<&'static str, u32, Signature, Id>,Error=Error>
 		) {
 			let mut global_messages = self.global_messages.lock();
-			global_messages.add_node(|message| match message {
+			let (incoming, outgoing) = global_messages.add_node(&mut self.conditions.lock(), node_id, |message| match message {
 				CommunicationOut::Commit(r, commit) => CommunicationIn::Commit(r, commit.into(), Callback::Blank),
-			})
+				CommunicationOut::SignedCommit(r, signed) => CommunicationIn::SignedCommit(r, signed, Callback::Blank),
+			});
+
+			(incoming, FailingSink { inner: outgoing, conditions: self.conditions.clone() })
 		}
 
 		/// Send a message to all nodes.
 		pub fn send_message(&self, message: CommunicationIn<&'static str, u32, Signature, Id>) {
 			self.global_messages.lock().send_message(message);
 		}
+
+		/// Cut the given peers off from the rest of the network. Messages
+		/// between the two sides are held back until the partition heals.
+		pub fn partition<I: IntoIterator<Item=Id>>(&self, peers: I) {
+			let mut conditions = self.conditions.lock();
+			conditions.config.partitions = vec![peers.into_iter().collect()];
+			conditions.wake_routing();
+		}
+
+		/// Reconnect all peers, delivering the messages held back by
+		/// partitions.
+		pub fn heal(&self) {
+			let mut conditions = self.conditions.lock();
+			conditions.config.partitions.clear();
+			conditions.wake_routing();
+		}
+
+		/// Delay messages sent from now on by a duration in the given range.
+		pub fn set_latency(&self, latency: Range<Duration>) {
+			self.conditions.lock().config.latency = latency;
+		}
+
+		/// Make sending on global comms fail, like on a closed connection,
+		/// until this is called again with `false`.
+		pub fn set_global_out_failing(&self, failing: bool) {
+			self.conditions.lock().global_out_failing = failing;
+		}
+	}
+
+	// a sink which fails to accept messages while the network says so.
+	struct FailingSink<S> {
+		inner: S,
+		conditions: Arc<Mutex<Conditions>>,
+	}
+
+	impl<T, S: Sink<T, Error=Error> + Unpin> Sink<T> for FailingSink<S> {
+		type Error = Error;
+
+		fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+			if self.conditions.lock().global_out_failing {
+				return Poll::Ready(Err(Error::SinkClosed));
+			}
+
+			Sink::poll_ready(Pin::new(&mut self.inner), cx)
+		}
+
+		fn start_send(mut self: Pin<&mut Self>, message: T) -> Result<(), Error> {
+			Sink::start_send(Pin::new(&mut self.inner), message)
+		}
+
+		fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+			Sink::poll_flush(Pin::new(&mut self.inner), cx)
+		}
+
+		fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+			Sink::poll_close(Pin::new(&mut self.inner), cx)
+		}
 	}
 
 	/// the network routing task.
 	pub struct NetworkRouting {
-		rounds: Arc<Mutex<HashMap<u64, RoundNetwork>>>,
+		rounds: Arc<Mutex<BTreeMap<u64, RoundNetwork>>>,
 		global_messages: Arc<Mutex<GlobalMessageNetwork>>,
+		conditions: Arc<Mutex<Conditions>>,
 	}
 
 	impl Future for NetworkRouting {
 		type Output = ();
 
 		fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+			// locks are always taken in this order.
 			let mut rounds = self.rounds.lock();
-			rounds.retain(|_, round| match round.route(cx) {
-				Poll::Ready(()) => false,
-				Poll::Pending => true,
-			});
-
 			let mut global_messages = self.global_messages.lock();
-			let _ = global_messages.route(cx);
+			let mut conditions = self.conditions.lock();
+			conditions.routing = Some(cx.waker().clone());
+
+			let finished: Vec<_> = rounds.iter_mut()
+				.filter_map(|(number, round)| match round.route(&mut conditions, cx) {
+					Poll::Ready(()) => Some(*number),
+					Poll::Pending => None,
+				})
+				.collect();
+
+			for number in finished {
+				rounds.remove(&number);
+			}
+
+			let _ = global_messages.route(&mut conditions, cx);
 
 			Poll::Pending
 		}
@@ -411,3 +1643,197 @@ pub mod environment {
 	impl Unpin for NetworkRouting {
 	}
 }
+
+#[cfg(all(test, feature = "std"))]
+pub mod malicious {
+	//! Byzantine voters taking part in rounds on the test network.
+	//!
+	//! A byzantine voter doesn't run a `Voter`. It follows the rounds the
+	//! honest voters start and, in each, votes for whatever the first honest
+	//! vote it sees targets, deviating from that as configured by its
+	//! `Behaviour`.
+
+	use super::chain::GENESIS_HASH;
+	use super::environment::{Environment, Id, Network, Signature};
+	use crate::voter::CommunicationOut;
+	use crate::{Chain, Commit, Error, Message, Prevote, Precommit, SignedMessage, SignedPrecommit};
+	use futures::prelude::*;
+	use futures::stream::select_all;
+	use std::collections::HashMap;
+	use std::pin::Pin;
+	use std::sync::Arc;
+
+	/// How a byzantine voter misbehaves. Behaviours can be combined, e.g. a
+	/// voter can equivocate and withhold its precommits at the same time.
+	#[derive(Clone, Default)]
+	pub struct Behaviour {
+		equivocate: bool,
+		withhold_precommits: bool,
+		spam_commits: bool,
+		forge_commits_for: Option<(&'static str, u32)>,
+	}
+
+	impl Behaviour {
+		/// Cast two prevotes in every round: one like the honest voters, and
+		/// one for the parent of its target.
+		pub fn equivocating(mut self) -> Self {
+			self.equivocate = true;
+			self
+		}
+
+		/// Never precommit.
+		pub fn withholding_precommits(mut self) -> Self {
+			self.withhold_precommits = true;
+			self
+		}
+
+		/// Flood the network with commits for all prior rounds whenever a new
+		/// round starts.
+		pub fn spamming_commits(mut self) -> Self {
+			self.spam_commits = true;
+			self
+		}
+
+		/// Send a commit for the given block in every round, with the weight
+		/// to finalize it made up of repeated and fabricated precommits.
+		pub fn forging_commits_for(mut self, target: (&'static str, u32)) -> Self {
+			self.forge_commits_for = Some(target);
+			self
+		}
+	}
+
+	// copies of each commit sent when spamming.
+	const SPAM_COPIES: usize = 5;
+	// precommits of voters which aren't in the set, in each forged commit.
+	const FABRICATED_VOTERS: u32 = 10;
+
+	type RoundMessages = Pin<Box<dyn Stream<Item=(u64, SignedMessage<&'static str, u32, Signature, Id>)> + Send>>;
+	type RoundSink = Pin<Box<dyn Sink<Message<&'static str, u32>, Error=Error> + Send>>;
+
+	// the votes cast by the byzantine voter in a round.
+	#[derive(Default)]
+	struct RoundVotes {
+		prevoted: bool,
+		precommitted: Option<Precommit<&'static str, u32>>,
+	}
+
+	/// Run a byzantine voter with the given id. Its chain is the one of the
+	/// given environment, which doesn't run a voter of its own.
+	pub fn byzantine_voter(
+		id: Id,
+		set_id: u64,
+		network: Network,
+		env: Arc<Environment>,
+		behaviour: Behaviour,
+	) -> impl Future<Output=()> + Send {
+		let subscribe = {
+			let network = network.clone();
+			move |round: u64| -> (RoundMessages, RoundSink) {
+				let (incoming, outgoing) = network.make_round_comms(round, id);
+				let incoming = incoming.filter_map(move |message| future::ready(message.ok().map(|m| (round, m))));
+				(Box::pin(incoming), Box::pin(outgoing))
+			}
+		};
+
+		async move {
+			let (_, mut global_out) = network.make_global_comms_for(id);
+			let (incoming, outgoing) = subscribe(1);
+			let mut incoming = select_all(vec![incoming]);
+			let mut outgoing = vec![(1, outgoing)].into_iter().collect::<HashMap<_, _>>();
+			let mut votes = HashMap::<u64, RoundVotes>::new();
+			let mut last_subscribed = 1;
+
+			while let Some((round, SignedMessage { message, id: sender, .. })) = incoming.next().await {
+				if sender == id { continue }
+
+				// the round started, so follow the next one.
+				if round == last_subscribed {
+					let (next_incoming, next_outgoing) = subscribe(round + 1);
+					incoming.push(next_incoming);
+					outgoing.insert(round + 1, next_outgoing);
+					last_subscribed += 1;
+
+					if behaviour.spam_commits {
+						for old_round in 0..round {
+							for commit in spam(old_round, &votes, set_id, id) {
+								let _ = global_out.send(CommunicationOut::Commit(old_round, commit)).await;
+							}
+						}
+					}
+				}
+
+				let sink = outgoing.get_mut(&round).expect("subscribed to every round we receive messages of; qed");
+				let cast = votes.entry(round).or_default();
+
+				match message {
+					Message::Prevote(prevote) if !cast.prevoted => {
+						cast.prevoted = true;
+						let parent = parent(&env, prevote.target_hash);
+						let _ = sink.send(Message::Prevote(prevote)).await;
+
+						if behaviour.equivocate {
+							if let Some((hash, number)) = parent {
+								let _ = sink.send(Message::Prevote(Prevote::new(hash, number))).await;
+							}
+						}
+					}
+					Message::Precommit(precommit) if cast.precommitted.is_none() => {
+						cast.precommitted = Some(precommit.clone());
+						if !behaviour.withhold_precommits {
+							let _ = sink.send(Message::Precommit(precommit)).await;
+						}
+
+						if let Some(target) = behaviour.forge_commits_for {
+							let commit = forge(target, set_id, id);
+							let _ = global_out.send(CommunicationOut::Commit(round, commit)).await;
+						}
+					}
+					_ => {}
+				}
+			}
+		}
+	}
+
+	// the parent of a block, if it isn't the genesis block.
+	fn parent(env: &Environment, block: &'static str) -> Option<(&'static str, u32)> {
+		if block == GENESIS_HASH { return None }
+
+		env.with_chain(|chain| {
+			let parent = chain.ancestry(GENESIS_HASH, block).ok()?.first().cloned().unwrap_or(GENESIS_HASH);
+			Some((parent, chain.number(parent)))
+		})
+	}
+
+	// copies of a commit of our own precommit in the given round, if we
+	// precommitted in it.
+	fn spam(round: u64, votes: &HashMap<u64, RoundVotes>, set_id: u64, id: Id) -> Vec<Commit<&'static str, u32, Signature, Id>> {
+		let precommit = match votes.get(&round).and_then(|cast| cast.precommitted.clone()) {
+			Some(precommit) => precommit,
+			None => return Vec::new(),
+		};
+
+		let commit = Commit {
+			target_hash: precommit.target_hash,
+			target_number: precommit.target_number,
+			precommits: vec![SignedPrecommit { precommit, signature: Signature(id.0), id }],
+			set_id,
+		};
+
+		vec![commit; SPAM_COPIES]
+	}
+
+	// a commit for the given block whose precommits seem to add up to enough
+	// weight: our own precommit repeated, and ones of made up voters.
+	fn forge(target: (&'static str, u32), set_id: u64, id: Id) -> Commit<&'static str, u32, Signature, Id> {
+		let precommit = |id: Id| SignedPrecommit {
+			precommit: Precommit::new(target.0, target.1),
+			signature: Signature(id.0),
+			id,
+		};
+
+		let mut precommits = vec![precommit(id); FABRICATED_VOTERS as usize];
+		precommits.extend((0..FABRICATED_VOTERS).map(|i| precommit(Id(u32::MAX - i))));
+
+		Commit { target_hash: target.0, target_number: target.1, precommits, set_id }
+	}
+}
@@ -22,7 +22,11 @@ use crate::std::{
 
 use super::{Chain, Error, BlockNumberOps};
 
-#[cfg_attr(feature = "std", derive(Debug))]
+/// The default maximum length of an ancestry fetched from the chain when
+/// inserting a vote. High enough to not be hit by any realistic chain.
+pub const DEFAULT_MAX_ANCESTRY_LEN: usize = 1 << 24;
+
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
 struct Entry<H, N, V> {
 	number: N,
 	// ancestor hashes in reverse order, e.g. ancestors[0] is the parent
@@ -43,7 +47,7 @@ impl<H: Hash + PartialEq + Clone, N: BlockNumberOps, V> Entry<H, N, V> {
 	// by that number in the direct ancestry.
 	fn ancestor_block(&self, number: N) -> Option<&H> {
 		if number >= self.number { return None }
-		let offset = self.number - number - N::one();
+		let offset = self.number.checked_sub(&number)?.checked_sub(&N::one())?;
 
 		self.ancestors.get(offset.as_())
 	}
@@ -54,6 +58,26 @@ impl<H: Hash + PartialEq + Clone, N: BlockNumberOps, V> Entry<H, N, V> {
 	}
 }
 
+// the number of the block the given number of blocks above another. block
+// numbers only convert into `usize`, so the offset is put together from
+// powers of two.
+fn offset_number<N: Copy + BlockNumberOps>(number: N, mut offset: usize) -> N {
+	let mut result = number;
+	let mut power = N::one();
+	while offset > 0 {
+		if offset & 1 == 1 {
+			result = result + power;
+		}
+
+		offset >>= 1;
+		if offset > 0 {
+			power = power + power;
+		}
+	}
+
+	result
+}
+
 // a subchain of blocks by hash.
 struct Subchain<H, N> {
 	hashes: Vec<H>, // forward order.
@@ -61,16 +85,6 @@ struct Subchain<H, N> {
 }
 
 impl<H: Clone, N: Copy + BlockNumberOps> Subchain<H, N> {
-	fn blocks_reverse<'a>(&'a self) -> impl Iterator<Item = (H, N)> + 'a {
-		let best = self.best_number;
-		let mut count = N::zero();
-		self.hashes.iter().rev().cloned().map(move |x| {
-			let res = (x, best - count);
-			count = count + N::one();
-			res
-		})
-	}
-
 	fn best(&self) -> Option<(H, N)> {
 		self.hashes.last().map(|x| (x.clone(), self.best_number))
 	}
@@ -78,11 +92,15 @@ impl<H: Clone, N: Copy + BlockNumberOps> Subchain<H, N> {
 
 /// Maintains a DAG of blocks in the chain which have votes attached to them,
 /// and vote data which is accumulated along edges.
+///
+/// Vote data is accumulated with `AddAssign` all the way up to the base, so
+/// implementations should not wrap around on overflow.
 pub struct VoteGraph<H: Hash + Eq, N, V> {
 	entries: HashMap<H, Entry<H, N, V>>,
 	heads: HashSet<H>,
 	base: H,
 	base_number: N,
+	max_ancestry_len: usize,
 }
 
 impl<H, N, V> VoteGraph<H, N, V> where
@@ -108,9 +126,21 @@ impl<H, N, V> VoteGraph<H, N, V> where
 			heads,
 			base: base_hash,
 			base_number,
+			max_ancestry_len: DEFAULT_MAX_ANCESTRY_LEN,
 		}
 	}
 
+	/// Set the maximum length of an ancestry fetched from the chain when
+	/// inserting a vote.
+	/// Inserts exceeding it fail with `Error::AncestryTooLong`, so that votes
+	/// far above the base can't make the graph hold arbitrary amounts of data.
+	/// The chain is asked with `Chain::ancestry_bounded`, so backends can stop
+	/// walking once the bound is exceeded. Defaults to
+	/// `DEFAULT_MAX_ANCESTRY_LEN`.
+	pub fn set_max_ancestry_len(&mut self, max_ancestry_len: usize) {
+		self.max_ancestry_len = max_ancestry_len;
+	}
+
 	/// Get the base block.
 	pub fn base(&self) -> (H, N) {
 		(self.base.clone(), self.base_number)
@@ -127,21 +157,24 @@ impl<H, N, V> VoteGraph<H, N, V> where
 			Some(h) => h,
 		};
 
-		// not a valid ancestry proof. TODO: error?
-		if ancestry_proof.len() > self.base_number.as_() { return }
-
 		// hack because we can't convert usize -> N, only vice-versa.
 		// hopefully LLVM can optimize.
 		//
 		// TODO: Add TryFrom to `BlockNumberOps`.
 		let new_number = {
-			let mut new_number = self.base_number;
+			let mut new_number = Some(self.base_number);
 			for _ in 0..ancestry_proof.len() {
-				new_number = new_number - N::one();
+				new_number = new_number.and_then(|n| n.checked_sub(&N::one()));
 			}
 			new_number
 		};
 
+		// not a valid ancestry proof. TODO: error?
+		let new_number = match new_number {
+			Some(n) => n,
+			None => return,
+		};
+
 		let entry = {
 			let old_entry = self.entries.get_mut(&self.base)
 				.expect("base hash entry always exists; qed");
@@ -162,7 +195,15 @@ impl<H, N, V> VoteGraph<H, N, V> where
 	}
 
 	/// Insert a vote with given value into the graph at given hash and number.
+	///
+	/// Fails with `Error::BlockNotInSubtree` for blocks at or below the base,
+	/// other than the base itself, and with `Error::NotDescendent` for blocks
+	/// above it which aren't its descendents.
 	pub fn insert<C: Chain<H, N>>(&mut self, hash: H, number: N, vote: V, chain: &C) -> Result<(), Error> {
+		if number <= self.base_number && hash != self.base {
+			return Err(Error::BlockNotInSubtree);
+		}
+
 		if let Some(containing) = self.find_containing_nodes(hash.clone(), number) {
 			if containing.is_empty() {
 				self.append(hash.clone(), number, chain)?;
@@ -191,65 +232,229 @@ impl<H, N, V> VoteGraph<H, N, V> where
 		Ok(())
 	}
 
+	/// The cumulative vote of the vote-node with the given hash, i.e. the votes
+	/// on it and all its descendents. Returns `None` if `block` isn't a
+	/// vote-node, even if it's a block on an edge between vote-nodes.
+	pub fn weight_of(&self, block: &H) -> Option<&V> {
+		self.entries.get(block).map(|entry| &entry.cumulative_vote)
+	}
+
+	/// Whether the given block is a vote-node. As for `weight_of`, blocks on
+	/// the edges between vote-nodes aren't.
+	pub fn contains(&self, block: &H) -> bool {
+		self.entries.contains_key(block)
+	}
+
+	/// The number of vote-nodes, including the base.
+	// the base is always a vote-node, so the graph is never empty.
+	#[allow(clippy::len_without_is_empty)]
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	/// Whether the vote-node with the given hash is a strict descendent of the given block.
+	///
+	/// Only the path of vote-nodes from `hash` back towards the base is inspected,
+	/// which is exactly the set of nodes whose cumulative vote changes when a vote
+	/// is inserted at `hash`. Returns `false` if `hash` isn't a vote-node.
+	pub fn is_descendent_of(&self, hash: &H, ancestor: (&H, N)) -> bool {
+		let (ancestor_hash, ancestor_number) = ancestor;
+		if hash == ancestor_hash { return false }
+
+		let mut active_entry = match self.entries.get(hash) {
+			Some(entry) => entry,
+			None => return false,
+		};
+
+		loop {
+			if ancestor_number >= active_entry.number { return false }
+
+			match active_entry.in_direct_ancestry(ancestor_hash, ancestor_number) {
+				Some(in_ancestry) => return in_ancestry,
+				None => {
+					let parent = match active_entry.ancestor_node() {
+						Some(parent) => parent,
+						None => return false,
+					};
+
+					active_entry = self.entries.get(&parent)
+						.expect("ancestor vote-nodes always present in node storage; qed");
+				}
+			}
+		}
+	}
+
+	/// Whether the given block, which needn't be a vote-node, is the given
+	/// ancestor or one of its descendents.
+	///
+	/// Only the vote-nodes from the block back to the ancestor are visited.
+	/// Returns `false` if the block isn't in the graph.
+	pub fn is_equal_or_descendent_of(&self, block: (H, N), ancestor: (&H, N)) -> bool {
+		let (ancestor_hash, ancestor_number) = ancestor;
+		let mut found = false;
+		self.walk_back_segments(block.0, block.1, |node, top, bottom, _| {
+			if ancestor_number < bottom { return true }
+
+			found = ancestor_number <= top && self.segment_block(node, ancestor_number) == ancestor_hash;
+			false
+		});
+
+		found
+	}
+
 	/// Find the highest block which is either an ancestor of or equal to the given, which fulfills a
 	/// condition.
-	pub fn find_ancestor<'a, F>(&'a self, hash: H, number: N, condition: F) -> Option<(H, N)>
+	pub fn find_ancestor<F>(&self, hash: H, number: N, condition: F) -> Option<(H, N)>
 		where F: Fn(&V) -> bool
 	{
-		let entries = &self.entries;
-		let get_node = |hash: &_| -> &'a _ {
-			entries.get(hash)
-				.expect("node either base or referenced by other in graph; qed")
-		};
+		// the vote only grows walking back, so the top of the first segment
+		// which fulfills the condition is the block.
+		let mut found = None;
+		self.walk_back_segments(hash, number, |node, top, _, vote| {
+			if !condition(vote) { return true }
+
+			found = Some((self.segment_block(node, top).clone(), top));
+			false
+		});
+
+		found
+	}
+
+	/// Visit the given block and its ancestors down to the base, highest first, with the
+	/// cumulative vote of each, for as long as the visitor returns `true`.
+	///
+	/// Nothing is visited if the given block isn't in the graph, and blocks below
+	/// a vote-node inserted at a number which doesn't match its ancestry aren't.
+	pub fn walk_back<F>(&self, hash: H, number: N, mut visit: F)
+		where F: FnMut(&H, N, &V) -> bool
+	{
+		self.walk_back_segments(hash, number, |node, top, bottom, vote| {
+			let mut number = top;
+			loop {
+				if !visit(self.segment_block(node, number), number, vote) { return false }
+				if number == bottom { return true }
+				number = number - N::one();
+			}
+		})
+	}
 
-		// we store two nodes with an edge between them that is the canonical
-		// chain.
-		// the `node_key` always points to the ancestor node, and the `canonical_node`
-		// points to the higher node.
-		let (mut node_key, mut canonical_node) = match self.find_containing_nodes(hash.clone(), number) {
-			None =>	{
+	// walk back from the given block to the base like `walk_back`, but in
+	// segments of consecutive blocks with the same cumulative vote, highest
+	// first. each segment is given by the vote-node it ends in or whose
+	// ancestor-edge contains it, the numbers of its top and bottom block, and
+	// the vote.
+	//
+	// the walk ends early at a vote-node whose number doesn't match its
+	// ancestry, which votes with made-up numbers can introduce.
+	//
+	// only vote-nodes are visited on the way: walking back in an edge, the
+	// vote only changes where the edges of other vote-nodes join it.
+	fn walk_back_segments<F>(&self, hash: H, number: N, mut visit: F)
+		where F: FnMut(&H, N, N, &V) -> bool
+	{
+		let get_node = |hash: &_| self.entries.get(hash)
+			.expect("node either base or referenced by other in graph; qed");
+
+		// the vote-node whose ancestry is followed, the highest block below it
+		// which wasn't visited yet, the vote-nodes joining in that block counted
+		// already, and their vote.
+		let (mut path, mut top, mut counted, mut vote) = match self.find_containing_nodes(hash.clone(), number) {
+			None => {
 				let node = get_node(&hash);
-				if condition(&node.cumulative_vote) {
-					return Some((hash, number))
+				if node.number != number { return }
+				if !visit(&hash, number, number, &node.cumulative_vote) || node.ancestors.is_empty() {
+					return
 				}
 
-				(node.ancestor_node()?, node)
+				(hash.clone(), number - N::one(), vec![hash], node.cumulative_vote.clone())
 			}
-			Some(ref x) if !x.is_empty() => {
-				let node = get_node(&x[0]);
-				let key = node.ancestor_node()
-					.expect("node containing block in ancestry has ancestor node; qed");
+			Some(ref descendents) if !descendents.is_empty() => {
+				let mut vote = V::default();
+				for descendent in descendents {
+					vote += get_node(descendent).cumulative_vote.clone();
+				}
 
-				(key, node)
+				(descendents[0].clone(), number, descendents.clone(), vote)
 			}
-			Some(_) => return None,
+			Some(_) => return,
 		};
 
-		// search backwards until we find the first vote-node that
-		// meets the condition.
-		let mut active_node = get_node(&node_key);
-		while !condition(&active_node.cumulative_vote) {
-			node_key = match active_node.ancestor_node() {
-				Some(n) => n,
-				None => return None,
-			};
-
-			canonical_node = active_node;
-			active_node = get_node(&node_key);
-		}
+		loop {
+			let path_node = get_node(&path);
+			let bottom_hash = path_node.ancestor_node()
+				.expect("only the base has no ancestors, and walking back stops there; qed");
+			let bottom = get_node(&bottom_hash);
+
+			// votes may claim any number for a block, so the edge of a
+			// vote-node needn't span as many blocks as the numbers say. its
+			// blocks can't be told by number then, so the walk ends there.
+			let edge_len: usize = (path_node.number - bottom.number).as_();
+			if edge_len != path_node.ancestors.len() { return }
+
+			// blocks in the edge are identified by their offset above the
+			// vote-node it starts at.
+			let top_offset: usize = (top - bottom.number).as_();
+			fn block_at<H, N, V>(node: &Entry<H, N, V>, offset: usize) -> &H {
+				&node.ancestors[node.ancestors.len() - 1 - offset]
+			}
+
+			// the other vote-nodes on the edge, by the offset of the highest
+			// block they share with it.
+			let mut joining: Vec<(usize, &V)> = bottom.descendents.iter()
+				.filter(|descendent| !counted.contains(descendent))
+				.filter_map(|descendent| {
+					let node = get_node(descendent);
+
+					// blocks are shared up to where the edges fork.
+					let (mut shared, mut unshared) = (0, std::cmp::min(top_offset, node.ancestors.len() - 1) + 1);
+					while unshared - shared > 1 {
+						let mid = shared + (unshared - shared) / 2;
+						if block_at(node, mid) == block_at(path_node, mid) {
+							shared = mid;
+						} else {
+							unshared = mid;
+						}
+					}
 
-		// find the GHOST merge-point after the active_node.
-		// constrain it to be within the canonical chain.
-		let good_subchain = self.ghost_find_merge_point(node_key, active_node, None, condition);
+					if shared == 0 { None } else { Some((shared, &node.cumulative_vote)) }
+				})
+				.collect();
+			joining.sort_by_key(|&(shared, _)| std::cmp::Reverse(shared));
+
+			let mut joining = joining.into_iter().peekable();
+			let mut offset = top_offset;
+			while offset > 0 {
+				while let Some((_, joined)) = joining.next_if(|&(shared, _)| shared == offset) {
+					vote += joined.clone();
+				}
 
-		// FIXME: binding is required for some reason.
-		let mut blocks_reverse = good_subchain.blocks_reverse();
+				let next_offset = joining.peek().map_or(0, |&(shared, _)| shared);
+				let segment_top = offset_number(bottom.number, offset);
+				let segment_bottom = offset_number(bottom.number, next_offset + 1);
+				if !visit(&path, segment_top, segment_bottom, &vote) { return }
 
-		blocks_reverse.find(|&(ref good_hash, good_number)| {
-			canonical_node
-				.in_direct_ancestry(good_hash, good_number)
-				.unwrap_or(false)
-		})
+				offset = next_offset;
+			}
+
+			if !visit(&bottom_hash, bottom.number, bottom.number, &bottom.cumulative_vote) || bottom.ancestors.is_empty() {
+				return
+			}
+
+			top = bottom.number - N::one();
+			counted = vec![bottom_hash.clone()];
+			vote = bottom.cumulative_vote.clone();
+			path = bottom_hash;
+		}
+	}
+
+	// the block with the given number in a segment of `walk_back_segments`.
+	fn segment_block<'a>(&'a self, node: &'a H, number: N) -> &'a H {
+		let entry = self.entries.get(node).expect("segments are of vote-nodes in the graph; qed");
+		if number == entry.number {
+			node
+		} else {
+			entry.ancestor_block(number).expect("segments are in the ancestry of their vote-node; qed")
+		}
 	}
 
 	/// Find the best GHOST descendent of the given block.
@@ -262,6 +467,10 @@ impl<H, N, V> VoteGraph<H, N, V> where
 	/// descendent of a block, in that only one fork of a block can be "heavy"
 	/// enough to trigger the threshold.
 	///
+	/// The `current_best` should be a previous result of this function with the same
+	/// condition, since vote weight only grows: the search resumes from there rather than
+	/// re-evaluating the blocks leading up to it.
+	///
 	/// Returns `None` when the given `current_best` does not fulfill the condition.
 	pub fn find_ghost<'a, F>(&'a self, current_best: Option<(H, N)>, condition: F) -> Option<(H, N)>
 		where F: Fn(&V) -> bool
@@ -350,38 +559,52 @@ impl<H, N, V> VoteGraph<H, N, V> where
 			})
 			.collect();
 
-		let base_number = active_node.number;
 		let mut best_number = active_node.number;
 		let mut descendent_blocks = Vec::with_capacity(descendent_nodes.len());
 		let mut hashes = vec![node_key];
 
+		// the constraining block is the current best, which fulfills the condition,
+		// and all remaining descendents have it in their ancestry. skip straight to it.
+		if let (Some((_, number)), Some(d_node)) = (force_constrain, descendent_nodes.first()) {
+			while best_number < number {
+				best_number = best_number.checked_add(&N::one())
+					.expect("best_number is less than number; qed");
+				hashes.push(
+					d_node.ancestor_block(best_number)
+						.expect("constraining block is in the direct ancestry of the node; qed")
+						.clone()
+				);
+			}
+		}
+
 		// TODO: for long ranges of blocks this could get inefficient
-		let mut offset = N::zero();
-		loop {
-			offset = offset + N::one();
+		// no block can be higher than the maximum block number.
+		while let Some(number) = best_number.checked_add(&N::one()) {
 
-			let mut new_best = None;
 			for d_node in &descendent_nodes {
-				if let Some(d_block) = d_node.ancestor_block(base_number + offset) {
-					match descendent_blocks.binary_search_by_key(&d_block, |&(ref x, _)| x) {
+				if let Some(d_block) = d_node.ancestor_block(number) {
+					match descendent_blocks.binary_search_by_key(&d_block, |(x, _, _)| x) {
 						Ok(idx) => {
 							descendent_blocks[idx].1 += d_node.cumulative_vote.clone();
-							if condition(&descendent_blocks[idx].1) {
-								new_best = Some(d_block.clone());
-								break
-							}
+							descendent_blocks[idx].2 = true;
 						}
 						Err(idx) => descendent_blocks.insert(idx, (
 							d_block.clone(),
-							d_node.cumulative_vote.clone()
+							d_node.cumulative_vote.clone(),
+							false,
 						)),
 					}
 				}
 			}
 
+			// only blocks where descendents merge can fulfill the condition.
+			let new_best = descendent_blocks.iter()
+				.find(|(_, vote, merged)| *merged && condition(vote))
+				.map(|(hash, _, _)| hash.clone());
+
 			match new_best {
 				Some(new_best) => {
-					best_number = best_number + N::one();
+					best_number = number;
 
 					descendent_blocks.clear();
 					descendent_nodes.retain(
@@ -511,19 +734,29 @@ impl<H, N, V> VoteGraph<H, N, V> where
 	// append a vote-node onto the chain-tree. This should only be called if
 	// no node in the tree keeps the target anyway.
 	fn append<C: Chain<H, N>>(&mut self, hash: H, number: N, chain: &C) -> Result<(), Error> {
-		let mut ancestry = chain.ancestry(self.base.clone(), hash.clone())?;
-		ancestry.push(self.base.clone()); // ancestry doesn't include base.
+		let (base, mut ancestry) = match self.anchored_ancestry(&hash, number, chain) {
+			Some(anchored) => anchored,
+			None => (self.base.clone(), chain.ancestry_bounded(self.base.clone(), hash.clone(), self.max_ancestry_len)?),
+		};
+
+		ancestry.push(base.clone()); // ancestry doesn't include base.
 
 		let mut ancestor_index = None;
-		for (i, ancestor) in ancestry.iter().chain(std::iter::once(&self.base)).enumerate() {
+		for (i, ancestor) in ancestry.iter().chain(std::iter::once(&base)).enumerate() {
 			if let Some(entry) = self.entries.get_mut(ancestor) {
+				// offsets into the ancestry are computed by subtracting block numbers,
+				// which would underflow for a block claimed to be below its ancestor.
+				if number.checked_sub(&entry.number).is_none_or(|offset| offset.is_zero()) {
+					return Err(Error::BlockNumberOverflow);
+				}
+
 				entry.descendents.push(hash.clone());
 				ancestor_index = Some(i);
 				break;
 			}
 		}
 
-		let ancestor_index = ancestor_index.expect("base is kept; \
+		let ancestor_index = ancestor_index.expect("base and finalized anchor are kept; \
 			chain returns ancestry only if the block is a descendent of base; qed");
 
 		let ancestor_hash = ancestry[ancestor_index].clone();
@@ -541,6 +774,21 @@ impl<H, N, V> VoteGraph<H, N, V> where
 
 		Ok(())
 	}
+
+	// the ancestry of a block down to the last finalized block of the chain,
+	// along with that block, if it's between the base and the given block.
+	// the finalized block is made a vote-node, so that walks stop there.
+	fn anchored_ancestry<C: Chain<H, N>>(&mut self, hash: &H, number: N, chain: &C) -> Option<(H, Vec<H>)> {
+		let (anchor_hash, anchor_number) = chain.last_finalized()?;
+		if anchor_number <= self.base_number || anchor_number >= number { return None }
+
+		let ancestry = chain.ancestry_bounded(anchor_hash.clone(), hash.clone(), self.max_ancestry_len).ok()?;
+		if !self.entries.contains_key(&anchor_hash) {
+			self.insert(anchor_hash.clone(), anchor_number, V::default(), chain).ok()?;
+		}
+
+		Some((anchor_hash, ancestry))
+	}
 }
 
 #[cfg(test)]
@@ -597,7 +845,7 @@ mod tests {
 		tracker2.insert("F2", 7, 100, &chain).unwrap();
 		tracker2.insert("C", 4, 100, &chain).unwrap();
 
-		for tracker in &[&tracker2] {
+		for tracker in &[&tracker1, &tracker2] {
 			assert!(tracker.heads.contains("E1"));
 			assert!(tracker.heads.contains("F2"));
 			assert!(!tracker.heads.contains("C"));
@@ -618,6 +866,63 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn weight_of_vote_nodes_across_fork() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+		chain.push_blocks("C", &["D1", "E1", "F1"]);
+		chain.push_blocks("C", &["D2", "E2", "F2"]);
+
+		tracker.insert("B", 3, 50u32, &chain).unwrap();
+		tracker.insert("E1", 6, 100, &chain).unwrap();
+		tracker.insert("F1", 7, 20, &chain).unwrap();
+		tracker.insert("F2", 7, 100, &chain).unwrap();
+
+		assert_eq!(tracker.weight_of(&GENESIS_HASH), Some(&270));
+		assert_eq!(tracker.weight_of(&"B"), Some(&270));
+		assert_eq!(tracker.weight_of(&"E1"), Some(&120));
+		assert_eq!(tracker.weight_of(&"F1"), Some(&20));
+		assert_eq!(tracker.weight_of(&"F2"), Some(&100));
+
+		// blocks on edges between vote-nodes, including the fork point, and
+		// unknown blocks.
+		assert_eq!(tracker.weight_of(&"C"), None);
+		assert_eq!(tracker.weight_of(&"D2"), None);
+		assert_eq!(tracker.weight_of(&"A"), None);
+		assert_eq!(tracker.weight_of(&"X"), None);
+	}
+
+	#[test]
+	fn contains_and_len_count_vote_nodes() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+		assert_eq!(tracker.len(), 1);
+		assert!(tracker.contains(&GENESIS_HASH));
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+		chain.push_blocks("C", &["D1", "E1"]);
+		chain.push_blocks("C", &["D2", "E2"]);
+
+		tracker.insert("B", 3, 1u32, &chain).unwrap();
+		tracker.insert("E1", 6, 1, &chain).unwrap();
+		tracker.insert("E2", 6, 1, &chain).unwrap();
+		assert_eq!(tracker.len(), 4);
+		assert!(["B", "E1", "E2"].iter().all(|block| tracker.contains(block)));
+		assert!(!tracker.contains(&"C"));
+		assert!(!tracker.contains(&"X"));
+
+		// a vote for a block on an edge splits it.
+		tracker.insert("D1", 5, 1, &chain).unwrap();
+		assert_eq!(tracker.len(), 5);
+		assert!(tracker.contains(&"D1"));
+
+		// more votes for a vote-node don't add any.
+		tracker.insert("B", 3, 1, &chain).unwrap();
+		assert_eq!(tracker.len(), 5);
+	}
+
 	#[test]
 	fn ghost_merge_at_node() {
 		let mut chain = DummyChain::new();
@@ -774,6 +1079,278 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn walk_back_from_block_in_edge_does_not_overshoot() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+		chain.push_blocks("C", &["D1"]);
+		chain.push_blocks("C", &["D2"]);
+
+		tracker.insert("D1", 5, 5u32, &chain).unwrap();
+		tracker.insert("D2", 5, 5, &chain).unwrap();
+
+		// "C" is the merge point with enough votes, but we're walking back from "A".
+		assert_eq!(tracker.find_ancestor("C", 4, |&x| x >= 10).unwrap(), ("C", 4));
+		assert_eq!(tracker.find_ancestor("A", 2, |&x| x >= 10).unwrap(), ("A", 2));
+	}
+
+	#[test]
+	fn walk_back_from_block_in_several_edges_beside_another_fork() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+
+		chain.push_blocks(GENESIS_HASH, &["A"]);
+		chain.push_blocks(GENESIS_HASH, &["B", "C"]);
+		chain.push_blocks("B", &["F"]);
+
+		tracker.insert("A", 2, 5u32, &chain).unwrap();
+		tracker.insert("C", 3, 4, &chain).unwrap();
+		tracker.insert("F", 3, 1, &chain).unwrap();
+
+		// "B" isn't a vote-node, but has the weight of both "C" and "F". the
+		// fork to "A" below it, which meets the condition as well, doesn't
+		// matter when walking back.
+		assert_eq!(tracker.find_ancestor("B", 2, |&x| x >= 5).unwrap(), ("B", 2));
+		assert_eq!(tracker.find_ancestor("B", 2, |&x| x >= 6).unwrap(), (GENESIS_HASH, 1));
+	}
+
+	#[test]
+	fn walk_back_counts_forks_where_they_join_the_edge() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E", "F", "G"]);
+		chain.push_blocks("B", &["C1"]);
+		chain.push_blocks("E", &["F1", "G1"]);
+		chain.push_blocks("E", &["F2"]);
+
+		tracker.insert("G", 8, 1u32, &chain).unwrap();
+		tracker.insert("C1", 4, 2, &chain).unwrap();
+		tracker.insert("G1", 8, 4, &chain).unwrap();
+		tracker.insert("F2", 7, 8, &chain).unwrap();
+
+		let mut visited = Vec::new();
+		tracker.walk_back("G", 8, |hash, number, &vote| {
+			visited.push((*hash, number, vote));
+			true
+		});
+
+		assert_eq!(visited, vec![
+			("G", 8, 1),
+			("F", 7, 1),
+			("E", 6, 13),
+			("D", 5, 13),
+			("C", 4, 13),
+			("B", 3, 15),
+			("A", 2, 15),
+			(GENESIS_HASH, 1, 15),
+		]);
+
+		assert_eq!(tracker.find_ancestor("G", 8, |&x| x >= 13).unwrap(), ("E", 6));
+		assert_eq!(tracker.find_ancestor("F", 7, |&x| x >= 14).unwrap(), ("B", 3));
+
+		let mut visited = Vec::new();
+		tracker.walk_back("D", 5, |hash, number, _| {
+			visited.push((*hash, number));
+			number > 4
+		});
+
+		assert_eq!(visited, vec![("D", 5), ("C", 4)]);
+	}
+
+	#[test]
+	fn blocks_in_edges_are_descendents_of_their_ancestors() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+		chain.push_blocks("C", &["D1", "E1", "F1"]);
+		chain.push_blocks("C", &["D2", "E2", "F2"]);
+
+		tracker.insert("F1", 7, 100u32, &chain).unwrap();
+		tracker.insert("F2", 7, 100, &chain).unwrap();
+
+		assert!(tracker.is_equal_or_descendent_of(("E1", 6), (&"E1", 6)));
+		assert!(tracker.is_equal_or_descendent_of(("E1", 6), (&"D1", 5)));
+		assert!(tracker.is_equal_or_descendent_of(("E1", 6), (&"B", 3)));
+		assert!(tracker.is_equal_or_descendent_of(("C", 4), (&GENESIS_HASH, 1)));
+
+		assert!(!tracker.is_equal_or_descendent_of(("E1", 6), (&"D2", 5)));
+		assert!(!tracker.is_equal_or_descendent_of(("D1", 5), (&"E1", 6)));
+		assert!(!tracker.is_equal_or_descendent_of(("X", 6), (&"A", 2)));
+	}
+
+	#[test]
+	fn is_descendent_of_walks_vote_nodes() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+		chain.push_blocks("C", &["D1", "E1", "F1"]);
+		chain.push_blocks("C", &["D2", "E2", "F2"]);
+
+		tracker.insert("A", 2, 100u32, &chain).unwrap();
+		tracker.insert("E1", 6, 100, &chain).unwrap();
+		tracker.insert("F2", 7, 100, &chain).unwrap();
+
+		assert!(tracker.is_descendent_of(&"E1", (&"A", 2)));
+		assert!(tracker.is_descendent_of(&"E1", (&"C", 4)));
+		assert!(tracker.is_descendent_of(&"E1", (&GENESIS_HASH, 1)));
+		assert!(tracker.is_descendent_of(&"F2", (&"D2", 5)));
+
+		assert!(!tracker.is_descendent_of(&"E1", (&"E1", 6)));
+		assert!(!tracker.is_descendent_of(&"E1", (&"D2", 5)));
+		assert!(!tracker.is_descendent_of(&"E1", (&"F1", 7)));
+		assert!(!tracker.is_descendent_of(&"A", (&"E1", 6)));
+
+		// not a vote-node.
+		assert!(!tracker.is_descendent_of(&"F1", (&"A", 2)));
+	}
+
+	#[test]
+	fn near_max_block_numbers_do_not_overflow() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, u32::MAX - 3);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+		chain.push_blocks("A", &["B2", "C2"]);
+
+		tracker.insert("C", u32::MAX, 5u32, &chain).unwrap();
+		tracker.insert("C2", u32::MAX, 5, &chain).unwrap();
+		tracker.insert("B", u32::MAX - 1, 5, &chain).unwrap();
+
+		assert_eq!(tracker.find_ghost(None, |&x| x >= 10), Some(("B", u32::MAX - 1)));
+		assert_eq!(tracker.find_ghost(None, |&x| x >= 15), Some(("A", u32::MAX - 2)));
+		assert_eq!(tracker.find_ancestor("C", u32::MAX, |&x| x >= 10), Some(("B", u32::MAX - 1)));
+		assert_eq!(tracker.find_ancestor("C2", u32::MAX, |&x| x >= 10), Some(("A", u32::MAX - 2)));
+
+		// the maximum block is a vote-node with no room for descendents.
+		assert_eq!(tracker.find_ghost(Some(("C", u32::MAX)), |&x| x >= 5), Some(("C", u32::MAX)));
+	}
+
+	#[test]
+	fn block_below_ancestor_node_is_number_overflow() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 0);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+
+		tracker.insert("A", u32::MAX - 1, 1u32, &chain).unwrap();
+
+		// "C" descends from "A" but is claimed to be at a lower or equal number.
+		assert_eq!(tracker.insert("C", 3, 1, &chain), Err(Error::BlockNumberOverflow));
+		assert_eq!(tracker.insert("C", u32::MAX - 1, 1, &chain), Err(Error::BlockNumberOverflow));
+
+		// and nothing was changed.
+		assert!(!tracker.entries.contains_key("C"));
+		assert!(tracker.entries.get("A").unwrap().descendents.is_empty());
+		assert_eq!(tracker.entries.get(GENESIS_HASH).unwrap().cumulative_vote, 1);
+
+		tracker.insert("C", u32::MAX, 1, &chain).unwrap();
+		assert_eq!(tracker.find_ghost(None, |&x| x >= 2), Some(("A", u32::MAX - 1)));
+	}
+
+	#[test]
+	fn walks_end_at_vote_nodes_with_made_up_numbers() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D"]);
+
+		// "D" is block 5, but claimed far above.
+		tracker.insert("D", 100, 2u32, &chain).unwrap();
+
+		let mut visited = Vec::new();
+		tracker.walk_back("D", 100, |hash, number, _| { visited.push((*hash, number)); true });
+		assert_eq!(visited, vec![("D", 100)]);
+
+		assert_eq!(tracker.find_ancestor("D", 100, |&x| x >= 3), None);
+		assert_eq!(tracker.find_ancestor("D", 100, |&x| x >= 2), Some(("D", 100)));
+		assert!(!tracker.is_equal_or_descendent_of(("D", 100), (&"C", 4)));
+
+		// nor is it walked from at the number of the block on the chain.
+		visited.clear();
+		tracker.walk_back("D", 5, |hash, number, _| { visited.push((*hash, number)); true });
+		assert!(visited.is_empty());
+	}
+
+	#[test]
+	fn blocks_outside_the_subtree_of_the_base_are_rejected() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D"]);
+		chain.push_blocks("A", &["B2", "C2"]);
+
+		let mut tracker = VoteGraph::new("B", 3);
+
+		// at or below the base.
+		assert_eq!(tracker.insert("A", 2, 1u32, &chain), Err(Error::BlockNotInSubtree));
+		assert_eq!(tracker.insert("B2", 3, 1, &chain), Err(Error::BlockNotInSubtree));
+
+		// above it, but on another fork.
+		assert_eq!(tracker.insert("C2", 4, 1, &chain), Err(Error::NotDescendent));
+
+		assert_eq!(tracker.len(), 1);
+		assert_eq!(tracker.entries.get("B").unwrap().cumulative_vote, 0);
+
+		// the base itself and its descendents are fine.
+		tracker.insert("B", 3, 1, &chain).unwrap();
+		tracker.insert("D", 5, 1, &chain).unwrap();
+		assert_eq!(tracker.entries.get("B").unwrap().cumulative_vote, 2);
+	}
+
+	// a chain backend returning a fixed-length ancestry for any block.
+	struct LongAncestry(usize);
+
+	impl Chain<&'static str, u32> for LongAncestry {
+		fn ancestry(&self, _base: &'static str, _block: &'static str) -> Result<Vec<&'static str>, Error> {
+			Ok(vec!["X"; self.0])
+		}
+
+		fn best_chain_containing(&self, _base: &'static str) -> Option<(&'static str, u32)> {
+			None
+		}
+	}
+
+	#[test]
+	fn over_long_ancestry_is_rejected() {
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+		tracker.set_max_ancestry_len(10);
+
+		// the chain returns a huge ancestry for the block.
+		assert_eq!(tracker.insert("B", 3, 1u32, &LongAncestry(100_000)), Err(Error::AncestryTooLong));
+
+		// the ancestry isn't walked back further than the bound.
+		let mut chain = DummyChain::new();
+		let blocks = ["A", "B", "C", "D", "E", "F", "G", "H", "I", "J", "K", "L"];
+		chain.push_blocks(GENESIS_HASH, &blocks);
+		assert_eq!(chain.ancestry_bounded(GENESIS_HASH, "L", 10), Err(Error::AncestryTooLong));
+		assert_eq!(tracker.insert("L", 3, 1u32, &chain), Err(Error::AncestryTooLong));
+		assert_eq!(tracker.entries.len(), 1);
+		assert_eq!(tracker.entries.get(GENESIS_HASH).unwrap().cumulative_vote, 0);
+
+		// blocks within the bound are fine.
+		tracker.insert("K", 12, 1, &chain).unwrap();
+		assert_eq!(tracker.entries.get("K").unwrap().ancestors.len(), 11);
+		assert_eq!(tracker.find_ghost(None, |&x| x >= 1), Some(("K", 12)));
+	}
+
+	#[test]
+	fn adjust_base_below_zero_is_ignored() {
+		let mut chain = DummyChain::new();
+		let mut tracker = VoteGraph::new("B", 1);
+
+		chain.push_blocks(GENESIS_HASH, &["A", "B"]);
+		tracker.insert("B", 1, 1u32, &chain).unwrap();
+
+		tracker.adjust_base(&["A", GENESIS_HASH]);
+		assert_eq!(tracker.base(), ("B", 1));
+
+		tracker.adjust_base(&["A"]);
+		assert_eq!(tracker.base(), ("A", 0));
+	}
+
 	#[test]
 	fn adjust_base() {
 		let mut chain = DummyChain::new();
@@ -803,4 +1380,52 @@ mod tests {
 
 		assert_eq!(tracker.entries.get(GENESIS_HASH).unwrap().cumulative_vote, 15);
 	}
+
+	// a chain which reports the given block as finalized.
+	struct AnchoredChain {
+		chain: DummyChain,
+		finalized: (&'static str, u32),
+	}
+
+	impl Chain<&'static str, u32> for AnchoredChain {
+		fn ancestry(&self, base: &'static str, block: &'static str) -> Result<Vec<&'static str>, Error> {
+			self.chain.ancestry(base, block)
+		}
+
+		fn best_chain_containing(&self, base: &'static str) -> Option<(&'static str, u32)> {
+			self.chain.best_chain_containing(base)
+		}
+
+		fn last_finalized(&self) -> Option<(&'static str, u32)> {
+			Some(self.finalized)
+		}
+	}
+
+	#[test]
+	fn ancestry_stops_at_finalized_anchor() {
+		let mut chain = DummyChain::new();
+		chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E", "F", "G", "H"]);
+		chain.push_blocks("F", &["G2", "H2"]);
+		let chain = AnchoredChain { chain, finalized: ("E", 6) };
+
+		let mut tracker = VoteGraph::new(GENESIS_HASH, 1);
+
+		// blocks up to the anchor are inserted as usual.
+		tracker.insert("B", 3, 1u32, &chain).unwrap();
+		assert_eq!(tracker.entries.get("B").unwrap().ancestors, vec!["A", GENESIS_HASH]);
+		assert!(!tracker.entries.contains_key("E"));
+
+		// beyond it, the anchor becomes a vote-node and the ancestry stops there.
+		tracker.insert("H", 9, 2, &chain).unwrap();
+		assert_eq!(tracker.entries.get("H").unwrap().ancestors, vec!["G", "F", "E"]);
+		assert_eq!(tracker.entries.get("E").unwrap().ancestors, vec!["D", "C", "B"]);
+
+		tracker.insert("H2", 9, 4, &chain).unwrap();
+		assert_eq!(tracker.entries.get("H2").unwrap().ancestors, vec!["G2", "F", "E"]);
+
+		// the anchor doesn't carry any votes of its own.
+		assert_eq!(tracker.weight_of(&"E"), Some(&6));
+		assert_eq!(tracker.weight_of(&"B"), Some(&7));
+		assert_eq!(tracker.find_ghost(None, |&x| x >= 6), Some(("F", 7)));
+	}
 }
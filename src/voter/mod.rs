@@ -26,27 +26,62 @@
 //!  transitions state as if the votes had been pushed out.
 
 use futures::{prelude::*, ready};
+use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
 use futures::channel::mpsc::{self, UnboundedReceiver};
+#[cfg(not(target_arch = "wasm32"))]
+use futures_timer::Delay;
 #[cfg(feature = "std")]
-use log::trace;
+use log::{debug, trace, warn};
 
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, HashSet, VecDeque};
 use std::hash::Hash;
+use std::ops::{Add, Sub};
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
-use crate::round::State as RoundState;
+use crate::round::{CompletabilityBlockers, State as RoundState};
 use crate::{
-	CatchUp, Chain, Commit, CompactCommit, Equivocation, Message, Prevote, Precommit,
-	PrimaryPropose, SignedMessage, BlockNumberOps, validate_commit, CommitValidationResult,
-	HistoricalVotes,
+	BadCatchUpReason, CatchUp, Chain, Commit, CompactCommit, Equivocation, Message, Prevote, Precommit,
+	PrimaryPropose, SignedCommit, SignedMessage, BlockNumberOps, validate_commit, CommitValidationResult,
+	SignedPrecommit, HistoricalVotes, ImportResult,
 };
 use crate::voter_set::VoterSet;
-use past_rounds::PastRounds;
+use past_rounds::{ImportCommitError, PastRounds};
+pub use past_rounds::{BackgroundRoundSnapshot, CompletedRound, CompletedRounds};
+pub use crate::bridge_state::RoundStateStream;
 use voting_round::{VotingRound, State as VotingRoundState};
 
+#[cfg(feature = "derive-codec")]
+use parity_scale_codec::{Encode, Decode};
+
+// emit an event with the given fields, e.g. `round = n, target_hash = h`,
+// which are recorded in their `Debug` representation. with the `tracing`
+// feature this is a structured event. otherwise the given log line is
+// emitted instead, or one listing the fields.
+macro_rules! afg_event {
+	($level:ident, [$($field:ident = $value:expr),+], $message:literal; $($log:tt)+) => {{
+		#[cfg(feature = "tracing")]
+		tracing::$level!(target: "afg", $($field = ?$value),+, $message);
+		#[cfg(not(feature = "tracing"))]
+		log::$level!(target: "afg", $($log)+);
+	}};
+	($level:ident, [$($field:ident = $value:expr),+], $message:literal) => {{
+		#[cfg(feature = "tracing")]
+		tracing::$level!(target: "afg", $($field = ?$value),+, $message);
+		#[cfg(not(feature = "tracing"))]
+		log::$level!(
+			target: "afg",
+			concat!($message $(, ", ", stringify!($field), " = {:?}")+),
+			$($value),+
+		);
+	}};
+}
+
 mod past_rounds;
+pub mod replay;
 mod voting_round;
 
 /// Necessary environment for a voter.
@@ -59,6 +94,32 @@ pub trait Environment<H: Eq, N: BlockNumberOps>: Chain<H, N> {
 	type In: Stream<Item=Result<SignedMessage<H, N, Self::Signature, Self::Id>, Self::Error>> + Unpin;
 	type Out: Sink<Message<H, N>, Error=Self::Error> + Unpin;
 	type Error: From<crate::Error> + ::std::error::Error;
+	type Clock: Clock;
+
+	/// The clock the voter measures time with, e.g. `SystemClock`. The timers
+	/// produced by the environment should be driven by the same clock.
+	fn clock(&self) -> &Self::Clock;
+
+	/// Look up the best chain containing the given block, like
+	/// `Chain::best_chain_containing`, but asynchronously, e.g. while the block
+	/// is still being fetched.
+	///
+	/// Lookups for prevotes are started as soon as a round starts, and
+	/// incoming votes are still imported while they're pending. If a lookup
+	/// hasn't resolved once the prevote timer fires, the last round estimate
+	/// is prevoted instead. Errors are fatal to the voter.
+	///
+	/// By default `None` is returned and `Chain::best_chain_containing` is
+	/// used right away.
+	#[allow(clippy::type_complexity)]
+	fn best_chain(&self, _base: H) -> Option<BoxFuture<'static, Result<Option<(H, N)>, Self::Error>>> {
+		None
+	}
+
+	/// The id of the voter set we're voting in. Commits and catch-ups for other
+	/// sets are rejected, and rounds are started with this set id. Defaults to
+	/// zero.
+	fn set_id(&self) -> u64 { 0 }
 
 	/// Produce data necessary to start a round of voting.
 	///
@@ -89,6 +150,62 @@ pub trait Environment<H: Eq, N: BlockNumberOps>: Chain<H, N> {
 	/// commit messages that are sent (e.g. random value in [0, 1] seconds).
 	fn round_commit_timer(&self) -> Self::Timer;
 
+	/// Return a timer for the given phase of a round, replacing the
+	/// corresponding timer from `round_data` or `round_commit_timer`. This
+	/// allows adding jitter to timers, so that voters don't all act at once, or
+	/// adapting them to how long previous rounds took (see
+	/// `Voter::last_round_duration`).
+	///
+	/// By default `None` is returned, and the other timers are used.
+	fn round_timer(&self, _round: u64, _phase: Phase) -> Option<Self::Timer> { None }
+
+	/// Decide on the primary proposal to make in the given round, where we are
+	/// the primary. The proposal for the last round's estimate is given and
+	/// returned by default.
+	///
+	/// Return `None` to suppress the proposal, or a proposal for another
+	/// block to substitute it, e.g. a descendent of the estimate that is known
+	/// to be widely available. A substitute must be equal to or a descendent of
+	/// the estimate, otherwise it is ignored and the estimate is proposed. While
+	/// no proposal has been made, this may be called again until we prevote.
+	fn primary_proposal(
+		&self,
+		_round: u64,
+		estimate: PrimaryPropose<H, N>,
+	) -> Result<Option<PrimaryPropose<H, N>>, Self::Error> {
+		Ok(Some(estimate))
+	}
+
+	/// Restrict the target of a vote we're about to cast in the given round,
+	/// e.g. to apply voting rules such as not voting for blocks more than a
+	/// number of blocks beyond the last finalized one.
+	///
+	/// For prevotes, `base` is the block the prevote builds on (the last
+	/// round estimate, or the primary's block), `best_target` the best chain
+	/// containing it and `current_target` the block we'd prevote for. For
+	/// precommits, `base` is the round base, and both targets are the
+	/// prevote-GHOST we'd precommit for.
+	///
+	/// Once the returned future resolves to `Some` block, the vote is cast for
+	/// it instead, as long as it's equal to or a descendent of `base` and equal
+	/// to or an ancestor of `current_target`. Otherwise the restriction is
+	/// ignored. The vote isn't cast while the future is pending, and errors are
+	/// fatal to the voter. A prevote for the last round estimate, cast because
+	/// the best chain wasn't known in time, isn't restricted.
+	///
+	/// By default `None` is returned and votes aren't restricted.
+	#[allow(clippy::type_complexity)]
+	fn restrict_vote(
+		&self,
+		_round: u64,
+		_phase: Phase,
+		_base: (H, N),
+		_best_target: (H, N),
+		_current_target: (H, N),
+	) -> Option<BoxFuture<'static, Result<Option<(H, N)>, Self::Error>>> {
+		None
+	}
+
 	/// Note that we've done a primary proposal in the given round.
 	fn proposed(&self, round: u64, propose: PrimaryPropose<H, N>) -> Result<(), Self::Error>;
 
@@ -98,6 +215,32 @@ pub trait Environment<H: Eq, N: BlockNumberOps>: Chain<H, N> {
 	/// Note that we have precommitted in the given round.
 	fn precommitted(&self, round: u64, precommit: Precommit<H, N>) -> Result<(), Self::Error>;
 
+	/// Note that sending on the global outgoing sink failed, e.g. because its
+	/// connection was closed. This isn't fatal to the voter, so that the sink
+	/// can reconnect meanwhile: commits which weren't handed to the sink yet
+	/// are kept, and sending them is retried after a second. By default this
+	/// is ignored, besides the warning logged by the voter.
+	fn global_out_error(&self, _error: Self::Error) {}
+
+	/// Note that a commit for the given round was never handed to the global
+	/// outgoing sink, because the voter stopped with an error before the sink
+	/// accepted it, e.g. to send it by other means. Commits are held back
+	/// while the sink is congested or failing, so this is only called for
+	/// those still waiting when the voter fails. By default this is ignored.
+	fn commit_not_sent(&self, _round: u64, _commit: Commit<H, N, Self::Signature, Self::Id>) {}
+
+	/// Note that the finalized block hasn't advanced for the given number of
+	/// rounds, with the given block number finalized last, once stall
+	/// detection is enabled with `Voter::set_stall_detection`. This is called
+	/// once the threshold is reached, and again whenever the number of rounds
+	/// doubles while the stall lasts. By default this is ignored.
+	fn finality_stalled(&self, _rounds_without_progress: u64, _last_finalized: N) {}
+
+	/// Note that the finalized block advanced to the given number again, after
+	/// `finality_stalled` was called. The number of rounds the stall lasted is
+	/// given. By default this is ignored.
+	fn finality_resumed(&self, _rounds_without_progress: u64, _finalized: N) {}
+
 	/// Note that a round was completed. This is called when a round has been
 	/// voted in. Should return an error when something fatal occurs.
 	fn completed(
@@ -108,6 +251,20 @@ pub trait Environment<H: Eq, N: BlockNumberOps>: Chain<H, N> {
 		votes: &HistoricalVotes<H, N, Self::Signature, Self::Id>,
 	) -> Result<(), Self::Error>;
 
+	/// Whether the given block is in our chain database. Votes for blocks
+	/// which aren't available yet, and incoming commits for such blocks above
+	/// the last finalized one, are kept pending rather than rejected until
+	/// `Voter::block_imported` is called for their target (or
+	/// `Voter::note_block_available`, for commits only). By default all
+	/// blocks are available.
+	fn block_available(&self, _hash: H) -> bool { true }
+
+	/// Request a catch-up to the given round, since a commit for it finalized
+	/// a block beyond the estimate of our current round, i.e. we're lagging
+	/// behind. A catch-up received in response is imported from the global
+	/// incoming stream as usual. By default this is ignored.
+	fn request_catch_up(&self, _round: u64) {}
+
 	/// Called when a block should be finalized.
 	// TODO: make this a future that resolves when it's e.g. written to disk?
 	fn finalize_block(&self, hash: H, number: N, round: u64, commit: Commit<H, N, Self::Signature, Self::Id>) -> Result<(), Self::Error>;
@@ -116,15 +273,158 @@ pub trait Environment<H: Eq, N: BlockNumberOps>: Chain<H, N> {
 	fn prevote_equivocation(&self, round: u64, equivocation: Equivocation<Self::Id, Prevote<H, N>, Self::Signature>);
 	// Note that an equivocation in precommits has occurred.
 	fn precommit_equivocation(&self, round: u64, equivocation: Equivocation<Self::Id, Precommit<H, N>, Self::Signature>);
+
+	/// Note that the best chain didn't build on the expected block when
+	/// constructing a prevote in the given round. By default this is ignored,
+	/// besides the warning logged by the voter.
+	fn best_chain_issue(&self, _round: u64, _issue: BestChainIssue<H, N>) {}
+
+	/// Note that the weight of equivocating voters in a round exceeds what the
+	/// protocol can tolerate. This is emitted at most once per round, and the
+	/// round will not be completed or finalize any further blocks afterwards.
+	/// Return an error to halt the voter. By default this is ignored, besides
+	/// the warning logged by the voter.
+	fn too_many_equivocations(&self, _event: TooManyEquivocations<Self::Id>) -> Result<(), Self::Error> {
+		Ok(())
+	}
+
+	/// Called when a round is started on top of the previous one, with a
+	/// stream of the previous round's state. The stream yields whenever that
+	/// state changes, e.g. once its estimate is finalized, and ends when the
+	/// previous round is dropped. By default the stream is ignored.
+	fn prior_round_state(&self, _round: u64, _states: RoundStateStream<H, N>) {}
+
+	/// Sign a commit we're about to broadcast for the given round. Once the
+	/// returned future resolves, the commit is broadcast as
+	/// `CommunicationOut::SignedCommit`, and errors are fatal to the voter.
+	///
+	/// By default `None` is returned and commits are broadcast unsigned, as
+	/// `CommunicationOut::Commit`.
+	#[allow(clippy::type_complexity)]
+	fn sign_commit(
+		&self,
+		_round: u64,
+		_commit: &Commit<H, N, Self::Signature, Self::Id>,
+	) -> Option<BoxFuture<'static, Result<SignedCommit<H, N, Self::Signature, Self::Id>, Self::Error>>> {
+		None
+	}
+
+	/// Verify the signature on an incoming signed commit for the given round,
+	/// e.g. with `SignedCommit::verify`. Commits which fail verification are
+	/// dropped before being validated. There is no default, so that every
+	/// environment decides how signed commits are checked; environments which
+	/// never receive them can return `false`.
+	fn verify_commit_signature(
+		&self,
+		round: u64,
+		commit: &SignedCommit<H, N, Self::Signature, Self::Id>,
+	) -> bool;
+}
+
+/// An unexpected answer from `Environment::best_chain` when constructing a
+/// prevote, e.g. because the chain was reorged between rounds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+pub enum BestChainIssue<H, N> {
+	/// No best chain containing the block we're building on is known. No
+	/// prevote is cast, and the query is retried on the next poll.
+	Unknown {
+		/// The block we're building on.
+		base: (H, N),
+	},
+	/// The best chain returned doesn't contain the block we're building on.
+	/// The prevote is cast for the last round estimate instead.
+	NotDescendent {
+		/// The block we're building on.
+		base: (H, N),
+		/// The best block returned.
+		best: (H, N),
+	},
+	/// The lookup didn't resolve before the prevote timer fired. The prevote
+	/// is cast for the last round estimate instead.
+	Unresolved {
+		/// The block we're building on.
+		base: (H, N),
+	},
+}
+
+/// Raised when voters with more than the tolerated fault weight (i.e. the
+/// total weight minus the supermajority threshold) have equivocated in a
+/// round, either in prevotes or in precommits. Equivocators are counted as
+/// voting for everything, so the round's results can no longer be trusted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+pub struct TooManyEquivocations<Id> {
+	/// The round number.
+	pub round: u64,
+	/// The total equivocation weight in prevotes and precommits.
+	pub weight: (u64, u64),
+	/// The voters who have equivocated, in prevotes or precommits.
+	pub equivocators: Vec<Id>,
 }
 
 /// Communication between nodes that is not round-localized.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
 pub enum CommunicationOut<H, N, S, Id> {
 	/// A commit message.
+	#[cfg_attr(feature = "derive-codec", codec(index = "0"))]
 	Commit(u64, Commit<H, N, S, Id>),
+	/// A commit message signed with `Environment::sign_commit`.
+	#[cfg_attr(feature = "derive-codec", codec(index = "1"))]
+	SignedCommit(u64, SignedCommit<H, N, S, Id>),
+}
+
+impl<H, N: Copy, S, Id> CommunicationOut<H, N, S, Id> {
+	// the round of the commit, and the number of the block it finalizes.
+	fn round_and_target(&self) -> (u64, N) {
+		match *self {
+			CommunicationOut::Commit(round, ref commit) => (round, commit.target_number),
+			CommunicationOut::SignedCommit(round, ref signed) => (round, signed.commit.target_number),
+		}
+	}
 }
 
+/// The outcome of routing a round message with `Voter::route_message`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoutingOutcome {
+	/// The message was imported into the current round or a past round which
+	/// is still running in the background.
+	Accepted,
+	/// The message is for a future round and was buffered until that round
+	/// starts.
+	Buffered,
+	/// The message is for a past round which isn't running anymore and was
+	/// dropped.
+	TooOld,
+	/// The message is for a future round, but too many messages are buffered
+	/// for it already. It was dropped.
+	BufferFull,
+	/// The message is for a round further ahead of the current one than
+	/// messages are buffered for, see `Voter::set_future_round_limit`. It was
+	/// dropped, and its sender may be reported for misbehavior.
+	TooFarAhead,
+}
+
+// the maximum number of messages buffered for each future round.
+const MAX_BUFFERED_FUTURE_MESSAGES: usize = 4096;
+
+// how many rounds ahead of the current one messages are buffered by default.
+const DEFAULT_FUTURE_ROUND_LIMIT: u64 = 1;
+
+// how long to wait before sending on the global outgoing sink again after it
+// failed.
+const GLOBAL_OUT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+// how many commits are queued for the global outgoing sink by default.
+const DEFAULT_GLOBAL_OUT_QUEUE_LIMIT: usize = 16;
+
+// how many items of past rounds are processed per poll of the voter by default.
+const DEFAULT_PAST_ROUNDS_BUDGET: usize = 4;
+
+// how many incoming commits wait for their target to become available.
+const MAX_DEFERRED_COMMITS: usize = 16;
+
 /// The outcome of processing a commit.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CommitProcessingOutcome {
@@ -285,8 +585,384 @@ impl<O> Callback<O> {
 pub enum CommunicationIn<H, N, S, Id> {
 	/// A commit message.
 	Commit(u64, CompactCommit<H, N, S, Id>, Callback<CommitProcessingOutcome>),
+	/// A signed commit message. The signature is checked with
+	/// `Environment::verify_commit_signature` before the commit is processed.
+	SignedCommit(u64, SignedCommit<H, N, S, Id>, Callback<CommitProcessingOutcome>),
 	/// A catch up message.
 	CatchUp(CatchUp<H, N, S, Id>, Callback<CatchUpProcessingOutcome>),
+	/// A batch of messages, e.g. received together from the network. They are
+	/// processed in order, as if they were yielded by the stream one by one,
+	/// but without waking the voter for each of them.
+	Batch(Vec<CommunicationIn<H, N, S, Id>>),
+}
+
+/// A phase of a round, e.g. one that a timer is requested for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+pub enum Phase {
+	/// Waiting before prevotes are cast.
+	Prevote,
+	/// Waiting before precommits are cast.
+	Precommit,
+	/// Waiting before a commit for a past round is broadcast.
+	Commit,
+}
+
+/// An external trigger for a phase of a round, e.g. from a slot-based block
+/// production scheme. It substitutes for the timer of that phase when the
+/// voter is driven by a `Pacemaker`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacemakerEvent {
+	/// The round the phase belongs to.
+	pub round: u64,
+	/// The phase which may start now.
+	pub phase: Phase,
+}
+
+/// A stream of pacemaker events, which drives the phases of the voter's
+/// rounds instead of the timers of the environment. See
+/// `Voter::set_pacemaker`.
+pub trait Pacemaker: Stream<Item=PacemakerEvent> + Send + Unpin {}
+
+impl<T: Stream<Item=PacemakerEvent> + Send + Unpin> Pacemaker for T {}
+
+/// The outcome of importing a prevote or precommit into a round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoteImportOutcome {
+	/// The vote was imported.
+	Imported,
+	/// The voter had already cast the same vote.
+	Duplicated,
+	/// The vote conflicts with one the voter cast before.
+	Equivocation,
+	/// The voter isn't part of the voter set.
+	InvalidVoter,
+	/// The vote targets a block that isn't a descendent of the round base,
+	/// so it was ignored.
+	BelowBase,
+	/// The vote targets a block that isn't available yet, so it's pending
+	/// until the block is imported.
+	Pending,
+	/// The vote targets a block that isn't available yet, and it was dropped
+	/// since the voter has a vote pending already, or too many votes are.
+	Unavailable,
+	/// The vote is for exactly the round base, which isn't counted since the
+	/// voter is strict about it (see `Voter::set_strict_base`).
+	ForBase,
+	/// The vote can't be applied to the round, e.g. since its block number
+	/// doesn't match the block, so it was ignored.
+	Malformed,
+}
+
+impl<Id, P, Signature> From<&ImportResult<Id, P, Signature>> for VoteImportOutcome {
+	fn from(result: &ImportResult<Id, P, Signature>) -> Self {
+		if !result.valid_voter {
+			VoteImportOutcome::InvalidVoter
+		} else if result.equivocation.is_some() {
+			VoteImportOutcome::Equivocation
+		} else if result.duplicated {
+			VoteImportOutcome::Duplicated
+		} else if result.vote_for_base {
+			VoteImportOutcome::ForBase
+		} else {
+			VoteImportOutcome::Imported
+		}
+	}
+}
+
+/// The reason a commit was dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitDropReason {
+	/// An incoming commit was made in another voter set.
+	OtherSet,
+	/// An incoming commit failed validation.
+	Invalid,
+	/// An incoming commit for a block which isn't available yet was dropped,
+	/// since too many commits were waiting for their blocks already.
+	Unavailable,
+	/// The signature on an incoming signed commit failed verification.
+	BadSignature,
+	/// An incoming commit for a past round targets a block lower than the
+	/// one already finalized in that round.
+	Obsolete,
+	/// No commit was broadcast for a past round, since a commit for the same
+	/// or a higher block was imported in the meantime.
+	Superseded,
+	/// No commit was broadcast for a past round, since we didn't finalize
+	/// anything in it with our own votes.
+	NotFinalized,
+	/// No commit was broadcast for a past round, since its commit timer
+	/// failed.
+	TimerFailed,
+	/// No commit was broadcast for a past round, since it doesn't finalize a
+	/// block far enough above the last commit broadcast, see
+	/// `Voter::set_min_commit_advance`.
+	TooLittleAdvance,
+	/// A valid incoming commit finalizes a block too far above the last one
+	/// we finalized, see `Voter::set_max_commit_gap`.
+	GapTooLarge,
+	/// A commit queued for broadcasting was dropped before it went out, since
+	/// a commit of a later round was queued after it, or the queue was full.
+	Elided,
+}
+
+/// Hooks for instrumenting a voter, e.g. to export metrics. These also cover
+/// events internal to the voter, which aren't visible to the environment.
+///
+/// All methods do nothing by default, and so does the implementation for `()`.
+pub trait Metrics: Send + Sync {
+	/// A prevote or precommit was imported into the current or a past round.
+	fn on_vote_imported(&self, _phase: Phase, _outcome: VoteImportOutcome) {}
+
+	/// A commit for a past round was produced for broadcasting.
+	fn on_commit_emitted(&self, _round: u64) {}
+
+	/// A commit was dropped, or none was broadcast for a past round.
+	fn on_commit_dropped(&self, _reason: CommitDropReason) {}
+
+	/// A new best round was started.
+	fn on_round_started(&self, _round: u64) {}
+
+	/// The best round was completed, roughly the given duration after it was
+	/// started.
+	fn on_round_completed(&self, _round: u64, _duration: Duration) {}
+
+	/// The number of past rounds running in the background changed.
+	fn on_background_rounds(&self, _count: usize) {}
+
+	/// A message routed with `Voter::route_message` for the given round was
+	/// dropped, for the reason given by the outcome.
+	fn on_message_dropped(&self, _round: u64, _outcome: RoutingOutcome) {}
+
+	/// The number of commits queued for the global outgoing sink changed.
+	fn on_global_out_queue(&self, _depth: usize) {}
+}
+
+impl Metrics for () {}
+
+impl<M: Metrics + ?Sized> Metrics for Arc<M> {
+	fn on_vote_imported(&self, phase: Phase, outcome: VoteImportOutcome) {
+		(**self).on_vote_imported(phase, outcome)
+	}
+
+	fn on_commit_emitted(&self, round: u64) {
+		(**self).on_commit_emitted(round)
+	}
+
+	fn on_commit_dropped(&self, reason: CommitDropReason) {
+		(**self).on_commit_dropped(reason)
+	}
+
+	fn on_round_started(&self, round: u64) {
+		(**self).on_round_started(round)
+	}
+
+	fn on_round_completed(&self, round: u64, duration: Duration) {
+		(**self).on_round_completed(round, duration)
+	}
+
+	fn on_background_rounds(&self, count: usize) {
+		(**self).on_background_rounds(count)
+	}
+
+	fn on_message_dropped(&self, round: u64, outcome: RoutingOutcome) {
+		(**self).on_message_dropped(round, outcome)
+	}
+
+	fn on_global_out_queue(&self, depth: usize) {
+		(**self).on_global_out_queue(depth)
+	}
+}
+
+/// Reports to both, e.g. to keep an `EventLog` along with other metrics.
+impl<A: Metrics, B: Metrics> Metrics for (A, B) {
+	fn on_vote_imported(&self, phase: Phase, outcome: VoteImportOutcome) {
+		self.0.on_vote_imported(phase, outcome);
+		self.1.on_vote_imported(phase, outcome);
+	}
+
+	fn on_commit_emitted(&self, round: u64) {
+		self.0.on_commit_emitted(round);
+		self.1.on_commit_emitted(round);
+	}
+
+	fn on_commit_dropped(&self, reason: CommitDropReason) {
+		self.0.on_commit_dropped(reason);
+		self.1.on_commit_dropped(reason);
+	}
+
+	fn on_round_started(&self, round: u64) {
+		self.0.on_round_started(round);
+		self.1.on_round_started(round);
+	}
+
+	fn on_round_completed(&self, round: u64, duration: Duration) {
+		self.0.on_round_completed(round, duration);
+		self.1.on_round_completed(round, duration);
+	}
+
+	fn on_background_rounds(&self, count: usize) {
+		self.0.on_background_rounds(count);
+		self.1.on_background_rounds(count);
+	}
+
+	fn on_message_dropped(&self, round: u64, outcome: RoutingOutcome) {
+		self.0.on_message_dropped(round, outcome);
+		self.1.on_message_dropped(round, outcome);
+	}
+
+	fn on_global_out_queue(&self, depth: usize) {
+		self.0.on_global_out_queue(depth);
+		self.1.on_global_out_queue(depth);
+	}
+}
+
+/// An event of a voter, as recorded by an `EventLog`. These correspond to the
+/// hooks of `Metrics`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VoterEvent {
+	/// A vote was imported, see `Metrics::on_vote_imported`.
+	VoteImported(Phase, VoteImportOutcome),
+	/// A commit was produced for the given round.
+	CommitEmitted(u64),
+	/// A commit was dropped, or none was broadcast for a past round.
+	CommitDropped(CommitDropReason),
+	/// The given round was started.
+	RoundStarted(u64),
+	/// The given round was completed, after about the given duration.
+	RoundCompleted(u64, Duration),
+	/// The given number of past rounds are running in the background.
+	BackgroundRounds(usize),
+	/// A routed message for the given round was dropped.
+	MessageDropped(u64, RoutingOutcome),
+	/// The given number of commits are queued for the global outgoing sink.
+	GlobalOutQueue(usize),
+}
+
+/// A bounded log of the most recent events of a voter, e.g. to dump when
+/// finality stalls. Once full, the oldest events are overwritten.
+///
+/// Set it with `Voter::set_metrics`, shared in an `Arc` to keep access to it,
+/// and paired with any other metrics to report to both.
+pub struct EventLog {
+	capacity: usize,
+	events: parking_lot::Mutex<VecDeque<VoterEvent>>,
+}
+
+impl EventLog {
+	/// Create a log keeping the given number of most recent events.
+	pub fn new(capacity: usize) -> Self {
+		EventLog {
+			capacity,
+			events: parking_lot::Mutex::new(VecDeque::with_capacity(capacity)),
+		}
+	}
+
+	/// The events in the log, oldest first.
+	pub fn dump(&self) -> Vec<VoterEvent> {
+		self.events.lock().iter().cloned().collect()
+	}
+
+	fn record(&self, event: VoterEvent) {
+		if self.capacity == 0 { return }
+
+		let mut events = self.events.lock();
+		if events.len() == self.capacity {
+			events.pop_front();
+		}
+		events.push_back(event);
+	}
+}
+
+impl Metrics for EventLog {
+	fn on_vote_imported(&self, phase: Phase, outcome: VoteImportOutcome) {
+		self.record(VoterEvent::VoteImported(phase, outcome));
+	}
+
+	fn on_commit_emitted(&self, round: u64) {
+		self.record(VoterEvent::CommitEmitted(round));
+	}
+
+	fn on_commit_dropped(&self, reason: CommitDropReason) {
+		self.record(VoterEvent::CommitDropped(reason));
+	}
+
+	fn on_round_started(&self, round: u64) {
+		self.record(VoterEvent::RoundStarted(round));
+	}
+
+	fn on_round_completed(&self, round: u64, duration: Duration) {
+		self.record(VoterEvent::RoundCompleted(round, duration));
+	}
+
+	fn on_background_rounds(&self, count: usize) {
+		self.record(VoterEvent::BackgroundRounds(count));
+	}
+
+	fn on_message_dropped(&self, round: u64, outcome: RoutingOutcome) {
+		self.record(VoterEvent::MessageDropped(round, outcome));
+	}
+
+	fn on_global_out_queue(&self, depth: usize) {
+		self.record(VoterEvent::GlobalOutQueue(depth));
+	}
+}
+
+/// A source of time for a voter. Rounds are timed with it, and so should be
+/// the timers of the environment, so that the timing of a voter can be
+/// simulated with a manual clock.
+pub trait Clock {
+	/// A point in time.
+	type Instant: Copy + Ord + Add<Duration, Output=Self::Instant> + Sub<Output=Duration>;
+	/// A timer, resolving once a point in time has been reached.
+	type Timer: Future<Output=()> + Unpin;
+
+	/// The current point in time.
+	fn now(&self) -> Self::Instant;
+
+	/// A timer resolving at the given point in time.
+	fn timer_at(&self, at: Self::Instant) -> Self::Timer;
+
+	/// A timer resolving after the given duration.
+	fn timer(&self, duration: Duration) -> Self::Timer {
+		self.timer_at(self.now() + duration)
+	}
+}
+
+/// The system clock, with timers from `futures-timer`.
+///
+/// It's not available on wasm, which has no system time, and where timers
+/// need a clock of the environment, e.g. one backed by the browser's.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Clock for SystemClock {
+	type Instant = std::time::Instant;
+	type Timer = SystemTimer;
+
+	fn now(&self) -> Self::Instant {
+		std::time::Instant::now()
+	}
+
+	fn timer_at(&self, at: Self::Instant) -> Self::Timer {
+		SystemTimer(Delay::new_at(at))
+	}
+}
+
+/// A timer of the `SystemClock`.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct SystemTimer(Delay);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Future for SystemTimer {
+	type Output = ();
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+		// a failed timer only means it fires early.
+		Future::poll(Pin::new(&mut self.0), cx).map(|_| ())
+	}
 }
 
 /// Data necessary to participate in a round.
@@ -300,12 +976,16 @@ pub struct RoundData<Id, Timer, Input, Output> {
 	pub precommit_timer: Timer,
 	/// Incoming messages.
 	pub incoming: Input,
-	/// Outgoing messages.
+	/// Outgoing messages. Votes are only cast while this sink accepts
+	/// messages, so a congested sink delays them rather than having them
+	/// buffered.
 	pub outgoing: Output,
 }
 
 struct Buffered<S, I> {
 	inner: S,
+	// a replaced sink, which must be flushed before `inner` is used.
+	retiring: Option<S>,
 	buffer: VecDeque<I>,
 }
 
@@ -313,6 +993,7 @@ impl<S: Sink<I> + Unpin, I> Buffered<S, I> {
 	fn new(inner: S) -> Buffered<S, I> {
 		Buffered {
 			buffer: VecDeque::new(),
+			retiring: None,
 			inner
 		}
 	}
@@ -323,8 +1004,31 @@ impl<S: Sink<I> + Unpin, I> Buffered<S, I> {
 		self.buffer.push_back(item);
 	}
 
+	// take the items which weren't sent on the sink yet.
+	fn take_buffered(&mut self) -> VecDeque<I> {
+		std::mem::take(&mut self.buffer)
+	}
+
+	// replace the underlying sink. items already sent to the old sink are
+	// flushed there, and any buffered items go out on the new one afterwards.
+	// the sink _must_ be driven to completion with `poll` afterwards.
+	fn set_inner(&mut self, inner: S) {
+		let old = std::mem::replace(&mut self.inner, inner);
+
+		// nothing is sent on a sink while another one is retiring, so if we
+		// replace a sink twice the intermediate one can just be dropped.
+		if self.retiring.is_none() {
+			self.retiring = Some(old);
+		}
+	}
+
 	// returns ready when the sink and the buffer are completely flushed.
 	fn poll(&mut self, cx: &mut Context) -> Poll<Result<(), S::Error>> {
+		if let Some(ref mut retiring) = self.retiring {
+			ready!(Sink::poll_flush(Pin::new(retiring), cx))?;
+			self.retiring = None;
+		}
+
 		let polled = self.schedule_all(cx)?;
 
 		match polled {
@@ -336,6 +1040,18 @@ impl<S: Sink<I> + Unpin, I> Buffered<S, I> {
 		}
 	}
 
+	// returns ready when all pushed items went out and the sink accepts
+	// another one, e.g. to hold off producing items while it's congested.
+	fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), S::Error>> {
+		if let Some(ref mut retiring) = self.retiring {
+			ready!(Sink::poll_flush(Pin::new(retiring), cx))?;
+			self.retiring = None;
+		}
+
+		ready!(self.schedule_all(cx))?;
+		Sink::poll_ready(Pin::new(&mut self.inner), cx)
+	}
+
 	fn schedule_all(&mut self, cx: &mut Context) -> Poll<Result<(), S::Error>> {
 		while !self.buffer.is_empty() {
 			ready!(Sink::poll_ready(Pin::new(&mut self.inner), cx))?;
@@ -349,6 +1065,57 @@ impl<S: Sink<I> + Unpin, I> Buffered<S, I> {
 	}
 }
 
+// counts the rounds in which the finalized block didn't advance, to tell
+// when finality stalls and when it resumes again.
+struct StallDetector<N> {
+	threshold: u64,
+	// rounds which don't complete count once per this duration.
+	round_duration: Duration,
+	// the finalized number at the last progress.
+	finalized: N,
+	rounds_without_progress: u64,
+	// the number of rounds without progress at which to report next.
+	next_report: u64,
+}
+
+impl<N: Copy + PartialOrd> StallDetector<N> {
+	fn new(threshold: u64, round_duration: Duration, finalized: N) -> Self {
+		let threshold = std::cmp::max(threshold, 1);
+		// timers for a zero duration fire right away, and would be re-armed
+		// over and over.
+		let round_duration = std::cmp::max(round_duration, Duration::from_millis(1));
+		StallDetector {
+			threshold,
+			round_duration,
+			finalized,
+			rounds_without_progress: 0,
+			next_report: threshold,
+		}
+	}
+
+	// note the finalized number, returning the number of rounds finality was
+	// stalled for if it resumes now.
+	fn progress(&mut self, finalized: N) -> Option<u64> {
+		if finalized <= self.finalized { return None }
+
+		let rounds = std::mem::replace(&mut self.rounds_without_progress, 0);
+		self.finalized = finalized;
+		self.next_report = self.threshold;
+
+		if rounds >= self.threshold { Some(rounds) } else { None }
+	}
+
+	// note that a round passed without progress, returning the number of rounds
+	// finality is stalled for if that should be reported now.
+	fn round_passed(&mut self) -> Option<u64> {
+		self.rounds_without_progress += 1;
+		if self.rounds_without_progress < self.next_report { return None }
+
+		self.next_report = self.next_report.saturating_mul(2);
+		Some(self.rounds_without_progress)
+	}
+}
+
 type FinalizedNotification<H, N, E> = (
 	H,
 	N,
@@ -356,6 +1123,74 @@ type FinalizedNotification<H, N, E> = (
 	Commit<H, N, <E as Environment<H, N>>::Signature, <E as Environment<H, N>>::Id>,
 );
 
+type SignedMessageFor<H, N, E> = SignedMessage<
+	H,
+	N,
+	<E as Environment<H, N>>::Signature,
+	<E as Environment<H, N>>::Id,
+>;
+
+type SignedCommitFor<H, N, E> = SignedCommit<
+	H,
+	N,
+	<E as Environment<H, N>>::Signature,
+	<E as Environment<H, N>>::Id,
+>;
+
+// whether the precommits of distinct voters in the set for the commit target
+// or its descendents may add up to the threshold, so that the commit may be
+// valid.
+fn precommit_weight_reaches_threshold<H, N, S, Id>(commit: &Commit<H, N, S, Id>, voters: &VoterSet<Id>) -> bool where
+	N: Copy + BlockNumberOps,
+	Id: Hash + Clone + Eq,
+{
+	let mut counted = HashSet::new();
+	let weight: u64 = commit.precommits.iter()
+		.filter(|signed| signed.precommit.target_number >= commit.target_number)
+		.filter(|signed| counted.insert(signed.id.clone()))
+		.filter_map(|signed| voters.get(&signed.id))
+		.map(|info| info.weight())
+		.sum();
+
+	weight >= voters.threshold()
+}
+
+// tell the sender of a commit which isn't processed that it was of no use.
+fn run_bad_commit_outcome<H, N, S, Id>(
+	commit: &Commit<H, N, S, Id>,
+	mut process_commit_outcome: Callback<CommitProcessingOutcome>,
+) {
+	let validation_result = CommitValidationResult::<H, N> {
+		num_precommits: commit.precommits.len(),
+		..Default::default()
+	};
+	process_commit_outcome.run(CommitProcessingOutcome::Bad(BadCommit::from(validation_result)));
+}
+
+// an incoming commit for a block which isn't available yet, along with its
+// round and the callback to run once it's processed.
+type DeferredCommit<H, N, E> = (
+	u64,
+	Commit<H, N, <E as Environment<H, N>>::Signature, <E as Environment<H, N>>::Id>,
+	Callback<CommitProcessingOutcome>,
+);
+
+// a commit of a past round being signed with `Environment::sign_commit`,
+// which resolves to the round number along with the signed commit.
+struct SigningCommit<T, Error> {
+	round_number: u64,
+	signing: BoxFuture<'static, Result<T, Error>>,
+}
+
+impl<T, Error> Future for SigningCommit<T, Error> {
+	type Output = (u64, Result<T, Error>);
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+		let signed = ready!(self.signing.poll_unpin(cx));
+		Poll::Ready((self.round_number, signed))
+	}
+}
+
 /// A future that maintains and multiplexes between different rounds,
 /// and caches votes.
 ///
@@ -364,12 +1199,12 @@ type FinalizedNotification<H, N, E> = (
 /// given block and includes a set of precommits as proof.
 ///
 /// - When a round is completable and we precommitted we start a commit timer
-/// and start accepting commit messages;
+///   and start accepting commit messages;
 /// - When we receive a commit message if it targets a block higher than what
-/// we've finalized we validate it and import its precommits if valid;
+///   we've finalized we validate it and import its precommits if valid;
 /// - When our commit timer triggers we check if we've received any commit
-/// message for a block equal to what we've finalized, if we haven't then we
-/// broadcast a commit.
+///   message for a block equal to what we've finalized, if we haven't then we
+///   broadcast a commit.
 ///
 /// Additionally, we also listen to commit messages from rounds that aren't
 /// currently running, we validate the commit and dispatch a finalization
@@ -377,8 +1212,10 @@ type FinalizedNotification<H, N, E> = (
 pub struct Voter<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> where
 	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
 	N: Copy + BlockNumberOps + ::std::fmt::Debug,
-	GlobalIn: Stream<Item=Result<CommunicationIn<H, N, E::Signature, E::Id>, E::Error>> + Unpin,
-	GlobalOut: Sink<CommunicationOut<H, N, E::Signature, E::Id>, Error=E::Error> + Unpin,
+	GlobalIn: TryStream<Ok=CommunicationIn<H, N, E::Signature, E::Id>> + Unpin,
+	GlobalIn::Error: Into<E::Error>,
+	GlobalOut: Sink<CommunicationOut<H, N, E::Signature, E::Id>> + Unpin,
+	<GlobalOut as Sink<CommunicationOut<H, N, E::Signature, E::Id>>>::Error: Into<E::Error>,
 {
 	env: Arc<E>,
 	voters: VoterSet<E::Id>,
@@ -386,19 +1223,61 @@ pub struct Voter<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> where
 	past_rounds: PastRounds<H, N, E>,
 	finalized_notifications: UnboundedReceiver<FinalizedNotification<H, N, E>>,
 	last_finalized_number: N,
+	last_finalized_hash: H,
 	global_in: GlobalIn,
 	global_out: Buffered<GlobalOut, CommunicationOut<H, N, E::Signature, E::Id>>,
+	// set while waiting to retry sending on `global_out` after it failed.
+	global_out_retry: Option<<E::Clock as Clock>::Timer>,
+	// commits waiting for `global_out` to accept them, oldest first.
+	global_out_queue: VecDeque<CommunicationOut<H, N, E::Signature, E::Id>>,
+	global_out_queue_limit: usize,
+	// how many items of past rounds are processed per poll, so that the best
+	// round isn't held up by busy background rounds.
+	past_rounds_budget: usize,
 	// the commit protocol might finalize further than the current round (if we're
 	// behind), we keep track of last finalized in round so we don't violate any
 	// assumptions from round-to-round.
 	last_finalized_in_rounds: (H, N),
+	// messages routed to us for rounds we haven't started yet, by round number.
+	future_messages: BTreeMap<u64, Vec<SignedMessageFor<H, N, E>>>,
+	n_future_messages: usize,
+	// how many rounds ahead of the best round messages are buffered.
+	future_round_limit: u64,
+	// how far above our finality incoming commits may finalize, if limited.
+	max_commit_gap: Option<N>,
+	last_round_duration: Option<Duration>,
+	metrics: Arc<dyn Metrics>,
+	// commits of past rounds which are being signed before being broadcast.
+	signing_commits: FuturesUnordered<SigningCommit<SignedCommitFor<H, N, E>, E::Error>>,
+	// if set, drives the phases of rounds instead of their timers.
+	pacemaker: Option<stream::Fuse<Box<dyn Pacemaker>>>,
+	// whether casting votes is paused.
+	paused: bool,
+	// whether rounds leave out votes for their base.
+	strict_base: bool,
+	// the maximum length of the ancestry fetched for a vote in a round.
+	max_ancestry_len: usize,
+	// the last estimate of the best round, and those listening for updates.
+	last_estimate: Option<(H, N)>,
+	estimate_senders: Vec<mpsc::UnboundedSender<(H, N)>>,
+	// incoming commits waiting for their target to become available.
+	deferred_commits: Vec<DeferredCommit<H, N, E>>,
+	// if enabled, detects stalled finality, timing rounds which don't complete.
+	stall_detector: Option<StallDetector<N>>,
+	stall_timer: Option<<E::Clock as Clock>::Timer>,
+	// blocks finalized since `poll_finality` last yielded, once it's used.
+	newly_finalized: Option<VecDeque<(H, N)>>,
+	// the error the voter failed with, once `poll_finality` saw it.
+	finality_error: Option<E::Error>,
 }
 
 impl<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> Voter<H, N, E, GlobalIn, GlobalOut> where
 	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
 	N: Copy + BlockNumberOps + ::std::fmt::Debug,
-	GlobalIn: Stream<Item=Result<CommunicationIn<H, N, E::Signature, E::Id>, E::Error>> + Unpin,
-	GlobalOut: Sink<CommunicationOut<H, N, E::Signature, E::Id>, Error=E::Error> + Unpin,
+	GlobalIn: TryStream<Ok=CommunicationIn<H, N, E::Signature, E::Id>> + Unpin,
+	GlobalIn::Error: Into<E::Error>,
+	GlobalOut: Sink<CommunicationOut<H, N, E::Signature, E::Id>> + Unpin,
+	<GlobalOut as Sink<CommunicationOut<H, N, E::Signature, E::Id>>>::Error: Into<E::Error>,
 {
 	/// Create new `Voter` tracker with given round number and base block.
 	///
@@ -407,9 +1286,15 @@ impl<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> Voter<H, N, E, GlobalIn, G
 	/// should be provided.
 	///
 	/// The input stream for commit messages should provide commits which
-	/// correspond to known blocks only (including all its precommits). It
+	/// correspond to known blocks only (including all its precommits), except
+	/// for commits whose target isn't `Environment::block_available` yet. It
 	/// is also responsible for validating the signature data in commit
 	/// messages.
+	///
+	/// The errors of the global comms only need to convert into those of the
+	/// environment. Errors of the incoming stream are fatal, while those of
+	/// the outgoing sink are reported with `Environment::global_out_error` and
+	/// sending is retried.
 	pub fn new(
 		env: Arc<E>,
 		voters: VoterSet<E::Id>,
@@ -443,722 +1328,5005 @@ impl<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> Voter<H, N, E, GlobalIn, G
 			past_rounds: PastRounds::new(),
 			finalized_notifications,
 			last_finalized_number,
+			last_finalized_hash: last_finalized.0.clone(),
 			last_finalized_in_rounds: last_finalized,
 			global_in,
 			global_out: Buffered::new(global_out),
+			global_out_retry: None,
+			global_out_queue: VecDeque::new(),
+			global_out_queue_limit: DEFAULT_GLOBAL_OUT_QUEUE_LIMIT,
+			past_rounds_budget: DEFAULT_PAST_ROUNDS_BUDGET,
+			future_messages: BTreeMap::new(),
+			n_future_messages: 0,
+			future_round_limit: DEFAULT_FUTURE_ROUND_LIMIT,
+			max_commit_gap: None,
+			last_round_duration: None,
+			metrics: Arc::new(()),
+			signing_commits: FuturesUnordered::new(),
+			pacemaker: None,
+			paused: false,
+			strict_base: false,
+			max_ancestry_len: crate::vote_graph::DEFAULT_MAX_ANCESTRY_LEN,
+			last_estimate: None,
+			estimate_senders: Vec::new(),
+			deferred_commits: Vec::new(),
+			stall_detector: None,
+			stall_timer: None,
+			newly_finalized: None,
+			finality_error: None,
 		}
 	}
 
-	fn prune_background_rounds(&mut self, cx: &mut Context) -> Result<(), E::Error> {
-		// Do work on all background rounds, broadcasting any commits generated.
-		while let Poll::Ready(Some(item)) = Stream::poll_next(Pin::new(&mut self.past_rounds), cx) {
-			let (number, commit) = item?;
-			self.global_out.push(CommunicationOut::Commit(number, commit));
-		}
+	/// Replace the sink for outgoing messages of the current round, e.g. after
+	/// a network reconfiguration. Messages already sent to the previous sink are
+	/// flushed before any further messages are sent on the new one.
+	///
+	/// Later rounds get their sinks from `Environment::round_data` as usual.
+	pub fn set_round_outgoing_sink(&mut self, outgoing: E::Out) {
+		self.best_round.set_outgoing_sink(outgoing);
+	}
 
-		while let Poll::Ready(res) = Stream::poll_next(Pin::new(&mut self.finalized_notifications), cx) {
-			let (f_hash, f_num, round, commit) =
-				res.expect("one sender always kept alive in self.best_round; qed");
+	/// Buffer messages routed with `route_message` for up to the given number
+	/// of rounds ahead of the current one, until their round starts. Messages
+	/// for rounds further ahead are dropped as `RoutingOutcome::TooFarAhead`.
+	/// Defaults to one, i.e. only messages for the next round are buffered.
+	pub fn set_future_round_limit(&mut self, limit: u64) {
+		self.future_round_limit = limit;
+	}
 
+	/// Queue at most the given number of commits while the global outgoing
+	/// sink is congested. Queued commits of earlier rounds are dropped when
+	/// one of a later round is queued, and the oldest ones when the queue is
+	/// full, but the latest commit is always kept. Defaults to 16.
+	pub fn set_global_out_queue_limit(&mut self, limit: usize) {
+		self.global_out_queue_limit = limit;
+	}
 
-			self.past_rounds.update_finalized(f_num);
+	/// Process at most the given number of commits of past rounds each time
+	/// the voter is polled, after the current round and incoming messages.
+	/// If there are more, the voter wakes itself up to process them on the
+	/// next poll, so that neither side is starved. At least one is processed
+	/// per poll. Defaults to 4.
+	pub fn set_past_rounds_budget(&mut self, budget: usize) {
+		self.past_rounds_budget = budget;
+	}
 
-			if self.set_last_finalized_number(f_num) {
-				self.env.finalize_block(f_hash.clone(), f_num, round, commit)?;
-			}
+	/// Keep past rounds for the given duration after they become irrelevant,
+	/// so that catch-up messages for them can still be assembled with
+	/// `past_round_catch_up`. Rounds don't produce commits during that window.
+	/// By default rounds are dropped as soon as they become irrelevant.
+	pub fn set_retain_finalized_for(&mut self, retain_finalized_for: Duration) {
+		self.past_rounds.set_retain_finalized_for(retain_finalized_for);
+	}
 
-			if f_num > self.last_finalized_in_rounds.1 {
-				self.last_finalized_in_rounds = (f_hash, f_num);
-			}
-		}
+	/// Keep the given number of the latest rounds which are no longer the
+	/// best round in `completed_rounds`, dropping the oldest ones beyond
+	/// that. Zero keeps none. Defaults to 16.
+	pub fn set_completed_rounds_capacity(&mut self, capacity: usize) {
+		self.past_rounds.completed_rounds().set_capacity(capacity);
+	}
 
-		Ok(())
+	/// A handle to the latest rounds which are no longer the best round, with
+	/// their final state and votes, e.g. to answer queries about a round after
+	/// it concluded. The handle is cheap to clone and keeps being updated as
+	/// the voter runs.
+	pub fn completed_rounds(&self) -> CompletedRounds<H, N, E::Signature, E::Id> {
+		self.past_rounds.completed_rounds()
 	}
 
-	/// Process all incoming messages from other nodes.
+	/// Drive the voter like polling it as a future, yielding each block it
+	/// finalizes from then on exactly once, in increasing order. This includes
+	/// blocks finalized with commits and with `set_last_finalized`. Blocks
+	/// finalized before the first call aren't yielded.
 	///
-	/// Commit messages are handled with extra care. If a commit message references
-	/// a currently backgrounded round, we send it to that round so that when we commit
-	/// on that round, our commit message will be informed by those that we've seen.
+	/// Yields `None` once the voter failed, after any blocks finalized before.
+	/// The failed voter isn't polled again.
 	///
-	/// Otherwise, we will simply handle the commit and issue a finalization command
-	/// to the environment.
-	fn process_incoming(&mut self, cx: &mut Context) -> Result<(), E::Error> {
-		while let Poll::Ready(Some(item)) = Stream::poll_next(Pin::new(&mut self.global_in), cx) {
-			match item? {
-				CommunicationIn::Commit(round_number, commit, mut process_commit_outcome) => {
-					trace!(target: "afg", "Got commit for round_number {:?}: target_number: {:?}, target_hash: {:?}",
-						round_number,
-						commit.target_number,
-						commit.target_hash,
-					);
+	/// Once this was called, finalized blocks are queued until they're yielded.
+	/// The queue isn't bounded, so a voter which is driven as a future after
+	/// this was called should keep calling it, or its queue keeps growing.
+	pub fn poll_finality(&mut self, cx: &mut Context) -> Poll<Option<(H, N)>> {
+		if let Some(finalized) = self.newly_finalized.get_or_insert_with(VecDeque::new).pop_front() {
+			return Poll::Ready(Some(finalized));
+		}
 
-					let commit: Commit<_, _, _, _> = commit.into();
-
-					// if the commit is for a background round dispatch to round committer.
-					// that returns Some if there wasn't one.
-					if let Some(commit) = self.past_rounds.import_commit(round_number, commit) {
-						// otherwise validate the commit and signal the finalized block
-						// (if any) to the environment
-						let validation_result = validate_commit(&commit, &self.voters, &*self.env)?;
-
-						if let Some((finalized_hash, finalized_number)) = validation_result.ghost {
-							// this can't be moved to a function because the compiler
-							// will complain about getting two mutable borrows to self
-							// (due to the call to `self.rounds.get_mut`).
-							let last_finalized_number = &mut self.last_finalized_number;
-
-							if finalized_number > *last_finalized_number {
-								*last_finalized_number = finalized_number;
-								self.env.finalize_block(finalized_hash, finalized_number, round_number, commit)?;
-							}
-							process_commit_outcome.run(CommitProcessingOutcome::Good(GoodCommit::new()));
-						} else {
-							// Failing validation of a commit is bad.
-							process_commit_outcome.run(
-								CommitProcessingOutcome::Bad(BadCommit::from(validation_result)),
-							);
-						}
-					} else {
-						// Import to backgrounded round is good.
-						process_commit_outcome.run(CommitProcessingOutcome::Good(GoodCommit::new()));
-					}
-				}
-				CommunicationIn::CatchUp(catch_up, mut process_catch_up_outcome) => {
-					trace!(target: "afg", "Got catch-up message for round {}", catch_up.round_number);
-
-					let round = if let Some(round) = validate_catch_up(
-						catch_up,
-						&*self.env,
-						&self.voters,
-						self.best_round.round_number(),
-					) {
-						round
-					} else {
-						process_catch_up_outcome.run(CatchUpProcessingOutcome::Bad(BadCatchUp::new()));
-						return Ok(());
-					};
+		if self.finality_error.is_some() {
+			return Poll::Ready(None);
+		}
 
-					let state = round.state();
+		if let Poll::Ready(Err(e)) = Future::poll(Pin::new(&mut *self), cx) {
+			warn!(target: "afg", "Voter failed, no more blocks are finalized: {:?}", e);
+			self.finality_error = Some(e);
+		}
 
-					// beyond this point, we set this round to the past and
-					// start voting in the next round.
-					let mut just_completed = VotingRound::completed(
-						round,
-						self.best_round.finalized_sender(),
-						self.env.clone(),
-					);
+		match self.newly_finalized.as_mut().and_then(|n| n.pop_front()) {
+			Some(finalized) => Poll::Ready(Some(finalized)),
+			None if self.finality_error.is_some() => Poll::Ready(None),
+			None => Poll::Pending,
+		}
+	}
 
-					let new_best = VotingRound::new(
-						just_completed.round_number() + 1,
-						self.voters.clone(),
-						self.last_finalized_in_rounds.clone(),
-						Some(just_completed.bridge_state()),
-						self.best_round.finalized_sender(),
-						self.env.clone(),
-					);
+	/// Restart the commit timer of a past round whenever a commit for a higher
+	/// block than the previous one is imported for it. Bursts of improving
+	/// commits then lead to at most one commit being broadcast, once they've
+	/// stopped. By default the timer only runs once, from when the round is
+	/// moved to the background.
+	pub fn set_reset_commit_timer(&mut self, reset_commit_timer: bool) {
+		self.past_rounds.set_reset_commit_timer(reset_commit_timer);
+	}
 
-					// update last-finalized in rounds _after_ starting new round.
-					// otherwise the base could be too eagerly set forward.
-					if let Some((f_hash, f_num)) = state.finalized.clone() {
-						if f_num > self.last_finalized_in_rounds.1 {
-							self.last_finalized_in_rounds = (f_hash, f_num);
-						}
-					}
-
-					self.env.completed(
-						just_completed.round_number(),
-						just_completed.round_state(),
-						just_completed.dag_base(),
-						just_completed.historical_votes(),
-					)?;
-
-					self.past_rounds.push(&*self.env, just_completed);
-
-					self.past_rounds.push(
-						&*self.env,
-						std::mem::replace(&mut self.best_round, new_best),
-					);
-
-					process_catch_up_outcome.run(CatchUpProcessingOutcome::Good(GoodCatchUp::new()));
-				},
-			}
-		}
+	/// Broadcast the commit of a past round right away once a supermajority
+	/// precommitted for exactly the block it finalizes, since waiting for more
+	/// precommits can't improve it anymore. This trades the deduplication of
+	/// commits across voters, which the commit timer provides, for latency. By
+	/// default all commits wait for the timer.
+	pub fn set_fast_commits(&mut self, fast_commits: bool) {
+		self.past_rounds.set_fast_commits(fast_commits);
+	}
 
-		Ok(())
+	/// Only act on valid incoming commits which finalize at most the given
+	/// number of blocks above the last block we finalized, see
+	/// `CommitValidationResult::gap_from_previous`. Commits beyond that are
+	/// reported as good, but don't finalize anything, and are reported as
+	/// dropped with `CommitDropReason::GapTooLarge`, e.g. so that the blocks
+	/// in between can be requested first. By default there is no limit.
+	pub fn set_max_commit_gap(&mut self, max_commit_gap: Option<N>) {
+		self.max_commit_gap = max_commit_gap;
 	}
 
-	// process the logic of the best round.
-	fn process_best_round(&mut self, cx: &mut Context) -> Poll<Result<(), E::Error>> {
-		// If the current `best_round` is completable and we've already precommitted,
-		// we start a new round at `best_round + 1`.
-		let should_start_next = {
-			let completable = match self.best_round.poll(cx)? {
-				Poll::Ready(()) => true,
-				Poll::Pending => false,
-			};
+	/// Only broadcast the commit of a past round if it finalizes a block at
+	/// least `min_advance` blocks above the last commit broadcast from any
+	/// round, to reduce commit traffic. The first commit is always broadcast,
+	/// and others are reported as dropped with
+	/// `CommitDropReason::TooLittleAdvance`. By default commits must advance
+	/// finality by one block, i.e. finalize a higher block than the last one.
+	/// Only applies to rounds completed afterwards.
+	pub fn set_min_commit_advance(&mut self, min_advance: N) {
+		self.past_rounds.set_min_commit_advance(min_advance);
+	}
 
-			let precommitted = match self.best_round.state() {
-				Some(&VotingRoundState::Precommitted) => true, // start when we've cast all votes.
-				_ => false,
-			};
+	/// Broadcast the commit of a past round right away when it finalizes a
+	/// block higher than any commit broadcast before, so that peers learn of
+	/// it as soon as possible. Commits which don't advance finality still wait
+	/// for the commit timer. By default all commits wait for the timer.
+	pub fn set_emit_early_commits(&mut self, emit_early_commits: bool) {
+		self.past_rounds.set_emit_early_commits(emit_early_commits);
+	}
 
-			completable && precommitted
-		};
+	/// Leave votes for exactly the base of a round out of the round, instead
+	/// of counting them (see `Round::set_strict_base`). They are reported with
+	/// `VoteImportOutcome::ForBase`. Applies to the current round and all
+	/// rounds started after. Disabled by default.
+	pub fn set_strict_base(&mut self, strict_base: bool) {
+		self.strict_base = strict_base;
+		self.best_round.set_strict_base(strict_base);
+	}
 
-		if !should_start_next { return Poll::Pending }
+	/// Set the maximum length of the ancestry fetched from the environment
+	/// for a vote, see `Round::set_max_ancestry_len`. Votes exceeding it are
+	/// ignored, so that votes far above the base of a round don't make the
+	/// voter fetch and hold arbitrary amounts of data. Applies to the current
+	/// round and all rounds started after. Defaults to
+	/// `vote_graph::DEFAULT_MAX_ANCESTRY_LEN`.
+	pub fn set_max_ancestry_len(&mut self, max_ancestry_len: usize) {
+		self.max_ancestry_len = max_ancestry_len;
+		self.best_round.set_max_ancestry_len(max_ancestry_len);
+	}
 
-		trace!(target: "afg", "Best round at {} has become completable. Starting new best round at {}",
-			self.best_round.round_number(),
-			self.best_round.round_number() + 1,
-		);
+	/// Call `Environment::finality_stalled` once the finalized block hasn't
+	/// advanced for the given number of rounds, and `finality_resumed` when
+	/// it does again. Rounds count whether they complete or not, and a round
+	/// which doesn't complete counts once for every `round_duration` it runs,
+	/// so that this also detects rounds getting stuck. Rounds in which there
+	/// was nothing new to finalize count as well. A zero round duration is
+	/// treated as one millisecond. Disabled by default.
+	pub fn set_stall_detection(&mut self, threshold: u64, round_duration: Duration) {
+		self.stall_detector = Some(StallDetector::new(threshold, round_duration, self.last_finalized_number));
+		self.stall_timer = None;
+	}
 
-		self.completed_best_round()?;
+	/// Report events of the voter to the given metrics. This should be set
+	/// right after creating the voter, since rounds which are already running
+	/// in the background keep reporting to the previous ones. The current best
+	/// round is reported as started right away.
+	pub fn set_metrics(&mut self, metrics: Arc<dyn Metrics>) {
+		self.best_round.set_metrics(metrics.clone());
+		self.past_rounds.set_metrics(metrics.clone());
+		metrics.on_round_started(self.best_round.round_number());
+		self.metrics = metrics;
+	}
 
-		// round has been updated. so we need to re-poll.
-		Future::poll(Pin::new(self), cx)
+	/// Drive the phases of rounds by the events of the given pacemaker instead
+	/// of the timers of the environment. An event substitutes for the timer of
+	/// its phase in its round, and events for rounds which aren't running are
+	/// ignored. Phases without an event only start early, e.g. once the round
+	/// is completable. This should be set right after creating the voter,
+	/// since rounds which are already running in the background keep using
+	/// their timers.
+	pub fn set_pacemaker<P: Pacemaker + 'static>(&mut self, pacemaker: P) {
+		self.best_round.set_paced();
+		self.pacemaker = Some((Box::new(pacemaker) as Box<dyn Pacemaker>).fuse());
 	}
 
-	fn completed_best_round(&mut self) -> Result<(), E::Error> {
-		self.env.completed(
-			self.best_round.round_number(),
-			self.best_round.round_state(),
-			self.best_round.dag_base(),
-			self.best_round.historical_votes(),
-		)?;
+	/// Stop casting votes and primary proposals, e.g. while syncing the chain.
+	/// Votes of others are still imported and rounds still progress on them,
+	/// as if we weren't a voter, and commits are still processed and
+	/// broadcast.
+	pub fn pause(&mut self) {
+		self.set_paused(true);
+	}
 
-		let old_round_number = self.best_round.round_number();
+	/// Resume casting votes after `pause`. Rounds which progressed past a
+	/// phase while paused don't cast a vote for it anymore.
+	pub fn resume(&mut self) {
+		self.set_paused(false);
+	}
 
-		let next_round = VotingRound::new(
-			old_round_number + 1,
-			self.voters.clone(),
-			self.last_finalized_in_rounds.clone(),
-			Some(self.best_round.bridge_state()),
-			self.best_round.finalized_sender(),
-			self.env.clone(),
-		);
+	/// Whether casting votes is paused.
+	pub fn is_paused(&self) -> bool {
+		self.paused
+	}
 
-		let old_round = ::std::mem::replace(&mut self.best_round, next_round);
-		self.past_rounds.push(&*self.env, old_round);
-		Ok(())
+	fn set_paused(&mut self, paused: bool) {
+		self.paused = paused;
+		self.best_round.set_paused(paused);
+		self.past_rounds.set_paused(paused);
 	}
 
-	fn set_last_finalized_number(&mut self, finalized_number: N) -> bool {
-		let last_finalized_number = &mut self.last_finalized_number;
-		if finalized_number > *last_finalized_number {
-			*last_finalized_number = finalized_number;
-			return true;
+	// apply the voter's per-round options to a newly created round.
+	fn configure_round(&self, round: &mut VotingRound<H, N, E>) {
+		round.set_metrics(self.metrics.clone());
+		if self.pacemaker.is_some() {
+			round.set_paced();
 		}
-		false
+		round.set_paused(self.paused);
+		round.set_strict_base(self.strict_base);
+		round.set_max_ancestry_len(self.max_ancestry_len);
 	}
-}
-
-impl<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> Future for Voter<H, N, E, GlobalIn, GlobalOut> where
-	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
-	N: Copy + BlockNumberOps + ::std::fmt::Debug,
-	GlobalIn: Stream<Item=Result<CommunicationIn<H, N, E::Signature, E::Id>, E::Error>> + Unpin,
-	GlobalOut: Sink<CommunicationOut<H, N, E::Signature, E::Id>, Error=E::Error> + Unpin,
-{
-	type Output = Result<(), E::Error>;
 
-	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), E::Error>> {
-		self.process_incoming(cx)?;
-		self.prune_background_rounds(cx)?;
-		self.global_out.poll(cx)?;
+	/// A stream of the estimates of the current round, e.g. to drive fork
+	/// choice. The current estimate, if any, is yielded right away, and then
+	/// again whenever it changes, also when a new round is started. Repeated
+	/// estimates, e.g. of consecutive rounds, are yielded once.
+	pub fn estimate_updates(&mut self) -> UnboundedReceiver<(H, N)> {
+		let (tx, rx) = mpsc::unbounded();
+		if let Some(ref estimate) = self.last_estimate {
+			let _ = tx.unbounded_send(estimate.clone());
+		}
 
-		self.process_best_round(cx)
+		self.estimate_senders.push(tx);
+		rx
 	}
-}
-
-impl<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> Unpin for Voter<H, N, E, GlobalIn, GlobalOut> where
-	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
-	N: Copy + BlockNumberOps + ::std::fmt::Debug,
-	GlobalIn: Stream<Item=Result<CommunicationIn<H, N, E::Signature, E::Id>, E::Error>> + Unpin,
-	GlobalOut: Sink<CommunicationOut<H, N, E::Signature, E::Id>, Error=E::Error> + Unpin,
-{
-}
 
-/// Validate the given catch up and return a completed round with all prevotes
-/// and precommits from the catch up imported. If the catch up is invalid `None`
-/// is returned instead.
-fn validate_catch_up<H, N, S, I, E>(
-	catch_up: CatchUp<H, N, S, I>,
-	env: &E,
-	voters: &VoterSet<I>,
-	best_round_number: u64,
-) -> Option<crate::round::Round<I, H, N, S>> where
-	H: Clone + Eq + Ord + std::fmt::Debug + std::hash::Hash,
-	N: BlockNumberOps + std::fmt::Debug,
-	S: Clone + Eq,
-	I: Clone + Eq + std::fmt::Debug + std::hash::Hash,
-	E: Environment<H, N>,
-{
-	if catch_up.round_number <= best_round_number {
-		trace!(target: "afg", "Ignoring because best round number is {}",
-			   best_round_number);
+	/// How long it took from starting the last completed round until it became
+	/// completable and we moved on to the next one. `None` until the first
+	/// round completes.
+	pub fn last_round_duration(&self) -> Option<Duration> {
+		self.last_round_duration
+	}
 
-		return None;
+	/// The rounds being run, i.e. the best round and those in the background,
+	/// each with the round it succeeded, if known. Ordered by round number.
+	pub fn round_parents(&mut self) -> Vec<(u64, Option<u64>)> {
+		let mut rounds: Vec<_> = self.past_rounds.parent_rounds().collect();
+		rounds.push((self.best_round.round_number(), self.best_round.parent_round()));
+		rounds.sort();
+		rounds
 	}
 
-	// check threshold support in prevotes and precommits.
+	/// Assemble a catch-up message for a past round, if we still have its data.
+	pub fn past_round_catch_up(&mut self, round_number: u64)
+		-> Option<CatchUp<H, N, E::Signature, E::Id>>
 	{
-		let mut map = std::collections::HashMap::new();
+		self.past_rounds.catch_up(round_number)
+	}
 
-		for prevote in &catch_up.prevotes {
-			if !voters.contains_key(&prevote.id) {
-				trace!(target: "afg",
-					   "Ignoring invalid catch up, invalid voter: {:?}",
-					   prevote.id,
-				);
+	/// Whether the given round was skipped on catch-up, i.e. we jumped past it
+	/// without running it. Commits for such rounds are validated and finalize
+	/// their block directly, like those for any other round which isn't run in
+	/// the background. Only the latest catch-ups are remembered.
+	pub fn round_skipped(&self, round_number: u64) -> bool {
+		self.past_rounds.skipped(round_number)
+	}
+
+	/// Note a block finalized outside of the voter, e.g. by importing a
+	/// justification during warp sync, which happened in round `round_hint`,
+	/// as far as known. The environment isn't asked to finalize the block.
+	///
+	/// Rounds in the background are pruned as if the block was finalized by
+	/// one of them, and later rounds are based on the block. If the round
+	/// hint is beyond the current round, the voter skips to the round after
+	/// it, as on catch-up, but without votes for the skipped rounds.
+	///
+	/// Fails with `Error::FinalityReverted` if the block is below our last
+	/// finalized block, and with `Error::NotDescendent` if it conflicts with
+	/// it, i.e. is a different block at the same height or on another fork.
+	pub fn set_last_finalized(&mut self, hash: H, number: N, round_hint: u64) -> Result<(), E::Error> {
+		if number < self.last_finalized_number {
+			return Err(crate::Error::FinalityReverted.into());
+		}
 
-				return None;
+		if hash != self.last_finalized_hash {
+			if number == self.last_finalized_number {
+				return Err(crate::Error::NotDescendent.into());
 			}
 
-			map.entry(prevote.id.clone()).or_insert((false, false)).0 = true;
+			self.env.ancestry(self.last_finalized_hash.clone(), hash.clone())?;
 		}
 
-		for precommit in &catch_up.precommits {
-			if !voters.contains_key(&precommit.id) {
-				trace!(target: "afg",
-					   "Ignoring invalid catch up, invalid voter: {:?}",
-					   precommit.id,
-				);
+		trace!(target: "afg", "Finalized {:?} (#{:?}) of round {} outside of the voter",
+			hash, number, round_hint);
 
-				return None;
-			}
+		self.set_last_finalized_block(hash.clone(), number);
+		self.past_rounds.update_finalized(number);
 
-			map.entry(precommit.id.clone()).or_insert((false, false)).1 = true;
+		// the current round keeps its base, it has votes on it already.
+		if number > self.last_finalized_in_rounds.1 {
+			self.last_finalized_in_rounds = (hash, number);
 		}
 
-		let (pv, pc) = map.into_iter().fold(
-			(0, 0),
-			|(mut pv, mut pc), (id, (prevoted, precommitted))| {
-				let weight = voters.info(&id).map_or(0, |i| i.weight());
+		if round_hint <= self.best_round.round_number() { return Ok(()) }
 
-				if prevoted {
-					pv += weight;
-				}
+		trace!(target: "afg", "Skipping from round {} to round {} after finality of round {}",
+			self.best_round.round_number(), round_hint + 1, round_hint);
 
-				if precommitted {
-					pc += weight;
-				}
+		// start out like a voter created with the finalized block.
+		let (_, last_round_state) = crate::bridge_state::bridge_state(
+			RoundState::genesis(self.last_finalized_in_rounds.clone()),
+		);
 
-				(pv, pc)
-			},
+		let mut new_best = VotingRound::new(
+			round_hint + 1,
+			self.voters.clone(),
+			self.last_finalized_in_rounds.clone(),
+			Some(last_round_state),
+			self.best_round.finalized_sender(),
+			self.env.clone(),
 		);
 
-		let threshold = voters.threshold();
-		if pv < threshold || pc < threshold {
-			trace!(target: "afg",
-				   "Ignoring invalid catch up, missing voter threshold"
-			);
+		self.configure_round(&mut new_best);
 
-			return None;
-		}
-	}
+		self.past_rounds.note_skipped(self.best_round.round_number() + 1..round_hint + 1);
+		self.past_rounds.push(
+			&*self.env,
+			std::mem::replace(&mut self.best_round, new_best),
+		);
 
-	let mut round = crate::round::Round::new(crate::round::RoundParams {
-		round_number: catch_up.round_number,
-		voters: voters.clone(),
-		base: (catch_up.base_hash.clone(), catch_up.base_number),
-	});
+		self.metrics.on_round_started(self.best_round.round_number());
 
-	// import prevotes first.
-	for crate::SignedPrevote { prevote, id, signature } in catch_up.prevotes {
-		match round.import_prevote(env, prevote, id, signature) {
-			Ok(_) => {},
-			Err(e) => {
-				trace!(target: "afg",
-					   "Ignoring invalid catch up, error importing prevote: {:?}",
-					   e,
-				);
+		self.import_buffered_messages()
+	}
 
-				return None;
-			},
+	/// Get a minimal set of precommits which finalized the best block of the
+	/// given round, see `Round::finalization_evidence`, e.g. to persist them
+	/// for rewards once `Environment::finalize_block` was called for it. Only
+	/// the current round and those running in the background are covered, and
+	/// `None` is returned for any other, or if it didn't finalize anything.
+	#[allow(clippy::type_complexity)]
+	pub fn finalization_evidence(&mut self, round_number: u64)
+		-> Option<Vec<SignedPrecommit<H, N, E::Signature, E::Id>>>
+	{
+		if round_number == self.best_round.round_number() {
+			return self.best_round.finalization_evidence();
 		}
+
+		self.past_rounds.finalization_evidence(round_number)
 	}
 
-	// then precommits.
-	for crate::SignedPrecommit { precommit, id, signature } in catch_up.precommits {
-		match round.import_precommit(env, precommit, id, signature) {
-			Ok(_) => {},
-			Err(e) => {
-				trace!(target: "afg",
-					   "Ignoring invalid catch up, error importing precommit: {:?}",
-					   e,
-				);
+	/// Drive only the given past round until its commit is ready, resolving to
+	/// the commit instead of broadcasting it. Resolves to `None` if the round
+	/// isn't running in the background anymore, or its commit was already
+	/// produced, either here or by polling the voter.
+	#[allow(clippy::type_complexity)]
+	pub fn poll_commit_for(&mut self, round_number: u64, cx: &mut Context)
+		-> Poll<Result<Option<Commit<H, N, E::Signature, E::Id>>, E::Error>>
+	{
+		self.past_rounds.poll_commit_for(round_number, cx)
+	}
 
-				return None;
-			},
+	/// Import a commit which was already validated elsewhere, e.g. during block
+	/// import, along with the result of `validate_commit` for it. The result is
+	/// trusted, so the commit isn't validated again before its block is
+	/// finalized. Commits which don't finalize a block above the last one we
+	/// finalized are ignored.
+	///
+	/// Commits for rounds which still run in the background are imported
+	/// there, like commits received from the network.
+	pub fn note_commit_validated(
+		&mut self,
+		round_number: u64,
+		commit: Commit<H, N, E::Signature, E::Id>,
+		validation_result: CommitValidationResult<H, N>,
+	) -> Result<(), E::Error> {
+		let commit = match self.past_rounds.import_commit(round_number, commit) {
+			Ok(()) | Err(ImportCommitError::Duplicate) => return Ok(()),
+			Err(ImportCommitError::UnknownRound(commit))
+				| Err(ImportCommitError::Skipped(commit))
+				| Err(ImportCommitError::ChannelClosed(commit)) => commit,
+		};
+
+		if commit.set_id != self.env.set_id() {
+			trace!(target: "afg", "Ignoring commit for set {}, expected set {}",
+				commit.set_id, self.env.set_id());
+			self.metrics.on_commit_dropped(CommitDropReason::OtherSet);
+			return Ok(());
 		}
-	}
 
-	let state = round.state();
-	if !state.completable {
-		return None;
+		self.finalize_validated_commit(round_number, commit, &validation_result).map(|_| ())
 	}
 
-	Some(round)
-}
+	/// Take snapshots of the past rounds run in the background, e.g. to
+	/// persist them and restore them with `restore_past_rounds` after a
+	/// restart.
+	pub fn snapshot_past_rounds(&mut self) -> Vec<BackgroundRoundSnapshot<H, N, E::Signature, E::Id>> {
+		self.past_rounds.snapshot()
+	}
 
-#[cfg(test)]
-mod tests {
-	use super::*;
-	use crate::SignedPrecommit;
-	use crate::testing::{
-		self,
-		chain::GENESIS_HASH,
-		environment::{Environment, Id, Signature},
-	};
-	use futures_timer::TryFutureExt as _;
-	use std::time::Duration;
+	/// Run past rounds restored from snapshots taken with
+	/// `snapshot_past_rounds` in the background. They resume producing
+	/// commits and are pruned as before, but don't cast any more votes. This
+	/// should be called right after creating the voter, for rounds before the
+	/// one it starts at.
+	pub fn restore_past_rounds(
+		&mut self,
+		snapshots: Vec<BackgroundRoundSnapshot<H, N, E::Signature, E::Id>>,
+	) -> Result<(), E::Error> {
+		let finalized_sender = self.best_round.finalized_sender();
+		self.past_rounds.restore(&self.env, &self.voters, &finalized_sender, snapshots)?;
+		self.past_rounds.update_finalized(self.last_finalized_number);
 
-	#[test]
-	fn talking_to_myself() {
-		let local_id = Id(5);
-		let voters = std::iter::once((local_id, 100)).collect();
+		Ok(())
+	}
 
-		let (network, routing_task) = testing::environment::make_network();
-		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+	/// Report why the current round isn't completable yet, or `None` if it
+	/// is. See `Round::completability_blockers`.
+	pub fn completability_blockers(&self) -> Option<CompletabilityBlockers<E::Id, H, N>> {
+		self.best_round.completability_blockers()
+	}
 
-		let global_comms = network.make_global_comms();
-		let env = Arc::new(Environment::new(network, local_id));
-		futures::executor::block_on(::futures::future::lazy(move |_| {
-			// initialize chain
-			let last_finalized = env.with_chain(|chain| {
-				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
-				chain.last_finalized()
-			});
+	/// Note that the given block was imported, so it's
+	/// `Environment::block_available` now. Votes for it which were kept
+	/// pending by the current round or rounds run in the background are
+	/// imported, and incoming commits for it are processed as with
+	/// `note_block_available`. The voter must be polled to act on the votes.
+	pub fn block_imported(&mut self, hash: H, number: N) -> Result<(), E::Error> {
+		self.best_round.block_imported(&hash, number)?;
+		self.past_rounds.block_imported(&hash, number)?;
 
-			let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
+		self.note_block_available(hash)
+	}
 
-			// run voter in background. scheduling it to shut down at the end.
-			let finalized = env.finalized_stream();
-			let voter = Voter::new(
-				env.clone(),
-				voters,
-				global_comms,
-				0,
-				last_round_state,
-				last_finalized,
-			);
-			threads_pool.spawn_ok(voter.map(|v| v.expect("Error voting")));
+	/// Note that the given block became available, see
+	/// `Environment::block_available`. Incoming commits for it which were
+	/// deferred until then are validated and processed now.
+	pub fn note_block_available(&mut self, hash: H) -> Result<(), E::Error> {
+		let (available, deferred) = std::mem::take(&mut self.deferred_commits).into_iter()
+			.partition::<Vec<_>, _>(|(_, commit, _)| commit.target_hash == hash);
+		self.deferred_commits = deferred;
 
-			threads_pool.spawn_ok(routing_task);
+		for (round_number, commit, process_commit_outcome) in available {
+			self.validate_and_finalize(round_number, commit, process_commit_outcome)?;
+		}
 
-			// wait for the best block to finalize.
-			finalized
-				.take_while(|&(_, n, _)| future::ready(n < 6))
-				.for_each(|_| future::ready(()))
-		}).flatten());
+		Ok(())
 	}
 
-	#[test]
-	fn finalizing_at_fault_threshold() {
-		// 10 voters
-		let voters: VoterSet<_> = (0..10).map(|i| (Id(i), 1)).collect();
+	/// Route a message for the given round to wherever it belongs, on top of
+	/// the messages received on the rounds' incoming streams.
+	///
+	/// Messages for the current round or a past round which is still running
+	/// are imported right away, and messages for future rounds within the
+	/// limit set with `set_future_round_limit` are buffered until the round
+	/// starts. The voter must be polled to act on them.
+	pub fn route_message(
+		&mut self,
+		round_number: u64,
+		message: SignedMessageFor<H, N, E>,
+	) -> Result<RoutingOutcome, E::Error> {
+		let best_round_number = self.best_round.round_number();
+
+		if round_number == best_round_number {
+			self.best_round.handle_message(message)?;
+			return Ok(RoutingOutcome::Accepted);
+		}
 
-		let (network, routing_task) = testing::environment::make_network();
-		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+		if round_number < best_round_number {
+			return match self.past_rounds.import_message(round_number, message) {
+				Some(res) => res.map(|()| RoutingOutcome::Accepted),
+				None => {
+					trace!(target: "afg", "Dropping message for finished round {}", round_number);
+					Ok(self.dropped_message(round_number, RoutingOutcome::TooOld))
+				}
+			};
+		}
 
-		futures::executor::block_on(::futures::future::lazy(move |_| {
-			// 3 voters offline.
-			let finalized_streams = (0..7).map(|i| {
-				let local_id = Id(i);
-				// initialize chain
-				let env = Arc::new(Environment::new(network.clone(), local_id));
-				let last_finalized = env.with_chain(|chain| {
-					chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
-					chain.last_finalized()
-				});
+		if round_number - best_round_number > self.future_round_limit {
+			trace!(target: "afg", "Dropping message for round {}, too far ahead of round {}",
+				round_number, best_round_number);
+			return Ok(self.dropped_message(round_number, RoutingOutcome::TooFarAhead));
+		}
 
-				let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
+		let buffered = self.future_messages.entry(round_number).or_default();
+		if buffered.len() >= MAX_BUFFERED_FUTURE_MESSAGES {
+			trace!(target: "afg", "Dropping message for future round {}, buffer is full", round_number);
+			return Ok(self.dropped_message(round_number, RoutingOutcome::BufferFull));
+		}
 
-				// run voter in background. scheduling it to shut down at the end.
-				let finalized = env.finalized_stream();
-				let voter = Voter::new(
-					env.clone(),
-					voters.clone(),
-					network.make_global_comms(),
-					0,
-					last_round_state,
-					last_finalized,
-				);
-				threads_pool.spawn_ok(voter.map(|v| v.expect("Error voting")));
+		buffered.push(message);
+		self.n_future_messages += 1;
 
-				// wait for the best block to be finalized by all honest voters
-				finalized
-					.take_while(|&(_, n, _)| future::ready(n < 6))
-					.for_each(|_| future::ready(()))
-			}).collect::<Vec<_>>();
+		Ok(RoutingOutcome::Buffered)
+	}
 
-			threads_pool.spawn_ok(routing_task.map(|_| ()));
-			::futures::future::join_all(finalized_streams.into_iter())
-		}).flatten());
+	/// Process a commit or catch-up message right away, on top of those of the
+	/// incoming global stream, e.g. one received out-of-band. It's handled
+	/// exactly like a message of the stream, including its callback. The voter
+	/// must be polled to act on it, e.g. to broadcast commits or to vote in a
+	/// round we caught up to.
+	pub fn inject_global_message(
+		&mut self,
+		message: CommunicationIn<H, N, E::Signature, E::Id>,
+	) -> Result<(), E::Error> {
+		self.process_incoming_message(message)
 	}
 
-	#[test]
-	fn broadcast_commit() {
-		let local_id = Id(5);
-		let voters: VoterSet<_> = std::iter::once((local_id, 100)).collect();
+	fn dropped_message(&self, round_number: u64, outcome: RoutingOutcome) -> RoutingOutcome {
+		self.metrics.on_message_dropped(round_number, outcome);
+		outcome
+	}
 
-		let (network, routing_task) = testing::environment::make_network();
-		let (commits, _) = network.make_global_comms();
+	// import any messages buffered for the current best round, and drop those
+	// for rounds we skipped.
+	fn import_buffered_messages(&mut self) -> Result<(), E::Error> {
+		let best_round_number = self.best_round.round_number();
+		let later = self.future_messages.split_off(&(best_round_number + 1));
+		let earlier = std::mem::replace(&mut self.future_messages, later);
 
-		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+		for (round_number, messages) in earlier {
+			self.n_future_messages -= messages.len();
 
-		let global_comms = network.make_global_comms();
-		let env = Arc::new(Environment::new(network, local_id));
-		futures::executor::block_on(::futures::future::lazy(move |_| {
-			// initialize chain
-			let last_finalized = env.with_chain(|chain| {
-				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
-				chain.last_finalized()
-			});
+			if round_number != best_round_number { continue }
+			for message in messages {
+				self.best_round.handle_message(message)?;
+			}
+		}
 
-			let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
+		Ok(())
+	}
 
-			// run voter in background. scheduling it to shut down at the end.
-			let voter = Voter::new(
-				env.clone(),
-				voters.clone(),
-				global_comms,
-				0,
-				last_round_state,
-				last_finalized,
+	fn prune_background_rounds(&mut self, cx: &mut Context) -> Result<(), E::Error> {
+		// Do work on all background rounds, broadcasting any commits generated.
+		// Commits are queued until the outgoing sink accepts them, so that
+		// stale ones can be dropped while the network is congested. Only a
+		// few are processed per poll, and we're woken up again for the rest.
+		let mut budget = std::cmp::max(self.past_rounds_budget, 1);
+		while let Poll::Ready(Some(item)) = Stream::poll_next(Pin::new(&mut self.past_rounds), cx) {
+			let (number, commit) = item?;
+			match self.env.sign_commit(number, &commit) {
+				None => self.queue_commit(CommunicationOut::Commit(number, commit)),
+				Some(signing) => self.signing_commits.push(SigningCommit { round_number: number, signing }),
+			}
+
+			budget -= 1;
+			if budget == 0 {
+				trace!(target: "afg", "Past rounds budget exhausted, yielding");
+				cx.waker().wake_by_ref();
+				break;
+			}
+		}
+
+		while let Poll::Ready(Some((number, signed))) = Stream::poll_next(Pin::new(&mut self.signing_commits), cx) {
+			self.queue_commit(CommunicationOut::SignedCommit(number, signed?));
+		}
+
+		let depth = self.global_out_queue.len();
+		while !self.global_out_queue.is_empty() && self.global_out_ready(cx) {
+			let item = self.global_out_queue.pop_front().expect("queue checked to be non-empty; qed");
+			self.global_out.push(item);
+		}
+		if self.global_out_queue.len() != depth {
+			self.metrics.on_global_out_queue(self.global_out_queue.len());
+		}
+
+		while let Poll::Ready(res) = Stream::poll_next(Pin::new(&mut self.finalized_notifications), cx) {
+			let (f_hash, f_num, round, commit) =
+				res.expect("one sender always kept alive in self.best_round; qed");
+
+
+			self.past_rounds.update_finalized(f_num);
+
+			if self.set_last_finalized_block(f_hash.clone(), f_num) && !self.finalized_by_chain(round, f_num) {
+				self.env.finalize_block(f_hash.clone(), f_num, round, commit)?;
+			}
+
+			if f_num > self.last_finalized_in_rounds.1 {
+				self.last_finalized_in_rounds = (f_hash, f_num);
+			}
+		}
+
+		Ok(())
+	}
+
+	// queue a commit for the global outgoing sink. queued commits of earlier
+	// rounds which don't finalize anything higher are superseded by it and
+	// dropped, and so are the oldest ones beyond the limit, but never this one.
+	fn queue_commit(&mut self, item: CommunicationOut<H, N, E::Signature, E::Id>) {
+		let (round, target_number) = item.round_and_target();
+		let before = self.global_out_queue.len();
+
+		self.global_out_queue.retain(|queued| {
+			let (queued_round, queued_target) = queued.round_and_target();
+			queued_round >= round || queued_target > target_number
+		});
+		self.global_out_queue.push_back(item);
+
+		let limit = std::cmp::max(self.global_out_queue_limit, 1);
+		let overflow = self.global_out_queue.len().saturating_sub(limit);
+		self.global_out_queue.drain(..overflow);
+
+		for _ in self.global_out_queue.len()..before + 1 {
+			self.metrics.on_commit_dropped(CommitDropReason::Elided);
+		}
+		if before > 0 {
+			trace!(target: "afg", "Queued commit for round {}, {} of {} queued commits elided",
+				round, before + 1 - self.global_out_queue.len(), before);
+		}
+
+		self.metrics.on_global_out_queue(self.global_out_queue.len());
+	}
+
+	// whether the global outgoing sink accepts another commit, i.e. all those
+	// pushed before went out. after sending failed, this waits for the retry.
+	fn global_out_ready(&mut self, cx: &mut Context) -> bool {
+		if !self.global_out_retry_due(cx) { return false }
+
+		match self.global_out.poll_ready(cx) {
+			Poll::Ready(Ok(())) => true,
+			Poll::Ready(Err(e)) => {
+				self.global_out_failed(e.into(), cx);
+				false
+			},
+			Poll::Pending => false,
+		}
+	}
+
+	// send and flush all commits pushed to the global outgoing sink.
+	fn flush_global_out(&mut self, cx: &mut Context) {
+		if !self.global_out_retry_due(cx) { return }
+
+		if let Poll::Ready(Err(e)) = self.global_out.poll(cx) {
+			self.global_out_failed(e.into(), cx);
+		}
+	}
+
+	fn global_out_retry_due(&mut self, cx: &mut Context) -> bool {
+		if let Some(ref mut retry) = self.global_out_retry {
+			if Future::poll(Pin::new(retry), cx).is_pending() { return false }
+			self.global_out_retry = None;
+		}
+
+		true
+	}
+
+	// sending on the global outgoing sink failed. this isn't fatal, so that
+	// the environment gets a chance to reconnect. commits which weren't sent
+	// yet are kept, and sending them is retried after a delay.
+	fn global_out_failed(&mut self, error: E::Error, cx: &mut Context) {
+		warn!(target: "afg", "Failed to send on global outgoing sink, retrying in {:?}: {}",
+			GLOBAL_OUT_RETRY_DELAY, error);
+		self.env.global_out_error(error);
+
+		let mut retry = self.env.clock().timer(GLOBAL_OUT_RETRY_DELAY);
+		if Future::poll(Pin::new(&mut retry), cx).is_ready() {
+			cx.waker().wake_by_ref();
+		}
+		self.global_out_retry = Some(retry);
+	}
+
+	// report finality resuming, and count the rounds passing without it when
+	// they take longer than the round duration.
+	fn process_stall(&mut self, cx: &mut Context) {
+		let detector = match self.stall_detector {
+			Some(ref mut detector) => detector,
+			None => return,
+		};
+
+		if let Some(rounds) = detector.progress(self.last_finalized_number) {
+			self.env.finality_resumed(rounds, self.last_finalized_number);
+			self.stall_timer = None;
+		}
+
+		let mut stalled = None;
+		loop {
+			let env = &self.env;
+			let timer = self.stall_timer.get_or_insert_with(|| env.clock().timer(detector.round_duration));
+			if Future::poll(Pin::new(timer), cx).is_pending() { break }
+
+			self.stall_timer = None;
+			stalled = detector.round_passed().or(stalled);
+		}
+
+		if let Some(rounds) = stalled {
+			self.finality_stalled(rounds);
+		}
+	}
+
+	// a round completed, which counts as passing without progress unless
+	// finality advanced meanwhile.
+	fn stall_round_completed(&mut self) {
+		let detector = match self.stall_detector {
+			Some(ref mut detector) => detector,
+			None => return,
+		};
+
+		if let Some(rounds) = detector.progress(self.last_finalized_number) {
+			self.env.finality_resumed(rounds, self.last_finalized_number);
+		} else if let Some(rounds) = detector.round_passed() {
+			self.finality_stalled(rounds);
+		}
+
+		// the next round gets the full round duration.
+		self.stall_timer = None;
+	}
+
+	fn finality_stalled(&self, rounds: u64) {
+		warn!(target: "afg", "No block finalized for {} rounds, last finalized #{:?}",
+			rounds, self.last_finalized_number);
+		self.env.finality_stalled(rounds, self.last_finalized_number);
+	}
+
+	// note the phases the pacemaker signalled in the rounds they belong to.
+	fn process_pacemaker(&mut self, cx: &mut Context) {
+		let pacemaker = match self.pacemaker {
+			Some(ref mut pacemaker) => pacemaker,
+			None => return,
+		};
+
+		while let Poll::Ready(Some(event)) = Stream::poll_next(Pin::new(&mut *pacemaker), cx) {
+			let PacemakerEvent { round, phase } = event;
+			trace!(target: "afg", "Pacemaker signalled phase {:?} of round {}", phase, round);
+
+			if round == self.best_round.round_number() {
+				self.best_round.pace(phase);
+			} else if !self.past_rounds.pace(round, phase) {
+				trace!(target: "afg", "Ignoring pacemaker event for round {}, which isn't running", round);
+			}
+		}
+	}
+
+	/// Process all incoming messages from other nodes.
+	///
+	/// Commit messages are handled with extra care. If a commit message references
+	/// a currently backgrounded round, we send it to that round so that when we commit
+	/// on that round, our commit message will be informed by those that we've seen.
+	///
+	/// Otherwise, we will simply handle the commit and issue a finalization command
+	/// to the environment.
+	fn process_incoming(&mut self, cx: &mut Context) -> Result<(), E::Error> {
+		// the stream is drained before any derived state, e.g. finality
+		// notifications of background rounds, is looked at again.
+		while let Poll::Ready(Some(item)) = TryStream::try_poll_next(Pin::new(&mut self.global_in), cx) {
+			self.process_incoming_message(item.map_err(Into::into)?)?;
+		}
+
+		Ok(())
+	}
+
+	// process a single message from other nodes. batches are processed
+	// message by message, in order.
+	fn process_incoming_message(
+		&mut self,
+		message: CommunicationIn<H, N, E::Signature, E::Id>,
+	) -> Result<(), E::Error> {
+		match message {
+			CommunicationIn::Batch(messages) => {
+				trace!(target: "afg", "Got batch of {} messages", messages.len());
+
+				for message in messages {
+					self.process_incoming_message(message)?;
+				}
+			}
+			CommunicationIn::Commit(round_number, commit, process_commit_outcome) => {
+				afg_event!(trace, [
+					round = round_number,
+					target_number = commit.target_number,
+					target_hash = commit.target_hash
+				], "Got commit";
+					"Got commit for round_number {:?}: target_number: {:?}, target_hash: {:?}",
+					round_number,
+					commit.target_number,
+					commit.target_hash,
+				);
+
+				self.process_commit(round_number, commit.into(), process_commit_outcome)?;
+			}
+			CommunicationIn::SignedCommit(round_number, signed, mut process_commit_outcome) => {
+				trace!(target: "afg", "Got signed commit for round {} from {:?}", round_number, signed.id);
+
+				// the envelope is checked before the commit itself.
+				if !self.env.verify_commit_signature(round_number, &signed) {
+					trace!(target: "afg", "Ignoring commit with a bad signature from {:?}", signed.id);
+					self.metrics.on_commit_dropped(CommitDropReason::BadSignature);
+
+					let validation_result = CommitValidationResult::<H, N> {
+						num_precommits: signed.commit.precommits.len(),
+						..Default::default()
+					};
+					process_commit_outcome.run(
+						CommitProcessingOutcome::Bad(BadCommit::from(validation_result)),
+					);
+					return Ok(());
+				}
+
+				self.process_commit(round_number, signed.commit, process_commit_outcome)?;
+			}
+			CommunicationIn::CatchUp(catch_up, mut process_catch_up_outcome) => {
+				trace!(target: "afg", "Got catch-up message for round {}", catch_up.round_number);
+
+				let round = match validate_catch_up(
+					catch_up,
+					&*self.env,
+					&self.voters,
+					self.best_round.round_number(),
+				) {
+					Ok(round) => round,
+					Err(e) => {
+						trace!(target: "afg", "Ignoring invalid catch up: {}", e);
+						process_catch_up_outcome.run(CatchUpProcessingOutcome::Bad(BadCatchUp::new()));
+						return Ok(());
+					}
+				};
+
+				let state = round.state();
+
+				// beyond this point, we set this round to the past and
+				// start voting in the next round.
+				let mut just_completed = VotingRound::completed(
+					round,
+					self.best_round.finalized_sender(),
+					self.env.clone(),
+				);
+
+				let mut new_best = VotingRound::new(
+					just_completed.round_number() + 1,
+					self.voters.clone(),
+					self.last_finalized_in_rounds.clone(),
+					Some(just_completed.bridge_state()),
+					self.best_round.finalized_sender(),
+					self.env.clone(),
+				);
+
+				just_completed.set_metrics(self.metrics.clone());
+				self.configure_round(&mut new_best);
+
+				// update last-finalized in rounds _after_ starting new round.
+				// otherwise the base could be too eagerly set forward.
+				if let Some((f_hash, f_num)) = state.finalized.clone() {
+					if f_num > self.last_finalized_in_rounds.1 {
+						self.last_finalized_in_rounds = (f_hash, f_num);
+					}
+				}
+
+				self.env.completed(
+					just_completed.round_number(),
+					just_completed.round_state(),
+					just_completed.dag_base(),
+					just_completed.historical_votes(),
+				)?;
+
+				self.metrics.on_round_completed(just_completed.round_number(), just_completed.elapsed());
+				self.past_rounds.note_skipped(self.best_round.round_number() + 1..just_completed.round_number());
+				self.past_rounds.push(&*self.env, just_completed);
+
+				self.past_rounds.push(
+					&*self.env,
+					std::mem::replace(&mut self.best_round, new_best),
+				);
+
+				self.metrics.on_round_started(self.best_round.round_number());
+
+				self.import_buffered_messages()?;
+
+				process_catch_up_outcome.run(CatchUpProcessingOutcome::Good(GoodCatchUp::new()));
+			},
+		}
+
+		Ok(())
+	}
+
+	// import a commit received for the given round. commits for rounds which
+	// still run in the background are imported there, others are validated
+	// and signal the finalized block (if any) to the environment.
+	fn process_commit(
+		&mut self,
+		round_number: u64,
+		commit: Commit<H, N, E::Signature, E::Id>,
+		mut process_commit_outcome: Callback<CommitProcessingOutcome>,
+	) -> Result<(), E::Error> {
+		// if the commit is for a background round dispatch to round committer.
+		// otherwise it's handed back, e.g. if we skipped the round on catch-up.
+		let commit = match self.past_rounds.import_commit(round_number, commit) {
+			Ok(()) => None,
+			Err(ImportCommitError::Duplicate) => {
+				trace!(target: "afg", "Got a duplicate commit for round {}", round_number);
+				None
+			},
+			Err(ImportCommitError::Skipped(commit)) => {
+				trace!(target: "afg", "Got commit for round {}, which was skipped on catch-up", round_number);
+				Some(commit)
+			},
+			Err(ImportCommitError::ChannelClosed(commit)) => {
+				trace!(target: "afg", "Got commit for round {}, which doesn't take commits anymore", round_number);
+				Some(commit)
+			},
+			Err(ImportCommitError::UnknownRound(commit)) => Some(commit),
+		};
+
+		if let Some(commit) = commit {
+			// commits of other voter sets are never valid in ours.
+			if commit.set_id != self.env.set_id() {
+				trace!(target: "afg", "Ignoring commit for set {}, expected set {}",
+					commit.set_id, self.env.set_id());
+				self.metrics.on_commit_dropped(CommitDropReason::OtherSet);
+				run_bad_commit_outcome(&commit, process_commit_outcome);
+				return Ok(());
+			}
+
+			// commits for blocks we don't have yet can't be validated, so
+			// they wait for the blocks unless they're obsolete anyway.
+			if commit.target_number > self.last_finalized_number
+				&& !self.env.block_available(commit.target_hash.clone())
+			{
+				// only commits which may turn out valid are kept, so that others
+				// can't push them out. signed commits had their signature
+				// verified already.
+				if !precommit_weight_reaches_threshold(&commit, &self.voters) {
+					trace!(target: "afg", "Ignoring commit for round {} without enough precommit weight",
+						round_number);
+					self.metrics.on_commit_dropped(CommitDropReason::Invalid);
+					run_bad_commit_outcome(&commit, process_commit_outcome);
+					return Ok(());
+				}
+
+				trace!(target: "afg", "Deferring commit for round {} until {:?} is available",
+					round_number, commit.target_hash);
+
+				if self.deferred_commits.len() >= MAX_DEFERRED_COMMITS {
+					let (_, evicted, evicted_outcome) = self.deferred_commits.remove(0);
+					self.metrics.on_commit_dropped(CommitDropReason::Unavailable);
+					run_bad_commit_outcome(&evicted, evicted_outcome);
+				}
+				self.deferred_commits.push((round_number, commit, process_commit_outcome));
+				return Ok(());
+			}
+
+			self.validate_and_finalize(round_number, commit, process_commit_outcome)?;
+		} else {
+			// Import to backgrounded round is good.
+			process_commit_outcome.run(CommitProcessingOutcome::Good(GoodCommit::new()));
+		}
+
+		Ok(())
+	}
+
+	// validate the commit and signal the finalized block (if any) to the
+	// environment.
+	fn validate_and_finalize(
+		&mut self,
+		round_number: u64,
+		commit: Commit<H, N, E::Signature, E::Id>,
+		mut process_commit_outcome: Callback<CommitProcessingOutcome>,
+	) -> Result<(), E::Error> {
+		let validation_result = validate_commit(&commit, &self.voters, &*self.env)?;
+
+		if self.finalize_validated_commit(round_number, commit, &validation_result)? {
+			process_commit_outcome.run(CommitProcessingOutcome::Good(GoodCommit::new()));
+		} else {
+			// Failing validation of a commit is bad.
+			process_commit_outcome.run(
+				CommitProcessingOutcome::Bad(BadCommit::from(validation_result)),
 			);
-			threads_pool.spawn_ok(voter.map(|v| v.expect("Error voting")));
+		}
+
+		Ok(())
+	}
+
+	// signal the block finalized by a validated commit (if any) to the
+	// environment, unless we finalized it or a later block already. returns
+	// whether the commit is valid.
+	fn finalize_validated_commit(
+		&mut self,
+		round_number: u64,
+		commit: Commit<H, N, E::Signature, E::Id>,
+		validation_result: &CommitValidationResult<H, N>,
+	) -> Result<bool, E::Error> {
+		let (finalized_hash, finalized_number) = match validation_result.ghost {
+			Some(ref ghost) => ghost.clone(),
+			None => {
+				self.metrics.on_commit_dropped(CommitDropReason::Invalid);
+				return Ok(false);
+			}
+		};
+
+		if let Some(max_commit_gap) = self.max_commit_gap {
+			let gap = validation_result.gap_from_previous(self.last_finalized_number);
+			if gap.is_some_and(|gap| gap > max_commit_gap) {
+				trace!(target: "afg", "Ignoring commit for round {} finalizing {:?}, too far above #{:?}",
+					round_number, finalized_number, self.last_finalized_number);
+				self.metrics.on_commit_dropped(CommitDropReason::GapTooLarge);
+				return Ok(true);
+			}
+		}
+
+		// finalizing beyond the estimate of the best round means we're lagging
+		// behind, so background rounds are told right away, and we catch up
+		// to the round of the commit if it's ahead of ours.
+		let lagging = match self.best_round.round_state().estimate {
+			Some((_, estimate_number)) => finalized_number > estimate_number,
+			None => true,
+		};
+
+		if self.set_last_finalized_block(finalized_hash.clone(), finalized_number) {
+			if !self.finalized_by_chain(round_number, finalized_number) {
+				self.env.finalize_block(finalized_hash, finalized_number, round_number, commit)?;
+			}
+
+			if lagging {
+				self.past_rounds.update_finalized(finalized_number);
+
+				if round_number > self.best_round.round_number() {
+					trace!(target: "afg", "Commit for round {} is ahead of round {}, requesting catch-up",
+						round_number, self.best_round.round_number());
+					self.env.request_catch_up(round_number);
+				}
+			}
+		}
+
+		Ok(true)
+	}
+
+	// process the logic of the best round.
+	fn process_best_round(&mut self, cx: &mut Context) -> Result<(), E::Error> {
+		// If the current `best_round` is completable and we've already precommitted,
+		// we start a new round at `best_round + 1`.
+		loop {
+			if !self.best_round_completable(cx)? { return Ok(()) }
+
+			trace!(target: "afg", "Best round at {} has become completable. Starting new best round at {}",
+				self.best_round.round_number(),
+				self.best_round.round_number() + 1,
+			);
+
+			// the new round is polled right away.
+			self.completed_best_round()?;
+		}
+	}
+
+	// poll the best round, returning whether we should start the next one.
+	fn best_round_completable(&mut self, cx: &mut Context) -> Result<bool, E::Error> {
+		let completable = match self.best_round.poll(cx)? {
+			Poll::Ready(()) => true,
+			Poll::Pending => false,
+		};
+
+		// rounds usually take about as long as the last one did.
+		if let Some(expected) = self.last_round_duration {
+			self.best_round.log_if_slow(expected);
+		}
+
+		// start when we've cast all votes, or right away if our votes can't
+		// change the outcome anymore. the round keeps running in the
+		// background, where any remaining votes are cast as usual.
+		let precommitted = matches!(self.best_round.state(), Some(&VotingRoundState::Precommitted));
+
+		Ok(completable && (precommitted || self.best_round.settled()))
+	}
+
+	// send the estimate of the best round to listeners, if it changed.
+	fn notify_estimate(&mut self) {
+		let estimate = match self.best_round.round_state().estimate {
+			Some(estimate) => estimate,
+			None => return,
+		};
+
+		if self.last_estimate.as_ref() == Some(&estimate) { return }
+
+		self.estimate_senders.retain(|tx| tx.unbounded_send(estimate.clone()).is_ok());
+		self.last_estimate = Some(estimate);
+	}
+
+	fn completed_best_round(&mut self) -> Result<(), E::Error> {
+		// the estimate might have changed since the round was last looked at.
+		self.notify_estimate();
+
+		self.env.completed(
+			self.best_round.round_number(),
+			self.best_round.round_state(),
+			self.best_round.dag_base(),
+			self.best_round.historical_votes(),
+		)?;
+
+		let old_round_number = self.best_round.round_number();
+		let duration = self.best_round.elapsed();
+		self.last_round_duration = Some(duration);
+		self.metrics.on_round_completed(old_round_number, duration);
+		self.stall_round_completed();
+
+		let mut next_round = VotingRound::new(
+			old_round_number + 1,
+			self.voters.clone(),
+			self.last_finalized_in_rounds.clone(),
+			Some(self.best_round.bridge_state()),
+			self.best_round.finalized_sender(),
+			self.env.clone(),
+		);
+
+		self.configure_round(&mut next_round);
+
+		let mut old_round = ::std::mem::replace(&mut self.best_round, next_round);
+		old_round.left_settled();
+		self.past_rounds.push(&*self.env, old_round);
+		self.metrics.on_round_started(old_round_number + 1);
+
+		self.import_buffered_messages()
+	}
+
+	fn poll_voter(&mut self, cx: &mut Context) -> Poll<Result<(), E::Error>> {
+		// incoming messages and the best round come first, past rounds only
+		// get a budget after them.
+		self.process_incoming(cx)?;
+		self.process_pacemaker(cx);
+		self.process_best_round(cx)?;
+		self.prune_background_rounds(cx)?;
+		self.flush_global_out(cx);
+		self.process_stall(cx);
+		self.notify_estimate();
+
+		Poll::Pending
+	}
+
+	// the voter failed, so commits which the global outgoing sink didn't take
+	// yet are never sent. they're handed to the environment, oldest first.
+	fn commits_not_sent(&mut self) {
+		let pushed = self.global_out.take_buffered();
+		for item in pushed.into_iter().chain(self.global_out_queue.drain(..)) {
+			let (round, commit) = match item {
+				CommunicationOut::Commit(round, commit) => (round, commit),
+				CommunicationOut::SignedCommit(round, signed) => (round, signed.commit),
+			};
+
+			warn!(target: "afg", "Commit for round {} wasn't sent before the voter failed", round);
+			self.env.commit_not_sent(round, commit);
+		}
+	}
+
+	// whether the chain has finalized the given block number already, e.g.
+	// when block import raced with the voter. finalizing it again would be
+	// redundant at best, and a regression of finality at worst.
+	fn finalized_by_chain(&self, round: u64, finalized_number: N) -> bool {
+		match self.env.last_finalized() {
+			Some((_, chain_finalized)) if chain_finalized >= finalized_number => {
+				debug!(target: "afg", "Not finalizing #{:?} of round {}, chain finalized #{:?} already",
+					finalized_number, round, chain_finalized);
+				true
+			}
+			_ => false,
+		}
+	}
+
+	// note the block as finalized, if it's above the last one finalized.
+	// deferred commits which don't finalize anything above it anymore are
+	// dropped.
+	fn set_last_finalized_block(&mut self, finalized_hash: H, finalized_number: N) -> bool {
+		if finalized_number > self.last_finalized_number {
+			self.last_finalized_number = finalized_number;
+			self.last_finalized_hash = finalized_hash.clone();
+			if let Some(ref mut newly_finalized) = self.newly_finalized {
+				newly_finalized.push_back((finalized_hash, finalized_number));
+			}
+
+			let (obsolete, deferred) = std::mem::take(&mut self.deferred_commits).into_iter()
+				.partition::<Vec<_>, _>(|(_, commit, _)| commit.target_number <= finalized_number);
+			self.deferred_commits = deferred;
+
+			for (_, _, mut process_commit_outcome) in obsolete {
+				self.metrics.on_commit_dropped(CommitDropReason::Obsolete);
+				process_commit_outcome.run(CommitProcessingOutcome::Good(GoodCommit::new()));
+			}
+
+			return true;
+		}
+		false
+	}
+}
+
+impl<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> Future for Voter<H, N, E, GlobalIn, GlobalOut> where
+	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
+	N: Copy + BlockNumberOps + ::std::fmt::Debug,
+	GlobalIn: TryStream<Ok=CommunicationIn<H, N, E::Signature, E::Id>> + Unpin,
+	GlobalIn::Error: Into<E::Error>,
+	GlobalOut: Sink<CommunicationOut<H, N, E::Signature, E::Id>> + Unpin,
+	<GlobalOut as Sink<CommunicationOut<H, N, E::Signature, E::Id>>>::Error: Into<E::Error>,
+{
+	type Output = Result<(), E::Error>;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), E::Error>> {
+		let polled = self.poll_voter(cx);
+		if let Poll::Ready(Err(_)) = polled {
+			self.commits_not_sent();
+		}
+
+		polled
+	}
+}
+
+impl<H, N, E: Environment<H, N>, GlobalIn, GlobalOut> Unpin for Voter<H, N, E, GlobalIn, GlobalOut> where
+	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
+	N: Copy + BlockNumberOps + ::std::fmt::Debug,
+	GlobalIn: TryStream<Ok=CommunicationIn<H, N, E::Signature, E::Id>> + Unpin,
+	GlobalIn::Error: Into<E::Error>,
+	GlobalOut: Sink<CommunicationOut<H, N, E::Signature, E::Id>> + Unpin,
+	<GlobalOut as Sink<CommunicationOut<H, N, E::Signature, E::Id>>>::Error: Into<E::Error>,
+{
+}
+
+/// Validate the given catch up and return a completed round with all prevotes
+/// and precommits from the catch up imported. If the catch up is invalid, the
+/// error says why, e.g. `Error::ThresholdNotReached` if its votes don't have
+/// the weight to complete the round, or `Error::BadCatchUp` otherwise.
+fn validate_catch_up<H, N, S, I, E>(
+	catch_up: CatchUp<H, N, S, I>,
+	env: &E,
+	voters: &VoterSet<I>,
+	best_round_number: u64,
+) -> Result<crate::round::Round<I, H, N, S>, crate::Error> where
+	H: Clone + Eq + Ord + std::fmt::Debug + std::hash::Hash,
+	N: BlockNumberOps + std::fmt::Debug,
+	S: Clone + Eq,
+	I: Clone + Eq + std::fmt::Debug + std::hash::Hash,
+	E: Environment<H, N>,
+{
+	if catch_up.round_number <= best_round_number {
+		return Err(crate::Error::BadCatchUp(BadCatchUpReason::Stale));
+	}
+
+	if catch_up.set_id != env.set_id() {
+		return Err(crate::Error::BadCatchUp(BadCatchUpReason::WrongSetId));
+	}
+
+	// check threshold support in prevotes and precommits.
+	{
+		let mut map = std::collections::HashMap::new();
+
+		for prevote in &catch_up.prevotes {
+			if !voters.contains(&prevote.id) {
+				return Err(crate::Error::UnknownVoter);
+			}
+
+			map.entry(prevote.id.clone()).or_insert((false, false)).0 = true;
+		}
+
+		for precommit in &catch_up.precommits {
+			if !voters.contains(&precommit.id) {
+				return Err(crate::Error::UnknownVoter);
+			}
+
+			map.entry(precommit.id.clone()).or_insert((false, false)).1 = true;
+		}
+
+		let (pv, pc) = map.into_iter().fold(
+			(0, 0),
+			|(mut pv, mut pc), (id, (prevoted, precommitted))| {
+				let weight = voters.get(&id).map_or(0, |i| i.weight());
+
+				if prevoted {
+					pv = u64::saturating_add(pv, weight);
+				}
+
+				if precommitted {
+					pc = u64::saturating_add(pc, weight);
+				}
+
+				(pv, pc)
+			},
+		);
+
+		let threshold = voters.threshold();
+		if pv < threshold || pc < threshold {
+			return Err(crate::Error::ThresholdNotReached { got: pv.min(pc), needed: threshold });
+		}
+	}
+
+	let mut round = crate::round::Round::new(crate::round::RoundParams {
+		round_number: catch_up.round_number,
+		set_id: catch_up.set_id,
+		voters: voters.clone(),
+		base: (catch_up.base_hash.clone(), catch_up.base_number),
+	});
+
+	// import prevotes first.
+	for crate::SignedPrevote { prevote, id, signature } in catch_up.prevotes {
+		round.import_prevote(env, prevote, id, signature)?;
+	}
+
+	// then precommits.
+	for crate::SignedPrecommit { precommit, id, signature } in catch_up.precommits {
+		round.import_precommit(env, precommit, id, signature)?;
+	}
+
+	let state = round.state();
+	if !state.completable {
+		return Err(crate::Error::BadCatchUp(BadCatchUpReason::NotCompletable));
+	}
+
+	Ok(round)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::SignedPrecommit;
+	use crate::testing::{
+		self,
+		chain::GENESIS_HASH,
+		environment::{Environment, Id, Signature},
+	};
+	use futures_timer::TryFutureExt as _;
+	use std::time::Duration;
+
+	#[test]
+	fn talking_to_myself() {
+		let local_id = Id(5);
+		let voters = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		futures::executor::block_on(::futures::future::lazy(move |_| {
+			// initialize chain
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
+
+			// run voter in background. scheduling it to shut down at the end.
+			let finalized = env.finalized_stream();
+			let voter = Voter::new(
+				env.clone(),
+				voters,
+				global_comms,
+				0,
+				last_round_state,
+				last_finalized,
+			);
+			threads_pool.spawn_ok(voter.map(|v| v.expect("Error voting")));
+
+			threads_pool.spawn_ok(routing_task);
+
+			// wait for the best block to finalize.
+			finalized
+				.take_while(|&(_, n, _)| future::ready(n < 6))
+				.for_each(|_| future::ready(()))
+		}).flatten());
+	}
+
+	// also run headless in the browser, where there are no threads or system
+	// time, see the wasm job in `.travis.yml`.
+	#[cfg(target_arch = "wasm32")]
+	wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
+
+	#[cfg_attr(not(target_arch = "wasm32"), test)]
+	#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test::wasm_bindgen_test)]
+	fn two_voters_finalize_on_a_single_thread() {
+		use futures::executor::LocalPool;
+		use futures::task::LocalSpawnExt;
+		use crate::testing::clock::TestClock;
+
+		let voters = VoterSet::new((0..2).map(|i| (Id(i), 1))).unwrap();
+		let (network, routing_task) = testing::environment::make_network();
+		let clock = TestClock::manual();
+
+		let mut pool = LocalPool::new();
+		pool.spawner().spawn_local(routing_task).unwrap();
+
+		let envs: Vec<_> = (0..2).map(|i| {
+			let env = Arc::new(
+				Environment::new(network.clone(), Id(i))
+					.with_clock(clock.clone())
+					.with_commit_delay(Duration::from_millis(100)),
+			);
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let voter = Voter::new(
+				env.clone(),
+				voters.clone(),
+				network.make_global_comms(),
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			pool.spawner().spawn_local(voter.map(|v| v.expect("Error voting"))).unwrap();
+
+			env
+		}).collect();
+
+		// time only passes as we advance it, through the prevote and precommit
+		// timers of the first round.
+		for _ in 0..2 {
+			clock.advance(Duration::from_millis(500));
+			pool.run_until_stalled();
+		}
+
+		for env in envs {
+			assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("E", 6));
+		}
+	}
+
+	#[test]
+	fn swapping_round_outgoing_sink() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+		threads_pool.spawn_ok(routing_task);
+
+		let env = Arc::new(Environment::new(network.clone(), local_id));
+		env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]));
+
+		let (_, last_round_state) = crate::bridge_state::bridge_state(
+			RoundState::genesis((GENESIS_HASH, 1)),
+		);
+		let (finalized_sender, _finalized) = mpsc::unbounded();
+
+		let mut round = VotingRound::new(
+			1,
+			voters,
+			(GENESIS_HASH, 1),
+			Some(last_round_state),
+			finalized_sender,
+			env.clone(),
+		);
+
+		// our own votes must still reach the round through the network for it
+		// to progress, so every sink also forwards to the network.
+		let make_sink = || {
+			let (tx, rx) = mpsc::unbounded();
+			let (_, network_out) = network.make_round_comms(1, local_id);
+			let sink = network_out.fanout(tx.sink_map_err(|e| panic!("Error sending messages: {:?}", e)));
+
+			(Box::pin(sink) as <Environment as crate::voter::Environment<_, _>>::Out, rx)
+		};
+
+		let (sink_a, mut messages_a) = make_sink();
+		let (sink_b, mut messages_b) = make_sink();
+
+		// drive the round until a message is sent on the given receiver.
+		let next_message = |round: &mut VotingRound<_, _, Environment>, messages: &mut mpsc::UnboundedReceiver<_>| {
+			futures::executor::block_on(future::poll_fn(|cx| {
+				let _ = round.poll(cx).map(|res| res.unwrap());
+				messages.poll_next_unpin(cx)
+			}))
+		};
+
+		round.set_outgoing_sink(sink_a);
+		match next_message(&mut round, &mut messages_a) {
+			Some(Message::Prevote(_)) => {},
+			other => panic!("expected prevote on first sink, got {:?}", other),
+		}
+
+		round.set_outgoing_sink(sink_b);
+		match next_message(&mut round, &mut messages_b) {
+			Some(Message::Precommit(_)) => {},
+			other => panic!("expected precommit on second sink, got {:?}", other),
+		}
+
+		// nothing else was sent on either sink, and the first one was dropped.
+		assert!(matches!(messages_a.try_next(), Ok(None)));
+		assert!(messages_b.try_next().is_err());
+	}
+
+	#[test]
+	fn rounds_know_the_round_they_succeeded() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+
+		let (network, _routing_task) = testing::environment::make_network();
+		let env = Arc::new(Environment::new(network.clone(), local_id));
+		let last_finalized = env.with_chain(|chain| chain.last_finalized());
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters.clone(),
+			network.make_global_comms(),
+			3,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		assert_eq!(voter.round_parents(), vec![(4, Some(3))]);
+
+		// the successor of the best round links back to it once it's in the background.
+		voter.completed_best_round().unwrap();
+		assert_eq!(voter.round_parents(), vec![(4, Some(3)), (5, Some(4))]);
+
+		// a round completed from a catch-up doesn't follow one of ours.
+		let (finalized_sender, _finalized) = mpsc::unbounded();
+		let round = crate::round::Round::new(crate::round::RoundParams {
+			round_number: 7,
+			set_id: 0,
+			voters,
+			base: (GENESIS_HASH, 1),
+		});
+		let completed = VotingRound::completed(round, finalized_sender, env);
+		assert_eq!(completed.parent_round(), None);
+	}
+
+	#[test]
+	fn too_many_equivocations_stops_finalization() {
+		// 4 voters of weight 1: the threshold is 3 and a single equivocator is tolerated.
+		let voters: VoterSet<_> = VoterSet::new((0..4).map(|i| (Id(i), 1))).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+		threads_pool.spawn_ok(routing_task);
+
+		// we only observe the round.
+		let env = Arc::new(Environment::new(network.clone(), Id(100)).allowing_equivocations());
+		env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]));
+
+		let (_, last_round_state) = crate::bridge_state::bridge_state(
+			RoundState::genesis((GENESIS_HASH, 1)),
+		);
+		let (finalized_sender, mut finalized) = mpsc::unbounded();
+
+		let mut round = VotingRound::new(
+			1,
+			voters,
+			(GENESIS_HASH, 1),
+			Some(last_round_state),
+			finalized_sender,
+			env.clone(),
+		);
+
+		// honest voters 2 and 3 vote for E, voters 0 and 1 equivocate in both phases.
+		let mut messages = Vec::new();
+		for i in 0..4 {
+			messages.push((i, Message::Prevote(Prevote::new("E", 6))));
+		}
+		for i in 0..2 {
+			messages.push((i, Message::Prevote(Prevote::new("C", 4))));
+		}
+		for i in 0..4 {
+			messages.push((i, Message::Precommit(Precommit::new("E", 6))));
+		}
+		for i in 0..2 {
+			messages.push((i, Message::Precommit(Precommit::new("C", 4))));
+		}
+
+		futures::executor::block_on(async {
+			for (i, message) in messages {
+				let (_, mut sink) = network.make_round_comms(1, Id(i));
+				sink.send(message).await.unwrap();
+			}
+		});
+
+		// all votes are imported and E has supermajority precommits once the
+		// equivocators are counted as voting for everything...
+		futures::executor::block_on(future::poll_fn(|cx| {
+			if let Poll::Ready(res) = round.poll(cx) {
+				res.unwrap();
+				panic!("round should not be completable");
+			}
+
+			if round.round_state().finalized == Some(("E", 6)) {
+				Poll::Ready(())
+			} else {
+				Poll::Pending
+			}
+		}));
+
+		// ...but the voter doesn't trust it. the report is raised once, as soon
+		// as the second prevote equivocation is imported.
+		assert_eq!(env.equivocation_reports(), vec![TooManyEquivocations {
+			round: 1,
+			weight: (2, 0),
+			equivocators: vec![Id(0), Id(1)],
+		}]);
+		assert!(finalized.try_next().is_err());
+		assert!(round.finalizing_commit().is_none());
+	}
+
+	#[test]
+	fn finalizing_at_fault_threshold() {
+		// 10 voters
+		let voters: VoterSet<_> = VoterSet::new((0..10).map(|i| (Id(i), 1))).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+
+		futures::executor::block_on(::futures::future::lazy(move |_| {
+			// 3 voters offline.
+			let finalized_streams = (0..7).map(|i| {
+				let local_id = Id(i);
+				// initialize chain
+				let env = Arc::new(Environment::new(network.clone(), local_id));
+				let last_finalized = env.with_chain(|chain| {
+					chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+					chain.last_finalized()
+				});
+
+				let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
+
+				// run voter in background. scheduling it to shut down at the end.
+				let finalized = env.finalized_stream();
+				let voter = Voter::new(
+					env.clone(),
+					voters.clone(),
+					network.make_global_comms(),
+					0,
+					last_round_state,
+					last_finalized,
+				);
+				threads_pool.spawn_ok(voter.map(|v| v.expect("Error voting")));
+
+				// wait for the best block to be finalized by all honest voters
+				finalized
+					.take_while(|&(_, n, _)| future::ready(n < 6))
+					.for_each(|_| future::ready(()))
+			}).collect::<Vec<_>>();
+
+			threads_pool.spawn_ok(routing_task.map(|_| ()));
+			::futures::future::join_all(finalized_streams)
+		}).flatten());
+	}
+
+	#[test]
+	fn broadcast_commit() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (commits, _) = network.make_global_comms();
+
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		futures::executor::block_on(::futures::future::lazy(move |_| {
+			// initialize chain
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
+
+			// run voter in background. scheduling it to shut down at the end.
+			let voter = Voter::new(
+				env.clone(),
+				voters.clone(),
+				global_comms,
+				0,
+				last_round_state,
+				last_finalized,
+			);
+			threads_pool.spawn_ok(voter.map(|v| v.expect("Error voting")));
+
+			threads_pool.spawn_ok(routing_task);
+
+			// wait for the node to broadcast a commit message
+			commits.take(1).for_each(|_| future::ready(()))
+		}).flatten());
+	}
+
+	#[test]
+	fn commit_is_resent_after_global_out_fails() {
+		use futures::executor::LocalPool;
+		use futures::task::LocalSpawnExt;
+		use crate::testing::clock::TestClock;
+
+		let local_id = Id(5);
+		let voters: VoterSet<_> = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (mut commits, _) = network.make_global_comms();
+
+		let clock = TestClock::manual();
+		let env = Arc::new(
+			Environment::new(network.clone(), local_id)
+				.with_clock(clock.clone())
+				.with_commit_delay(Duration::from_millis(100)),
+		);
+
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		let mut pool = LocalPool::new();
+		pool.spawner().spawn_local(voter.map(|v| v.expect("Error voting"))).unwrap();
+		pool.spawner().spawn_local(routing_task).unwrap();
+
+		// the commit of the first round is due while the sink fails, which is
+		// reported without bringing down the voter.
+		network.set_global_out_failing(true);
+		clock.advance(Duration::from_secs(1));
+		pool.run_until_stalled();
+		clock.advance(Duration::from_millis(100));
+		pool.run_until_stalled();
+
+		assert!(!env.take_global_out_errors().is_empty());
+		assert!(commits.next().now_or_never().is_none());
+
+		// it's sent once the sink works again and the retry is due.
+		network.set_global_out_failing(false);
+		clock.advance(GLOBAL_OUT_RETRY_DELAY);
+		pool.run_until_stalled();
+
+		match commits.next().now_or_never() {
+			Some(Some(Ok(CommunicationIn::Commit(1, commit, _)))) => {
+				assert_eq!((commit.target_hash, commit.target_number), ("E", 6));
+			},
+			_ => panic!("expected the commit of round 1"),
+		}
+	}
+
+	type TestCommunicationOut = CommunicationOut<&'static str, u32, Signature, Id>;
+
+	// a global outgoing sink which accepts one commit per `allow_one`.
+	#[derive(Default, Clone)]
+	struct ThrottledSink {
+		permits: Arc<parking_lot::Mutex<usize>>,
+		waker: Arc<parking_lot::Mutex<Option<futures::task::Waker>>>,
+		sent: Arc<parking_lot::Mutex<Vec<TestCommunicationOut>>>,
+	}
+
+	impl ThrottledSink {
+		fn allow_one(&self) {
+			*self.permits.lock() += 1;
+			if let Some(waker) = self.waker.lock().take() {
+				waker.wake();
+			}
+		}
+	}
+
+	impl Sink<TestCommunicationOut> for ThrottledSink {
+		type Error = crate::Error;
+
+		fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), crate::Error>> {
+			if *self.permits.lock() > 0 { return Poll::Ready(Ok(())) }
+
+			*self.waker.lock() = Some(cx.waker().clone());
+			Poll::Pending
+		}
+
+		fn start_send(self: Pin<&mut Self>, item: TestCommunicationOut) -> Result<(), crate::Error> {
+			*self.permits.lock() -= 1;
+			self.sent.lock().push(item);
+			Ok(())
+		}
+
+		fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), crate::Error>> {
+			Poll::Ready(Ok(()))
+		}
+
+		fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), crate::Error>> {
+			Poll::Ready(Ok(()))
+		}
+	}
+
+	#[test]
+	fn stale_commits_are_elided_while_global_out_is_congested() {
+		use futures::executor::LocalPool;
+		use futures::task::LocalSpawnExt;
+		use crate::testing::clock::TestClock;
+
+		let local_id = Id(5);
+		let voters: VoterSet<_> = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+
+		let clock = TestClock::manual();
+		let env = Arc::new(
+			Environment::new(network.clone(), local_id)
+				.with_clock(clock.clone())
+				.with_commit_delay(Duration::from_millis(100)),
+		);
+
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let sink = ThrottledSink::default();
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			(network.make_global_comms().0, sink.clone()),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		let metrics = Arc::new(RecordingMetrics::default());
+		voter.set_metrics(metrics.clone());
+
+		let mut pool = LocalPool::new();
+		pool.spawner().spawn_local(voter.map(|v| v.expect("Error voting"))).unwrap();
+		pool.spawner().spawn_local(routing_task).unwrap();
+
+		// a round finalizing one more block each time, while the sink doesn't
+		// accept anything.
+		let mut parent = "E";
+		for block in &["F", "G", "H"] {
+			env.with_chain(|chain| chain.push_blocks(parent, &[block]));
+			parent = block;
+
+			clock.advance(Duration::from_secs(1));
+			pool.run_until_stalled();
+			clock.advance(Duration::from_millis(100));
+			pool.run_until_stalled();
+		}
+
+		// the commit of the round finalizing "H".
+		clock.advance(Duration::from_secs(1));
+		pool.run_until_stalled();
+		clock.advance(Duration::from_millis(100));
+		pool.run_until_stalled();
+
+		let events = metrics.events();
+		assert!(events.contains(&MetricsEvent::CommitDropped(CommitDropReason::Elided)));
+		assert_eq!(events.iter().rev().find(|e| matches!(e, MetricsEvent::GlobalOutQueue(_))),
+			Some(&MetricsEvent::GlobalOutQueue(1)));
+
+		// the sink accepting one commit per poll only gets the latest one.
+		for _ in 0..3 {
+			sink.allow_one();
+			pool.run_until_stalled();
+		}
+
+		let sent = sink.sent.lock();
+		assert_eq!(sent.len(), 1);
+		match sent[0] {
+			CommunicationOut::Commit(_, ref commit) => {
+				assert_eq!((commit.target_hash, commit.target_number), ("H", 9));
+			},
+			_ => panic!("expected an unsigned commit"),
+		}
+		assert_eq!(metrics.events().last(), Some(&MetricsEvent::GlobalOutQueue(0)));
+	}
+
+	#[test]
+	fn commit_is_sent_once_after_global_out_was_pending() {
+		use futures::executor::LocalPool;
+		use futures::task::LocalSpawnExt;
+		use crate::testing::clock::TestClock;
+
+		let local_id = Id(5);
+		let voters: VoterSet<_> = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+
+		let clock = TestClock::manual();
+		let env = Arc::new(
+			Environment::new(network.clone(), local_id)
+				.with_clock(clock.clone())
+				.with_commit_delay(Duration::from_millis(100)),
+		);
+
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let sink = ThrottledSink::default();
+		let voter = Voter::new(
+			env.clone(),
+			voters,
+			(network.make_global_comms().0, sink.clone()),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		let mut pool = LocalPool::new();
+		pool.spawner().spawn_local(voter.map(|v| v.expect("Error voting"))).unwrap();
+		pool.spawner().spawn_local(routing_task).unwrap();
+
+		// the commit of the first round is due, and the voter is polled again
+		// and again while the sink stays pending.
+		clock.advance(Duration::from_secs(1));
+		pool.run_until_stalled();
+		for _ in 0..5 {
+			clock.advance(Duration::from_millis(100));
+			pool.run_until_stalled();
+		}
+		assert!(sink.sent.lock().is_empty());
+
+		// once the sink is ready it's sent, and only once.
+		for _ in 0..3 {
+			sink.allow_one();
+			pool.run_until_stalled();
+		}
+
+		let sent = sink.sent.lock();
+		assert_eq!(sent.len(), 1);
+		match sent[0] {
+			CommunicationOut::Commit(1, ref commit) => {
+				assert_eq!((commit.target_hash, commit.target_number), ("E", 6));
+			},
+			_ => panic!("expected the commit of round 1"),
+		}
+		assert!(env.take_commits_not_sent().is_empty());
+	}
+
+	#[test]
+	fn pending_commits_are_reported_when_the_voter_fails() {
+		use futures::executor::LocalPool;
+		use futures::task::LocalSpawnExt;
+		use crate::testing::clock::TestClock;
+
+		let local_id = Id(5);
+		let voters: VoterSet<_> = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+
+		let clock = TestClock::manual();
+		let env = Arc::new(
+			Environment::new(network.clone(), local_id)
+				.with_clock(clock.clone())
+				.with_commit_delay(Duration::from_millis(100)),
+		);
+
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let (global_in_tx, global_in) = mpsc::unbounded();
+		let sink = ThrottledSink::default();
+		let voter = Voter::new(
+			env.clone(),
+			voters,
+			(global_in, sink.clone()),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		let result = Arc::new(parking_lot::Mutex::new(None));
+		let mut pool = LocalPool::new();
+		pool.spawner().spawn_local(voter.map({
+			let result = result.clone();
+			move |res| *result.lock() = Some(res)
+		})).unwrap();
+		pool.spawner().spawn_local(routing_task).unwrap();
+
+		// the commit of the first round waits for the sink.
+		clock.advance(Duration::from_secs(1));
+		pool.run_until_stalled();
+		clock.advance(Duration::from_millis(100));
+		pool.run_until_stalled();
+		assert!(env.take_commits_not_sent().is_empty());
+
+		// errors of the incoming global stream are fatal.
+		global_in_tx.unbounded_send(Err(crate::Error::NotDescendent)).unwrap();
+		pool.run_until_stalled();
+		assert_eq!(*result.lock(), Some(Err(crate::Error::NotDescendent)));
+
+		let not_sent = env.take_commits_not_sent();
+		assert_eq!(not_sent.len(), 1);
+		assert_eq!(not_sent[0].0, 1);
+		assert_eq!((not_sent[0].1.target_hash, not_sent[0].1.target_number), ("E", 6));
+		assert!(sink.sent.lock().is_empty());
+	}
+
+	#[test]
+	fn broadcast_commit_only_if_newer() {
+		let local_id = Id(5);
+		let test_id = Id(42);
+		let voters: VoterSet<_> = VoterSet::new([
+			(local_id, 100),
+			(test_id, 201),
+		].iter().cloned()).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (commits_stream, commits_sink) = network.make_global_comms();
+		let (round_stream, round_sink) = network.make_round_comms(1, test_id);
+
+		let prevote = Message::Prevote(Prevote {
+			target_hash: "E",
+			target_number: 6,
+		});
+
+		let precommit = Message::Precommit(Precommit {
+			target_hash: "E",
+			target_number: 6,
+		});
+
+		let commit = (1, Commit {
+			target_hash: "E",
+			target_number: 6,
+			precommits: vec![SignedPrecommit {
+				precommit: Precommit { target_hash: "E", target_number: 6 },
+				signature: Signature(test_id.0),
+				id: test_id
+			}],
+			set_id: 0,
+		});
+
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+
+		let global_comms = network.make_global_comms();
+		// the voter must not commit on its own before our commit arrives.
+		let env = Arc::new(Environment::new(network, local_id).with_commit_delay(Duration::from_secs(1)));
+		futures::executor::block_on(::futures::future::lazy(move |_| {
+			// initialize chain
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
+
+			// run voter in background. scheduling it to shut down at the end.
+			let voter = Voter::new(
+				env.clone(),
+				voters.clone(),
+				global_comms,
+				0,
+				last_round_state,
+				last_finalized,
+			);
+			threads_pool.spawn_ok(voter.map(|v| v.expect("Error voting: {:?}")));
+
+			threads_pool.spawn_ok(routing_task.map(|_| ()));
+
+			threads_pool.spawn_ok(::futures::future::lazy(move |_| {
+				round_stream.into_future()
+					.then(|(value, stream)| { // wait for a prevote
+						assert!(matches!(
+							value,
+							Some(Ok(SignedMessage { message: Message::Prevote(_), id: Id(5), .. })),
+						));
+						let votes = vec![prevote, precommit].into_iter().map(Result::Ok);
+						futures::stream::iter(votes).forward(round_sink).map(|_| stream) // send our prevote
+					})
+					.then(|stream| {
+						stream.take_while(|value| match value { // wait for a precommit
+							Ok(SignedMessage { message: Message::Precommit(_), id: Id(5), .. }) => future::ready(false),
+							_ => future::ready(true),
+						}).for_each(|_| future::ready(()))
+					})
+					.then(|_| {
+						// send our commit
+						stream::iter(std::iter::once(Ok(CommunicationOut::Commit(commit.0, commit.1)))).forward(commits_sink)
+					})
+					.map_err(|_| ())
+			}).flatten().map(|_| ()));
+
+			// wait for the first commit (ours)
+			commits_stream.into_future()
+				.then(|(_, stream)| {
+					stream.take(1).for_each(|_| future::ready(())) // the second commit should never arrive
+						.map(|()| Ok::<(), std::io::Error>(()))
+						.timeout(Duration::from_millis(500)).map_err(|_| ())
+				})
+				.then(|res| {
+					assert!(res.is_err()); // so the previous future times out
+					futures::future::ok::<(), ()>(())
+				})
+		}).flatten()).unwrap();
+	}
+
+	#[test]
+	fn import_commit_for_any_round() {
+		let local_id = Id(5);
+		let test_id = Id(42);
+		let voters: VoterSet<_> = VoterSet::new([
+			(local_id, 100),
+			(test_id, 201),
+		].iter().cloned()).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (_, commits_sink) = network.make_global_comms();
+
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+
+		// this is a commit for a previous round
+		let commit = (0, Commit {
+			target_hash: "E",
+			target_number: 6,
+			precommits: vec![SignedPrecommit {
+				precommit: Precommit { target_hash: "E", target_number: 6 },
+				signature: Signature(test_id.0),
+				id: test_id
+			}],
+			set_id: 0,
+		});
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id));
+		futures::executor::block_on(::futures::future::lazy(move |_| {
+			// initialize chain
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
+
+			// listen before anything can be finalized.
+			let finalized = env.finalized_stream();
+
+			// run voter in background. scheduling it to shut down at the end.
+			let voter = Voter::new(
+				env.clone(),
+				voters.clone(),
+				global_comms,
+				1,
+				last_round_state,
+				last_finalized,
+			);
+			threads_pool.spawn_ok(voter.map(|v| v.expect("Error voting")));
+
+			threads_pool.spawn_ok(routing_task.map(|_| ()));
+
+			threads_pool.spawn_ok(stream::iter(std::iter::once(Ok(CommunicationOut::Commit(commit.0, commit.1)))).forward(commits_sink)
+				.map_err(|_| ()).map(|_| ()));
+
+			// wait for the commit message to be processed which finalized block 6
+			finalized
+				.take_while(|&(_, n, _)| future::ready(n < 6))
+				.for_each(|_| future::ready(()))
+		}).flatten());
+	}
+
+	#[test]
+	fn finalization_evidence_of_current_and_past_rounds() {
+		let voters: VoterSet<_> = VoterSet::new((0..3).map(|i| (Id(i), 1))).unwrap();
+		let (network, _) = testing::environment::make_network();
+
+		let env = Arc::new(Environment::new(network.clone(), Id(5)));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		let vote = |id, message| SignedMessage { message, signature: Signature(id), id: Id(id) };
+		for id in 0..3 {
+			voter.route_message(1, vote(id, Message::Prevote(Prevote::new("C", 4)))).unwrap();
+		}
+		assert_eq!(voter.finalization_evidence(1), None);
+
+		for id in 0..3 {
+			voter.route_message(1, vote(id, Message::Precommit(Precommit::new("C", 4)))).unwrap();
+		}
+		let evidence = voter.finalization_evidence(1).unwrap();
+		assert_eq!(evidence.iter().map(|signed| signed.id).collect::<Vec<_>>(), vec![Id(0), Id(1), Id(2)]);
+
+		// still available once the round runs in the background.
+		voter.completed_best_round().unwrap();
+		assert_eq!(voter.finalization_evidence(1), Some(evidence));
+		assert_eq!(voter.finalization_evidence(2), None);
+		assert_eq!(voter.finalization_evidence(0), None);
+	}
+
+	#[test]
+	fn routes_messages_by_round() {
+		let voters: VoterSet<_> = VoterSet::new((0..3).map(|i| (Id(i), 1))).unwrap();
+		let (network, _) = testing::environment::make_network();
+
+		let env = Arc::new(Environment::new(network.clone(), Id(5)));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		voter.set_future_round_limit(2);
+
+		// move on to round 3, keeping rounds 1 and 2 in the background.
+		voter.completed_best_round().unwrap();
+		voter.completed_best_round().unwrap();
+		assert_eq!(voter.best_round.round_number(), 3);
+
+		let prevote = |id| SignedMessage {
+			message: Message::Prevote(Prevote { target_hash: "C", target_number: 4 }),
+			signature: Signature(id),
+			id: Id(id),
+		};
+
+		// current round.
+		assert_eq!(voter.route_message(3, prevote(0)).unwrap(), RoutingOutcome::Accepted);
+		assert_eq!(voter.best_round.historical_votes().seen(), &vec![prevote(0)]);
+
+		// past round still running in the background.
+		assert_eq!(voter.route_message(2, prevote(1)).unwrap(), RoutingOutcome::Accepted);
+		assert_eq!(voter.past_round_catch_up(2).unwrap().prevotes.len(), 1);
+
+		// round which never ran.
+		assert_eq!(voter.route_message(0, prevote(1)).unwrap(), RoutingOutcome::TooOld);
+
+		// future rounds are buffered until they start.
+		assert_eq!(voter.route_message(4, prevote(1)).unwrap(), RoutingOutcome::Buffered);
+		assert_eq!(voter.route_message(5, prevote(2)).unwrap(), RoutingOutcome::Buffered);
+		assert_eq!(voter.n_future_messages, 2);
+
+		voter.completed_best_round().unwrap();
+		assert_eq!(voter.best_round.historical_votes().seen(), &vec![prevote(1)]);
+		assert_eq!(voter.n_future_messages, 1);
+
+		voter.completed_best_round().unwrap();
+		assert_eq!(voter.best_round.historical_votes().seen(), &vec![prevote(2)]);
+		assert!(voter.future_messages.is_empty());
+		assert_eq!(voter.n_future_messages, 0);
+	}
+
+	#[test]
+	fn environment_watches_prior_round_state() {
+		let voters: VoterSet<_> = VoterSet::new((0..3).map(|i| (Id(i), 1))).unwrap();
+		let (network, _) = testing::environment::make_network();
+
+		let env = Arc::new(Environment::new(network.clone(), Id(5)));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+			chain.last_finalized()
+		});
+
+		let genesis_state = RoundState::genesis((GENESIS_HASH, 1));
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			genesis_state.clone(),
+			last_finalized,
+		);
+
+		// round 1 starts on top of the given state, which isn't backed by a round.
+		let mut states = env.take_prior_round_states();
+		assert_eq!(states.len(), 1);
+		let (round, genesis_states) = states.pop().unwrap();
+		assert_eq!(round, 1);
+		assert_eq!(
+			futures::executor::block_on(genesis_states.collect::<Vec<_>>()),
+			vec![genesis_state],
+		);
+
+		// round 2 starts on top of round 1, which is still running.
+		voter.completed_best_round().unwrap();
+		let (round, mut round_one_states) = env.take_prior_round_states().pop().unwrap();
+		assert_eq!(round, 2);
+
+		assert!(futures::executor::block_on(round_one_states.next()).is_some());
+
+		// and the stream ends once round 1 is dropped.
+		drop(voter);
+		assert!(futures::executor::block_on(round_one_states.next()).is_none());
+	}
+
+	#[test]
+	fn round_timers_are_requested_per_phase() {
+		let voters: VoterSet<_> = VoterSet::new((0..3).map(|i| (Id(i), 1))).unwrap();
+		let (network, _) = testing::environment::make_network();
+
+		let env = Arc::new(Environment::new(network.clone(), Id(5)));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+			chain.last_finalized()
+		});
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		assert_eq!(env.take_timer_requests(), vec![(1, Phase::Prevote), (1, Phase::Precommit)]);
+		assert_eq!(voter.last_round_duration(), None);
+
+		std::thread::sleep(Duration::from_millis(10));
+
+		// moving on starts round 2 and the commit timer of round 1.
+		voter.completed_best_round().unwrap();
+		assert_eq!(
+			env.take_timer_requests(),
+			vec![(2, Phase::Prevote), (2, Phase::Precommit), (1, Phase::Commit)],
+		);
+		assert!(voter.last_round_duration().unwrap() >= Duration::from_millis(10));
+	}
+
+	#[test]
+	fn environment_decides_on_primary_proposal() {
+		// round 1 starts on top of a completable round, whose estimate isn't
+		// finalized yet. we (`Id(1)`) are the primary of round 1.
+		let propose = |configure: &dyn Fn(Environment) -> Environment| {
+			let voters: VoterSet<_> = VoterSet::new((0..3).map(|i| (Id(i), 1))).unwrap();
+			let (network, _) = testing::environment::make_network();
+
+			let env = Arc::new(configure(Environment::new(network.clone(), Id(1))));
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.push_blocks("A", &["B2"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				network.make_global_comms(),
+				0,
+				RoundState {
+					prevote_ghost: Some(("D", 5)),
+					finalized: Some(last_finalized),
+					estimate: Some(("B", 3)),
+					completable: true,
+				},
+				last_finalized,
+			);
+
+			futures::executor::block_on(future::poll_fn(|cx| {
+				let _ = Future::poll(Pin::new(&mut voter), cx);
+				Poll::Ready(())
+			}));
+
+			env.take_proposals()
+		};
+
+		let primary = |hash, number| PrimaryPropose { target_hash: hash, target_number: number };
+
+		// by default the estimate is proposed.
+		assert_eq!(propose(&|env| env), vec![(1, primary("B", 3))]);
+
+		// the proposal can be suppressed.
+		assert_eq!(propose(&|env| env.with_primary_proposal(|_| None)), vec![]);
+
+		// or substituted by a descendent of the estimate.
+		assert_eq!(
+			propose(&|env| env.with_primary_proposal(move |_| Some(primary("D", 5)))),
+			vec![(1, primary("D", 5))],
+		);
+
+		// but not by a block on another chain, or by an ancestor.
+		for invalid in [primary("B2", 3), primary("A", 2)] {
+			assert_eq!(
+				propose(&|env| {
+					let invalid = invalid.clone();
+					env.with_primary_proposal(move |_| Some(invalid.clone()))
+				}),
+				vec![(1, primary("B", 3))],
+			);
+		}
+	}
+
+	#[test]
+	fn prevote_follows_chain_fork_choice() {
+		let voters: VoterSet<_> = VoterSet::new((0..3).map(|i| (Id(i), 1))).unwrap();
+		let (network, _) = testing::environment::make_network();
+
+		// the chain prefers a shorter fork over the longest one.
+		let env = Arc::new(Environment::new(network.clone(), Id(0)));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.push_blocks("A", &["B2", "C2"]);
+			chain.set_best_chain("C2");
+			chain.last_finalized()
+		});
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		futures::executor::block_on(future::poll_fn(|cx| {
+			let _ = Future::poll(Pin::new(&mut voter), cx);
+			match voter.best_round.state() {
+				Some(VotingRoundState::Prevoted(_)) => Poll::Ready(()),
+				_ => Poll::Pending,
+			}
+		}));
+
+		assert_eq!(env.take_prevotes(), vec![(1, Prevote::new("C2", 4))]);
+		assert!(env.take_best_chain_issues().is_empty());
+	}
+
+	#[test]
+	fn votes_are_restricted_by_voting_rules() {
+		let local_id = Id(5);
+		let voters = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+
+		// vote for the block 2 blocks below the target, but not below the base.
+		let env = Arc::new(Environment::new(network.clone(), local_id)
+			.with_vote_restriction(|chain, _, base, target| {
+				chain.ancestor_at(target.0, std::cmp::max(base.1, target.1.saturating_sub(2)))
+			}));
+
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		let finalized = env.finalized_stream();
+		threads_pool.spawn_ok(voter.map(|v| v.expect("Error voting")));
+		threads_pool.spawn_ok(routing_task);
+
+		let (first_finalized, _) = futures::executor::block_on(finalized.into_future());
+		assert_eq!(first_finalized.map(|(hash, number, _)| (hash, number)), Some(("A", 2)));
+
+		// the best chain ends in E, so we prevote for C, and precommit for A
+		// below the prevote-GHOST.
+		assert_eq!(env.take_prevotes()[0], (1, Prevote::new("C", 4)));
+		assert_eq!(env.take_precommits()[0], (1, Precommit::new("A", 2)));
+	}
+
+	#[test]
+	fn invalid_vote_restrictions_are_ignored() {
+		let voters: VoterSet<_> = VoterSet::new((0..3).map(|i| (Id(i), 1))).unwrap();
+		let (network, _) = testing::environment::make_network();
+
+		// restrict prevotes to a block on another fork.
+		let env = Arc::new(Environment::new(network.clone(), Id(0))
+			.with_vote_restriction(|_, _, _, _| Some(("C2", 4))));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.push_blocks("A", &["B2", "C2"]);
+			chain.last_finalized()
+		});
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		futures::executor::block_on(future::poll_fn(|cx| {
+			let _ = Future::poll(Pin::new(&mut voter), cx);
+			match voter.best_round.state() {
+				Some(VotingRoundState::Prevoted(_)) => Poll::Ready(()),
+				_ => Poll::Pending,
+			}
+		}));
+
+		assert_eq!(env.take_prevotes(), vec![(1, Prevote::new("E", 6))]);
+	}
+
+	#[test]
+	fn prevote_target_survives_reorg() {
+		let voters: VoterSet<_> = VoterSet::new((0..3).map(|i| (Id(i), 1))).unwrap();
+		let (network, _) = testing::environment::make_network();
+
+		let env = Arc::new(Environment::new(network.clone(), Id(0)));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.push_blocks("A", &["B2", "C2", "D2", "E2", "F2"]);
+			chain.last_finalized()
+		});
+
+		let new_voter = || Voter::new(
+			env.clone(),
+			voters.clone(),
+			network.make_global_comms(),
+			0,
+			RoundState {
+				prevote_ghost: Some(("D", 5)),
+				finalized: Some(last_finalized),
+				estimate: Some(("B", 3)),
+				completable: true,
+			},
+			last_finalized,
+		);
+
+		// poll the voter until it's done waiting for the prevote timer.
+		let poll_until_prevote = |voter: &mut Voter<_, _, _, _, _>| {
+			futures::executor::block_on(future::poll_fn(|cx| {
+				let _ = Future::poll(Pin::new(&mut *voter), cx);
+				match voter.best_round.state() {
+					Some(VotingRoundState::Start(..)) => Poll::Pending,
+					_ => Poll::Ready(()),
+				}
+			}));
+		};
+
+		// the best chain moved to another fork, so we prevote for the last
+		// round estimate.
+		env.set_best_chain(Some(Some(("F2", 7))));
+		let mut voter = new_voter();
+		poll_until_prevote(&mut voter);
+
+		assert!(matches!(voter.best_round.state(), Some(VotingRoundState::Prevoted(_))));
+		assert_eq!(env.take_prevotes(), vec![(1, Prevote::new("B", 3))]);
+		assert_eq!(
+			env.take_best_chain_issues(),
+			vec![(1, BestChainIssue::NotDescendent { base: ("B", 3), best: ("F2", 7) })],
+		);
+
+		// the estimate is unknown for a while, we don't prevote until it's
+		// known again.
+		env.set_best_chain(Some(None));
+		let mut voter = new_voter();
+		poll_until_prevote(&mut voter);
+
+		assert!(matches!(voter.best_round.state(), Some(VotingRoundState::Prevoting(_))));
+		assert!(env.take_prevotes().is_empty());
+		assert_eq!(env.take_best_chain_issues(), vec![(1, BestChainIssue::Unknown { base: ("B", 3) })]);
+
+		env.set_best_chain(None);
+		poll_until_prevote(&mut voter);
+
+		assert!(matches!(voter.best_round.state(), Some(VotingRoundState::Prevoted(_))));
+		assert_eq!(env.take_prevotes(), vec![(1, Prevote::new("E", 6))]);
+		assert!(env.take_best_chain_issues().is_empty());
+	}
+
+	#[test]
+	fn prevote_awaits_best_chain_lookup_until_timer() {
+		let prevote_with = |env: Environment| {
+			let voters: VoterSet<_> = VoterSet::new((0..3).map(|i| (Id(i), 1))).unwrap();
+			let (network, _) = testing::environment::make_network();
+
+			let env = Arc::new(env);
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let mut voter = Voter::new(
+				env.clone(),
+				voters,
+				network.make_global_comms(),
+				0,
+				RoundState {
+					prevote_ghost: Some(("D", 5)),
+					finalized: Some(last_finalized),
+					estimate: Some(("B", 3)),
+					completable: true,
+				},
+				last_finalized,
+			);
+
+			// votes are still imported while the lookup is pending.
+			let prevote = SignedMessage {
+				message: Message::Prevote(Prevote::new("C", 4)),
+				signature: Signature(1),
+				id: Id(1),
+			};
+			assert_eq!(voter.route_message(1, prevote).unwrap(), RoutingOutcome::Accepted);
+
+			futures::executor::block_on(future::poll_fn(|cx| {
+				let _ = Future::poll(Pin::new(&mut voter), cx);
+				match voter.best_round.state() {
+					Some(VotingRoundState::Start(..)) => Poll::Pending,
+					_ => Poll::Ready(()),
+				}
+			}));
+
+			(env.take_prevotes(), env.take_best_chain_issues())
+		};
+
+		// the lookup resolves before the prevote timer (of 500ms) fires.
+		let network = || testing::environment::make_network().0;
+		let (prevotes, issues) = prevote_with(
+			Environment::new(network(), Id(0)).with_best_chain_delay(Duration::from_millis(100)),
+		);
+		assert_eq!(prevotes, vec![(1, Prevote::new("E", 6))]);
+		assert!(issues.is_empty());
+
+		// the lookup never resolves, so we fall back to the last round estimate.
+		let (prevotes, issues) = prevote_with(Environment::new(network(), Id(0)).with_unresolved_best_chain());
+		assert_eq!(prevotes, vec![(1, Prevote::new("B", 3))]);
+		assert_eq!(issues, vec![(1, BestChainIssue::Unresolved { base: ("B", 3) })]);
+	}
+
+	#[test]
+	fn commits_and_catch_ups_of_other_sets_are_rejected() {
+		let voters: VoterSet<_> = VoterSet::new((0..3).map(|i| (Id(i), 1))).unwrap();
+		let (network, _) = testing::environment::make_network();
+
+		let env = Arc::new(Environment::new(network.clone(), Id(5)).with_set_id(1));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let catch_up = |set_id| CatchUp {
+			round_number: 5,
+			prevotes: (0..3).map(|i| crate::SignedPrevote {
+				prevote: Prevote::new("C", 4),
+				id: Id(i),
+				signature: Signature(i),
+			}).collect(),
+			precommits: (0..3).map(|i| SignedPrecommit {
+				precommit: Precommit::new("C", 4),
+				id: Id(i),
+				signature: Signature(i),
+			}).collect(),
+			base_hash: GENESIS_HASH,
+			base_number: 1,
+			set_id,
+		};
+
+		assert_eq!(
+			validate_catch_up(catch_up(0), &*env, &voters, 1).err(),
+			Some(crate::Error::BadCatchUp(BadCatchUpReason::WrongSetId)),
+		);
+		assert_eq!(validate_catch_up(catch_up(1), &*env, &voters, 1).unwrap().set_id(), 1);
+
+		// a commit finalizing "C" in another set.
+		let commit = Commit {
+			target_hash: "C",
+			target_number: 4,
+			precommits: catch_up(0).precommits,
+			set_id: 0,
+		};
+
+		let outcome = Arc::new(parking_lot::Mutex::new(None));
+		let callback = Callback::Work(Box::new({
+			let outcome = outcome.clone();
+			move |o| *outcome.lock() = Some(o)
+		}));
+
+		let global_in = stream::iter(vec![Ok::<_, crate::Error>(CommunicationIn::Commit(0, commit.into(), callback))])
+			.chain(stream::pending());
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			(global_in, network.make_global_comms().1),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		futures::executor::block_on(future::poll_fn(|cx| {
+			let _ = Future::poll(Pin::new(&mut voter), cx);
+			Poll::Ready(())
+		}));
+
+		assert!(matches!(outcome.lock().take(), Some(CommitProcessingOutcome::Bad(_))));
+		assert_eq!(voter.last_finalized_number, 1);
+
+		// the catch-ups we produce are tagged with our set id.
+		voter.completed_best_round().unwrap();
+		assert_eq!(voter.past_round_catch_up(1).unwrap().set_id, 1);
+	}
+
+	#[test]
+	fn malformed_commits_and_votes_do_not_stop_the_voter() {
+		let voters: VoterSet<_> = VoterSet::new((0..3).map(|i| (Id(i), 1))).unwrap();
+		let (network, _) = testing::environment::make_network();
+
+		let env = Arc::new(Environment::new(network.clone(), Id(5)));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		// a commit for "C" with a precommit for "D" claimed at the number of "C".
+		let precommits = [("C", 4), ("D", 4), ("C", 4)].iter().enumerate().map(|(i, &(hash, number))| SignedPrecommit {
+			precommit: Precommit::new(hash, number),
+			id: Id(i as u32),
+			signature: Signature(i as u32),
+		}).collect();
+		let commit = Commit { target_hash: "C", target_number: 4, precommits, set_id: 0 };
+
+		let outcome = Arc::new(parking_lot::Mutex::new(None));
+		let callback = Callback::Work(Box::new({
+			let outcome = outcome.clone();
+			move |o| *outcome.lock() = Some(o)
+		}));
+
+		let global_in = stream::iter(vec![Ok::<_, crate::Error>(CommunicationIn::Commit(1, commit.into(), callback))])
+			.chain(stream::pending());
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			(global_in, network.make_global_comms().1),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		// a prevote for "B" claimed at the number of the base.
+		voter.best_round.handle_message(SignedMessage {
+			message: Message::Prevote(Prevote::new("B", 1)),
+			signature: Signature(0),
+			id: Id(0),
+		}).unwrap();
+
+		futures::executor::block_on(future::poll_fn(|cx| {
+			assert!(Future::poll(Pin::new(&mut voter), cx).is_pending());
+			Poll::Ready(())
+		}));
+
+		// the commit is invalid, and the voter keeps running.
+		assert!(matches!(outcome.lock().take(), Some(CommitProcessingOutcome::Bad(_))));
+		assert_eq!(voter.last_finalized_number, 1);
+		assert_eq!(voter.best_round.round_state().prevote_ghost, None);
+	}
+
+	#[test]
+	fn strict_voter_leaves_out_votes_for_the_base() {
+		let voters: VoterSet<_> = VoterSet::new((0..3).map(|i| (Id(i), 1))).unwrap();
+		let (network, _) = testing::environment::make_network();
+
+		let env = Arc::new(Environment::new(network.clone(), Id(5)));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+			chain.last_finalized()
+		});
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		let metrics = Arc::new(RecordingMetrics::default());
+		voter.set_metrics(metrics.clone());
+		voter.set_strict_base(true);
+
+		let prevote = |hash, number, id| SignedMessage {
+			message: Message::Prevote(Prevote::new(hash, number)),
+			signature: Signature(id),
+			id: Id(id),
+		};
+
+		// the vote for the base is reported, and doesn't stop the voter.
+		voter.best_round.handle_message(prevote(GENESIS_HASH, 1, 0)).unwrap();
+		voter.best_round.handle_message(prevote("C", 4, 1)).unwrap();
+
+		use MetricsEvent::*;
+		assert_eq!(metrics.events(), vec![
+			RoundStarted(1),
+			VoteImported(Phase::Prevote, VoteImportOutcome::ForBase),
+			VoteImported(Phase::Prevote, VoteImportOutcome::Imported),
+		]);
+		assert!(voter.best_round.historical_votes().seen().iter().all(|signed| signed.id == Id(1)));
+	}
+
+	#[test]
+	fn invalid_catch_ups_are_rejected_with_the_reason() {
+		let voters: VoterSet<_> = VoterSet::new((0..4).map(|i| (Id(i), 1))).unwrap();
+		let (network, _) = testing::environment::make_network();
+
+		let env = Arc::new(Environment::new(network.clone(), Id(5)));
+		env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.push_blocks("B", &["C2"]);
+		});
+
+		let catch_up = |round_number, voters: &[u32], precommit: (&'static str, u32)| CatchUp {
+			round_number,
+			prevotes: voters.iter().map(|&i| crate::SignedPrevote {
+				prevote: Prevote::new("C", 4),
+				id: Id(i),
+				signature: Signature(i),
+			}).collect(),
+			precommits: voters.iter().map(|&i| SignedPrecommit {
+				precommit: Precommit::new(precommit.0, precommit.1),
+				id: Id(i),
+				signature: Signature(i),
+			}).collect(),
+			base_hash: GENESIS_HASH,
+			base_number: 1,
+			set_id: 0,
+		};
+
+		assert!(validate_catch_up(catch_up(5, &[0, 1, 2], ("C", 4)), &*env, &voters, 1).is_ok());
+
+		assert_eq!(
+			validate_catch_up(catch_up(1, &[0, 1, 2], ("C", 4)), &*env, &voters, 1).err(),
+			Some(crate::Error::BadCatchUp(BadCatchUpReason::Stale)),
+		);
+		assert_eq!(
+			validate_catch_up(catch_up(5, &[0, 1], ("C", 4)), &*env, &voters, 1).err(),
+			Some(crate::Error::ThresholdNotReached { got: 2, needed: 3 }),
+		);
+		assert_eq!(
+			validate_catch_up(catch_up(5, &[0, 1, 7], ("C", 4)), &*env, &voters, 1).err(),
+			Some(crate::Error::UnknownVoter),
+		);
+
+		// with a nil precommit, a block above the prevote-GHOST could still be
+		// finalized in the round.
+		let mut incomplete = catch_up(5, &[0, 1, 2], ("E", 6));
+		incomplete.precommits[2].precommit = Precommit::nil((GENESIS_HASH, 1));
+		assert_eq!(
+			validate_catch_up(incomplete, &*env, &voters, 1).err(),
+			Some(crate::Error::BadCatchUp(BadCatchUpReason::NotCompletable)),
+		);
+
+		// votes which don't build on the base.
+		let mut invalid = catch_up(5, &[0, 1, 2], ("C", 4));
+		invalid.base_hash = "C2";
+		invalid.base_number = 4;
+		assert_eq!(validate_catch_up(invalid, &*env, &voters, 1).err(), Some(crate::Error::BlockNotInSubtree));
+	}
+
+	#[test]
+	fn broadcast_signed_commit() {
+		let local_id = Id(5);
+		let voters: VoterSet<_> = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let (commits, _) = network.make_global_comms();
+
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+
+		let global_comms = network.make_global_comms();
+		let env = Arc::new(Environment::new(network, local_id).with_commit_signing());
+		futures::executor::block_on(::futures::future::lazy(move |_| {
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let voter = Voter::new(
+				env.clone(),
+				voters.clone(),
+				global_comms,
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+			threads_pool.spawn_ok(voter.map(|v| v.expect("Error voting")));
+
+			threads_pool.spawn_ok(routing_task);
+
+			// the commit is broadcast in an envelope signed by us.
+			commits.take(1).for_each(move |commit| {
+				match commit.unwrap() {
+					CommunicationIn::SignedCommit(_, signed, _) => {
+						assert_eq!(signed.id, local_id);
+						assert_eq!(signed.signature, Signature(local_id.0));
+						assert!(!signed.commit.precommits.is_empty());
+					}
+					_ => panic!("commit wasn't signed"),
+				}
+				future::ready(())
+			})
+		}).flatten());
+	}
+
+	#[test]
+	fn signed_commits_are_verified_before_processing() {
+		let voters: VoterSet<_> = VoterSet::new((0..3).map(|i| (Id(i), 1))).unwrap();
+		let (network, _) = testing::environment::make_network();
+
+		let env = Arc::new(Environment::new(network.clone(), Id(5)).with_commit_signing());
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		// a commit finalizing "C", signed by voter 0.
+		let signed = SignedCommit {
+			commit: Commit {
+				target_hash: "C",
+				target_number: 4,
+				precommits: (0..3).map(|i| SignedPrecommit {
+					precommit: Precommit::new("C", 4),
+					id: Id(i),
+					signature: Signature(i),
+				}).collect(),
+				set_id: 0,
+			},
+			signature: Signature(0),
+			id: Id(0),
+		};
+
+		let mut forged = signed.clone();
+		forged.signature = Signature(9);
+
+		let outcomes = Arc::new(parking_lot::Mutex::new(Vec::new()));
+		let callback = || Callback::Work(Box::new({
+			let outcomes = outcomes.clone();
+			move |o| outcomes.lock().push(o)
+		}));
+
+		let global_in = stream::iter(vec![
+			Ok::<_, crate::Error>(CommunicationIn::SignedCommit(0, forged, callback())),
+		]).chain(stream::pending());
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters.clone(),
+			(global_in, network.make_global_comms().1),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		futures::executor::block_on(future::poll_fn(|cx| {
+			let _ = Future::poll(Pin::new(&mut voter), cx);
+			Poll::Ready(())
+		}));
+
+		// the forged envelope is dropped without finalizing anything.
+		assert!(matches!(outcomes.lock().pop(), Some(CommitProcessingOutcome::Bad(_))));
+		assert_eq!(voter.last_finalized_number, 1);
+
+		let global_in = stream::iter(vec![
+			Ok::<_, crate::Error>(CommunicationIn::SignedCommit(0, signed, callback())),
+		]).chain(stream::pending());
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			(global_in, network.make_global_comms().1),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		futures::executor::block_on(future::poll_fn(|cx| {
+			let _ = Future::poll(Pin::new(&mut voter), cx);
+			Poll::Ready(())
+		}));
+
+		// the envelope is stripped and the commit inside finalizes "C".
+		assert!(matches!(outcomes.lock().pop(), Some(CommitProcessingOutcome::Good(_))));
+		assert_eq!(voter.last_finalized_number, 4);
+	}
+
+	#[test]
+	fn batched_messages_are_processed_like_single_ones() {
+		let voters: VoterSet<_> = VoterSet::new((0..3).map(|i| (Id(i), 1))).unwrap();
+		let (network, _) = testing::environment::make_network();
+
+		let commit = |target: (&'static str, u32), set_id| CompactCommit::from(Commit {
+			target_hash: target.0,
+			target_number: target.1,
+			precommits: (0..3).map(|i| SignedPrecommit {
+				precommit: Precommit::new(target.0, target.1),
+				id: Id(i),
+				signature: Signature(i),
+			}).collect(),
+			set_id,
+		});
+
+		// feeds the messages to a fresh voter, either one by one or in a
+		// single batch, returning the outcomes and the last finalized number.
+		let run = |batched: bool| {
+			let env = Arc::new(Environment::new(network.clone(), Id(5)));
+			let last_finalized = env.with_chain(|chain| {
+				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+				chain.last_finalized()
+			});
+
+			let outcomes = Arc::new(parking_lot::Mutex::new(Vec::new()));
+			let callback = || Callback::Work(Box::new({
+				let outcomes = outcomes.clone();
+				move |o| outcomes.lock().push(o)
+			}));
+
+			let messages = vec![
+				CommunicationIn::Commit(0, commit(("C", 4), 0), callback()),
+				CommunicationIn::Commit(0, commit(("E", 6), 1), callback()),
+				CommunicationIn::Commit(0, commit(("B", 3), 0), callback()),
+			];
+			let messages = if batched {
+				vec![Ok::<_, crate::Error>(CommunicationIn::Batch(messages))]
+			} else {
+				messages.into_iter().map(Ok).collect()
+			};
+
+			let mut voter = Voter::new(
+				env,
+				voters.clone(),
+				(stream::iter(messages).chain(stream::pending()), network.make_global_comms().1),
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				last_finalized,
+			);
+
+			futures::executor::block_on(future::poll_fn(|cx| {
+				let _ = Future::poll(Pin::new(&mut voter), cx);
+				Poll::Ready(())
+			}));
+
+			let outcomes = outcomes.lock().iter()
+				.map(|o| matches!(o, CommitProcessingOutcome::Good(_)))
+				.collect::<Vec<_>>();
+			(outcomes, voter.last_finalized_number)
+		};
+
+		assert_eq!(run(false), (vec![true, false, true], 4));
+		assert_eq!(run(true), run(false));
+	}
+
+	#[test]
+	fn future_message_buffer_is_bounded() {
+		let voters: VoterSet<_> = VoterSet::new((0..3).map(|i| (Id(i), 1))).unwrap();
+		let (network, _) = testing::environment::make_network();
+
+		let env = Arc::new(Environment::new(network.clone(), Id(5)));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+			chain.last_finalized()
+		});
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		let prevote = SignedMessage {
+			message: Message::Prevote(Prevote { target_hash: "C", target_number: 4 }),
+			signature: Signature(0),
+			id: Id(0),
+		};
+
+		voter.set_future_round_limit(10);
+
+		for _ in 0..MAX_BUFFERED_FUTURE_MESSAGES {
+			assert_eq!(voter.route_message(10, prevote.clone()).unwrap(), RoutingOutcome::Buffered);
+		}
+
+		assert_eq!(voter.route_message(10, prevote).unwrap(), RoutingOutcome::BufferFull);
+	}
+
+	#[test]
+	fn messages_for_next_round_are_replayed_when_it_starts() {
+		let voters: VoterSet<_> = VoterSet::new((0..3).map(|i| (Id(i), 1))).unwrap();
+		let (network, _) = testing::environment::make_network();
+
+		let env = Arc::new(Environment::new(network.clone(), Id(5)));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		let metrics = Arc::new(RecordingMetrics::default());
+		voter.set_metrics(metrics.clone());
+
+		let prevote = |id| SignedMessage {
+			message: Message::Prevote(Prevote { target_hash: "C", target_number: 4 }),
+			signature: Signature(id),
+			id: Id(id),
+		};
+
+		// only messages for the next round are buffered by default.
+		assert_eq!(voter.route_message(2, prevote(0)).unwrap(), RoutingOutcome::Buffered);
+		assert_eq!(voter.route_message(3, prevote(1)).unwrap(), RoutingOutcome::TooFarAhead);
+		assert_eq!(voter.n_future_messages, 1);
+		assert!(metrics.events().contains(&MetricsEvent::MessageDropped(3, RoutingOutcome::TooFarAhead)));
+
+		// the buffered message is imported once the round starts, without
+		// being sent again.
+		voter.completed_best_round().unwrap();
+		assert_eq!(voter.best_round.historical_votes().seen(), &vec![prevote(0)]);
+		assert_eq!(voter.n_future_messages, 0);
+
+		// a catch-up past buffered rounds clears them.
+		assert_eq!(voter.route_message(3, prevote(1)).unwrap(), RoutingOutcome::Buffered);
+
+		let pv = |id| crate::SignedPrevote { prevote: Prevote::new("C", 4), id: Id(id), signature: Signature(id) };
+		let pc = |id| SignedPrecommit { precommit: Precommit::new("C", 4), id: Id(id), signature: Signature(id) };
+		let catch_up = CatchUp {
+			base_number: 1,
+			base_hash: GENESIS_HASH,
+			round_number: 5,
+			prevotes: vec![pv(0), pv(1), pv(2)],
+			precommits: vec![pc(0), pc(1), pc(2)],
+			set_id: 0,
+		};
+		voter.process_incoming_message(CommunicationIn::CatchUp(catch_up, Callback::Blank)).unwrap();
+
+		assert_eq!(voter.best_round.round_number(), 6);
+		assert!(voter.future_messages.is_empty());
+		assert_eq!(voter.n_future_messages, 0);
+	}
+
+	#[test]
+	fn estimate_updates_follow_the_best_round() {
+		use crate::testing::clock::TestClock;
+
+		let voters: VoterSet<_> = VoterSet::new((0..4).map(|i| (Id(i), 1))).unwrap();
+		let (network, _) = testing::environment::make_network();
+
+		// observe without voting, the estimate follows the injected votes.
+		let env = Arc::new(Environment::new(network.clone(), Id(9)).with_clock(TestClock::manual()));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E", "F"]);
+			chain.last_finalized()
+		});
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		let mut updates = voter.estimate_updates();
+
+		let prevote = |id, hash, number| SignedMessage {
+			message: Message::Prevote(Prevote::new(hash, number)),
+			signature: Signature(id),
+			id: Id(id),
+		};
+		let precommit = |id, hash, number| SignedMessage {
+			message: Message::Precommit(Precommit::new(hash, number)),
+			signature: Signature(id),
+			id: Id(id),
+		};
+
+		let drive = |voter: &mut Voter<_, _, _, _, _>, round, messages: Vec<_>| {
+			for message in messages {
+				voter.route_message(round, message).unwrap();
+			}
+
+			futures::executor::block_on(future::poll_fn(|cx| {
+				let _ = Future::poll(Pin::new(&mut *voter), cx);
+				Poll::Ready(())
+			}));
+		};
+
+		// a supermajority prevotes "D" and then finalizes it in round 1.
+		drive(&mut voter, 1, vec![prevote(0, "D", 5), prevote(1, "D", 5), prevote(2, "D", 5)]);
+		drive(&mut voter, 1, vec![precommit(0, "D", 5), precommit(1, "D", 5), precommit(2, "D", 5)]);
+		assert_eq!(voter.best_round.round_number(), 2);
+
+		// round 2 moves the estimate on to "F", and back to "D" once
+		// precommits rule "F" out.
+		drive(&mut voter, 2, vec![prevote(0, "F", 7), prevote(1, "F", 7), prevote(2, "F", 7)]);
+		drive(&mut voter, 2, vec![precommit(0, "D", 5), precommit(1, "D", 5), precommit(2, "D", 5)]);
+
+		let mut seen = Vec::new();
+		while let Some(Some(estimate)) = updates.next().now_or_never() {
+			seen.push(estimate);
+		}
+
+		assert_eq!(seen, vec![("D", 5), ("F", 7), ("D", 5)]);
+
+		// new listeners start with the current estimate.
+		let mut late = voter.estimate_updates();
+		assert_eq!(late.next().now_or_never(), Some(Some(("D", 5))));
+	}
+
+	#[test]
+	fn skips_to_latest_round_after_catch_up() {
+		// 3 voters
+		let voters: VoterSet<_> = VoterSet::new((0..3).map(|i| (Id(i), 1))).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+
+		futures::executor::block_on(::futures::future::lazy(move |_| {
+			threads_pool.spawn_ok(routing_task.map(|_| ()));
+
+			// initialize unsynced voter at round 0
+			let mut unsynced_voter = {
+				let local_id = Id(4);
+
+				let env = Arc::new(Environment::new(network.clone(), local_id));
+				let last_finalized = env.with_chain(|chain| {
+					chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+					chain.last_finalized()
+				});
+
+				let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
+
+				Voter::new(
+					env.clone(),
+					voters.clone(),
+					network.make_global_comms(),
+					0,
+					last_round_state,
+					last_finalized,
+				)
+			};
+
+			let pv = |id| crate::SignedPrevote {
+				prevote: crate::Prevote { target_hash: "C", target_number: 4 },
+				id: Id(id),
+				signature: Signature(99),
+			};
+
+			let pc = |id| crate::SignedPrecommit {
+				precommit: crate::Precommit { target_hash: "C", target_number: 4 },
+				id: Id(id),
+				signature: Signature(99),
+			};
+
+			// send in a catch-up message for round 5.
+			network.send_message(CommunicationIn::CatchUp(
+				CatchUp {
+					base_number: 1,
+					base_hash: GENESIS_HASH,
+					round_number: 5,
+					prevotes: vec![pv(0), pv(1), pv(2)],
+					precommits: vec![pc(0), pc(1), pc(2)],
+					set_id: 0,
+				},
+				Callback::Blank,
+			));
+
+			// poll until it's caught up.
+			// should skip to round 6
+			::futures::future::poll_fn(move |cx| -> Poll<Result<(), ()>> {
+				let poll = Future::poll(Pin::new(&mut unsynced_voter), cx);
+				if unsynced_voter.best_round.round_number() == 6 {
+					Poll::Ready(Ok(()))
+				} else {
+					futures::ready!(poll).map_err(|_| ())?;
+					Poll::Ready(Ok(()))
+				}
+			})
+		}).flatten()).unwrap();
+	}
+
+	#[derive(Debug, Clone, PartialEq)]
+	enum MetricsEvent {
+		VoteImported(Phase, VoteImportOutcome),
+		CommitEmitted(u64),
+		CommitDropped(CommitDropReason),
+		RoundStarted(u64),
+		RoundCompleted(u64),
+		BackgroundRounds(usize),
+		MessageDropped(u64, RoutingOutcome),
+		GlobalOutQueue(usize),
+	}
+
+	#[derive(Default)]
+	struct RecordingMetrics(parking_lot::Mutex<Vec<MetricsEvent>>);
+
+	impl RecordingMetrics {
+		fn events(&self) -> Vec<MetricsEvent> {
+			self.0.lock().clone()
+		}
+	}
+
+	impl Metrics for RecordingMetrics {
+		fn on_vote_imported(&self, phase: Phase, outcome: VoteImportOutcome) {
+			self.0.lock().push(MetricsEvent::VoteImported(phase, outcome));
+		}
+
+		fn on_commit_emitted(&self, round: u64) {
+			self.0.lock().push(MetricsEvent::CommitEmitted(round));
+		}
+
+		fn on_commit_dropped(&self, reason: CommitDropReason) {
+			self.0.lock().push(MetricsEvent::CommitDropped(reason));
+		}
+
+		fn on_round_started(&self, round: u64) {
+			self.0.lock().push(MetricsEvent::RoundStarted(round));
+		}
+
+		fn on_round_completed(&self, round: u64, _duration: Duration) {
+			self.0.lock().push(MetricsEvent::RoundCompleted(round));
+		}
+
+		fn on_background_rounds(&self, count: usize) {
+			self.0.lock().push(MetricsEvent::BackgroundRounds(count));
+		}
+
+		fn on_message_dropped(&self, round: u64, outcome: RoutingOutcome) {
+			self.0.lock().push(MetricsEvent::MessageDropped(round, outcome));
+		}
+
+		fn on_global_out_queue(&self, depth: usize) {
+			self.0.lock().push(MetricsEvent::GlobalOutQueue(depth));
+		}
+	}
+
+	#[test]
+	fn metrics_are_reported_over_two_rounds() {
+		let local_id = Id(5);
+		let voters = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let env = Arc::new(
+			Environment::new(network.clone(), local_id).with_commit_delay(Duration::from_millis(200)),
+		);
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+		threads_pool.spawn_ok(routing_task);
+
+		let metrics = Arc::new(RecordingMetrics::default());
+		voter.set_metrics(metrics.clone());
+
+		// run until the second round completes.
+		futures::executor::block_on(future::poll_fn(|cx| {
+			let _ = voter.poll_unpin(cx).map(|res| res.unwrap());
+			if metrics.events().contains(&MetricsEvent::RoundCompleted(2)) {
+				Poll::Ready(())
+			} else {
+				Poll::Pending
+			}
+		}));
+
+		// the commit timer of round 1 fires before round 2 prevotes, after
+		// which round 1 is done.
+		use MetricsEvent::*;
+		use VoteImportOutcome::Imported;
+		assert_eq!(metrics.events(), vec![
+			RoundStarted(1),
+			VoteImported(Phase::Prevote, Imported),
+			VoteImported(Phase::Precommit, Imported),
+			RoundCompleted(1),
+			BackgroundRounds(1),
+			RoundStarted(2),
+			CommitEmitted(1),
+			GlobalOutQueue(1),
+			BackgroundRounds(0),
+			GlobalOutQueue(0),
+			VoteImported(Phase::Prevote, Imported),
+			VoteImported(Phase::Precommit, Imported),
+			RoundCompleted(2),
+			BackgroundRounds(1),
+			RoundStarted(3),
+		]);
+	}
+
+	#[test]
+	fn completed_rounds_can_be_queried_after_they_concluded() {
+		let local_id = Id(5);
+		let voters = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let env = Arc::new(Environment::new(network.clone(), local_id));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+		voter.set_completed_rounds_capacity(3);
+
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+		threads_pool.spawn_ok(routing_task);
+
+		// run until the fifth round is the best round, with the four before it
+		// recorded once they moved to the background.
+		let completed = voter.completed_rounds();
+		futures::executor::block_on(future::poll_fn(|cx| {
+			let _ = voter.poll_unpin(cx).map(|res| res.unwrap());
+			if completed.get(4).is_some() {
+				Poll::Ready(())
+			} else {
+				Poll::Pending
+			}
+		}));
+
+		// only the latest three rounds are kept.
+		let rounds = completed.rounds();
+		assert_eq!(rounds.iter().map(|round| round.number).collect::<Vec<_>>(), vec![2, 3, 4]);
+		assert_eq!(completed.get(1), None);
+
+		// the round in the middle of the history finalized the best block, with
+		// our prevote and precommit.
+		let round = completed.get(3).unwrap();
+		assert_eq!(round.state.finalized, Some(("E", 6)));
+		assert_eq!(round.state.estimate, Some(("E", 6)));
+		assert!(round.state.completable);
+		assert_eq!(round.base, ("E", 6));
+		assert_eq!(round.votes.seen().len(), 2);
+		assert!(round.votes.seen().iter().all(|vote| vote.id == local_id));
+	}
+
+	#[test]
+	fn finality_is_yielded_once_per_block_in_order() {
+		let local_id = Id(5);
+		let voters = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let env = Arc::new(Environment::new(network.clone(), local_id));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+		threads_pool.spawn_ok(routing_task);
+
+		// the first round finalizes the chain, and once it's built on a later
+		// round finalizes the new blocks.
+		let mut finalized = Vec::new();
+		futures::executor::block_on(future::poll_fn(|cx| {
+			while let Poll::Ready(block) = voter.poll_finality(cx) {
+				let block = block.expect("the voter doesn't fail; qed");
+				if block == ("E", 6) {
+					env.with_chain(|chain| chain.push_blocks("E", &["F", "G"]));
+				}
+				finalized.push(block);
+			}
+
+			if finalized.len() >= 2 { Poll::Ready(()) } else { Poll::Pending }
+		}));
+
+		assert_eq!(finalized, vec![("E", 6), ("G", 8)]);
+		assert!(voter.newly_finalized.as_ref().unwrap().is_empty());
+	}
+
+	#[test]
+	fn finality_ends_after_the_blocks_finalized_before_the_voter_failed() {
+		let local_id = Id(5);
+		let voters = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+
+		let (network, _) = testing::environment::make_network();
+		let env = Arc::new(Environment::new(network.clone(), local_id));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		// a commit finalizing "C", right before the incoming stream fails.
+		let commit = Commit {
+			target_hash: "C",
+			target_number: 4,
+			precommits: vec![SignedPrecommit {
+				precommit: Precommit::new("C", 4),
+				signature: Signature(local_id.0),
+				id: local_id,
+			}],
+			set_id: 0,
+		};
+
+		let global_in = stream::iter(vec![
+			Ok(CommunicationIn::Commit(1, commit.into(), Callback::Blank)),
+			Err(crate::Error::SinkClosed),
+		]).chain(stream::pending());
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			(global_in, network.make_global_comms().1),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		// the failed voter isn't polled again, which would leave it pending on
+		// the rest of the stream.
+		futures::executor::block_on(future::poll_fn(|cx| {
+			assert_eq!(voter.poll_finality(cx), Poll::Ready(Some(("C", 4))));
+			assert_eq!(voter.poll_finality(cx), Poll::Ready(None));
+			assert_eq!(voter.poll_finality(cx), Poll::Ready(None));
+			Poll::Ready(())
+		}));
+
+		assert_eq!(voter.finality_error, Some(crate::Error::SinkClosed));
+	}
+
+	#[test]
+	fn event_log_keeps_the_latest_events_of_a_round() {
+		let local_id = Id(5);
+		let voters = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let env = Arc::new(Environment::new(network.clone(), local_id));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+		threads_pool.spawn_ok(routing_task);
+
+		// logged along with other metrics, keeping only the last four events.
+		let log = Arc::new(EventLog::new(4));
+		let metrics = Arc::new(RecordingMetrics::default());
+		voter.set_metrics(Arc::new((metrics.clone(), log.clone())));
+
+		futures::executor::block_on(future::poll_fn(|cx| {
+			let _ = voter.poll_unpin(cx).map(|res| res.unwrap());
+			if metrics.events().contains(&MetricsEvent::RoundStarted(2)) {
+				Poll::Ready(())
+			} else {
+				Poll::Pending
+			}
+		}));
+
+		// the start of the first round and our prevote in it were overwritten.
+		let events = log.dump();
+		assert_eq!(events.len(), 4);
+		assert_eq!(events[0], VoterEvent::VoteImported(Phase::Precommit, VoteImportOutcome::Imported));
+		assert!(matches!(events[1], VoterEvent::RoundCompleted(1, _)));
+		assert_eq!(events[2..], [VoterEvent::BackgroundRounds(1), VoterEvent::RoundStarted(2)]);
+		assert_eq!(metrics.events().len(), 6);
+	}
+
+	#[test]
+	fn pre_validated_commit_finalizes_without_validation() {
+		use super::replay::{Call, Event, RecordingEnvironment};
+
+		let local_id = Id(5);
+		let test_id = Id(42);
+		let voters: VoterSet<_> = VoterSet::new([(local_id, 100), (test_id, 201)].iter().cloned()).unwrap();
+
+		let (network, _routing_task) = testing::environment::make_network();
+		let env = Arc::new(RecordingEnvironment::new(Environment::new(network.clone(), local_id)));
+		let last_finalized = env.inner().with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters.clone(),
+			network.make_global_comms(),
+			1,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		let commit = |target_hash, target_number| Commit {
+			target_hash,
+			target_number,
+			precommits: vec![SignedPrecommit {
+				precommit: Precommit { target_hash, target_number },
+				signature: Signature(test_id.0),
+				id: test_id,
+			}],
+			set_id: 0,
+		};
+
+		// validated during block import, outside of the voter.
+		let validated = |commit| validate_commit(commit, &voters, env.inner()).unwrap();
+
+		let e = commit("E", 6);
+		voter.note_commit_validated(0, e.clone(), validated(&e)).unwrap();
+		assert_eq!(voter.last_finalized_number, 6);
+
+		// commits below our finality are ignored.
+		let c = commit("C", 4);
+		voter.note_commit_validated(0, c.clone(), validated(&c)).unwrap();
+		assert_eq!(voter.last_finalized_number, 6);
+
+		let trace = env.trace();
+		let finalized: Vec<_> = trace.iter().filter_map(|event| match event {
+			Event::Call(Call::FinalizeBlock { number, .. }, _) => Some(*number),
+			_ => None,
+		}).collect();
+		assert_eq!(finalized, vec![6]);
+
+		// the chain was never consulted to validate the commits again.
+		assert!(!trace.iter().any(|event| matches!(
+			event,
+			Event::Call(Call::Ancestry { .. }, _) | Event::Call(Call::IsEqualOrDescendentOf { .. }, _),
+		)));
+	}
+
+	#[test]
+	fn commits_too_far_ahead_are_not_acted_on() {
+		let local_id = Id(5);
+		let test_id = Id(42);
+		let voters: VoterSet<_> = VoterSet::new([(local_id, 100), (test_id, 201)].iter().cloned()).unwrap();
+
+		let (network, _routing_task) = testing::environment::make_network();
+		let env = Arc::new(Environment::new(network.clone(), local_id));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters.clone(),
+			network.make_global_comms(),
+			1,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		let metrics = Arc::new(RecordingMetrics::default());
+		voter.set_metrics(metrics.clone());
+		voter.set_max_commit_gap(Some(3));
+
+		let commit = |target_hash, target_number| Commit {
+			target_hash,
+			target_number,
+			precommits: vec![SignedPrecommit {
+				precommit: Precommit { target_hash, target_number },
+				signature: Signature(test_id.0),
+				id: test_id,
+			}],
+			set_id: 0,
+		};
+
+		let (outcomes, callback) = {
+			let outcomes = Arc::new(parking_lot::Mutex::new(Vec::new()));
+			let recorded = outcomes.clone();
+			(outcomes, move || Callback::Work(Box::new({
+				let recorded = recorded.clone();
+				move |outcome| recorded.lock().push(outcome)
+			})))
+		};
+
+		// "E" is five blocks above genesis.
+		let e = commit("E", 6);
+		let validation_result = validate_commit(&e, &voters, &*env).unwrap();
+		assert_eq!(validation_result.gap_from_previous(1), Some(5));
+
+		voter.process_incoming_message(CommunicationIn::Commit(0, e.into(), callback())).unwrap();
+		assert_eq!(voter.last_finalized_number, 1);
+		assert!(matches!(outcomes.lock().pop(), Some(CommitProcessingOutcome::Good(_))));
+		assert!(metrics.events().contains(&MetricsEvent::CommitDropped(CommitDropReason::GapTooLarge)));
+
+		// a commit within the limit is acted on.
+		voter.process_incoming_message(CommunicationIn::Commit(0, commit("C", 4).into(), callback())).unwrap();
+		assert_eq!(voter.last_finalized_number, 4);
+		assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("C", 4));
+	}
+
+	// a commit signed by a supermajority of four voters with equal weight.
+	fn supermajority_commit(target_hash: &'static str, target_number: u32)
+		-> Commit<&'static str, u32, Signature, Id>
+	{
+		Commit {
+			target_hash,
+			target_number,
+			precommits: (0..3).map(|i| SignedPrecommit {
+				precommit: Precommit { target_hash, target_number },
+				signature: Signature(i),
+				id: Id(i),
+			}).collect(),
+			set_id: 0,
+		}
+	}
+
+	// sets up an observer of four voters in round 2, with "A" to "E" in its
+	// chain.
+	#[allow(clippy::type_complexity)]
+	fn lagging_observer() -> (
+		Arc<Environment>,
+		Voter<
+			&'static str,
+			u32,
+			Environment,
+			impl Stream<Item=Result<CommunicationIn<&'static str, u32, Signature, Id>, crate::Error>> + Unpin,
+			impl Sink<CommunicationOut<&'static str, u32, Signature, Id>, Error=crate::Error> + Unpin,
+		>,
+	) {
+		let voters = VoterSet::new((0..4).map(|i| (Id(i), 1))).unwrap();
+
+		let (network, _routing_task) = testing::environment::make_network();
+		let env = Arc::new(Environment::new(network.clone(), Id(5)));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			1,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		(env, voter)
+	}
+
+	#[test]
+	fn lagging_voter_finalizes_commits_ahead_and_catches_up() {
+		let (env, mut voter) = lagging_observer();
+
+		voter.process_incoming_message(CommunicationIn::Commit(5, supermajority_commit("E", 6).into(), Callback::Blank)).unwrap();
+
+		// finalized right away, well beyond the estimate of round 1.
+		assert_eq!(voter.last_finalized_number, 6);
+		assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("E", 6));
+		assert_eq!(env.take_catch_up_requests(), vec![5]);
+
+		// a commit for an earlier round doesn't ask for a catch-up again.
+		voter.process_incoming_message(CommunicationIn::Commit(2, supermajority_commit("E", 6).into(), Callback::Blank)).unwrap();
+		assert!(env.take_catch_up_requests().is_empty());
+	}
+
+	#[test]
+	fn commits_for_rounds_skipped_on_catch_up_are_finalized() {
+		let (env, mut voter) = lagging_observer();
+
+		// catch up from round 2 to round 6, skipping rounds 3 to 5.
+		let pv = |id| crate::SignedPrevote { prevote: Prevote::new("C", 4), id: Id(id), signature: Signature(id) };
+		let pc = |id| SignedPrecommit { precommit: Precommit::new("C", 4), id: Id(id), signature: Signature(id) };
+		let catch_up = CatchUp {
+			base_number: 1,
+			base_hash: GENESIS_HASH,
+			round_number: 6,
+			prevotes: (0..3).map(pv).collect(),
+			precommits: (0..3).map(pc).collect(),
+			set_id: 0,
+		};
+		voter.process_incoming_message(CommunicationIn::CatchUp(catch_up, Callback::Blank)).unwrap();
+		assert_eq!(voter.best_round.round_number(), 7);
+
+		assert!(!voter.round_skipped(2));
+		assert!((3..6).all(|round| voter.round_skipped(round)));
+		assert!(!voter.round_skipped(6));
+		assert!(!voter.round_skipped(8));
+
+		let outcomes = Arc::new(parking_lot::Mutex::new(Vec::new()));
+		let callback = Callback::Work(Box::new({
+			let outcomes = outcomes.clone();
+			move |outcome| outcomes.lock().push(outcome)
+		}));
+
+		// a commit for a skipped round isn't imported into any round, but
+		// still finalizes its block.
+		voter.process_incoming_message(CommunicationIn::Commit(4, supermajority_commit("E", 6).into(), callback)).unwrap();
+
+		assert!(matches!(outcomes.lock().pop(), Some(CommitProcessingOutcome::Good(_))));
+		assert_eq!(voter.last_finalized_number, 6);
+		assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("E", 6));
+	}
+
+	#[test]
+	fn finality_from_outside_the_voter_prunes_rounds_and_skips_ahead() {
+		use crate::testing::clock::TestClock;
+
+		let voters = VoterSet::new((0..4).map(|i| (Id(i), 1))).unwrap();
+		let (network, _routing_task) = testing::environment::make_network();
+
+		let clock = TestClock::manual();
+		let env = Arc::new(
+			Environment::new(network.clone(), Id(5))
+				.with_clock(clock.clone())
+				.with_commit_delay(Duration::from_millis(100)),
+		);
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.push_blocks("C", &["D2", "E2", "F2"]);
+			chain.last_finalized()
+		});
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			1,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		// prevotes for "C" leave round 2 with an estimate above our finality.
+		for id in 0..3 {
+			voter.best_round.handle_message(SignedMessage {
+				message: Message::Prevote(Prevote::new("C", 4)),
+				signature: Signature(id),
+				id: Id(id),
+			}).unwrap();
+		}
+		voter.completed_best_round().unwrap();
+
+		clock.advance(Duration::from_millis(100));
+		futures::executor::block_on(future::poll_fn(|cx| Poll::Ready(voter.prune_background_rounds(cx)))).unwrap();
+		assert_eq!(voter.round_parents(), vec![(2, Some(1)), (3, Some(2))]);
+
+		// warp sync finalized "E" in round 5, while we're in round 3.
+		voter.set_last_finalized("E", 6, 5).unwrap();
+		assert_eq!(voter.last_finalized_number, 6);
+		assert_eq!(voter.best_round.round_number(), 6);
+		assert_eq!(voter.best_round.dag_base(), ("E", 6));
+		assert!(!voter.round_skipped(3));
+		assert!((4..6).all(|round| voter.round_skipped(round)));
+
+		// the environment isn't asked to finalize the block again.
+		assert_eq!(env.with_chain(|chain| chain.last_finalized()), (GENESIS_HASH, 1));
+
+		// the rounds below are pruned, including the one we left.
+		clock.advance(Duration::from_millis(100));
+		futures::executor::block_on(future::poll_fn(|cx| Poll::Ready(voter.prune_background_rounds(cx)))).unwrap();
+		assert_eq!(voter.round_parents(), vec![(6, Some(5))]);
+
+		// finality can't be lowered, and earlier round hints don't move us back.
+		assert_eq!(voter.set_last_finalized("C", 4, 7).err(), Some(crate::Error::FinalityReverted));
+		voter.set_last_finalized("E", 6, 2).unwrap();
+		assert_eq!(voter.best_round.round_number(), 6);
+
+		// nor can a different block at the same height, or a block on another
+		// fork, be finalized.
+		assert_eq!(voter.set_last_finalized("E2", 6, 7).err(), Some(crate::Error::NotDescendent));
+		assert_eq!(voter.set_last_finalized("F2", 7, 7).err(), Some(crate::Error::NotDescendent));
+		assert_eq!(voter.last_finalized_number, 6);
+		assert_eq!(voter.best_round.round_number(), 6);
+	}
+
+	#[test]
+	fn injected_commits_are_processed_like_incoming_ones() {
+		let (env, mut voter) = lagging_observer();
+
+		let outcomes = Arc::new(parking_lot::Mutex::new(Vec::new()));
+		let callback = Callback::Work(Box::new({
+			let outcomes = outcomes.clone();
+			move |outcome| outcomes.lock().push(outcome)
+		}));
+
+		voter.inject_global_message(CommunicationIn::Commit(2, supermajority_commit("D", 5).into(), callback)).unwrap();
+
+		assert!(matches!(outcomes.lock().pop(), Some(CommitProcessingOutcome::Good(_))));
+		assert_eq!(voter.last_finalized_number, 5);
+		assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("D", 5));
+
+		// commits failing validation are reported as bad.
+		let mut commit = supermajority_commit("E", 6);
+		commit.precommits.truncate(2);
+		let callback = Callback::Work(Box::new({
+			let outcomes = outcomes.clone();
+			move |outcome| outcomes.lock().push(outcome)
+		}));
+		voter.inject_global_message(CommunicationIn::Commit(2, commit.into(), callback)).unwrap();
+
+		assert!(matches!(outcomes.lock().pop(), Some(CommitProcessingOutcome::Bad(_))));
+		assert_eq!(voter.last_finalized_number, 5);
+	}
+
+	#[test]
+	fn commits_below_the_finality_of_the_chain_are_not_finalized_again() {
+		let voters = VoterSet::new((0..4).map(|i| (Id(i), 1))).unwrap();
+		let (network, _routing_task) = testing::environment::make_network();
+		let env = Arc::new(Environment::new(network.clone(), Id(5)).reporting_last_finalized());
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			1,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		// block import finalized "E" behind the back of the voter.
+		env.with_chain(|chain| chain.set_last_finalized(("E", 6)));
+
+		// the test environment panics when finalizing backwards.
+		voter.process_incoming_message(CommunicationIn::Commit(2, supermajority_commit("D", 5).into(), Callback::Blank)).unwrap();
+
+		assert_eq!(voter.last_finalized_number, 5);
+		assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("E", 6));
+	}
+
+	#[test]
+	fn commits_for_unavailable_blocks_are_deferred() {
+		let (env, mut voter) = lagging_observer();
+
+		let outcomes = Arc::new(parking_lot::Mutex::new(Vec::new()));
+		let callback = Callback::Work(Box::new({
+			let outcomes = outcomes.clone();
+			move |outcome| outcomes.lock().push(outcome)
+		}));
+
+		// "F" isn't in our chain yet.
+		voter.process_incoming_message(CommunicationIn::Commit(5, supermajority_commit("F", 7).into(), callback)).unwrap();
+		assert_eq!(voter.last_finalized_number, 1);
+		assert!(outcomes.lock().is_empty());
+
+		// other blocks becoming available don't matter.
+		voter.note_block_available("E").unwrap();
+		assert!(outcomes.lock().is_empty());
+
+		env.with_chain(|chain| chain.push_blocks("E", &["F"]));
+		voter.note_block_available("F").unwrap();
+
+		assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("F", 7));
+		assert!(matches!(outcomes.lock().pop(), Some(CommitProcessingOutcome::Good(_))));
+		assert_eq!(env.take_catch_up_requests(), vec![5]);
+	}
+
+	#[test]
+	fn deferred_commits_are_checked_evicted_and_pruned() {
+		let (env, mut voter) = lagging_observer();
+
+		let outcomes = Arc::new(parking_lot::Mutex::new(Vec::new()));
+		let callback = |i| Callback::Work(Box::new({
+			let outcomes = outcomes.clone();
+			move |outcome| outcomes.lock().push((i, matches!(outcome, CommitProcessingOutcome::Good(_))))
+		}));
+
+		// "F" isn't in our chain yet, and a commit without enough precommits
+		// for it can't be valid, so it isn't deferred.
+		let mut commit = supermajority_commit("F", 7);
+		commit.precommits.truncate(2);
+		voter.process_incoming_message(CommunicationIn::Commit(5, commit.into(), callback(0))).unwrap();
+		assert_eq!(outcomes.lock().drain(..).collect::<Vec<_>>(), vec![(0, false)]);
+		assert!(voter.deferred_commits.is_empty());
+
+		// the oldest commit is dropped once too many are deferred, and its
+		// sender hears of it.
+		for i in 1..=MAX_DEFERRED_COMMITS + 1 {
+			voter.process_incoming_message(CommunicationIn::Commit(5, supermajority_commit("F", 7).into(), callback(i))).unwrap();
+		}
+		assert_eq!(outcomes.lock().drain(..).collect::<Vec<_>>(), vec![(1, false)]);
+		assert_eq!(voter.deferred_commits.len(), MAX_DEFERRED_COMMITS);
+
+		// finalizing "F2" at the same height makes the rest obsolete.
+		env.with_chain(|chain| chain.push_blocks("E", &["F2"]));
+		voter.process_incoming_message(CommunicationIn::Commit(5, supermajority_commit("F2", 7).into(), Callback::Blank)).unwrap();
+
+		assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("F2", 7));
+		assert!(voter.deferred_commits.is_empty());
+		assert_eq!(
+			outcomes.lock().drain(..).collect::<Vec<_>>(),
+			(2..=MAX_DEFERRED_COMMITS + 1).map(|i| (i, true)).collect::<Vec<_>>(),
+		);
+	}
+
+	#[test]
+	fn duplicate_votes_are_dropped_before_checking_the_chain() {
+		let voters = VoterSet::new((0..4).map(|i| (Id(i), 1))).unwrap();
+		let (network, _) = testing::environment::make_network();
+		let env = Arc::new(Environment::new(network.clone(), Id(5)).allowing_equivocations());
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.push_blocks("C", &["D2"]);
+			chain.last_finalized()
+		});
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+		let metrics = Arc::new(RecordingMetrics::default());
+		voter.set_metrics(metrics.clone());
+
+		let prevote = |hash, number| SignedMessage {
+			message: Message::Prevote(Prevote::new(hash, number)),
+			signature: Signature(0),
+			id: Id(0),
+		};
+
+		voter.route_message(1, prevote("E", 6)).unwrap();
+
+		// the ancestry of "E" can't be checked anymore, but duplicates of the
+		// vote don't need to.
+		env.with_chain(|chain| chain.mark_unsynced("E"));
+		for _ in 0..3 {
+			voter.route_message(1, prevote("E", 6)).unwrap();
+		}
+
+		// a prevote for another block still reveals the equivocation.
+		voter.route_message(1, prevote("D2", 5)).unwrap();
+
+		assert_eq!(metrics.events(), vec![
+			MetricsEvent::RoundStarted(1),
+			MetricsEvent::VoteImported(Phase::Prevote, VoteImportOutcome::Imported),
+			MetricsEvent::VoteImported(Phase::Prevote, VoteImportOutcome::Duplicated),
+			MetricsEvent::VoteImported(Phase::Prevote, VoteImportOutcome::Duplicated),
+			MetricsEvent::VoteImported(Phase::Prevote, VoteImportOutcome::Duplicated),
+			MetricsEvent::VoteImported(Phase::Prevote, VoteImportOutcome::Equivocation),
+		]);
+		assert_eq!(env.take_equivocations(), vec![(1, Id(0))]);
+	}
+
+	#[test]
+	fn busy_past_rounds_do_not_hold_up_the_best_round() {
+		use futures::executor::LocalPool;
+		use futures::task::LocalSpawnExt;
+		use crate::testing::clock::TestClock;
+
+		let voters = VoterSet::new((0..4).map(|i| (Id(i), 1))).unwrap();
+		let (network, routing_task) = testing::environment::make_network();
+		let env = Arc::new(
+			Environment::new(network.clone(), Id(5))
+				.with_clock(TestClock::manual())
+				.with_commit_delay(Duration::from_millis(0)),
+		);
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
+			chain.last_finalized()
+		});
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters.clone(),
+			network.make_global_comms(),
+			100,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+		let metrics = Arc::new(RecordingMetrics::default());
+		voter.set_metrics(metrics.clone());
+		voter.set_min_commit_advance(0);
+
+		// lots of rounds in the background, each with a commit due right away.
+		for round_number in 10..50 {
+			let mut round = crate::round::Round::new(crate::round::RoundParams {
+				round_number,
+				set_id: 0,
+				voters: voters.clone(),
+				base: (GENESIS_HASH, 1),
+			});
+			for i in 0..3 {
+				round.import_prevote(&*env, Prevote::new("C", 4), Id(i), Signature(i)).unwrap();
+				round.import_precommit(&*env, Precommit::new("C", 4), Id(i), Signature(i)).unwrap();
+			}
+
+			let mut completed = VotingRound::completed(round, voter.best_round.finalized_sender(), env.clone());
+			completed.restore_finalizing_commit();
+			voter.past_rounds.push(&*env, completed);
+		}
+
+		// votes completing the best round.
+		for i in 0..3 {
+			for message in [
+				Message::Prevote(Prevote::new("E", 6)),
+				Message::Precommit(Precommit::new("E", 6)),
+			] {
+				let signed = SignedMessage { message, signature: Signature(i), id: Id(i) };
+				assert_eq!(voter.route_message(101, signed).unwrap(), RoutingOutcome::Accepted);
+			}
+		}
+
+		let emitted = |metrics: &RecordingMetrics| metrics.events().iter()
+			.filter(|event| matches!(event, MetricsEvent::CommitEmitted(_)))
+			.count();
+
+		// a single poll moves on to the next round, but only handles a few
+		// commits of past rounds.
+		futures::executor::block_on(future::poll_fn(|cx| {
+			assert!(Future::poll(Pin::new(&mut voter), cx).is_pending());
+			Poll::Ready(())
+		}));
+		assert_eq!(voter.best_round.round_number(), 102);
+		assert_eq!(emitted(&metrics), DEFAULT_PAST_ROUNDS_BUDGET);
+
+		// the voter wakes itself up for the rest.
+		let mut pool = LocalPool::new();
+		pool.spawner().spawn_local(voter.map(|v| v.expect("Error voting"))).unwrap();
+		pool.spawner().spawn_local(routing_task).unwrap();
+		pool.run_until_stalled();
+
+		assert_eq!(emitted(&metrics), 40);
+	}
+
+	#[test]
+	fn votes_for_unavailable_blocks_are_imported_with_the_block() {
+		let (env, mut voter) = lagging_observer();
+		let metrics = Arc::new(RecordingMetrics::default());
+		voter.set_metrics(metrics.clone());
+
+		// "F" isn't in our chain yet.
+		let vote = |id, message| SignedMessage { message, signature: Signature(id), id: Id(id) };
+		for id in 0..3 {
+			voter.route_message(2, vote(id, Message::Prevote(Prevote::new("F", 7)))).unwrap();
+			voter.route_message(2, vote(id, Message::Precommit(Precommit::new("F", 7)))).unwrap();
+		}
+
+		// a voter only has one vote pending per phase.
+		voter.route_message(2, vote(0, Message::Prevote(Prevote::new("G", 8)))).unwrap();
+
+		let events = metrics.events();
+		let pending = events.iter()
+			.filter(|event| matches!(event, MetricsEvent::VoteImported(_, VoteImportOutcome::Pending)))
+			.count();
+		assert_eq!(pending, 6);
+		assert_eq!(
+			events.last(),
+			Some(&MetricsEvent::VoteImported(Phase::Prevote, VoteImportOutcome::Unavailable)),
+		);
+		assert_eq!(voter.best_round.round_state().prevote_ghost, None);
+
+		// once the block arrives, the votes are imported without being sent
+		// again, and finalize it.
+		env.with_chain(|chain| chain.push_blocks("E", &["F"]));
+		voter.block_imported("F", 7).unwrap();
+
+		futures::executor::block_on(future::poll_fn(|cx| {
+			let _ = Future::poll(Pin::new(&mut voter), cx);
+			Poll::Ready(())
+		}));
+
+		assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("F", 7));
+		assert_eq!(voter.finalization_evidence(2).map(|evidence| evidence.len()), Some(3));
+	}
+
+	#[test]
+	fn carries_on_after_reorg_mid_round() {
+		use futures::executor::LocalPool;
+		use futures::task::LocalSpawnExt;
+		use crate::testing::clock::TestClock;
+
+		let local_id = Id(5);
+		let voters = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let clock = TestClock::manual();
+		let env = Arc::new(Environment::new(network.clone(), local_id).with_clock(clock.clone()));
+
+		// the fork at `X` isn't synced yet, so `D` is the best block.
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D"]);
+			chain.push_blocks("B", &["X"]);
+			chain.mark_unsynced("X");
+			chain.last_finalized()
+		});
+
+		let voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		let mut pool = LocalPool::new();
+		pool.spawner().spawn_local(voter.map(|v| v.expect("Error voting"))).unwrap();
+		pool.spawner().spawn_local(routing_task).unwrap();
+		pool.run_until_stalled();
+
+		// the round started and looked up the best chain. now `X` is synced
+		// and becomes the best block, on a fork of the block we'll prevote.
+		env.with_chain(|chain| {
+			chain.mark_synced("X");
+			chain.set_best_chain("X");
+		});
+
+		clock.advance(Duration::from_secs(1));
+		pool.run_until_stalled();
+
+		assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("D", 5));
+		assert_eq!(env.take_prevotes(), vec![(1, Prevote::new("D", 5))]);
+
+		// the best chain doesn't contain the finalized block anymore, but the
+		// voter carries on from it.
+		clock.advance(Duration::from_secs(1));
+		pool.run_until_stalled();
+
+		assert_eq!(env.take_prevotes(), vec![(2, Prevote::new("D", 5))]);
+	}
+
+	#[test]
+	fn finalizes_despite_votes_on_pruned_fork() {
+		use futures::executor::LocalPool;
+		use futures::task::LocalSpawnExt;
+		use crate::testing::clock::TestClock;
+
+		let local_id = Id(5);
+		let pruned_voter = Id(6);
+		let voters = VoterSet::new([(local_id, 5), (pruned_voter, 1)].iter().cloned()).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let clock = TestClock::manual();
+		let env = Arc::new(Environment::new(network.clone(), local_id).with_clock(clock.clone()));
+
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]);
+			chain.push_blocks("A", &["X", "Y", "Z", "W"]);
+			chain.prune_fork("W");
+			chain.last_finalized()
+		});
+
+		// the other voter votes for a block we pruned.
+		let (_, outgoing) = network.make_round_comms(1, pruned_voter);
+		futures::executor::block_on(
+			stream::iter(vec![
+				Ok(Message::Prevote(Prevote::new("W", 6))),
+				Ok(Message::Precommit(Precommit::new("W", 6))),
+			]).forward(outgoing),
+		).unwrap();
+
+		let voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		let mut pool = LocalPool::new();
+		pool.spawner().spawn_local(voter.map(|v| v.expect("Error voting"))).unwrap();
+		pool.spawner().spawn_local(routing_task).unwrap();
+		pool.run_until_stalled();
+
+		clock.advance(Duration::from_secs(1));
+		pool.run_until_stalled();
+
+		assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("C", 4));
+	}
+
+	#[test]
+	fn pacemaker_drives_rounds_without_timers() {
+		use futures::executor::LocalPool;
+		use futures::task::LocalSpawnExt;
+		use crate::testing::clock::TestClock;
+
+		let local_id = Id(5);
+		let voters = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+
+		// the clock is never advanced, so no timer ever fires.
+		let (network, routing_task) = testing::environment::make_network();
+		let clock = TestClock::manual();
+		let env = Arc::new(Environment::new(network.clone(), local_id).with_clock(clock));
+
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D"]);
+			chain.last_finalized()
+		});
+
+		let (mut commits, _) = network.make_global_comms();
+		let (pacemaker, pacemaker_events) = mpsc::unbounded();
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+		voter.set_pacemaker(pacemaker_events);
+
+		let mut pool = LocalPool::new();
+		pool.spawner().spawn_local(voter.map(|v| v.expect("Error voting"))).unwrap();
+		pool.spawner().spawn_local(routing_task).unwrap();
+		pool.run_until_stalled();
+
+		let mut signal = |round, phase| {
+			pacemaker.unbounded_send(PacemakerEvent { round, phase }).unwrap();
+			pool.run_until_stalled();
+		};
+
+		// events for other rounds are ignored.
+		signal(2, Phase::Prevote);
+		assert!(env.take_prevotes().is_empty());
+
+		signal(1, Phase::Prevote);
+		assert_eq!(env.take_prevotes(), vec![(1, Prevote::new("D", 5))]);
+		assert_eq!(env.with_chain(|chain| chain.last_finalized()), (GENESIS_HASH, 1));
+
+		signal(1, Phase::Precommit);
+		assert_eq!(env.with_chain(|chain| chain.last_finalized()), ("D", 5));
+
+		// the next round waits for the pacemaker as well, and so does the
+		// commit of the first one.
+		assert!(env.take_prevotes().is_empty());
+		assert!(commits.next().now_or_never().is_none());
+
+		signal(1, Phase::Commit);
+		match commits.next().now_or_never() {
+			Some(Some(Ok(CommunicationIn::Commit(1, commit, _)))) => {
+				assert_eq!((commit.target_hash, commit.target_number), ("D", 5));
+			},
+			_ => panic!("expected a commit for round 1"),
+		}
+
+		signal(2, Phase::Prevote);
+		assert_eq!(env.take_prevotes(), vec![(2, Prevote::new("D", 5))]);
+	}
+
+	// a sink which only accepts messages while it's open.
+	#[derive(Clone, Default)]
+	struct GatedSink {
+		open: Arc<parking_lot::Mutex<bool>>,
+		// whether to close again after reporting to be ready once.
+		once: Arc<parking_lot::Mutex<bool>>,
+		waker: Arc<parking_lot::Mutex<Option<futures::task::Waker>>>,
+		sent: Arc<parking_lot::Mutex<Vec<Message<&'static str, u32>>>>,
+	}
+
+	impl GatedSink {
+		fn open(&self) {
+			*self.open.lock() = true;
+			if let Some(waker) = self.waker.lock().take() {
+				waker.wake();
+			}
+		}
+
+		// open only until the sink reports to be ready, without taking the
+		// message the caller prepares then.
+		fn open_once(&self) {
+			*self.once.lock() = true;
+			self.open();
+		}
+	}
+
+	impl Sink<Message<&'static str, u32>> for GatedSink {
+		type Error = crate::Error;
+
+		fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), crate::Error>> {
+			let mut open = self.open.lock();
+			if *open {
+				if std::mem::replace(&mut *self.once.lock(), false) {
+					*open = false;
+				}
+				return Poll::Ready(Ok(()))
+			}
+
+			*self.waker.lock() = Some(cx.waker().clone());
+			Poll::Pending
+		}
+
+		fn start_send(self: Pin<&mut Self>, message: Message<&'static str, u32>) -> Result<(), crate::Error> {
+			self.sent.lock().push(message);
+			Ok(())
+		}
+
+		fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), crate::Error>> {
+			Poll::Ready(Ok(()))
+		}
+
+		fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<Result<(), crate::Error>> {
+			Poll::Ready(Ok(()))
+		}
+	}
+
+	#[test]
+	fn paused_voter_imports_votes_without_casting() {
+		use crate::testing::clock::TestClock;
+
+		let voters: VoterSet<_> = VoterSet::new((0..4).map(|i| (Id(i), 1))).unwrap();
+		let (network, _) = testing::environment::make_network();
+
+		let clock = TestClock::manual();
+		let env = Arc::new(Environment::new(network.clone(), Id(0)).with_clock(clock.clone()));
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D"]);
+			chain.last_finalized()
+		});
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		let sink = GatedSink::default();
+		sink.open();
+		voter.set_round_outgoing_sink(Box::pin(sink.clone()));
+		voter.pause();
+
+		let vote = |id, message| SignedMessage { message, signature: Signature(id), id: Id(id) };
+		let poll = |voter: &mut Voter<_, _, _, _, _>| {
+			futures::executor::block_on(future::poll_fn(|cx| {
+				let _ = Future::poll(Pin::new(&mut *voter), cx);
+				Poll::Ready(())
+			}));
+		};
+
+		// the others prevote, and the round progresses on their votes, even
+		// past our timers.
+		for id in 1..4 {
+			voter.route_message(1, vote(id, Message::Prevote(Prevote::new("D", 5)))).unwrap();
+		}
+		clock.advance(Duration::from_secs(1));
+		poll(&mut voter);
+
+		assert_eq!(voter.best_round.round_state().estimate, Some(("D", 5)));
+		assert!(env.take_prevotes().is_empty());
+
+		for id in 1..4 {
+			voter.route_message(1, vote(id, Message::Precommit(Precommit::new("D", 5)))).unwrap();
+		}
+		poll(&mut voter);
+
+		assert_eq!(voter.best_round.round_number(), 2);
+		assert!(sink.sent.lock().is_empty());
+
+		// once resumed, we vote in the next round.
+		let sink = GatedSink::default();
+		sink.open();
+		voter.set_round_outgoing_sink(Box::pin(sink.clone()));
+		voter.resume();
+
+		clock.advance(Duration::from_secs(1));
+		poll(&mut voter);
+
+		assert_eq!(env.take_prevotes(), vec![(2, Prevote::new("D", 5))]);
+		assert_eq!(*sink.sent.lock(), vec![Message::Prevote(Prevote::new("D", 5))]);
+	}
+
+	#[test]
+	fn votes_wait_for_congested_sink() {
+		use futures::executor::LocalPool;
+		use futures::task::LocalSpawnExt;
+		use crate::testing::clock::TestClock;
+
+		let local_id = Id(5);
+		let voters = VoterSet::new(std::iter::once((local_id, 100))).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let clock = TestClock::manual();
+		let env = Arc::new(Environment::new(network.clone(), local_id).with_clock(clock.clone()));
+
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D"]);
+			chain.last_finalized()
+		});
+
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		let sink = GatedSink::default();
+		voter.set_round_outgoing_sink(Box::pin(sink.clone()));
+
+		let mut pool = LocalPool::new();
+		pool.spawner().spawn_local(voter.map(|v| v.expect("Error voting"))).unwrap();
+		pool.spawner().spawn_local(routing_task).unwrap();
+
+		// the prevote is due, but isn't cast while the sink is congested.
+		clock.advance(Duration::from_secs(1));
+		pool.run_until_stalled();
+
+		assert!(env.take_prevotes().is_empty());
+		assert!(sink.sent.lock().is_empty());
+
+		// it's cast and sent once the sink accepts messages again.
+		sink.open();
+		pool.run_until_stalled();
+
+		assert_eq!(env.take_prevotes(), vec![(1, Prevote::new("D", 5))]);
+		assert_eq!(*sink.sent.lock(), vec![Message::Prevote(Prevote::new("D", 5))]);
+	}
+
+	#[test]
+	fn cast_votes_are_recorded_before_they_are_sent() {
+		use crate::testing::clock::TestClock;
+
+		// we're not the primary of the first round, so the prevote is the
+		// first message we send.
+		let local_id = Id(5);
+		let voters = VoterSet::new(vec![(local_id, 100), (Id(6), 1)]).unwrap();
+
+		let (network, _) = testing::environment::make_network();
+		let clock = TestClock::manual();
+		let env = Arc::new(Environment::new(network.clone(), local_id).with_clock(clock.clone()));
 
-			threads_pool.spawn_ok(routing_task);
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D"]);
+			chain.last_finalized()
+		});
 
-			// wait for the node to broadcast a commit message
-			commits.take(1).for_each(|_| future::ready(()))
-		}).flatten());
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
+
+		let sink = GatedSink::default();
+		voter.set_round_outgoing_sink(Box::pin(sink.clone()));
+
+		// the prevote is constructed once the sink is ready, but the sink
+		// doesn't take it before the voter is dropped.
+		sink.open_once();
+		clock.advance(Duration::from_secs(1));
+		futures::executor::block_on(future::poll_fn(|cx| {
+			let _ = Future::poll(Pin::new(&mut voter), cx);
+			Poll::Ready(())
+		}));
+
+		let history = voter.best_round.historical_votes().clone();
+		drop(voter);
+
+		assert!(sink.sent.lock().is_empty());
+		assert_eq!(history.prevoted(), Some(&Prevote::new("D", 5)));
+		assert_eq!(history.prevote_idx(), Some(0));
+		assert!(history.seen().is_empty());
 	}
 
 	#[test]
-	fn broadcast_commit_only_if_newer() {
+	fn settled_round_is_left_before_its_timers() {
 		let local_id = Id(5);
-		let test_id = Id(42);
-		let voters: VoterSet<_> = [
-			(local_id, 100),
-			(test_id, 201),
-		].iter().cloned().collect();
+		let voters = VoterSet::new((5..9).map(|i| (Id(i), 1))).unwrap();
 
 		let (network, routing_task) = testing::environment::make_network();
-		let (commits_stream, commits_sink) = network.make_global_comms();
-		let (round_stream, round_sink) = network.make_round_comms(1, test_id);
-
-		let prevote = Message::Prevote(Prevote {
-			target_hash: "E",
-			target_number: 6,
-		});
 
-		let precommit = Message::Precommit(Precommit {
-			target_hash: "E",
-			target_number: 6,
+		// we can't prevote until long after the prevote timer, while the
+		// others' votes are delivered right away.
+		let env = Arc::new(
+			Environment::new(network.clone(), local_id).with_best_chain_delay(Duration::from_secs(10)),
+		);
+		let last_finalized = env.with_chain(|chain| {
+			chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D"]);
+			chain.last_finalized()
 		});
 
-		let commit = (1, Commit {
-			target_hash: "E",
-			target_number: 6,
-			precommits: vec![SignedPrecommit {
-				precommit: Precommit { target_hash: "E", target_number: 6 },
-				signature: Signature(test_id.0),
-				id: test_id
-			}],
-		});
+		for i in 6..9 {
+			let (_, outgoing) = network.make_round_comms(1, Id(i));
+			futures::executor::block_on(
+				stream::iter(vec![
+					Ok(Message::Prevote(Prevote::new("D", 5))),
+					Ok(Message::Precommit(Precommit::new("D", 5))),
+				]).forward(outgoing),
+			).unwrap();
+		}
 
 		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+		threads_pool.spawn_ok(routing_task);
 
-		let global_comms = network.make_global_comms();
-		let env = Arc::new(Environment::new(network, local_id));
-		futures::executor::block_on(::futures::future::lazy(move |_| {
-			// initialize chain
-			let last_finalized = env.with_chain(|chain| {
-				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
-				chain.last_finalized()
-			});
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			last_finalized,
+		);
 
-			let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
+		futures::executor::block_on(future::poll_fn(|cx| {
+			if let Poll::Ready(res) = Future::poll(Pin::new(&mut voter), cx) {
+				panic!("voter stopped: {:?}", res);
+			}
 
-			// run voter in background. scheduling it to shut down at the end.
-			let voter = Voter::new(
-				env.clone(),
-				voters.clone(),
-				global_comms,
-				0,
-				last_round_state,
-				last_finalized,
-			);
-			threads_pool.spawn_ok(voter.map(|v| v.expect("Error voting: {:?}")));
+			let finalized = env.with_chain(|chain| chain.last_finalized()) == ("D", 5);
+			if finalized && voter.last_round_duration().is_some() { Poll::Ready(()) } else { Poll::Pending }
+		}));
 
-			threads_pool.spawn_ok(routing_task.map(|_| ()));
+		// the round was left once it was settled, bounded by the delivery of
+		// votes rather than any timer. our prevote keeps to its timing rules.
+		let duration = voter.last_round_duration().unwrap();
+		assert!(duration < Duration::from_millis(500), "round took {:?}", duration);
+		assert!(env.take_prevotes().is_empty());
+	}
 
-			threads_pool.spawn_ok(::futures::future::lazy(move |_| {
-				round_stream.into_future()
-					.then(|(value, stream)| { // wait for a prevote
-						assert!(match value {
-							Some(Ok(SignedMessage { message: Message::Prevote(_), id: Id(5), .. })) => true,
-							_ => false,
-						});
-						let votes = vec![prevote, precommit].into_iter().map(Result::Ok);
-						futures::stream::iter(votes).forward(round_sink).map(|_| stream) // send our prevote
-					})
-					.then(|stream| {
-						stream.take_while(|value| match value { // wait for a precommit
-							Ok(SignedMessage { message: Message::Precommit(_), id: Id(5), .. }) => future::ready(false),
-							_ => future::ready(true),
-						}).for_each(|_| future::ready(()))
-					})
-					.then(|_| {
-						// send our commit
-						stream::iter(std::iter::once(Ok(CommunicationOut::Commit(commit.0, commit.1)))).forward(commits_sink)
-					})
-					.map_err(|_| ())
-			}).flatten().map(|_| ()));
+	// voters on a simulated network, run on a local pool and timed by a
+	// manual clock, so that a run only depends on the network's seed.
+	struct Simulation {
+		pool: futures::executor::LocalPool,
+		clock: testing::clock::TestClock,
+		network: testing::environment::Network,
+		envs: Vec<Arc<Environment>>,
+		best_block: (&'static str, u32),
+	}
 
-			// wait for the first commit (ours)
-			commits_stream.into_future()
-				.then(|(_, stream)| {
-					stream.take(1).for_each(|_| future::ready(())) // the second commit should never arrive
-						.map(|()| Ok::<(), std::io::Error>(()))
-						.timeout(Duration::from_millis(500)).map_err(|_| ())
-				})
-				.then(|res| {
-					assert!(res.is_err()); // so the previous future times out
-					futures::future::ok::<(), ()>(())
-				})
-		}).flatten()).unwrap();
+	impl Simulation {
+		fn new(weights: &[u64], config: testing::environment::NetworkConfig) -> Self {
+			Self::with_byzantine(weights, config, Vec::new())
+		}
+
+		// honest voters only, which detect stalled finality.
+		fn with_stall_detection(
+			weights: &[u64],
+			config: testing::environment::NetworkConfig,
+			threshold: u64,
+			round_duration: Duration,
+		) -> Self {
+			Self::build(weights, config, Vec::new(), Some((threshold, round_duration)))
+		}
+
+		// the voters at the given indices are byzantine and don't run a voter
+		// of their own, but still have an environment with a chain.
+		fn with_byzantine(
+			weights: &[u64],
+			config: testing::environment::NetworkConfig,
+			byzantine: Vec<(u32, testing::malicious::Behaviour)>,
+		) -> Self {
+			Self::build(weights, config, byzantine, None)
+		}
+
+		fn build(
+			weights: &[u64],
+			config: testing::environment::NetworkConfig,
+			byzantine: Vec<(u32, testing::malicious::Behaviour)>,
+			stall_detection: Option<(u64, Duration)>,
+		) -> Self {
+			use futures::task::LocalSpawnExt;
+
+			let clock = testing::clock::TestClock::manual();
+			let (network, routing_task) = testing::environment::make_network_with(config, clock.clone());
+			let voters = VoterSet::new(weights.iter().enumerate().map(|(i, weight)| (Id(i as u32), *weight))).unwrap();
+
+			let pool = futures::executor::LocalPool::new();
+			let envs = (0..weights.len() as u32).map(|i| {
+				let env = Arc::new(Environment::new(network.clone(), Id(i))
+					.with_clock(clock.clone())
+					.with_commit_delay(Duration::from_millis(200))
+					.allowing_equivocations());
+
+				if let Some((_, behaviour)) = byzantine.iter().find(|(index, _)| *index == i) {
+					let voter = testing::malicious::byzantine_voter(
+						Id(i),
+						0,
+						network.clone(),
+						env.clone(),
+						behaviour.clone(),
+					);
+					pool.spawner().spawn_local(voter).unwrap();
+					return env;
+				}
+
+				let mut voter = Voter::new(
+					env.clone(),
+					voters.clone(),
+					network.make_global_comms_for(Id(i)),
+					0,
+					RoundState::genesis((GENESIS_HASH, 1)),
+					(GENESIS_HASH, 1),
+				);
+				if let Some((threshold, round_duration)) = stall_detection {
+					voter.set_stall_detection(threshold, round_duration);
+				}
+				pool.spawner().spawn_local(voter.map(|v| v.expect("Error voting"))).unwrap();
+
+				env
+			}).collect();
+
+			pool.spawner().spawn_local(routing_task).unwrap();
+
+			Simulation { pool, clock, network, envs, best_block: (GENESIS_HASH, 1) }
+		}
+
+		// import a new best block at every voter, returning its number.
+		fn grow(&mut self) -> u32 {
+			let (parent, number) = self.best_block;
+			let block: &'static str = Box::leak(format!("block {}", number + 1).into_boxed_str());
+			for env in &self.envs {
+				env.with_chain(|chain| chain.push_blocks(parent, &[block]));
+			}
+
+			self.best_block = (block, number + 1);
+			number + 1
+		}
+
+		// let the given time pass, in small steps.
+		fn run_for(&mut self, duration: Duration) {
+			let step = Duration::from_millis(50);
+			for _ in 0..duration.as_millis() / step.as_millis() {
+				self.clock.advance(step);
+				self.pool.run_until_stalled();
+			}
+		}
+
+		// the number of the last block finalized by every voter.
+		fn finalized(&self) -> Vec<u32> {
+			self.envs.iter().map(|env| env.with_chain(|chain| chain.last_finalized().1)).collect()
+		}
 	}
 
 	#[test]
-	fn import_commit_for_any_round() {
-		let local_id = Id(5);
-		let test_id = Id(42);
-		let voters: VoterSet<_> = [
-			(local_id, 100),
-			(test_id, 201),
-		].iter().cloned().collect();
+	fn finality_continues_with_a_third_partitioned_off() {
+		// the set tolerates a faulty weight of 3.
+		let mut simulation = Simulation::new(&[3, 3, 3, 1], testing::environment::NetworkConfig {
+			latency: Duration::from_millis(10)..Duration::from_millis(100),
+			seed: 1,
+			..Default::default()
+		});
 
-		let (network, routing_task) = testing::environment::make_network();
-		let (_, commits_sink) = network.make_global_comms();
+		let before = simulation.grow();
+		simulation.run_for(Duration::from_secs(3));
+		assert_eq!(simulation.finalized(), vec![before; 4]);
 
-		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+		simulation.network.partition(vec![Id(0)]);
+		simulation.grow();
+		let best = simulation.grow();
+		simulation.run_for(Duration::from_secs(5));
+		assert_eq!(simulation.finalized(), vec![before, best, best, best]);
 
-		// this is a commit for a previous round
-		let commit = (0, Commit {
-			target_hash: "E",
-			target_number: 6,
-			precommits: vec![SignedPrecommit {
-				precommit: Precommit { target_hash: "E", target_number: 6 },
-				signature: Signature(test_id.0),
-				id: test_id
-			}],
+		// the partitioned voter catches up once it's reconnected.
+		simulation.network.heal();
+		simulation.run_for(Duration::from_secs(5));
+		assert_eq!(simulation.finalized(), vec![best; 4]);
+	}
+
+	#[test]
+	fn finality_stalls_with_more_than_a_third_partitioned_off() {
+		// the set tolerates a faulty weight of 3, one less than partitioned off.
+		let mut simulation = Simulation::new(&[3, 3, 3, 1], testing::environment::NetworkConfig {
+			latency: Duration::from_millis(10)..Duration::from_millis(100),
+			seed: 2,
+			..Default::default()
 		});
 
-		let global_comms = network.make_global_comms();
-		let env = Arc::new(Environment::new(network, local_id));
-		futures::executor::block_on(::futures::future::lazy(move |_| {
-			// initialize chain
-			let last_finalized = env.with_chain(|chain| {
-				chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
-				chain.last_finalized()
-			});
+		let before = simulation.grow();
+		simulation.run_for(Duration::from_secs(3));
+		assert_eq!(simulation.finalized(), vec![before; 4]);
 
-			let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
+		simulation.network.partition(vec![Id(0), Id(3)]);
+		let best = simulation.grow();
+		simulation.run_for(Duration::from_secs(5));
+		assert_eq!(simulation.finalized(), vec![before; 4]);
 
-			// run voter in background. scheduling it to shut down at the end.
-			let voter = Voter::new(
-				env.clone(),
-				voters.clone(),
-				global_comms,
-				1,
-				last_round_state,
-				last_finalized,
-			);
-			threads_pool.spawn_ok(voter.map(|v| v.expect("Error voting")));
+		simulation.network.heal();
+		simulation.run_for(Duration::from_secs(5));
+		assert_eq!(simulation.finalized(), vec![best; 4]);
+	}
 
-			threads_pool.spawn_ok(routing_task.map(|_| ()));
+	#[test]
+	fn zero_stall_round_duration_is_clamped() {
+		use crate::testing::clock::TestClock;
+		use testing::environment::StallReport;
 
-			threads_pool.spawn_ok(stream::iter(std::iter::once(Ok(CommunicationOut::Commit(commit.0, commit.1)))).forward(commits_sink)
-				.map_err(|_| ()).map(|_| ()));
+		let voters = VoterSet::new((0..4).map(|i| (Id(i), 1))).unwrap();
+		let (network, _routing_task) = testing::environment::make_network();
 
-			// wait for the commit message to be processed which finalized block 6
-			env.finalized_stream()
-				.take_while(|&(_, n, _)| future::ready(n < 6))
-				.for_each(|_| future::ready(()))
-		}).flatten());
+		let clock = TestClock::manual();
+		let env = Arc::new(Environment::new(network.clone(), Id(5)).with_clock(clock.clone()));
+		let mut voter = Voter::new(
+			env.clone(),
+			voters,
+			network.make_global_comms(),
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			(GENESIS_HASH, 1),
+		);
+		voter.set_stall_detection(1, Duration::from_secs(0));
+
+		// polling doesn't spin on timers which fire right away.
+		let mut poll_stall = || futures::executor::block_on(future::poll_fn(|cx| {
+			voter.process_stall(cx);
+			Poll::Ready(())
+		}));
+		poll_stall();
+		assert!(env.take_stall_reports().is_empty());
+
+		clock.advance(Duration::from_millis(1));
+		poll_stall();
+		assert_eq!(env.take_stall_reports(), vec![StallReport::Stalled(1, 1)]);
 	}
 
 	#[test]
-	fn skips_to_latest_round_after_catch_up() {
-		// 3 voters
-		let voters: VoterSet<_> = (0..3).map(|i| (Id(i), 1)).collect();
+	fn stalled_finality_is_reported_until_it_resumes() {
+		use testing::environment::StallReport::*;
+
+		let mut simulation = Simulation::with_stall_detection(
+			&[3, 3, 3, 1],
+			testing::environment::NetworkConfig {
+				latency: Duration::from_millis(10)..Duration::from_millis(100),
+				seed: 2,
+				..Default::default()
+			},
+			3,
+			Duration::from_secs(1),
+		);
 
-		let (network, routing_task) = testing::environment::make_network();
-		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+		// a new block every second keeps finality going.
+		let mut before = 0;
+		for _ in 0..3 {
+			before = simulation.grow();
+			simulation.run_for(Duration::from_secs(1));
+		}
+		simulation.run_for(Duration::from_secs(1));
+		assert_eq!(simulation.finalized(), vec![before; 4]);
+		assert!(simulation.envs.iter().all(|env| env.take_stall_reports().is_empty()));
+
+		// with more than a third partitioned off, rounds get stuck. this is
+		// reported after three rounds' time, and again after six.
+		simulation.network.partition(vec![Id(0), Id(3)]);
+		let mut best = 0;
+		for _ in 0..7 {
+			best = simulation.grow();
+			simulation.run_for(Duration::from_secs(1));
+		}
+		assert_eq!(simulation.finalized(), vec![before; 4]);
+		for env in &simulation.envs {
+			assert_eq!(env.take_stall_reports(), vec![Stalled(3, before), Stalled(6, before)]);
+		}
 
-		futures::executor::block_on(::futures::future::lazy(move |_| {
-			threads_pool.spawn_ok(routing_task.map(|_| ()));
+		// reported as resumed once finality advances again.
+		simulation.network.heal();
+		simulation.run_for(Duration::from_secs(3));
+		assert_eq!(simulation.finalized(), vec![best; 4]);
+		for env in &simulation.envs {
+			assert!(matches!(env.take_stall_reports()[..], [Resumed(rounds, finalized)] if rounds >= 6 && finalized == best));
+		}
+	}
 
-			// initialize unsynced voter at round 0
-			let mut unsynced_voter = {
-				let local_id = Id(4);
+	#[test]
+	fn simulated_network_is_deterministic_per_seed() {
+		let run = |seed| {
+			let mut simulation = Simulation::new(&[1, 1, 1, 1], testing::environment::NetworkConfig {
+				latency: Duration::from_millis(0)..Duration::from_millis(300),
+				drop_rate: 0.1,
+				seed,
+				..Default::default()
+			});
 
-				let env = Arc::new(Environment::new(network.clone(), local_id));
-				let last_finalized = env.with_chain(|chain| {
-					chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]);
-					chain.last_finalized()
-				});
+			simulation.grow();
+			simulation.run_for(Duration::from_secs(2));
+			simulation.network.set_latency(Duration::from_millis(100)..Duration::from_millis(200));
+			simulation.grow();
+			simulation.run_for(Duration::from_secs(2));
 
-				let last_round_state = RoundState::genesis((GENESIS_HASH, 1));
+			simulation.envs.iter().map(|env| env.take_prevotes()).collect::<Vec<_>>()
+		};
 
-				Voter::new(
-					env.clone(),
-					voters.clone(),
-					network.make_global_comms(),
-					0,
-					last_round_state,
-					last_finalized,
-				)
-			};
+		assert_eq!(run(3), run(3));
+	}
 
-			let pv = |id| crate::SignedPrevote {
-				prevote: crate::Prevote { target_hash: "C", target_number: 4 },
-				id: Id(id),
-				signature: Signature(99),
-			};
+	#[test]
+	fn honest_voters_finalize_despite_a_byzantine_voter() {
+		use testing::malicious::Behaviour;
+
+		let behaviour = Behaviour::default()
+			.equivocating()
+			.withholding_precommits()
+			.spamming_commits();
+
+		let mut simulation = Simulation::with_byzantine(
+			&[1, 1, 1, 1],
+			testing::environment::NetworkConfig {
+				latency: Duration::from_millis(10)..Duration::from_millis(100),
+				seed: 4,
+				..Default::default()
+			},
+			vec![(3, behaviour)],
+		);
 
-			let pc = |id| crate::SignedPrecommit {
-				precommit: crate::Precommit { target_hash: "C", target_number: 4 },
-				id: Id(id),
-				signature: Signature(99),
-			};
+		simulation.grow();
+		simulation.run_for(Duration::from_secs(3));
+		let best = simulation.grow();
+		simulation.run_for(Duration::from_secs(3));
 
-			// send in a catch-up message for round 5.
-			network.send_message(CommunicationIn::CatchUp(
-				CatchUp {
-					base_number: 1,
-					base_hash: GENESIS_HASH,
-					round_number: 5,
-					prevotes: vec![pv(0), pv(1), pv(2)],
-					precommits: vec![pc(0), pc(1), pc(2)],
-				},
-				Callback::Blank,
-			));
+		assert_eq!(&simulation.finalized()[..3], &[best; 3]);
+		for env in &simulation.envs[..3] {
+			assert!(env.take_equivocations().contains(&(1, Id(3))));
+		}
+	}
 
-			// poll until it's caught up.
-			// should skip to round 6
-			::futures::future::poll_fn(move |cx| -> Poll<Result<(), ()>> {
-				let poll = Future::poll(Pin::new(&mut unsynced_voter), cx);
-				if unsynced_voter.best_round.round_number() == 6 {
-					Poll::Ready(Ok(()))
-				} else {
-					futures::ready!(poll).map_err(|_| ())?;
-					Poll::Ready(Ok(()))
-				}
-			})
-		}).flatten()).unwrap();
+	#[test]
+	fn forged_commits_are_rejected() {
+		use testing::malicious::Behaviour;
+
+		let mut simulation = Simulation::with_byzantine(
+			&[1, 1, 1, 1],
+			testing::environment::NetworkConfig {
+				latency: Duration::from_millis(10)..Duration::from_millis(100),
+				seed: 5,
+				..Default::default()
+			},
+			vec![(3, Behaviour::default().forging_commits_for(("fork", 2)))],
+		);
+
+		for env in &simulation.envs {
+			env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["fork"]));
+		}
+		let (mut observed, _) = simulation.network.make_global_comms();
+
+		let best = simulation.grow();
+		simulation.run_for(Duration::from_secs(3));
+		assert_eq!(&simulation.finalized()[..3], &[best; 3]);
+
+		let voters = VoterSet::new((0..4).map(|i| (Id(i), 1))).unwrap();
+		let mut forged = 0;
+		while let Some(Some(Ok(CommunicationIn::Commit(_, commit, _)))) = observed.next().now_or_never() {
+			let commit = Commit::from(commit);
+			if commit.target_hash != "fork" { continue }
+
+			forged += 1;
+			simulation.envs[0].with_chain(|chain| {
+				assert!(crate::validate_commit(&commit, &voters, &*chain).unwrap().ghost().is_none());
+			});
+		}
+		assert!(forged > 0);
+	}
+
+	#[cfg(feature = "derive-codec")]
+	#[test]
+	fn outgoing_commit_variants_are_pinned() {
+		let commit = Commit { target_hash: [0xccu8, 0xdd], target_number: 6u32, precommits: Vec::<SignedPrecommit<_, _, u8, u8>>::new(), set_id: 3 };
+		let signed = crate::SignedCommit { commit: commit.clone(), signature: 0x33u8, id: 0x44u8 };
+
+		assert_eq!(CommunicationOut::Commit(9, commit).encode()[0], 0);
+		assert_eq!(CommunicationOut::SignedCommit(9, signed).encode()[0], 1);
 	}
 }
@@ -20,6 +20,10 @@
 //! Create a `PastRounds` struct, and drive it to completion while:
 //!   - Informing it of any new finalized block heights
 //!   - Passing it any validated commits (so backgrounded rounds don't produce conflicting ones)
+//!
+//! Irrelevant rounds can optionally be kept for a grace window, during which
+//! they can still be used to assemble catch-up messages but no longer produce
+//! commits.
 
 #[cfg(feature = "std")]
 use futures::ready;
@@ -28,18 +32,203 @@ use futures::stream::{self, futures_unordered::FuturesUnordered};
 use futures::task;
 use futures::channel::mpsc;
 #[cfg(feature = "std")]
-use log::{trace, debug};
+use log::{trace, warn};
 
 use std::cmp;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::hash::Hash;
+use std::ops::Range;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
+
+use parking_lot::Mutex;
 
-use crate::{Commit, BlockNumberOps};
-use super::Environment;
+use crate::{CatchUp, Commit, BlockNumberOps, HistoricalVotes, Message, SignedPrecommit, SignedPrevote};
+use crate::round::{Round, RoundParams, State as RoundState};
+use crate::voter_set::VoterSet;
+use super::{Clock, CommitDropReason, Environment, FinalizedNotification, Metrics, Phase, SignedMessageFor};
 use super::voting_round::VotingRound;
 
+#[cfg(feature = "derive-codec")]
+use parity_scale_codec::{Encode, Decode};
+
+type CommitFor<H, N, E> = Commit<
+	H,
+	N,
+	<E as Environment<H, N>>::Signature,
+	<E as Environment<H, N>>::Id,
+>;
+
+// how many ranges of rounds skipped on catch-up are remembered.
+const MAX_SKIPPED_RANGES: usize = 16;
+
+/// Why a commit wasn't imported into the rounds run in the background. The
+/// commit is handed back, unless it was imported already, so that it can be
+/// processed otherwise.
+#[derive(Debug)]
+pub(super) enum ImportCommitError<C> {
+	/// The round isn't run in the background, e.g. because it's been pruned
+	/// or is yet to come.
+	UnknownRound(C),
+	/// The round was skipped on catch-up, so it was never run.
+	Skipped(C),
+	/// The round is run in the background, but doesn't take commits anymore,
+	/// e.g. because it's about to be pruned.
+	ChannelClosed(C),
+	/// The commit is the same as the last one imported into the round.
+	Duplicate,
+}
+
+// the sender of commits to the committer of a round run in the background,
+// along with the last commit sent, to tell duplicates.
+struct CommitSender<C> {
+	tx: mpsc::UnboundedSender<C>,
+	last: Option<C>,
+}
+
+type CatchUpFor<H, N, E> = CatchUp<
+	H,
+	N,
+	<E as Environment<H, N>>::Signature,
+	<E as Environment<H, N>>::Id,
+>;
+
+/// The state of a round run in the background, taken with
+/// `Voter::snapshot_past_rounds` to restore it e.g. after a restart.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+pub struct BackgroundRoundSnapshot<H, N, S, Id> {
+	/// The round number.
+	pub round_number: u64,
+	/// The base block of the round.
+	pub base: (H, N),
+	/// The prevotes imported into the round, in import order.
+	pub prevotes: Vec<SignedPrevote<H, N, S, Id>>,
+	/// The precommits imported into the round, in import order.
+	pub precommits: Vec<SignedPrecommit<H, N, S, Id>>,
+	/// Whether the round is still due to broadcast a commit.
+	pub committing: bool,
+	/// The best commit for the round imported from the network, if it's still
+	/// due to broadcast a commit.
+	pub last_commit: Option<Commit<H, N, S, Id>>,
+	/// The last finalized block number the round was told about.
+	pub finalized_number: N,
+}
+
+type SnapshotFor<H, N, E> = BackgroundRoundSnapshot<
+	H,
+	N,
+	<E as Environment<H, N>>::Signature,
+	<E as Environment<H, N>>::Id,
+>;
+
+/// A round the voter no longer runs as the best round, as kept by
+/// `CompletedRounds`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompletedRound<H, N, S, Id> {
+	/// The round number.
+	pub number: u64,
+	/// The state of the round when it was last recorded.
+	pub state: RoundState<H, N>,
+	/// The base block of the round.
+	pub base: (H, N),
+	/// The votes imported into the round.
+	pub votes: HistoricalVotes<H, N, S, Id>,
+}
+
+type CompletedRoundFor<H, N, E> = CompletedRound<
+	H,
+	N,
+	<E as Environment<H, N>>::Signature,
+	<E as Environment<H, N>>::Id,
+>;
+
+struct CompletedRoundsInner<H, N, S, Id> {
+	capacity: usize,
+	rounds: VecDeque<CompletedRound<H, N, S, Id>>,
+}
+
+/// A bounded record of the latest rounds of a voter which are no longer the
+/// best round, e.g. to answer queries about a round shortly after it
+/// concluded. Once full, the oldest rounds are dropped.
+///
+/// A round is recorded when it's moved to the background, and recorded again
+/// with its final state once it becomes irrelevant. Handles are cheap to
+/// clone and all refer to the same record, which the voter keeps updating.
+pub struct CompletedRounds<H, N, S, Id> {
+	inner: Arc<Mutex<CompletedRoundsInner<H, N, S, Id>>>,
+}
+
+impl<H, N, S, Id> Clone for CompletedRounds<H, N, S, Id> {
+	fn clone(&self) -> Self {
+		CompletedRounds { inner: self.inner.clone() }
+	}
+}
+
+impl<H: Clone, N: Clone, S: Clone, Id: Clone> CompletedRounds<H, N, S, Id> {
+	pub(super) fn new(capacity: usize) -> Self {
+		CompletedRounds {
+			inner: Arc::new(Mutex::new(CompletedRoundsInner {
+				capacity,
+				rounds: VecDeque::with_capacity(capacity),
+			})),
+		}
+	}
+
+	/// The given round, if it's among the ones recorded.
+	pub fn get(&self, number: u64) -> Option<CompletedRound<H, N, S, Id>> {
+		self.inner.lock().rounds.iter().find(|round| round.number == number).cloned()
+	}
+
+	/// The rounds recorded, in the order they were first recorded, oldest first.
+	pub fn rounds(&self) -> Vec<CompletedRound<H, N, S, Id>> {
+		self.inner.lock().rounds.iter().cloned().collect()
+	}
+
+	// keep at most the given number of rounds, dropping the oldest ones.
+	pub(super) fn set_capacity(&self, capacity: usize) {
+		let mut inner = self.inner.lock();
+		inner.capacity = capacity;
+		while inner.rounds.len() > capacity {
+			inner.rounds.pop_front();
+		}
+	}
+
+	// whether any rounds are recorded at all, to avoid taking snapshots of
+	// rounds otherwise.
+	fn enabled(&self) -> bool {
+		self.inner.lock().capacity > 0
+	}
+
+	// record the round, replacing an earlier record of it in place.
+	fn record(&self, round: CompletedRound<H, N, S, Id>) {
+		let mut inner = self.inner.lock();
+		if inner.capacity == 0 { return }
+
+		if let Some(recorded) = inner.rounds.iter_mut().find(|r| r.number == round.number) {
+			*recorded = round;
+			return;
+		}
+
+		if inner.rounds.len() == inner.capacity {
+			inner.rounds.pop_front();
+		}
+		inner.rounds.push_back(round);
+	}
+}
+
+// the height of the last commit emitted from any past round, shared with the
+// committers which may emit ahead of their timer.
+type LastEmitted<N> = Arc<Mutex<Option<N>>>;
+
+// record that a commit for the given height was emitted.
+fn note_emitted<N: Copy + Ord>(last_emitted: &LastEmitted<N>, target_number: N) {
+	let mut last_emitted = last_emitted.lock();
+	*last_emitted = Some(last_emitted.map_or(target_number, |number| cmp::max(number, target_number)));
+}
+
 // wraps a voting round with a new future that resolves when the round can
 // be discarded from the working set.
 //
@@ -70,7 +259,92 @@ impl<H, N, E: Environment<H, N>> BackgroundRound<H, N, E> where
 		//   - if we skipped forward we may never complete this round and we don't need
 		//     to keep it forever.
 		self.round_committer.is_none() && self.inner.round_state().estimate
-			.map_or(true, |x| x.1 <= self.finalized_number)
+			.is_none_or(|x| x.1 <= self.finalized_number)
+	}
+
+	fn import_message(&mut self, message: SignedMessageFor<H, N, E>) -> Result<(), E::Error> {
+		self.inner.handle_message(message)?;
+
+		// the round needs to be polled to act on the message.
+		if let Some(ref waker) = self.waker {
+			waker.wake_by_ref();
+		}
+
+		Ok(())
+	}
+
+	fn block_imported(&mut self, hash: &H, number: N) -> Result<(), E::Error> {
+		self.inner.block_imported(hash, number)?;
+
+		// the round needs to be polled to act on pending votes.
+		if let Some(ref waker) = self.waker {
+			waker.wake_by_ref();
+		}
+
+		Ok(())
+	}
+
+	fn set_paused(&mut self, paused: bool) {
+		self.inner.set_paused(paused);
+
+		// the round needs to be polled to cast votes it held back.
+		if let Some(ref waker) = self.waker {
+			waker.wake_by_ref();
+		}
+	}
+
+	fn pace(&mut self, phase: Phase) {
+		self.inner.pace(phase);
+
+		// the round needs to be polled to act on the phase.
+		if let Some(ref waker) = self.waker {
+			waker.wake_by_ref();
+		}
+	}
+
+	// drive the round, and its committer until it's done. resolves to the
+	// commit to broadcast, if any, once the committer is done.
+	#[allow(clippy::type_complexity)]
+	fn poll_committer(&mut self, cx: &mut Context) -> Poll<Result<Option<CommitFor<H, N, E>>, E::Error>> {
+		let _ = self.inner.poll(cx)?;
+
+		let mut committer = match self.round_committer.take() {
+			None => return Poll::Ready(Ok(None)),
+			Some(committer) => committer,
+		};
+
+		match committer.commit(cx, &mut self.inner)? {
+			Poll::Ready(commit) => Poll::Ready(Ok(commit)),
+			Poll::Pending => {
+				self.round_committer = Some(committer);
+				Poll::Pending
+			}
+		}
+	}
+
+	fn snapshot(&self) -> SnapshotFor<H, N, E> {
+		let mut prevotes = Vec::new();
+		let mut precommits = Vec::new();
+		for signed in self.inner.historical_votes().seen() {
+			let (signature, id) = (signed.signature.clone(), signed.id.clone());
+			match signed.message {
+				Message::Prevote(ref prevote) =>
+					prevotes.push(SignedPrevote { prevote: prevote.clone(), signature, id }),
+				Message::Precommit(ref precommit) =>
+					precommits.push(SignedPrecommit { precommit: precommit.clone(), signature, id }),
+				Message::PrimaryPropose(_) => {},
+			}
+		}
+
+		BackgroundRoundSnapshot {
+			round_number: self.round_number(),
+			base: self.inner.dag_base(),
+			prevotes,
+			precommits,
+			committing: self.round_committer.is_some(),
+			last_commit: self.round_committer.as_ref().and_then(|committer| committer.last_commit.clone()),
+			finalized_number: self.finalized_number,
+		}
 	}
 
 	fn update_finalized(&mut self, new_finalized: N) {
@@ -103,20 +377,14 @@ impl<H, N, E: Environment<H, N>> Future for BackgroundRound<H, N, E> where
 	type Output = Result<BackgroundRoundChange<H, N, E>, E::Error>;
 
 	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
-		self.waker = Some(cx.waker().clone());
+		#[cfg(feature = "tracing")]
+		let _span = tracing::trace_span!(target: "afg", "background_round", round = self.round_number()).entered();
 
-		self.inner.poll(cx)?;
+		self.waker = Some(cx.waker().clone());
 
-		self.round_committer = match self.round_committer.take() {
-			None => None,
-			Some(mut committer) => match committer.commit(cx, &mut self.inner)? {
-				Poll::Ready(None) => None,
-				Poll::Ready(Some(commit)) => return Poll::Ready(Ok(
-					BackgroundRoundChange::Committed(commit)
-				)),
-				Poll::Pending => Some(committer),
-			}
-		};
+		if let Poll::Ready(Some(commit)) = self.poll_committer(cx)? {
+			return Poll::Ready(Ok(BackgroundRoundChange::Committed(commit)));
+		}
 
 		if self.is_done() {
 			// if this is fully done (has committed _and_ estimate finalized)
@@ -134,13 +402,25 @@ impl<H, N, E: Environment<H, N>> Unpin for BackgroundRound<H, N, E> where
 {
 }
 
+// the timer to wait for before broadcasting a commit for the given round.
+fn commit_timer<H: Eq, N: BlockNumberOps, E: Environment<H, N>>(env: &E, round: u64) -> E::Timer {
+	env.round_timer(round, Phase::Commit).unwrap_or_else(|| env.round_commit_timer())
+}
+
 struct RoundCommitter<H, N, E: Environment<H, N>> where
 	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
 	N: Copy + BlockNumberOps + ::std::fmt::Debug,
 {
 	commit_timer: E::Timer,
-	import_commits: stream::Fuse<mpsc::UnboundedReceiver<Commit<H, N, E::Signature, E::Id>>>,
-	last_commit: Option<Commit<H, N, E::Signature, E::Id>>,
+	import_commits: stream::Fuse<mpsc::UnboundedReceiver<CommitFor<H, N, E>>>,
+	last_commit: Option<CommitFor<H, N, E>>,
+	reset_timer: bool, // whether to restart the timer on importing a better commit.
+	fast: bool, // whether to skip the timer once the commit can't improve anymore.
+	emit_early: bool, // whether to emit without waiting for the timer when advancing finality.
+	last_emitted: LastEmitted<N>,
+	// how far the commit must advance beyond the last emitted one to be emitted.
+	min_advance: N,
+	metrics: Arc<dyn Metrics>,
 }
 
 impl<H, N, E: Environment<H, N>> RoundCommitter<H, N, E> where
@@ -150,11 +430,31 @@ impl<H, N, E: Environment<H, N>> RoundCommitter<H, N, E> where
 	fn new(
 		commit_timer: E::Timer,
 		commit_receiver: mpsc::UnboundedReceiver<Commit<H, N, E::Signature, E::Id>>,
+		reset_timer: bool,
+		fast: bool,
+		emit_early: bool,
+		last_emitted: LastEmitted<N>,
+		metrics: Arc<dyn Metrics>,
 	) -> Self {
 		RoundCommitter {
 			commit_timer,
 			import_commits: commit_receiver.fuse(),
 			last_commit: None,
+			reset_timer,
+			fast,
+			emit_early,
+			last_emitted,
+			min_advance: N::one(),
+			metrics,
+		}
+	}
+
+	// whether a commit finalizing the given block number advances far enough
+	// beyond the last commit emitted from any round. the first one always does.
+	fn advances(&self, target_number: N) -> bool {
+		match *self.last_emitted.lock() {
+			None => true,
+			Some(last) => last.checked_add(&self.min_advance).is_some_and(|min| target_number >= min),
 		}
 	}
 
@@ -165,6 +465,7 @@ impl<H, N, E: Environment<H, N>> RoundCommitter<H, N, E> where
 	) -> Result<bool, E::Error> {
 		// ignore commits for a block lower than we already finalized
 		if commit.target_number < voting_round.finalized().map_or_else(N::zero, |(_, n)| *n) {
+			self.metrics.on_commit_dropped(CommitDropReason::Obsolete);
 			return Ok(true);
 		}
 
@@ -172,33 +473,109 @@ impl<H, N, E: Environment<H, N>> RoundCommitter<H, N, E> where
 			return Ok(false)
 		}
 
+		// wait for another quiet period after a commit for a higher block, so
+		// that bursts of commits are answered at most once, after they end.
+		let better = self.last_commit.as_ref().is_none_or(|last| commit.target_number > last.target_number);
+		if self.reset_timer && better {
+			trace!(target: "afg", "Resetting commit timer for round {}", voting_round.round_number());
+			self.commit_timer = commit_timer(voting_round.env(), voting_round.round_number());
+		}
+
+		afg_event!(debug, [
+			round = voting_round.round_number(),
+			target_number = commit.target_number,
+			target_hash = commit.target_hash
+		], "Imported commit");
+
 		self.last_commit = Some(commit);
 
 		Ok(true)
 	}
 
+	#[allow(clippy::type_complexity)]
 	fn commit(&mut self, cx: &mut Context, voting_round: &mut VotingRound<H, N, E>)
-		-> Poll<Result<Option<Commit<H, N, E::Signature, E::Id>>, E::Error>>
+		-> Poll<Result<Option<CommitFor<H, N, E>>, E::Error>>
 	{
 		while let Poll::Ready(Some(commit)) = Stream::poll_next(Pin::new(&mut self.import_commits), cx) {
 			if !self.import_commit(voting_round, commit)? {
 				trace!(target: "afg", "Ignoring invalid commit");
+				self.metrics.on_commit_dropped(CommitDropReason::Invalid);
 			}
 		}
 
-		ready!(Future::poll(Pin::new(&mut self.commit_timer), cx))?;
+		if let Some(commit) = self.early_commit(voting_round) {
+			return Poll::Ready(Ok(Some(commit)));
+		}
+
+		// a commit backed by a supermajority on its exact target can't improve
+		// anymore, so it may not be worth waiting for. otherwise a paced round
+		// commits when the pacemaker signals it, instead of when its timer
+		// fires.
+		match voting_round.paced(Phase::Commit) {
+			_ if self.fast && voting_round.finalized_by_threshold() => {
+				trace!(target: "afg", "Committing round {} ahead of its timer, nothing left to wait for",
+					voting_round.round_number());
+			},
+			Some(true) => {},
+			Some(false) => return Poll::Pending,
+			None => {
+				// a failing timer only stops this round from committing, and
+				// doesn't take down the voter with it.
+				if let Err(e) = ready!(Future::poll(Pin::new(&mut self.commit_timer), cx)) {
+					warn!(target: "afg", "Commit timer of round {} failed, not committing: {:?}",
+						voting_round.round_number(), e);
+					self.metrics.on_commit_dropped(CommitDropReason::TimerFailed);
+					return Poll::Ready(Ok(None));
+				}
+			},
+		}
 
-		match (self.last_commit.take(), voting_round.finalized()) {
+		let commit = match (self.last_commit.take(), voting_round.finalized()) {
 			(None, Some(_)) => {
-				Poll::Ready(Ok(voting_round.finalizing_commit().cloned()))
+				voting_round.finalizing_commit().cloned()
 			},
 			(Some(Commit { target_number, .. }), Some((_, finalized_number))) if target_number < *finalized_number => {
-				Poll::Ready(Ok(voting_round.finalizing_commit().cloned()))
+				voting_round.finalizing_commit().cloned()
 			},
-			_ => {
-				Poll::Ready(Ok(None))
+			(Some(_), Some(_)) => {
+				self.metrics.on_commit_dropped(CommitDropReason::Superseded);
+				return Poll::Ready(Ok(None));
+			},
+			_ => None,
+		};
+
+		match commit {
+			None => self.metrics.on_commit_dropped(CommitDropReason::NotFinalized),
+			Some(ref commit) if !self.advances(commit.target_number) => {
+				trace!(target: "afg", "Not committing round {}, finality didn't advance enough",
+					voting_round.round_number());
+				self.metrics.on_commit_dropped(CommitDropReason::TooLittleAdvance);
+				return Poll::Ready(Ok(None));
 			},
+			Some(_) => {},
 		}
+
+		Poll::Ready(Ok(commit))
+	}
+
+	// the finalizing commit of the round, if it may be emitted before the
+	// timer fires: the round finalized a block far enough above the last
+	// commit emitted from any round, and higher than the best commit imported
+	// for it.
+	fn early_commit(&mut self, voting_round: &VotingRound<H, N, E>) -> Option<CommitFor<H, N, E>> {
+		if !self.emit_early { return None }
+		let finalized_number = voting_round.finalized()?.1;
+
+		let above_emitted = self.last_emitted.lock().is_none_or(|number| finalized_number > number)
+			&& self.advances(finalized_number);
+		let above_imported = self.last_commit.as_ref().is_none_or(|last| finalized_number > last.target_number);
+		if !above_emitted || !above_imported {
+			return None;
+		}
+
+		trace!(target: "afg", "Committing round {} ahead of its timer", voting_round.round_number());
+		self.last_commit = None;
+		voting_round.finalizing_commit().cloned()
 	}
 }
 
@@ -218,6 +595,14 @@ impl<F> SelfReturningFuture<F> {
 			x(inner)
 		}
 	}
+
+	fn get(&self) -> Option<&F> {
+		self.inner.as_ref()
+	}
+
+	fn get_mut(&mut self) -> Option<&mut F> {
+		self.inner.as_mut()
+	}
 }
 
 impl<F: Future + Unpin> Future for SelfReturningFuture<F> {
@@ -240,6 +625,37 @@ impl<F: Future + Unpin> Future for SelfReturningFuture<F> {
 impl<F> Unpin for SelfReturningFuture<F> {
 }
 
+// resolves to the round number once a retained round should be dropped.
+struct RetentionTimer<T> {
+	round_number: u64,
+	delay: T,
+}
+
+impl<T: Future<Output=()> + Unpin> Future for RetentionTimer<T> {
+	type Output = u64;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<u64> {
+		ready!(Future::poll(Pin::new(&mut self.delay), cx));
+		Poll::Ready(self.round_number)
+	}
+}
+
+// how many rounds are kept in `CompletedRounds` by default.
+const DEFAULT_COMPLETED_ROUNDS: usize = 16;
+
+// the current state of the round, to record in `CompletedRounds`.
+fn completed_round<H, N, E: Environment<H, N>>(round: &VotingRound<H, N, E>) -> CompletedRoundFor<H, N, E> where
+	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
+	N: Copy + BlockNumberOps + ::std::fmt::Debug,
+{
+	CompletedRound {
+		number: round.round_number(),
+		state: round.round_state(),
+		base: round.dag_base(),
+		votes: round.historical_votes().clone(),
+	}
+}
+
 /// A stream for past rounds, which produces any commit messages from those
 /// rounds and drives them to completion.
 pub(super) struct PastRounds<H, N, E: Environment<H, N>> where
@@ -247,7 +663,20 @@ pub(super) struct PastRounds<H, N, E: Environment<H, N>> where
 	N: Copy + BlockNumberOps + ::std::fmt::Debug,
 {
 	past_rounds: FuturesUnordered<SelfReturningFuture<BackgroundRound<H, N, E>>>,
-	commit_senders: HashMap<u64, mpsc::UnboundedSender<Commit<H, N, E::Signature, E::Id>>>,
+	commit_senders: HashMap<u64, CommitSender<CommitFor<H, N, E>>>,
+	retain_finalized_for: Duration,
+	reset_commit_timer: bool,
+	fast_commits: bool,
+	emit_early_commits: bool,
+	min_commit_advance: N,
+	last_emitted: LastEmitted<N>,
+	metrics: Arc<dyn Metrics>,
+	// irrelevant rounds kept for serving catch-ups until their timer fires.
+	retained: HashMap<u64, CatchUpFor<H, N, E>>,
+	retention_timers: FuturesUnordered<RetentionTimer<<E::Clock as Clock>::Timer>>,
+	// the latest ranges of rounds skipped on catch-up, oldest first.
+	skipped: VecDeque<Range<u64>>,
+	completed: CompletedRounds<H, N, E::Signature, E::Id>,
 }
 
 impl<H, N, E: Environment<H, N>> PastRounds<H, N, E> where
@@ -259,25 +688,193 @@ impl<H, N, E: Environment<H, N>> PastRounds<H, N, E> where
 		PastRounds {
 			past_rounds: FuturesUnordered::new(),
 			commit_senders: HashMap::new(),
+			retain_finalized_for: Duration::from_secs(0),
+			reset_commit_timer: false,
+			fast_commits: false,
+			emit_early_commits: false,
+			min_commit_advance: N::one(),
+			last_emitted: Arc::new(Mutex::new(None)),
+			metrics: Arc::new(()),
+			retained: HashMap::new(),
+			retention_timers: FuturesUnordered::new(),
+			skipped: VecDeque::new(),
+			completed: CompletedRounds::new(DEFAULT_COMPLETED_ROUNDS),
+		}
+	}
+
+	/// Keep rounds for the given duration after they become irrelevant, so
+	/// they can still be used to assemble catch-up messages. Rounds won't
+	/// produce any commits during that window. Only applies to rounds which
+	/// become irrelevant afterwards.
+	pub(super) fn set_retain_finalized_for(&mut self, retain_finalized_for: Duration) {
+		self.retain_finalized_for = retain_finalized_for;
+	}
+
+	/// Restart the commit timer of a round whenever a commit for a higher block
+	/// than the previous one is imported, instead of only running it once. Only
+	/// applies to rounds pushed afterwards.
+	pub(super) fn set_reset_commit_timer(&mut self, reset_commit_timer: bool) {
+		self.reset_commit_timer = reset_commit_timer;
+	}
+
+	/// Emit the commit of a round without waiting for its commit timer once a
+	/// supermajority precommitted for exactly the block it finalizes. Only
+	/// applies to rounds pushed afterwards.
+	pub(super) fn set_fast_commits(&mut self, fast_commits: bool) {
+		self.fast_commits = fast_commits;
+	}
+
+	/// Emit the commit of a round as soon as it finalizes a block higher than
+	/// the last commit emitted from any round, instead of waiting for its
+	/// commit timer. Other commits still wait for their timer. Only applies
+	/// to rounds pushed afterwards.
+	pub(super) fn set_emit_early_commits(&mut self, emit_early_commits: bool) {
+		self.emit_early_commits = emit_early_commits;
+	}
+
+	/// Only emit the commit of a round if it finalizes a block at least the
+	/// given number of blocks above the last commit emitted from any round.
+	/// The first commit is always emitted. Only applies to rounds pushed
+	/// afterwards.
+	pub(super) fn set_min_commit_advance(&mut self, min_commit_advance: N) {
+		self.min_commit_advance = min_commit_advance;
+	}
+
+	/// Report events to the given metrics. Only applies to rounds pushed
+	/// afterwards.
+	pub(super) fn set_metrics(&mut self, metrics: Arc<dyn Metrics>) {
+		self.metrics = metrics;
+	}
+
+	/// A handle to the latest rounds pushed onto this stream, see
+	/// `CompletedRounds`.
+	pub(super) fn completed_rounds(&self) -> CompletedRounds<H, N, E::Signature, E::Id> {
+		self.completed.clone()
+	}
+
+	/// Assemble a catch-up message for the given round, if it is still being
+	/// run in the background or retained.
+	pub(super) fn catch_up(&mut self, round_number: u64) -> Option<CatchUpFor<H, N, E>> {
+		if let Some(catch_up) = self.retained.get(&round_number) {
+			return Some(catch_up.clone());
 		}
+
+		self.past_rounds.iter_mut()
+			.filter_map(|bg| bg.get())
+			.find(|bg| bg.round_number() == round_number)
+			.map(|bg| bg.inner.catch_up())
+	}
+
+	// the precommits which finalized the best block of a background round.
+	#[allow(clippy::type_complexity)]
+	pub(super) fn finalization_evidence(&mut self, round_number: u64)
+		-> Option<Vec<SignedPrecommit<H, N, E::Signature, E::Id>>>
+	{
+		self.past_rounds.iter_mut()
+			.filter_map(|bg| bg.get())
+			.find(|bg| bg.round_number() == round_number)
+			.and_then(|bg| bg.inner.finalization_evidence())
 	}
 
 	// push an old voting round onto this stream.
 	pub(super) fn push(&mut self, env: &E, round: VotingRound<H, N, E>) {
+		// https://github.com/paritytech/finality-grandpa/issues/50
+		self.push_background(env, round, N::zero(), true, None);
+	}
+
+	/// Take snapshots of the rounds run in the background, in no particular
+	/// order. Retained rounds aren't included.
+	pub(super) fn snapshot(&mut self) -> Vec<SnapshotFor<H, N, E>> {
+		self.past_rounds.iter_mut()
+			.filter_map(|bg| bg.get())
+			.map(|bg| bg.snapshot())
+			.collect()
+	}
+
+	/// Run rounds restored from snapshots taken with `snapshot` in the
+	/// background, e.g. after a restart. Their votes are imported again, and
+	/// they resume committing and are pruned like any other background round,
+	/// but don't cast any more votes. Fails without restoring any round if
+	/// the votes of one can't be imported, e.g. since they're from a different
+	/// voter set.
+	pub(super) fn restore(
+		&mut self,
+		env: &Arc<E>,
+		voters: &VoterSet<E::Id>,
+		finalized_sender: &mpsc::UnboundedSender<FinalizedNotification<H, N, E>>,
+		snapshots: Vec<SnapshotFor<H, N, E>>,
+	) -> Result<(), crate::Error> {
+		let mut restored = Vec::with_capacity(snapshots.len());
+		for snapshot in snapshots {
+			let round_params = RoundParams {
+				round_number: snapshot.round_number,
+				set_id: env.set_id(),
+				voters: voters.clone(),
+				base: snapshot.base,
+			};
+			let votes = Round::from_parts(round_params, snapshot.prevotes, snapshot.precommits, &**env)?;
+			let mut round = VotingRound::completed(votes, finalized_sender.clone(), env.clone());
+			if snapshot.committing {
+				round.restore_finalizing_commit();
+			}
+
+			restored.push((round, snapshot.finalized_number, snapshot.committing, snapshot.last_commit));
+		}
+
+		for (round, finalized_number, committing, last_commit) in restored {
+			self.push_background(env, round, finalized_number, committing, last_commit);
+		}
+
+		Ok(())
+	}
+
+	fn push_background(
+		&mut self,
+		env: &E,
+		round: VotingRound<H, N, E>,
+		finalized_number: N,
+		committing: bool,
+		last_commit: Option<CommitFor<H, N, E>>,
+	) {
 		let round_number = round.round_number();
+		if self.completed.enabled() {
+			self.completed.record(completed_round(&round));
+		}
+
 		let (tx, rx) = mpsc::unbounded();
+		let round_committer = if committing {
+			let mut committer = RoundCommitter::new(
+				commit_timer(env, round_number),
+				rx,
+				self.reset_commit_timer,
+				self.fast_commits,
+				self.emit_early_commits,
+				self.last_emitted.clone(),
+				self.metrics.clone(),
+			);
+			committer.min_advance = self.min_commit_advance;
+			committer.last_commit = last_commit;
+			self.commit_senders.insert(round_number, CommitSender { tx, last: None });
+			Some(committer)
+		} else {
+			None
+		};
+
 		let background = BackgroundRound {
 			inner: round,
 			waker: None,
-			// https://github.com/paritytech/finality-grandpa/issues/50
-			finalized_number: N::zero(),
-			round_committer: Some(RoundCommitter::new(
-				env.round_commit_timer(),
-				rx,
-			)),
+			finalized_number,
+			round_committer,
 		};
 		self.past_rounds.push(background.into());
-		self.commit_senders.insert(round_number, tx);
+		self.metrics.on_background_rounds(self.past_rounds.len());
+	}
+
+	/// the number of each background round, along with the round it succeeded.
+	pub(super) fn parent_rounds(&mut self) -> impl Iterator<Item=(u64, Option<u64>)> + '_ {
+		self.past_rounds.iter_mut()
+			.filter_map(|bg| bg.get_mut())
+			.map(|bg| (bg.round_number(), bg.inner.parent_round()))
 	}
 
 	/// update the last finalized block. this will lead to
@@ -290,15 +887,128 @@ impl<H, N, E: Environment<H, N>> PastRounds<H, N, E> where
 		}
 	}
 
+	// import the message into the given backgrounded round. Returns `None` if
+	// the round isn't being run in the background.
+	pub(super) fn import_message(&mut self, round_number: u64, message: SignedMessageFor<H, N, E>)
+		-> Option<Result<(), E::Error>>
+	{
+		self.past_rounds.iter_mut()
+			.filter_map(|bg| bg.get_mut())
+			.find(|bg| bg.round_number() == round_number)
+			.map(|bg| bg.import_message(message))
+	}
+
+	/// Note that the given block was imported, importing the votes pending on
+	/// it in all rounds run in the background.
+	pub(super) fn block_imported(&mut self, hash: &H, number: N) -> Result<(), E::Error> {
+		for bg in self.past_rounds.iter_mut().filter_map(|bg| bg.get_mut()) {
+			bg.block_imported(hash, number)?;
+		}
+
+		Ok(())
+	}
+
+	/// Stop or resume casting votes in all rounds run in the background.
+	pub(super) fn set_paused(&mut self, paused: bool) {
+		for bg in self.past_rounds.iter_mut() {
+			bg.mutate(|bg| bg.set_paused(paused));
+		}
+	}
+
+	/// Note that the pacemaker signalled the given phase of a round. Returns
+	/// `false` if the round isn't being run in the background.
+	pub(super) fn pace(&mut self, round_number: u64, phase: Phase) -> bool {
+		let background = self.past_rounds.iter_mut()
+			.filter_map(|bg| bg.get_mut())
+			.find(|bg| bg.round_number() == round_number);
+
+		match background {
+			Some(background) => {
+				background.pace(phase);
+				true
+			},
+			None => false,
+		}
+	}
+
+	/// Drive only the given round and its committer, resolving to the commit
+	/// to broadcast for it, if any, once the committer is done. The commit is
+	/// then not yielded by the stream anymore, and vice versa. Resolves to
+	/// `None` right away if the round isn't run in the background, or its
+	/// committer is already done.
+	#[allow(clippy::type_complexity)]
+	pub(super) fn poll_commit_for(&mut self, round_number: u64, cx: &mut Context)
+		-> Poll<Result<Option<CommitFor<H, N, E>>, E::Error>>
+	{
+		let background = self.past_rounds.iter_mut()
+			.filter_map(|bg| bg.get_mut())
+			.find(|bg| bg.round_number() == round_number);
+
+		let background = match background {
+			Some(background) => background,
+			None => return Poll::Ready(Ok(None)),
+		};
+
+		let result = background.poll_committer(cx);
+		if let Poll::Ready(Ok(Some(ref commit))) = result {
+			afg_event!(debug, [
+				round = round_number,
+				target_number = commit.target_number,
+				target_hash = commit.target_hash
+			], "Committing");
+
+			note_emitted(&self.last_emitted, commit.target_number);
+			self.metrics.on_commit_emitted(round_number);
+		}
+
+		// the round is now registered for wakeups with `cx` only, so have the
+		// stream poll it again as well.
+		if let Some(ref waker) = background.waker {
+			waker.wake_by_ref();
+		}
+
+		result
+	}
+
+	// note that the given rounds were skipped on catch-up, and never run.
+	pub(super) fn note_skipped(&mut self, rounds: Range<u64>) {
+		if rounds.start >= rounds.end { return }
+
+		if self.skipped.len() >= MAX_SKIPPED_RANGES {
+			self.skipped.pop_front();
+		}
+		self.skipped.push_back(rounds);
+	}
+
+	// whether the given round was skipped on catch-up, as far as we remember.
+	pub(super) fn skipped(&self, round_number: u64) -> bool {
+		self.skipped.iter().any(|rounds| rounds.contains(&round_number))
+	}
+
 	// import the commit into the given backgrounded round. If not possible,
-	// just return and process the commit.
-	pub(super) fn import_commit(&self, round_number: u64, commit: Commit<H, N, E::Signature, E::Id>)
-		-> Option<Commit<H, N, E::Signature, E::Id>>
+	// the error says why, see `ImportCommitError`.
+	pub(super) fn import_commit(&mut self, round_number: u64, commit: CommitFor<H, N, E>)
+		-> Result<(), ImportCommitError<CommitFor<H, N, E>>>
 	{
-		if let Some(sender) = self.commit_senders.get(&round_number) {
-			sender.unbounded_send(commit).map_err(|e| e.into_inner()).err()
-		} else {
-			Some(commit)
+		if !self.commit_senders.contains_key(&round_number) {
+			return Err(if self.skipped(round_number) {
+				ImportCommitError::Skipped(commit)
+			} else {
+				ImportCommitError::UnknownRound(commit)
+			});
+		}
+
+		let sender = self.commit_senders.get_mut(&round_number).expect("checked to be present above; qed");
+		if sender.last.as_ref() == Some(&commit) {
+			return Err(ImportCommitError::Duplicate);
+		}
+
+		match sender.tx.unbounded_send(commit.clone()) {
+			Ok(()) => {
+				sender.last = Some(commit);
+				Ok(())
+			}
+			Err(e) => Err(ImportCommitError::ChannelClosed(e.into_inner())),
 		}
 	}
 }
@@ -310,10 +1020,37 @@ impl<H, N, E: Environment<H, N>> Stream for PastRounds<H, N, E> where
 	type Item = Result<(u64, Commit<H, N, E::Signature, E::Id>), E::Error>;
 
 	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+		while let Poll::Ready(Some(number)) = Stream::poll_next(Pin::new(&mut self.retention_timers), cx) {
+			trace!(target: "afg", "Dropping retained round {}", number);
+			self.retained.remove(&number);
+		}
+
 		loop {
 			match Stream::poll_next(Pin::new(&mut self.past_rounds), cx) {
-				Poll::Ready(Some((Ok(BackgroundRoundChange::Irrelevant(number)), _))) => {
+				Poll::Ready(Some((Ok(BackgroundRoundChange::Irrelevant(number)), round))) => {
 					self.commit_senders.remove(&number);
+					self.metrics.on_background_rounds(self.past_rounds.len());
+					afg_event!(debug, [round = number], "Pruning round");
+
+					if self.completed.enabled() {
+						self.completed.record(completed_round(&round.inner));
+					}
+
+					if self.retain_finalized_for > Duration::from_secs(0) {
+						self.retained.insert(number, round.inner.catch_up());
+
+						let mut timer = RetentionTimer {
+							round_number: number,
+							delay: round.inner.env().clock().timer(self.retain_finalized_for),
+						};
+
+						// register for wakeup.
+						if let Poll::Ready(number) = Future::poll(Pin::new(&mut timer), cx) {
+							self.retained.remove(&number);
+						} else {
+							self.retention_timers.push(timer);
+						}
+					}
 				}
 				Poll::Ready(Some((Ok(BackgroundRoundChange::Committed(commit)), round))) => {
 					let number = round.round_number();
@@ -321,14 +1058,20 @@ impl<H, N, E: Environment<H, N>> Stream for PastRounds<H, N, E> where
 					// reschedule until irrelevant.
 					self.past_rounds.push(round.into());
 
-					debug!(
-						target: "afg", "Committing: round_number = {}, \
+					afg_event!(debug, [
+						round = number,
+						target_number = commit.target_number,
+						target_hash = commit.target_hash
+					], "Committing";
+						"Committing: round_number = {}, \
 						target_number = {:?}, target_hash = {:?}",
 						number,
 						commit.target_number,
 						commit.target_hash,
 					);
 
+					note_emitted(&self.last_emitted, commit.target_number);
+					self.metrics.on_commit_emitted(number);
 					return Poll::Ready(Some(Ok((number, commit))));
 				}
 				Poll::Ready(Some((Err(err), _))) => return Poll::Ready(Some(Err(err))),
@@ -344,3 +1087,945 @@ impl<H, N, E: Environment<H, N>> Unpin for PastRounds<H, N, E> where
 	N: Copy + BlockNumberOps + ::std::fmt::Debug,
 {
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Message, Prevote, Precommit, SignedPrecommit};
+	use crate::round::State as RoundState;
+	use crate::testing::{
+		self,
+		chain::GENESIS_HASH,
+		clock::TestClock,
+		environment::{Environment, Id, Signature},
+	};
+	use crate::voter::voting_round::State;
+	use crate::voter_set::VoterSet;
+	use futures::executor::block_on;
+	use futures_timer::Delay;
+	use std::sync::Arc;
+	use std::time::Instant;
+
+	// poll the past rounds for the given duration, collecting any commits.
+	fn drive(
+		past_rounds: &mut PastRounds<&'static str, u32, Environment>,
+		duration: Duration,
+	) -> Vec<(u64, CommitFor<&'static str, u32, Environment>)> {
+		let mut delay = Delay::new(duration);
+		let mut commits = Vec::new();
+
+		block_on(future::poll_fn(|cx| {
+			while let Poll::Ready(Some(res)) = past_rounds.poll_next_unpin(cx) {
+				commits.push(res.ok().expect("past rounds don't fail in tests; qed"));
+			}
+
+			delay.poll_unpin(cx).map(|_| ())
+		}));
+
+		commits
+	}
+
+	#[test]
+	fn irrelevant_round_is_retained_for_catch_up() {
+		let voters: VoterSet<_> = VoterSet::new(std::iter::once((Id(5), 100))).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+		threads_pool.spawn_ok(routing_task);
+
+		let env = Arc::new(Environment::new(network.clone(), Id(5)));
+		env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B"]));
+
+		block_on(async {
+			let (_, mut sink) = network.make_round_comms(1, Id(5));
+			sink.send(Message::Prevote(Prevote::new("B", 3))).await.unwrap();
+			sink.send(Message::Precommit(Precommit::new("B", 3))).await.unwrap();
+		});
+
+		let (finalized_sender, _finalized) = mpsc::unbounded();
+		let mut round = VotingRound::new(1, voters, (GENESIS_HASH, 1), None, finalized_sender, env.clone());
+
+		// wait until the votes are imported.
+		block_on(future::poll_fn(|cx| {
+			let _ = round.poll(cx).map(|res| res.unwrap());
+			if round.finalized() == Some(&("B", 3)) { Poll::Ready(()) } else { Poll::Pending }
+		}));
+
+		let window = Duration::from_millis(200);
+		let mut past_rounds = PastRounds::new();
+		past_rounds.set_retain_finalized_for(window);
+
+		let start = Instant::now();
+		past_rounds.push(&*env, round);
+		past_rounds.update_finalized(3);
+
+		// the round becomes irrelevant once its commit timer fires.
+		block_on(future::poll_fn(|cx| {
+			while let Poll::Ready(Some(res)) = past_rounds.poll_next_unpin(cx) {
+				res.unwrap();
+			}
+
+			if past_rounds.past_rounds.is_empty() { Poll::Ready(()) } else { Poll::Pending }
+		}));
+
+		// it can still serve catch-ups, but doesn't take commits anymore.
+		let catch_up = past_rounds.catch_up(1).unwrap();
+		assert_eq!(catch_up.round_number, 1);
+		assert_eq!((catch_up.base_hash, catch_up.base_number), (GENESIS_HASH, 1));
+		assert_eq!(catch_up.prevotes.len(), 1);
+		assert_eq!(catch_up.precommits.len(), 1);
+
+		let commit = Commit { target_hash: "B", target_number: 3, precommits: Vec::new(), set_id: 0 };
+		assert!(matches!(past_rounds.import_commit(1, commit), Err(ImportCommitError::UnknownRound(_))));
+
+		// and it's dropped once the window has passed.
+		block_on(future::poll_fn(|cx| {
+			let _ = past_rounds.poll_next_unpin(cx);
+			if past_rounds.retained.is_empty() { Poll::Ready(()) } else { Poll::Pending }
+		}));
+
+		assert!(start.elapsed() >= window);
+		assert!(past_rounds.catch_up(1).is_none());
+	}
+
+	#[test]
+	fn commits_which_cant_be_imported_are_handed_back_with_the_reason() {
+		let voters: VoterSet<_> = VoterSet::new(std::iter::once((Id(5), 100))).unwrap();
+		let (network, _) = testing::environment::make_network();
+		let env = Arc::new(Environment::new(network.clone(), Id(5)));
+		env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]));
+
+		let (finalized_sender, _finalized) = mpsc::unbounded();
+		let round = VotingRound::new(1, voters, (GENESIS_HASH, 1), None, finalized_sender, env.clone());
+
+		let mut past_rounds = PastRounds::new();
+		past_rounds.push(&*env, round);
+		past_rounds.note_skipped(2..4);
+
+		let commit = |target_hash, target_number| Commit { target_hash, target_number, precommits: Vec::new(), set_id: 0 };
+
+		assert!(matches!(past_rounds.import_commit(5, commit("B", 3)), Err(ImportCommitError::UnknownRound(_))));
+		assert!(matches!(past_rounds.import_commit(3, commit("B", 3)), Err(ImportCommitError::Skipped(_))));
+
+		assert!(past_rounds.import_commit(1, commit("B", 3)).is_ok());
+		assert!(matches!(past_rounds.import_commit(1, commit("B", 3)), Err(ImportCommitError::Duplicate)));
+		assert!(past_rounds.import_commit(1, commit("C", 4)).is_ok());
+
+		// the committer of the round went away.
+		past_rounds.commit_senders[&1].tx.close_channel();
+		match past_rounds.import_commit(1, commit("A", 2)) {
+			Err(ImportCommitError::ChannelClosed(commit)) => assert_eq!(commit.target_hash, "A"),
+			other => panic!("Unexpected import of commit: {:?}", other),
+		}
+	}
+
+	#[test]
+	fn rounds_are_recorded_when_pushed_and_once_irrelevant() {
+		let voters: VoterSet<_> = VoterSet::new(vec![(Id(5), 1), (Id(6), 1)]).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+		threads_pool.spawn_ok(routing_task);
+
+		let env = Arc::new(Environment::new(network.clone(), Id(5)).with_commit_delay(Duration::from_millis(50)));
+		env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B"]));
+
+		block_on(async {
+			let (_, mut sink) = network.make_round_comms(1, Id(6));
+			sink.send(Message::Prevote(Prevote::new("B", 3))).await.unwrap();
+		});
+
+		let (_, last_round_state) = crate::bridge_state::bridge_state(RoundState::genesis((GENESIS_HASH, 1)));
+		let (finalized_sender, _finalized) = mpsc::unbounded();
+		let mut round = VotingRound::new(
+			1,
+			voters,
+			(GENESIS_HASH, 1),
+			Some(last_round_state),
+			finalized_sender,
+			env.clone(),
+		);
+
+		// we precommit, but nothing is finalized without the other precommit.
+		block_on(future::poll_fn(|cx| {
+			let _ = round.poll(cx).map(|res| res.unwrap());
+			if round.historical_votes().seen().len() == 3 { Poll::Ready(()) } else { Poll::Pending }
+		}));
+		assert!(matches!(round.state(), Some(State::Precommitted)));
+
+		let mut past_rounds = PastRounds::new();
+		past_rounds.push(&*env, round);
+
+		let completed = past_rounds.completed_rounds();
+		let recorded = completed.get(1).unwrap();
+		assert_eq!(recorded.base, (GENESIS_HASH, 1));
+		assert_eq!(recorded.state.finalized, None);
+		assert_eq!(recorded.votes.seen().len(), 3);
+
+		// the commit brings in the missing precommit, after which the round
+		// finalizes and becomes irrelevant.
+		let precommits = [5, 6].iter().map(|&id| SignedPrecommit {
+			precommit: Precommit::new("B", 3),
+			signature: Signature(id),
+			id: Id(id),
+		}).collect();
+		let commit = Commit { target_hash: "B", target_number: 3, precommits, set_id: 0 };
+		assert!(past_rounds.import_commit(1, commit).is_ok());
+		past_rounds.update_finalized(3);
+
+		block_on(future::poll_fn(|cx| {
+			while let Poll::Ready(Some(res)) = past_rounds.poll_next_unpin(cx) {
+				res.unwrap();
+			}
+
+			if past_rounds.past_rounds.is_empty() { Poll::Ready(()) } else { Poll::Pending }
+		}));
+
+		// the record of the round is updated with its final state.
+		let rounds = completed.rounds();
+		assert_eq!(rounds.len(), 1);
+		assert_eq!(rounds[0].state.finalized, Some(("B", 3)));
+		assert_eq!(rounds[0].votes.seen().len(), 4);
+
+		// and the oldest rounds are dropped beyond the capacity.
+		completed.set_capacity(0);
+		assert!(completed.rounds().is_empty());
+	}
+
+	#[test]
+	fn commit_is_pulled_for_round_once() {
+		let voters: VoterSet<_> = VoterSet::new(std::iter::once((Id(5), 100))).unwrap();
+		let commit_delay = Duration::from_millis(50);
+
+		let (network, routing_task) = testing::environment::make_network();
+		let env = Arc::new(Environment::new(network.clone(), Id(5)).with_commit_delay(commit_delay));
+		env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B"]));
+
+		let (_, last_round_state) = crate::bridge_state::bridge_state(RoundState::genesis((GENESIS_HASH, 1)));
+		let (finalized_sender, _finalized) = mpsc::unbounded();
+		let mut round = VotingRound::new(
+			1,
+			voters,
+			(GENESIS_HASH, 1),
+			Some(last_round_state),
+			finalized_sender,
+			env.clone(),
+		);
+
+		// route only once the round is on the network.
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+		threads_pool.spawn_ok(routing_task);
+
+		// wait until we've finalized "B" with our own votes.
+		block_on(future::poll_fn(|cx| {
+			let _ = round.poll(cx).map(|res| res.unwrap());
+			match (round.state(), round.finalized()) {
+				(Some(State::Precommitted), Some(&("B", 3))) => Poll::Ready(()),
+				_ => Poll::Pending,
+			}
+		}));
+
+		let mut past_rounds = PastRounds::new();
+		past_rounds.push(&*env, round);
+
+		let mut poll_commit_for = |round_number| {
+			block_on(future::poll_fn(|cx| past_rounds.poll_commit_for(round_number, cx))).unwrap()
+		};
+
+		// pulling the commit waits for the commit timer.
+		let start = Instant::now();
+		let commit = poll_commit_for(1).unwrap();
+		assert!(start.elapsed() >= commit_delay);
+		assert_eq!((commit.target_hash, commit.target_number), ("B", 3));
+		assert_eq!(commit.precommits.len(), 1);
+
+		// it's not pulled again, and unknown rounds have no commit.
+		assert!(poll_commit_for(1).is_none());
+		assert!(poll_commit_for(2).is_none());
+
+		// nor is it yielded by the stream later.
+		assert!(drive(&mut past_rounds, commit_delay * 2).is_empty());
+	}
+
+	#[test]
+	fn commit_is_emitted_as_manual_clock_advances() {
+		let voters: VoterSet<_> = VoterSet::new(std::iter::once((Id(5), 100))).unwrap();
+		let clock = TestClock::manual();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let env = Arc::new(
+			Environment::new(network.clone(), Id(5))
+				.with_clock(clock.clone())
+				.with_commit_delay(Duration::from_secs(1)),
+		);
+		env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B"]));
+
+		let (_, last_round_state) = crate::bridge_state::bridge_state(RoundState::genesis((GENESIS_HASH, 1)));
+		let (finalized_sender, _finalized) = mpsc::unbounded();
+		let mut round = VotingRound::new(
+			1,
+			voters,
+			(GENESIS_HASH, 1),
+			Some(last_round_state),
+			finalized_sender,
+			env.clone(),
+		);
+
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+		threads_pool.spawn_ok(routing_task);
+
+		// fire the prevote and precommit timers.
+		clock.advance(Duration::from_secs(1));
+
+		block_on(future::poll_fn(|cx| {
+			let _ = round.poll(cx).map(|res| res.unwrap());
+			match (round.state(), round.finalized()) {
+				(Some(State::Precommitted), Some(&("B", 3))) => Poll::Ready(()),
+				_ => Poll::Pending,
+			}
+		}));
+
+		assert_eq!(round.elapsed(), Duration::from_secs(1));
+
+		let mut past_rounds = PastRounds::new();
+		past_rounds.push(&*env, round);
+
+		let mut poll_once = || block_on(future::poll_fn(|cx| Poll::Ready(past_rounds.poll_next_unpin(cx))));
+
+		assert!(poll_once().is_pending());
+
+		clock.advance(Duration::from_millis(999));
+		assert!(poll_once().is_pending());
+
+		clock.advance(Duration::from_millis(1));
+		match poll_once() {
+			Poll::Ready(Some(Ok((1, commit)))) => assert_eq!((commit.target_hash, commit.target_number), ("B", 3)),
+			_ => panic!("commit is emitted once its timer fires"),
+		}
+	}
+
+	#[test]
+	fn restored_rounds_commit_and_prune_as_before() {
+		let voters: VoterSet<_> = VoterSet::new(std::iter::once((Id(5), 100))).unwrap();
+		let clock = TestClock::manual();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let env = Arc::new(
+			Environment::new(network.clone(), Id(5))
+				.with_clock(clock.clone())
+				.with_commit_delay(Duration::from_secs(1)),
+		);
+		env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B"]));
+
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+		threads_pool.spawn_ok(routing_task);
+
+		// rounds 1 and 2 finalize "A" and "B" with our own votes.
+		let (_, genesis_state) = crate::bridge_state::bridge_state(RoundState::genesis((GENESIS_HASH, 1)));
+		let (finalized_sender, _finalized) = mpsc::unbounded();
+		let mut last_round_state = Some(genesis_state);
+		let mut rounds = Vec::new();
+		for &(number, base, best) in &[(1, (GENESIS_HASH, 1), ("A", 2)), (2, ("A", 2), ("B", 3))] {
+			let mut round = VotingRound::new(
+				number,
+				voters.clone(),
+				base,
+				last_round_state.take(),
+				finalized_sender.clone(),
+				env.clone(),
+			);
+			env.with_chain(|chain| chain.set_best_chain(best.0));
+
+			clock.advance(Duration::from_secs(1));
+			block_on(future::poll_fn(|cx| {
+				let _ = round.poll(cx).map(|res| res.unwrap());
+				match (round.state(), round.finalized()) {
+					(Some(State::Precommitted), Some(finalized)) if *finalized == best => Poll::Ready(()),
+					_ => Poll::Pending,
+				}
+			}));
+			last_round_state = Some(round.bridge_state());
+			rounds.push(round);
+		}
+
+		let mut past_rounds = PastRounds::new();
+		for round in rounds {
+			past_rounds.push(&*env, round);
+		}
+
+		let mut snapshots = past_rounds.snapshot();
+		snapshots.sort_by_key(|snapshot| snapshot.round_number);
+		assert_eq!(snapshots.iter().map(|snapshot| snapshot.round_number).collect::<Vec<_>>(), vec![1, 2]);
+		assert_eq!(snapshots[1].precommits.len(), 1);
+		assert!(snapshots.iter().all(|snapshot| snapshot.committing));
+
+		let mut restored = PastRounds::new();
+		restored.restore(&env, &voters, &finalized_sender, snapshots.clone()).unwrap();
+
+		let mut restored_snapshots = restored.snapshot();
+		restored_snapshots.sort_by_key(|snapshot| snapshot.round_number);
+		assert_eq!(restored_snapshots, snapshots);
+
+		// drive the rounds as far as they get, collecting their commits.
+		let drive = |past_rounds: &mut PastRounds<_, _, _>| {
+			let mut commits = Vec::new();
+			block_on(future::poll_fn(|cx| {
+				while let Poll::Ready(Some(res)) = past_rounds.poll_next_unpin(cx) {
+					let (number, commit): (u64, CommitFor<&'static str, u32, Environment>) = res.unwrap();
+					commits.push((number, commit.target_hash, commit.target_number));
+				}
+				Poll::Ready(())
+			}));
+			commits.sort();
+			commits
+		};
+
+		assert!(drive(&mut past_rounds).is_empty());
+		assert!(drive(&mut restored).is_empty());
+
+		// both commit once their timers fire.
+		clock.advance(Duration::from_secs(1));
+		let commits = drive(&mut past_rounds);
+		assert_eq!(commits, vec![(1, "A", 2), (2, "B", 3)]);
+		assert_eq!(drive(&mut restored), commits);
+
+		// and both are pruned once their estimates are finalized.
+		for past_rounds in [&mut past_rounds, &mut restored] {
+			past_rounds.update_finalized(3);
+			assert!(drive(past_rounds).is_empty());
+			assert!(past_rounds.past_rounds.is_empty());
+		}
+	}
+
+	#[test]
+	fn better_commits_reset_commit_timer() {
+		let voters: VoterSet<_> = VoterSet::new((5..12).map(|i| (Id(i), 1))).unwrap();
+		let commit_delay = Duration::from_millis(200);
+
+		let (network, routing_task) = testing::environment::make_network();
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+		threads_pool.spawn_ok(routing_task);
+
+		let env = Arc::new(Environment::new(network.clone(), Id(5)).with_commit_delay(commit_delay));
+		env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]));
+
+		let send = |id, message| block_on(async {
+			let (_, mut sink) = network.make_round_comms(1, Id(id));
+			sink.send(message).await.unwrap();
+		});
+
+		// everyone prevotes for "D", but not everyone precommits for it.
+		for i in 6..12 {
+			send(i, Message::Prevote(Prevote::new("D", 5)));
+		}
+		for i in 6..9 {
+			send(i, Message::Precommit(Precommit::new("D", 5)));
+		}
+		send(9, Message::Precommit(Precommit::new("B", 3)));
+
+		let (_, last_round_state) = crate::bridge_state::bridge_state(RoundState::genesis((GENESIS_HASH, 1)));
+		let (finalized_sender, _finalized) = mpsc::unbounded();
+		let mut round = VotingRound::new(
+			1,
+			voters,
+			(GENESIS_HASH, 1),
+			Some(last_round_state),
+			finalized_sender,
+			env.clone(),
+		);
+
+		// once we've precommitted for "D" as well, "B" is finalized.
+		block_on(future::poll_fn(|cx| {
+			let _ = round.poll(cx).map(|res| res.unwrap());
+			match (round.state(), round.finalized()) {
+				(Some(State::Precommitted), Some(&("B", 3))) => Poll::Ready(()),
+				_ => Poll::Pending,
+			}
+		}));
+
+		let mut past_rounds = PastRounds::new();
+		past_rounds.set_reset_commit_timer(true);
+		past_rounds.push(&*env, round);
+
+		let precommit = |id, target_hash, target_number| SignedPrecommit {
+			precommit: Precommit::new(target_hash, target_number),
+			signature: Signature(id),
+			id: Id(id),
+		};
+
+		let mut precommits: Vec<_> = (5..9).map(|i| precommit(i, "D", 5)).collect();
+		precommits.push(precommit(9, "B", 3));
+		let commit = Commit { target_hash: "B", target_number: 3, precommits: precommits.clone(), set_id: 0 };
+		assert!(past_rounds.import_commit(1, commit).is_ok());
+
+		assert!(drive(&mut past_rounds, commit_delay / 2).is_empty());
+
+		// a better commit, and then a precommit which makes us finalize beyond it.
+		precommits.pop();
+		precommits.push(precommit(10, "C", 4));
+		let commit = Commit { target_hash: "C", target_number: 4, precommits, set_id: 0 };
+		assert!(past_rounds.import_commit(1, commit).is_ok());
+		let last_import = Instant::now();
+		assert!(drive(&mut past_rounds, commit_delay / 4).is_empty());
+
+		send(11, Message::Precommit(Precommit::new("D", 5)));
+
+		// we broadcast our own commit once, but only after a quiet period
+		// following the last commit.
+		let mut commits = drive(&mut past_rounds, commit_delay / 4);
+		assert!(commits.is_empty());
+
+		commits.extend(drive(&mut past_rounds, commit_delay));
+		assert!(last_import.elapsed() >= commit_delay);
+		assert_eq!(commits.len(), 1);
+
+		let (round_number, commit) = commits.pop().unwrap();
+		assert_eq!(round_number, 1);
+		assert_eq!((commit.target_hash, commit.target_number), ("D", 5));
+
+		assert!(drive(&mut past_rounds, commit_delay).is_empty());
+	}
+
+	#[test]
+	fn commits_not_advancing_enough_are_suppressed() {
+		let voters: VoterSet<_> = VoterSet::new(std::iter::once((Id(5), 100))).unwrap();
+		let clock = TestClock::manual();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let env = Arc::new(
+			Environment::new(network.clone(), Id(5))
+				.with_clock(clock.clone())
+				.with_commit_delay(Duration::from_secs(1)),
+		);
+		env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B"]));
+
+		let (finalized_sender, _finalized) = mpsc::unbounded();
+		let new_round = |round_number| {
+			let (_, last_round_state) = crate::bridge_state::bridge_state(RoundState::genesis((GENESIS_HASH, 1)));
+			VotingRound::new(
+				round_number,
+				voters.clone(),
+				(GENESIS_HASH, 1),
+				Some(last_round_state),
+				finalized_sender.clone(),
+				env.clone(),
+			)
+		};
+
+		// wait until we've finalized the given block with our own votes.
+		let finalize = |mut round: VotingRound<_, _, _>, target| {
+			block_on(future::poll_fn(|cx| {
+				let _ = round.poll(cx).map(|res| res.unwrap());
+				match (round.state(), round.finalized()) {
+					(Some(State::Precommitted), Some(finalized)) if *finalized == target => Poll::Ready(()),
+					_ => Poll::Pending,
+				}
+			}));
+
+			round
+		};
+
+		let rounds = (new_round(1), new_round(2), new_round(3));
+
+		// route only once the rounds are on the network.
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+		threads_pool.spawn_ok(routing_task);
+
+		// fire the prevote and precommit timers.
+		clock.advance(Duration::from_secs(1));
+
+		let mut past_rounds = PastRounds::new();
+		past_rounds.set_min_commit_advance(5);
+		let commit = |past_rounds: &mut PastRounds<_, _, _>, round| {
+			past_rounds.push(&*env, round);
+			clock.advance(Duration::from_secs(1));
+			block_on(future::poll_fn(|cx| Poll::Ready(past_rounds.poll_next_unpin(cx))))
+		};
+
+		// the first commit is always emitted.
+		match commit(&mut past_rounds, finalize(rounds.0, ("B", 3))) {
+			Poll::Ready(Some(Ok((1, commit)))) => assert_eq!((commit.target_hash, commit.target_number), ("B", 3)),
+			_ => panic!("first commit is emitted"),
+		}
+
+		// finalizing 2 blocks higher isn't enough.
+		env.with_chain(|chain| chain.push_blocks("B", &["C", "D"]));
+		assert!(commit(&mut past_rounds, finalize(rounds.1, ("D", 5))).is_pending());
+
+		// finalizing 5 blocks higher is.
+		env.with_chain(|chain| chain.push_blocks("D", &["E", "F", "G"]));
+		match commit(&mut past_rounds, finalize(rounds.2, ("G", 8))) {
+			Poll::Ready(Some(Ok((3, commit)))) => assert_eq!((commit.target_hash, commit.target_number), ("G", 8)),
+			_ => panic!("commit advancing far enough is emitted"),
+		}
+	}
+
+	#[test]
+	fn first_commit_finalizing_higher_is_emitted_early() {
+		let voters: VoterSet<_> = VoterSet::new(std::iter::once((Id(5), 100))).unwrap();
+		let clock = TestClock::manual();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let env = Arc::new(
+			Environment::new(network.clone(), Id(5))
+				.with_clock(clock.clone())
+				.with_commit_delay(Duration::from_secs(1)),
+		);
+		env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B"]));
+
+		let (finalized_sender, _finalized) = mpsc::unbounded();
+		let new_round = |round_number| {
+			let (_, last_round_state) = crate::bridge_state::bridge_state(RoundState::genesis((GENESIS_HASH, 1)));
+			VotingRound::new(
+				round_number,
+				voters.clone(),
+				(GENESIS_HASH, 1),
+				Some(last_round_state),
+				finalized_sender.clone(),
+				env.clone(),
+			)
+		};
+
+		// wait until we've finalized "B" with our own votes.
+		let finalize = |mut round: VotingRound<_, _, _>| {
+			block_on(future::poll_fn(|cx| {
+				let _ = round.poll(cx).map(|res| res.unwrap());
+				match (round.state(), round.finalized()) {
+					(Some(State::Precommitted), Some(&("B", 3))) => Poll::Ready(()),
+					_ => Poll::Pending,
+				}
+			}));
+
+			round
+		};
+
+		let (first, second) = (new_round(1), new_round(2));
+
+		// route only once the rounds are on the network.
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+		threads_pool.spawn_ok(routing_task);
+
+		// fire the prevote and precommit timers.
+		clock.advance(Duration::from_secs(1));
+		let (first, second) = (finalize(first), finalize(second));
+
+		// commits which don't advance finality are emitted as well, once their
+		// timer fires.
+		let mut past_rounds = PastRounds::new();
+		past_rounds.set_emit_early_commits(true);
+		past_rounds.set_min_commit_advance(0);
+		let poll_once = |past_rounds: &mut PastRounds<_, _, _>| {
+			block_on(future::poll_fn(|cx| Poll::Ready(past_rounds.poll_next_unpin(cx))))
+		};
+
+		// nothing was emitted yet, so the first commit doesn't wait.
+		past_rounds.push(&*env, first);
+		match poll_once(&mut past_rounds) {
+			Poll::Ready(Some(Ok((1, commit)))) => assert_eq!((commit.target_hash, commit.target_number), ("B", 3)),
+			_ => panic!("commit finalizing a higher block is emitted right away"),
+		}
+
+		// the follow-up doesn't finalize anything higher, so it waits.
+		past_rounds.push(&*env, second);
+		assert!(poll_once(&mut past_rounds).is_pending());
+
+		clock.advance(Duration::from_millis(999));
+		assert!(poll_once(&mut past_rounds).is_pending());
+
+		clock.advance(Duration::from_millis(1));
+		match poll_once(&mut past_rounds) {
+			Poll::Ready(Some(Ok((2, commit)))) => assert_eq!((commit.target_hash, commit.target_number), ("B", 3)),
+			_ => panic!("commit is emitted once its timer fires"),
+		}
+	}
+
+	#[test]
+	fn fast_commits_skip_the_timer_once_final() {
+		// we vote for `B`, and the other voter for the given block.
+		for &(other_vote, final_commit) in &[(("B", 3), true), (("C", 4), false)] {
+			let voters: VoterSet<_> = VoterSet::new([(Id(5), 1), (Id(6), 1)].iter().cloned()).unwrap();
+			let clock = TestClock::manual();
+
+			let (network, routing_task) = testing::environment::make_network();
+			let env = Arc::new(
+				Environment::new(network.clone(), Id(5))
+					.with_clock(clock.clone())
+					.with_commit_delay(Duration::from_secs(1)),
+			);
+			env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B", "C"]));
+			env.set_best_chain(Some(Some(("B", 3))));
+
+			block_on(async {
+				let (_, mut sink) = network.make_round_comms(1, Id(6));
+				sink.send(Message::Prevote(Prevote::new(other_vote.0, other_vote.1))).await.unwrap();
+				sink.send(Message::Precommit(Precommit::new(other_vote.0, other_vote.1))).await.unwrap();
+			});
+
+			let (_, last_round_state) = crate::bridge_state::bridge_state(RoundState::genesis((GENESIS_HASH, 1)));
+			let (finalized_sender, _finalized) = mpsc::unbounded();
+			let mut round = VotingRound::new(
+				1,
+				voters,
+				(GENESIS_HASH, 1),
+				Some(last_round_state),
+				finalized_sender,
+				env.clone(),
+			);
+
+			let threads_pool = futures::executor::ThreadPool::new().unwrap();
+			threads_pool.spawn_ok(routing_task);
+
+			// fire the prevote and precommit timers.
+			clock.advance(Duration::from_secs(1));
+
+			block_on(future::poll_fn(|cx| {
+				let _ = round.poll(cx).map(|res| res.unwrap());
+				match (round.state(), round.finalized()) {
+					(Some(State::Precommitted), Some(&("B", 3))) => Poll::Ready(()),
+					_ => Poll::Pending,
+				}
+			}));
+
+			let mut past_rounds = PastRounds::new();
+			past_rounds.set_fast_commits(true);
+			past_rounds.push(&*env, round);
+
+			let mut poll_once = || block_on(future::poll_fn(|cx| Poll::Ready(past_rounds.poll_next_unpin(cx))));
+
+			// only a commit with both precommits on `B` is emitted right away,
+			// the other one could still improve and waits for the timer.
+			if !final_commit {
+				assert!(poll_once().is_pending());
+				clock.advance(Duration::from_secs(1));
+			}
+
+			match poll_once() {
+				Poll::Ready(Some(Ok((1, commit)))) => {
+					assert_eq!((commit.target_hash, commit.target_number), ("B", 3));
+					assert_eq!(commit.precommits.len(), 2);
+				},
+				_ => panic!("expected a commit for round 1"),
+			}
+		}
+	}
+
+	#[test]
+	fn failing_commit_timer_only_stops_its_round() {
+		let voters: VoterSet<_> = VoterSet::new(std::iter::once((Id(5), 100))).unwrap();
+		let clock = TestClock::manual();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let env = Arc::new(
+			Environment::new(network.clone(), Id(5))
+				.with_clock(clock.clone())
+				.with_commit_delay(Duration::from_secs(1))
+				.with_failing_commit_timer(1),
+		);
+		env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B"]));
+
+		let (finalized_sender, _finalized) = mpsc::unbounded();
+		let new_round = |round_number| {
+			let (_, last_round_state) = crate::bridge_state::bridge_state(RoundState::genesis((GENESIS_HASH, 1)));
+			VotingRound::new(
+				round_number,
+				voters.clone(),
+				(GENESIS_HASH, 1),
+				Some(last_round_state),
+				finalized_sender.clone(),
+				env.clone(),
+			)
+		};
+
+		// wait until we've finalized "B" with our own votes.
+		let finalize = |mut round: VotingRound<_, _, _>| {
+			block_on(future::poll_fn(|cx| {
+				let _ = round.poll(cx).map(|res| res.unwrap());
+				match (round.state(), round.finalized()) {
+					(Some(State::Precommitted), Some(&("B", 3))) => Poll::Ready(()),
+					_ => Poll::Pending,
+				}
+			}));
+
+			round
+		};
+
+		let (first, second) = (new_round(1), new_round(2));
+
+		// route only once the rounds are on the network.
+		let threads_pool = futures::executor::ThreadPool::new().unwrap();
+		threads_pool.spawn_ok(routing_task);
+
+		// fire the prevote and precommit timers.
+		clock.advance(Duration::from_secs(1));
+		let (first, second) = (finalize(first), finalize(second));
+
+		let mut past_rounds = PastRounds::new();
+		let poll_once = |past_rounds: &mut PastRounds<_, _, _>| {
+			block_on(future::poll_fn(|cx| Poll::Ready(past_rounds.poll_next_unpin(cx))))
+		};
+
+		// the first round's timer fails right away, which isn't an error of
+		// the past rounds.
+		past_rounds.push(&*env, first);
+		past_rounds.push(&*env, second);
+		assert!(poll_once(&mut past_rounds).is_pending());
+
+		// the second round still commits, and the first never does.
+		clock.advance(Duration::from_secs(1));
+		match poll_once(&mut past_rounds) {
+			Poll::Ready(Some(Ok((2, commit)))) => assert_eq!((commit.target_hash, commit.target_number), ("B", 3)),
+			_ => panic!("commit of the other round is emitted once its timer fires"),
+		}
+
+		assert!(poll_once(&mut past_rounds).is_pending());
+	}
+
+	// a span or an event recorded by the `Capture` subscriber, with its fields
+	// in their `Debug` representation.
+	#[cfg(feature = "tracing")]
+	#[derive(Debug, Default, Clone)]
+	struct Record {
+		name: String,
+		fields: Vec<(String, String)>,
+		span: Option<String>, // the span the event was emitted in.
+	}
+
+	#[cfg(feature = "tracing")]
+	impl Record {
+		fn field(&self, name: &str) -> Option<&str> {
+			self.fields.iter().find(|(n, _)| n == name).map(|(_, value)| &value[..])
+		}
+	}
+
+	#[cfg(feature = "tracing")]
+	impl tracing::field::Visit for Record {
+		fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+			if field.name() == "message" {
+				self.name = format!("{:?}", value);
+			} else {
+				self.fields.push((field.name().to_string(), format!("{:?}", value)));
+			}
+		}
+	}
+
+	// a subscriber recording all spans and events.
+	#[cfg(feature = "tracing")]
+	#[derive(Default)]
+	struct Capture {
+		spans: parking_lot::Mutex<Vec<Record>>,
+		entered: parking_lot::Mutex<Vec<usize>>,
+		events: parking_lot::Mutex<Vec<Record>>,
+	}
+
+	#[cfg(feature = "tracing")]
+	impl tracing::Subscriber for Capture {
+		fn enabled(&self, _: &tracing::Metadata) -> bool {
+			true
+		}
+
+		fn new_span(&self, attributes: &tracing::span::Attributes) -> tracing::span::Id {
+			let mut record = Record { name: attributes.metadata().name().to_string(), ..Default::default() };
+			attributes.record(&mut record);
+
+			let mut spans = self.spans.lock();
+			spans.push(record);
+			tracing::span::Id::from_u64(spans.len() as u64)
+		}
+
+		fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record) {}
+
+		fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+		fn event(&self, event: &tracing::Event) {
+			let mut record = Record::default();
+			event.record(&mut record);
+
+			let entered = self.entered.lock().last().cloned();
+			record.span = entered.map(|idx| self.spans.lock()[idx].name.clone());
+			self.events.lock().push(record);
+		}
+
+		fn enter(&self, span: &tracing::span::Id) {
+			self.entered.lock().push(span.into_u64() as usize - 1);
+		}
+
+		fn exit(&self, _: &tracing::span::Id) {
+			self.entered.lock().pop();
+		}
+	}
+
+	#[cfg(feature = "tracing")]
+	#[test]
+	fn rounds_are_traced() {
+		let voters: VoterSet<_> = VoterSet::new(std::iter::once((Id(5), 100))).unwrap();
+
+		let (network, routing_task) = testing::environment::make_network();
+		let env = Arc::new(Environment::new(network.clone(), Id(5)).with_commit_delay(Duration::from_millis(50)));
+		env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B"]));
+
+		let capture = Arc::new(Capture::default());
+		tracing::subscriber::with_default(capture.clone(), || {
+			let (_, last_round_state) = crate::bridge_state::bridge_state(RoundState::genesis((GENESIS_HASH, 1)));
+			let (finalized_sender, _finalized) = mpsc::unbounded();
+			let mut round = VotingRound::new(
+				1,
+				voters,
+				(GENESIS_HASH, 1),
+				Some(last_round_state),
+				finalized_sender,
+				env.clone(),
+			);
+
+			let threads_pool = futures::executor::ThreadPool::new().unwrap();
+			threads_pool.spawn_ok(routing_task);
+
+			block_on(future::poll_fn(|cx| {
+				let _ = round.poll(cx).map(|res| res.unwrap());
+				match (round.state(), round.finalized()) {
+					(Some(State::Precommitted), Some(&("B", 3))) => Poll::Ready(()),
+					_ => Poll::Pending,
+				}
+			}));
+
+			// commit in the background, and prune the round afterwards.
+			let mut past_rounds = PastRounds::new();
+			past_rounds.push(&*env, round);
+			past_rounds.update_finalized(3);
+
+			block_on(future::poll_fn(|cx| {
+				while let Poll::Ready(Some(res)) = past_rounds.poll_next_unpin(cx) {
+					res.unwrap();
+				}
+
+				if past_rounds.past_rounds.is_empty() { Poll::Ready(()) } else { Poll::Pending }
+			}));
+		});
+
+		let spans = capture.spans.lock().clone();
+		let events = capture.events.lock().clone();
+		let event = |name: &str| events.iter().find(|event| event.name == name).cloned()
+			.unwrap_or_else(|| panic!("no {:?} event in {:?}", name, events));
+
+		for name in &["round", "background_round"] {
+			assert!(spans.iter().any(|span| &span.name == name && span.field("round") == Some("1")));
+		}
+
+		for name in &["Casting prevote", "Casting precommit", "Estimate updated"] {
+			let event = event(name);
+			assert_eq!(event.span.as_ref().map(|s| &s[..]), Some("round"));
+			assert_eq!(event.field("round"), Some("1"));
+			assert_eq!(event.field("target_number"), Some("3"));
+			assert_eq!(event.field("target_hash"), Some("\"B\""));
+		}
+
+		let imported = event("Importing vote");
+		assert_eq!(imported.field("voter"), Some("Id(5)"));
+		assert_eq!(imported.field("target_hash"), Some("\"B\""));
+
+		let committing = event("Committing");
+		assert_eq!(committing.field("round"), Some("1"));
+		assert_eq!(committing.field("target_number"), Some("3"));
+
+		assert_eq!(event("Pruning round").field("round"), Some("1"));
+	}
+}
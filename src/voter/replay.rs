@@ -0,0 +1,1636 @@
+// Copyright 2018-2019 Parity Technologies (UK) Ltd
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Recording the run of a voter and replaying it deterministically, e.g. to
+//! reproduce the state of a voter after a consensus incident.
+//!
+//! A `RecordingEnvironment` wraps the environment of a voter. It records every
+//! call the voter makes to it along with the result, and every item yielded by
+//! the timers, futures and streams it hands out, e.g. incoming votes. Together
+//! with the global messages and the points at which the voter is polled, this
+//! is a `Trace` of everything the voter observed.
+//!
+//! A `ReplayEnvironment` serves a trace to a fresh voter. Calls are answered
+//! with the recorded results and items are yielded in the recorded order, on
+//! the recorded polls, so the voter goes through exactly the same states. If
+//! the voter makes any other call than the recorded one, the replay panics,
+//! pointing out the first difference.
+//!
+//! Errors are recorded by their `Display` representation, and sinks are
+//! assumed to be always ready, like unbounded channels. Time stands still in a
+//! replay, so the round durations reported to metrics are zero.
+
+use futures::prelude::*;
+use futures::future::BoxFuture;
+use futures::task::noop_waker_ref;
+use parking_lot::Mutex;
+
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Display};
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use crate::round::State as RoundState;
+use crate::{
+	BlockNumberOps, CatchUp, Chain, Commit, CompactCommit, Equivocation, Error, HistoricalVotes,
+	Message, Prevote, Precommit, PrimaryPropose, SignedCommit, SignedMessage,
+};
+use super::{
+	BestChainIssue, Callback, CatchUpProcessingOutcome, Clock, CommitProcessingOutcome,
+	CommunicationIn, CommunicationOut, Environment, Phase, RoundData, RoundStateStream,
+	TooManyEquivocations,
+};
+
+#[cfg(feature = "derive-codec")]
+use parity_scale_codec::{Encode, Decode};
+
+/// The events of a recorded voter run, in order.
+pub type Trace<H, N, S, Id> = Vec<Event<H, N, S, Id>>;
+
+/// An event of a recorded voter run.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+pub enum Event<H, N, S, Id> {
+	/// The voter was polled.
+	Poll,
+	/// The voter called the environment, or produced some other output, with
+	/// the given result.
+	Call(Call<H, N, S, Id>, Return<H, N, Id>),
+	/// A source handed out by the environment, e.g. a timer or a stream of
+	/// incoming messages, yielded an item. Sources are numbered in the order
+	/// they were handed out.
+	Ready(u64, Item<H, N, S, Id>),
+}
+
+/// A call of the voter to the environment, with its arguments, or another
+/// output of the voter.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+pub enum Call<H, N, S, Id> {
+	/// `Chain::ancestry`.
+	Ancestry { base: H, block: H },
+	/// `Chain::best_chain_containing`.
+	BestChainContaining { base: H },
+	/// `Chain::is_equal_or_descendent_of`.
+	IsEqualOrDescendentOf { base: H, block: H },
+	/// `Chain::last_finalized`.
+	LastFinalized,
+	/// `Environment::best_chain`.
+	BestChain { base: H },
+	/// `Environment::set_id`.
+	SetId,
+	/// `Environment::round_data`.
+	RoundData { round: u64 },
+	/// `Environment::round_commit_timer`.
+	RoundCommitTimer,
+	/// `Environment::round_timer`.
+	RoundTimer { round: u64, phase: Phase },
+	/// `Environment::primary_proposal`.
+	PrimaryProposal { round: u64, estimate: PrimaryPropose<H, N> },
+	/// `Environment::restrict_vote`.
+	RestrictVote { round: u64, phase: Phase, base: (H, N), best_target: (H, N), current_target: (H, N) },
+	/// `Environment::proposed`.
+	Proposed { round: u64, propose: PrimaryPropose<H, N> },
+	/// `Environment::prevoted`.
+	Prevoted { round: u64, prevote: Prevote<H, N> },
+	/// `Environment::precommitted`.
+	Precommitted { round: u64, precommit: Precommit<H, N> },
+	/// `Environment::global_out_error`, with the error as text.
+	GlobalOutError { error: String },
+	/// `Environment::commit_not_sent`.
+	CommitNotSent { round: u64, commit: Commit<H, N, S, Id> },
+	/// `Environment::finality_stalled`.
+	FinalityStalled { rounds_without_progress: u64, last_finalized: N },
+	/// `Environment::finality_resumed`.
+	FinalityResumed { rounds_without_progress: u64, finalized: N },
+	/// `Environment::completed`.
+	Completed { round: u64, state: RoundState<H, N>, base: (H, N), votes: HistoricalVotes<H, N, S, Id> },
+	/// `Environment::block_available`.
+	BlockAvailable { hash: H },
+	/// `Environment::request_catch_up`.
+	RequestCatchUp { round: u64 },
+	/// `Environment::finalize_block`.
+	FinalizeBlock { hash: H, number: N, round: u64, commit: Commit<H, N, S, Id> },
+	/// `Environment::prevote_equivocation`.
+	PrevoteEquivocation { round: u64, equivocation: Equivocation<Id, Prevote<H, N>, S> },
+	/// `Environment::precommit_equivocation`.
+	PrecommitEquivocation { round: u64, equivocation: Equivocation<Id, Precommit<H, N>, S> },
+	/// `Environment::best_chain_issue`.
+	BestChainIssue { round: u64, issue: BestChainIssue<H, N> },
+	/// `Environment::too_many_equivocations`.
+	TooManyEquivocations { event: TooManyEquivocations<Id> },
+	/// `Environment::prior_round_state`. The stream of states isn't recorded.
+	PriorRoundState { round: u64 },
+	/// `Environment::sign_commit`.
+	SignCommit { round: u64, commit: Commit<H, N, S, Id> },
+	/// `Environment::verify_commit_signature`.
+	VerifyCommitSignature { round: u64, commit: SignedCommit<H, N, S, Id> },
+	/// `Clock::timer_at`. The point in time isn't recorded, since it's
+	/// specific to the clock.
+	ClockTimer,
+	/// The stream of global messages, set up with
+	/// `RecordingEnvironment::global_in`.
+	GlobalIn,
+	/// A message was sent on the outgoing sink of a round.
+	Send { round: u64, message: Message<H, N> },
+	/// A message was sent on the outgoing sink for global messages.
+	SendGlobal(CommunicationOut<H, N, S, Id>),
+	/// The callback of an incoming commit was run.
+	CommitOutcome(Outcome),
+	/// The callback of an incoming catch-up was run.
+	CatchUpOutcome(Outcome),
+}
+
+/// The recorded result of a call.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+pub enum Return<H, N, Id> {
+	/// The call has no result.
+	Unit,
+	/// A flag, e.g. whether a commit signature is valid.
+	Bool(bool),
+	/// A number, e.g. the voter set id.
+	Number(u64),
+	/// The ancestry of a block.
+	Ancestry(Result<Vec<H>, Error>),
+	/// A block, e.g. the best one containing another.
+	Block(Option<(H, N)>),
+	/// Whether the environment accepted a call.
+	Result(Result<(), String>),
+	/// A primary proposal.
+	PrimaryProposal(Result<Option<PrimaryPropose<H, N>>, String>),
+	/// A new source.
+	Source(u64),
+	/// A new source, if any was handed out.
+	OptionalSource(Option<u64>),
+	/// The data of a round, with new sources for its timers and incoming
+	/// messages.
+	RoundData { voter_id: Option<Id>, prevote_timer: u64, precommit_timer: u64, incoming: u64 },
+}
+
+/// An item yielded by a source.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+pub enum Item<H, N, S, Id> {
+	/// A timer of the environment fired.
+	Timer(Result<(), String>),
+	/// A timer of the clock fired.
+	ClockTimer,
+	/// A lookup of the best chain resolved.
+	BestChain(Result<Option<(H, N)>, String>),
+	/// A message was received in a round.
+	Message(Result<SignedMessage<H, N, S, Id>, String>),
+	/// A global message was received.
+	Global(Result<GlobalMessage<H, N, S, Id>, String>),
+	/// A commit was signed.
+	SignedCommit(Result<SignedCommit<H, N, S, Id>, String>),
+	/// A restriction of a vote target resolved.
+	RestrictedVote(Result<Option<(H, N)>, String>),
+	/// A stream ended.
+	End,
+}
+
+/// A global message, i.e. `CommunicationIn` without the callbacks.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+pub enum GlobalMessage<H, N, S, Id> {
+	/// A commit message.
+	Commit(u64, CompactCommit<H, N, S, Id>),
+	/// A signed commit message.
+	SignedCommit(u64, SignedCommit<H, N, S, Id>),
+	/// A catch up message.
+	CatchUp(CatchUp<H, N, S, Id>),
+	/// A batch of messages.
+	Batch(Vec<GlobalMessage<H, N, S, Id>>),
+}
+
+/// The outcome passed to the callback of a global message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "derive-codec", derive(Encode, Decode))]
+pub enum Outcome {
+	/// The message was good.
+	Good,
+	/// The message was bad.
+	Bad,
+	/// The message was useless.
+	Useless,
+}
+
+impl From<&CommitProcessingOutcome> for Outcome {
+	fn from(outcome: &CommitProcessingOutcome) -> Self {
+		match outcome {
+			CommitProcessingOutcome::Good(_) => Outcome::Good,
+			CommitProcessingOutcome::Bad(_) => Outcome::Bad,
+		}
+	}
+}
+
+impl From<&CatchUpProcessingOutcome> for Outcome {
+	fn from(outcome: &CatchUpProcessingOutcome) -> Self {
+		match outcome {
+			CatchUpProcessingOutcome::Good(_) => Outcome::Good,
+			CatchUpProcessingOutcome::Bad(_) => Outcome::Bad,
+			CatchUpProcessingOutcome::Useless => Outcome::Useless,
+		}
+	}
+}
+
+/// An error of the environment in a replayed run, by the `Display`
+/// representation of the recorded error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedError(pub String);
+
+impl Display for RecordedError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.0)
+	}
+}
+
+impl std::error::Error for RecordedError {}
+
+impl From<Error> for RecordedError {
+	fn from(error: Error) -> Self {
+		RecordedError(error.to_string())
+	}
+}
+
+fn recorded<T, E: Display>(result: &Result<T, E>) -> Result<(), String> {
+	result.as_ref().map(|_| ()).map_err(ToString::to_string)
+}
+
+fn recorded_value<T: Clone, E: Display>(result: &Result<T, E>) -> Result<T, String> {
+	match result {
+		Ok(value) => Ok(value.clone()),
+		Err(e) => Err(e.to_string()),
+	}
+}
+
+// the events recorded so far, and the number of sources handed out.
+struct Log<H, N, S, Id> {
+	events: Trace<H, N, S, Id>,
+	sources: u64,
+}
+
+type SharedLog<H, N, S, Id> = Arc<Mutex<Log<H, N, S, Id>>>;
+
+// how the output of a source is recorded.
+type RecordItem<O, H, N, S, Id> = fn(&O) -> Item<H, N, S, Id>;
+
+impl<H, N, S, Id> Log<H, N, S, Id> {
+	fn source(&mut self) -> u64 {
+		self.sources += 1;
+		self.sources - 1
+	}
+
+	fn call(&mut self, call: Call<H, N, S, Id>, result: Return<H, N, Id>) {
+		self.events.push(Event::Call(call, result));
+	}
+}
+
+/// An environment recording all calls of the voter to the inner environment,
+/// and the items yielded by the sources it hands out.
+///
+/// The stream and sink of global messages given to the voter must be wrapped
+/// with `global_in` and `global_out`, and the voter must be polled through
+/// `record`.
+pub struct RecordingEnvironment<H, N, E: Environment<H, N>> where
+	H: Eq,
+	N: BlockNumberOps,
+{
+	inner: E,
+	clock: RecordingClock<E::Clock, H, N, E::Signature, E::Id>,
+	log: SharedLog<H, N, E::Signature, E::Id>,
+}
+
+impl<H, N, E: Environment<H, N>> RecordingEnvironment<H, N, E> where
+	H: Eq + Clone,
+	N: BlockNumberOps,
+	E::Clock: Clone,
+{
+	/// Record the calls to the given environment.
+	pub fn new(inner: E) -> Self {
+		let log = Arc::new(Mutex::new(Log { events: Vec::new(), sources: 0 }));
+		let clock = RecordingClock { inner: inner.clock().clone(), log: log.clone() };
+		RecordingEnvironment { inner, clock, log }
+	}
+
+	/// The inner environment.
+	pub fn inner(&self) -> &E {
+		&self.inner
+	}
+
+	/// The events recorded so far.
+	pub fn trace(&self) -> Trace<H, N, E::Signature, E::Id> where E::Id: Clone {
+		self.log.lock().events.clone()
+	}
+
+	/// Record the voter being polled through the returned future.
+	pub fn record<F: Future + Unpin>(&self, voter: F) -> RecordedPolls<F, H, N, E::Signature, E::Id> {
+		RecordedPolls { inner: voter, log: self.log.clone() }
+	}
+
+	/// Record the global messages given to the voter, and the outcomes passed
+	/// to their callbacks.
+	pub fn global_in<I>(&self, global_in: I) -> RecordedGlobalIn<I, H, N, E::Signature, E::Id> where
+		I: Stream<Item=Result<CommunicationIn<H, N, E::Signature, E::Id>, E::Error>> + Unpin,
+	{
+		let mut log = self.log.lock();
+		let id = log.source();
+		log.call(Call::GlobalIn, Return::Source(id));
+
+		RecordedGlobalIn { inner: global_in, id, log: self.log.clone() }
+	}
+
+	/// Record the global messages sent by the voter.
+	pub fn global_out<O>(&self, global_out: O) -> RecordedGlobalOut<O, H, N, E::Signature, E::Id> {
+		RecordedGlobalOut { inner: global_out, log: self.log.clone() }
+	}
+
+	fn call<T>(&self, call: Call<H, N, E::Signature, E::Id>, result: Return<H, N, E::Id>, value: T) -> T {
+		self.log.lock().call(call, result);
+		value
+	}
+
+	fn source<T, O>(
+		&self,
+		inner: T,
+		item: RecordItem<O, H, N, E::Signature, E::Id>,
+	) -> Recorded<T, O, H, N, E::Signature, E::Id> {
+		let id = self.log.lock().source();
+		Recorded { inner, id, log: self.log.clone(), item }
+	}
+}
+
+impl<H, N, E: Environment<H, N>> Chain<H, N> for RecordingEnvironment<H, N, E> where
+	H: Eq + Clone,
+	N: BlockNumberOps,
+	E::Clock: Clone,
+{
+	fn ancestry(&self, base: H, block: H) -> Result<Vec<H>, Error> {
+		let ancestry = self.inner.ancestry(base.clone(), block.clone());
+		self.call(Call::Ancestry { base, block }, Return::Ancestry(ancestry.clone()), ancestry)
+	}
+
+	fn best_chain_containing(&self, base: H) -> Option<(H, N)> {
+		let best = self.inner.best_chain_containing(base.clone());
+		self.call(Call::BestChainContaining { base }, Return::Block(best.clone()), best)
+	}
+
+	fn is_equal_or_descendent_of(&self, base: H, block: H) -> bool {
+		let is_descendent = self.inner.is_equal_or_descendent_of(base.clone(), block.clone());
+		self.call(Call::IsEqualOrDescendentOf { base, block }, Return::Bool(is_descendent), is_descendent)
+	}
+
+	fn last_finalized(&self) -> Option<(H, N)> {
+		let finalized = self.inner.last_finalized();
+		self.call(Call::LastFinalized, Return::Block(finalized.clone()), finalized)
+	}
+}
+
+type SignedCommitFor<H, N, E> = SignedCommit<
+	H,
+	N,
+	<E as Environment<H, N>>::Signature,
+	<E as Environment<H, N>>::Id,
+>;
+
+impl<H, N, E: Environment<H, N>> Environment<H, N> for RecordingEnvironment<H, N, E> where
+	H: Eq + Clone + Send + 'static,
+	N: BlockNumberOps + Send + 'static,
+	E::Clock: Clone,
+	E::Signature: Send + 'static,
+	E::Id: Send + 'static,
+	E::Error: Send + 'static,
+{
+	type Timer = Recorded<E::Timer, Result<(), E::Error>, H, N, E::Signature, E::Id>;
+	type Id = E::Id;
+	type Signature = E::Signature;
+	type In = Recorded<
+		E::In,
+		Result<SignedMessage<H, N, E::Signature, E::Id>, E::Error>,
+		H, N, E::Signature, E::Id,
+	>;
+	type Out = RecordedSink<E::Out, H, N, E::Signature, E::Id>;
+	type Error = E::Error;
+	type Clock = RecordingClock<E::Clock, H, N, E::Signature, E::Id>;
+
+	fn clock(&self) -> &Self::Clock {
+		&self.clock
+	}
+
+	fn best_chain(&self, base: H) -> Option<BoxFuture<'static, Result<Option<(H, N)>, Self::Error>>> {
+		let best_chain = self.inner
+			.best_chain(base.clone())
+			.map(|best_chain| self.source(best_chain, |best| Item::BestChain(recorded_value(best))));
+
+		let result = Return::OptionalSource(best_chain.as_ref().map(|best_chain| best_chain.id));
+		self.call(Call::BestChain { base }, result, best_chain.map(|best_chain| Box::pin(best_chain) as BoxFuture<_>))
+	}
+
+	fn set_id(&self) -> u64 {
+		let set_id = self.inner.set_id();
+		self.call(Call::SetId, Return::Number(set_id), set_id)
+	}
+
+	fn round_data(&self, round: u64) -> RoundData<Self::Id, Self::Timer, Self::In, Self::Out> {
+		let data = self.inner.round_data(round);
+		let prevote_timer = self.source(data.prevote_timer, |fired| Item::Timer(recorded(fired)));
+		let precommit_timer = self.source(data.precommit_timer, |fired| Item::Timer(recorded(fired)));
+		let incoming = self.source(data.incoming, |message| Item::Message(recorded_value(message)));
+
+		let result = Return::RoundData {
+			voter_id: data.voter_id.clone(),
+			prevote_timer: prevote_timer.id,
+			precommit_timer: precommit_timer.id,
+			incoming: incoming.id,
+		};
+
+		self.call(Call::RoundData { round }, result, RoundData {
+			voter_id: data.voter_id,
+			prevote_timer,
+			precommit_timer,
+			incoming,
+			outgoing: RecordedSink { inner: data.outgoing, round, log: self.log.clone() },
+		})
+	}
+
+	fn round_commit_timer(&self) -> Self::Timer {
+		let timer = self.source(self.inner.round_commit_timer(), |fired| Item::Timer(recorded(fired)));
+		self.call(Call::RoundCommitTimer, Return::Source(timer.id), timer)
+	}
+
+	fn round_timer(&self, round: u64, phase: Phase) -> Option<Self::Timer> {
+		let timer = self.inner.round_timer(round, phase)
+			.map(|timer| self.source(timer, |fired| Item::Timer(recorded(fired))));
+		let result = Return::OptionalSource(timer.as_ref().map(|timer| timer.id));
+		self.call(Call::RoundTimer { round, phase }, result, timer)
+	}
+
+	fn primary_proposal(
+		&self,
+		round: u64,
+		estimate: PrimaryPropose<H, N>,
+	) -> Result<Option<PrimaryPropose<H, N>>, Self::Error> {
+		let proposal = self.inner.primary_proposal(round, estimate.clone());
+		let result = Return::PrimaryProposal(recorded_value(&proposal));
+		self.call(Call::PrimaryProposal { round, estimate }, result, proposal)
+	}
+
+	fn restrict_vote(
+		&self,
+		round: u64,
+		phase: Phase,
+		base: (H, N),
+		best_target: (H, N),
+		current_target: (H, N),
+	) -> Option<BoxFuture<'static, Result<Option<(H, N)>, Self::Error>>> {
+		let restriction = self.inner
+			.restrict_vote(round, phase, base.clone(), best_target.clone(), current_target.clone())
+			.map(|restriction| self.source(restriction, |restricted| Item::RestrictedVote(recorded_value(restricted))));
+
+		let result = Return::OptionalSource(restriction.as_ref().map(|restriction| restriction.id));
+		let call = Call::RestrictVote { round, phase, base, best_target, current_target };
+		self.call(call, result, restriction.map(|restriction| Box::pin(restriction) as BoxFuture<_>))
+	}
+
+	fn proposed(&self, round: u64, propose: PrimaryPropose<H, N>) -> Result<(), Self::Error> {
+		let result = self.inner.proposed(round, propose.clone());
+		self.call(Call::Proposed { round, propose }, Return::Result(recorded(&result)), result)
+	}
+
+	fn prevoted(&self, round: u64, prevote: Prevote<H, N>) -> Result<(), Self::Error> {
+		let result = self.inner.prevoted(round, prevote.clone());
+		self.call(Call::Prevoted { round, prevote }, Return::Result(recorded(&result)), result)
+	}
+
+	fn precommitted(&self, round: u64, precommit: Precommit<H, N>) -> Result<(), Self::Error> {
+		let result = self.inner.precommitted(round, precommit.clone());
+		self.call(Call::Precommitted { round, precommit }, Return::Result(recorded(&result)), result)
+	}
+
+	fn global_out_error(&self, error: Self::Error) {
+		let call = Call::GlobalOutError { error: error.to_string() };
+		self.inner.global_out_error(error);
+		self.call(call, Return::Unit, ())
+	}
+
+	fn commit_not_sent(&self, round: u64, commit: Commit<H, N, Self::Signature, Self::Id>) {
+		self.inner.commit_not_sent(round, commit.clone());
+		self.call(Call::CommitNotSent { round, commit }, Return::Unit, ())
+	}
+
+	fn finality_stalled(&self, rounds_without_progress: u64, last_finalized: N) {
+		self.inner.finality_stalled(rounds_without_progress, last_finalized);
+		self.call(Call::FinalityStalled { rounds_without_progress, last_finalized }, Return::Unit, ())
+	}
+
+	fn finality_resumed(&self, rounds_without_progress: u64, finalized: N) {
+		self.inner.finality_resumed(rounds_without_progress, finalized);
+		self.call(Call::FinalityResumed { rounds_without_progress, finalized }, Return::Unit, ())
+	}
+
+	fn completed(
+		&self,
+		round: u64,
+		state: RoundState<H, N>,
+		base: (H, N),
+		votes: &HistoricalVotes<H, N, Self::Signature, Self::Id>,
+	) -> Result<(), Self::Error> {
+		let result = self.inner.completed(round, state.clone(), base.clone(), votes);
+		let call = Call::Completed { round, state, base, votes: votes.clone() };
+		self.call(call, Return::Result(recorded(&result)), result)
+	}
+
+	fn block_available(&self, hash: H) -> bool {
+		let available = self.inner.block_available(hash.clone());
+		self.call(Call::BlockAvailable { hash }, Return::Bool(available), available)
+	}
+
+	fn request_catch_up(&self, round: u64) {
+		self.inner.request_catch_up(round);
+		self.call(Call::RequestCatchUp { round }, Return::Unit, ())
+	}
+
+	fn finalize_block(
+		&self,
+		hash: H,
+		number: N,
+		round: u64,
+		commit: Commit<H, N, Self::Signature, Self::Id>,
+	) -> Result<(), Self::Error> {
+		let result = self.inner.finalize_block(hash.clone(), number, round, commit.clone());
+		let call = Call::FinalizeBlock { hash, number, round, commit };
+		self.call(call, Return::Result(recorded(&result)), result)
+	}
+
+	fn prevote_equivocation(
+		&self,
+		round: u64,
+		equivocation: Equivocation<Self::Id, Prevote<H, N>, Self::Signature>,
+	) {
+		self.inner.prevote_equivocation(round, equivocation.clone());
+		self.call(Call::PrevoteEquivocation { round, equivocation }, Return::Unit, ())
+	}
+
+	fn precommit_equivocation(
+		&self,
+		round: u64,
+		equivocation: Equivocation<Self::Id, Precommit<H, N>, Self::Signature>,
+	) {
+		self.inner.precommit_equivocation(round, equivocation.clone());
+		self.call(Call::PrecommitEquivocation { round, equivocation }, Return::Unit, ())
+	}
+
+	fn best_chain_issue(&self, round: u64, issue: BestChainIssue<H, N>) {
+		self.inner.best_chain_issue(round, issue.clone());
+		self.call(Call::BestChainIssue { round, issue }, Return::Unit, ())
+	}
+
+	fn too_many_equivocations(&self, event: TooManyEquivocations<Self::Id>) -> Result<(), Self::Error> {
+		let result = self.inner.too_many_equivocations(event.clone());
+		self.call(Call::TooManyEquivocations { event }, Return::Result(recorded(&result)), result)
+	}
+
+	fn prior_round_state(&self, round: u64, states: RoundStateStream<H, N>) {
+		self.inner.prior_round_state(round, states);
+		self.call(Call::PriorRoundState { round }, Return::Unit, ())
+	}
+
+	fn sign_commit(
+		&self,
+		round: u64,
+		commit: &Commit<H, N, Self::Signature, Self::Id>,
+	) -> Option<BoxFuture<'static, Result<SignedCommitFor<H, N, E>, Self::Error>>> {
+		let signing = self.inner.sign_commit(round, commit)
+			.map(|signing| self.source(signing, |signed| Item::SignedCommit(recorded_value(signed))));
+
+		let result = Return::OptionalSource(signing.as_ref().map(|signing| signing.id));
+		let call = Call::SignCommit { round, commit: commit.clone() };
+		self.call(call, result, signing.map(|signing| Box::pin(signing) as BoxFuture<_>))
+	}
+
+	fn verify_commit_signature(&self, round: u64, commit: &SignedCommitFor<H, N, E>) -> bool {
+		let valid = self.inner.verify_commit_signature(round, commit);
+		let call = Call::VerifyCommitSignature { round, commit: commit.clone() };
+		self.call(call, Return::Bool(valid), valid)
+	}
+}
+
+/// A timer, future or stream handed out by a `RecordingEnvironment`, which
+/// records the items it yields.
+pub struct Recorded<T, O, H, N, S, Id> {
+	inner: T,
+	id: u64,
+	log: SharedLog<H, N, S, Id>,
+	item: RecordItem<O, H, N, S, Id>,
+}
+
+impl<T, O, H, N, S, Id> Future for Recorded<T, O, H, N, S, Id> where
+	T: Future<Output=O> + Unpin,
+{
+	type Output = O;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<O> {
+		let output = futures::ready!(self.inner.poll_unpin(cx));
+		let item = (self.item)(&output);
+		self.log.lock().events.push(Event::Ready(self.id, item));
+		Poll::Ready(output)
+	}
+}
+
+impl<T, O, H, N, S, Id> Stream for Recorded<T, O, H, N, S, Id> where
+	T: Stream<Item=O> + Unpin,
+{
+	type Item = O;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<O>> {
+		let next = futures::ready!(self.inner.poll_next_unpin(cx));
+		let item = next.as_ref().map_or(Item::End, self.item);
+		self.log.lock().events.push(Event::Ready(self.id, item));
+		Poll::Ready(next)
+	}
+}
+
+/// The outgoing sink of a round handed out by a `RecordingEnvironment`, which
+/// records the messages sent.
+pub struct RecordedSink<T, H, N, S, Id> {
+	inner: T,
+	round: u64,
+	log: SharedLog<H, N, S, Id>,
+}
+
+impl<T, H, N, S, Id> Sink<Message<H, N>> for RecordedSink<T, H, N, S, Id> where
+	T: Sink<Message<H, N>> + Unpin,
+	T::Error: Display,
+	H: Clone,
+	N: Clone,
+{
+	type Error = T::Error;
+
+	fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), T::Error>> {
+		Pin::new(&mut self.inner).poll_ready(cx)
+	}
+
+	fn start_send(mut self: Pin<&mut Self>, message: Message<H, N>) -> Result<(), T::Error> {
+		let result = Pin::new(&mut self.inner).start_send(message.clone());
+		let call = Call::Send { round: self.round, message };
+		self.log.lock().call(call, Return::Result(recorded(&result)));
+		result
+	}
+
+	fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), T::Error>> {
+		Pin::new(&mut self.inner).poll_flush(cx)
+	}
+
+	fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), T::Error>> {
+		Pin::new(&mut self.inner).poll_close(cx)
+	}
+}
+
+/// The sink of global messages sent by a voter, which records them. See
+/// `RecordingEnvironment::global_out`.
+pub struct RecordedGlobalOut<T, H, N, S, Id> {
+	inner: T,
+	log: SharedLog<H, N, S, Id>,
+}
+
+impl<T, H, N, S, Id> Sink<CommunicationOut<H, N, S, Id>> for RecordedGlobalOut<T, H, N, S, Id> where
+	T: Sink<CommunicationOut<H, N, S, Id>> + Unpin,
+	T::Error: Display,
+	H: Clone,
+	N: Clone,
+	S: Clone,
+	Id: Clone,
+{
+	type Error = T::Error;
+
+	fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), T::Error>> {
+		Pin::new(&mut self.inner).poll_ready(cx)
+	}
+
+	fn start_send(mut self: Pin<&mut Self>, message: CommunicationOut<H, N, S, Id>) -> Result<(), T::Error> {
+		let result = Pin::new(&mut self.inner).start_send(message.clone());
+		self.log.lock().call(Call::SendGlobal(message), Return::Result(recorded(&result)));
+		result
+	}
+
+	fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), T::Error>> {
+		Pin::new(&mut self.inner).poll_flush(cx)
+	}
+
+	fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), T::Error>> {
+		Pin::new(&mut self.inner).poll_close(cx)
+	}
+}
+
+/// The stream of global messages given to a voter, which records them along
+/// with the outcomes passed to their callbacks. See
+/// `RecordingEnvironment::global_in`.
+pub struct RecordedGlobalIn<T, H, N, S, Id> {
+	inner: T,
+	id: u64,
+	log: SharedLog<H, N, S, Id>,
+}
+
+// run the callback after recording the outcome passed to it.
+fn recorded_callback<O, H, N, S, Id>(
+	mut callback: Callback<O>,
+	log: &SharedLog<H, N, S, Id>,
+	call: fn(Outcome) -> Call<H, N, S, Id>,
+) -> Callback<O> where
+	O: 'static,
+	for<'a> Outcome: From<&'a O>,
+	H: Send + 'static,
+	N: Send + 'static,
+	S: Send + 'static,
+	Id: Send + 'static,
+{
+	let log = log.clone();
+	Callback::Work(Box::new(move |outcome| {
+		log.lock().call(call(Outcome::from(&outcome)), Return::Unit);
+		callback.run(outcome);
+	}))
+}
+
+// split a global message into what's recorded and what's given to the voter.
+#[allow(clippy::type_complexity)]
+fn record_global<H, N, S, Id>(
+	message: CommunicationIn<H, N, S, Id>,
+	log: &SharedLog<H, N, S, Id>,
+) -> (GlobalMessage<H, N, S, Id>, CommunicationIn<H, N, S, Id>) where
+	H: Clone + Send + 'static,
+	N: Clone + Send + 'static,
+	S: Clone + Send + 'static,
+	Id: Clone + Send + 'static,
+{
+	match message {
+		CommunicationIn::Commit(round, commit, callback) => (
+			GlobalMessage::Commit(round, commit.clone()),
+			CommunicationIn::Commit(round, commit, recorded_callback(callback, log, Call::CommitOutcome)),
+		),
+		CommunicationIn::SignedCommit(round, signed, callback) => (
+			GlobalMessage::SignedCommit(round, signed.clone()),
+			CommunicationIn::SignedCommit(round, signed, recorded_callback(callback, log, Call::CommitOutcome)),
+		),
+		CommunicationIn::CatchUp(catch_up, callback) => (
+			GlobalMessage::CatchUp(catch_up.clone()),
+			CommunicationIn::CatchUp(catch_up, recorded_callback(callback, log, Call::CatchUpOutcome)),
+		),
+		CommunicationIn::Batch(messages) => {
+			let (recorded, messages) = messages.into_iter()
+				.map(|message| record_global(message, log))
+				.unzip();
+			(GlobalMessage::Batch(recorded), CommunicationIn::Batch(messages))
+		}
+	}
+}
+
+impl<T, Err, H, N, S, Id> Stream for RecordedGlobalIn<T, H, N, S, Id> where
+	T: Stream<Item=Result<CommunicationIn<H, N, S, Id>, Err>> + Unpin,
+	Err: Display,
+	H: Clone + Send + 'static,
+	N: Clone + Send + 'static,
+	S: Clone + Send + 'static,
+	Id: Clone + Send + 'static,
+{
+	type Item = Result<CommunicationIn<H, N, S, Id>, Err>;
+
+	fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+		let (item, next) = match futures::ready!(self.inner.poll_next_unpin(cx)) {
+			None => (Item::End, None),
+			Some(Err(e)) => (Item::Global(Err(e.to_string())), Some(Err(e))),
+			Some(Ok(message)) => {
+				let (recorded, message) = record_global(message, &self.log);
+				(Item::Global(Ok(recorded)), Some(Ok(message)))
+			}
+		};
+
+		self.log.lock().events.push(Event::Ready(self.id, item));
+		Poll::Ready(next)
+	}
+}
+
+/// A future polling the voter, which records each poll. See
+/// `RecordingEnvironment::record`.
+pub struct RecordedPolls<F, H, N, S, Id> {
+	inner: F,
+	log: SharedLog<H, N, S, Id>,
+}
+
+impl<F, H, N, S, Id> RecordedPolls<F, H, N, S, Id> {
+	/// The voter being recorded.
+	pub fn inner(&self) -> &F {
+		&self.inner
+	}
+}
+
+impl<F: Future + Unpin, H, N, S, Id> Future for RecordedPolls<F, H, N, S, Id> {
+	type Output = F::Output;
+
+	fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<F::Output> {
+		self.log.lock().events.push(Event::Poll);
+		self.inner.poll_unpin(cx)
+	}
+}
+
+/// The clock of a `RecordingEnvironment`, which records the timers it hands
+/// out.
+pub struct RecordingClock<C, H, N, S, Id> {
+	inner: C,
+	log: SharedLog<H, N, S, Id>,
+}
+
+impl<C: Clock, H, N, S, Id> Clock for RecordingClock<C, H, N, S, Id> {
+	type Instant = C::Instant;
+	type Timer = Recorded<C::Timer, (), H, N, S, Id>;
+
+	fn now(&self) -> C::Instant {
+		self.inner.now()
+	}
+
+	fn timer_at(&self, at: C::Instant) -> Self::Timer {
+		let mut log = self.log.lock();
+		let id = log.source();
+		log.call(Call::ClockTimer, Return::Source(id));
+
+		Recorded { inner: self.inner.timer_at(at), id, log: self.log.clone(), item: |_| Item::ClockTimer }
+	}
+}
+
+// the trace being replayed, and the position of the next event in it.
+struct Replay<H, N, S, Id> {
+	events: Trace<H, N, S, Id>,
+	next: usize,
+	// tasks waiting for sources to yield their next item.
+	wakers: HashMap<u64, Waker>,
+}
+
+type SharedReplay<H, N, S, Id> = Arc<Mutex<Replay<H, N, S, Id>>>;
+
+impl<H: Debug, N: Debug, S: Debug, Id: Debug> Replay<H, N, S, Id> where
+	Call<H, N, S, Id>: PartialEq,
+	Return<H, N, Id>: Clone,
+	Item<H, N, S, Id>: Clone,
+{
+	fn advance(&mut self) {
+		self.next += 1;
+		if let Some(Event::Ready(id, _)) = self.events.get(self.next) {
+			if let Some(waker) = self.wakers.remove(id) {
+				waker.wake();
+			}
+		}
+	}
+
+	// answer a call with the recorded result. panics if it isn't the next
+	// recorded call.
+	fn call(&mut self, call: Call<H, N, S, Id>) -> Return<H, N, Id> {
+		let result = match self.events.get(self.next) {
+			Some(Event::Call(recorded, result)) if *recorded == call => result.clone(),
+			Some(Event::Call(recorded, _)) => panic!(
+				"Replay diverged at event {}: expected call {:?}, got {:?}{}",
+				self.next,
+				recorded,
+				call,
+				first_difference(recorded, &call),
+			),
+			event => panic!("Replay diverged at event {}: expected {:?}, got call {:?}", self.next, event, call),
+		};
+
+		self.advance();
+		result
+	}
+
+	// the next item of the given source, if it's the next recorded event.
+	// otherwise the task is woken once it is.
+	fn ready(&mut self, id: u64, waker: &Waker) -> Option<Item<H, N, S, Id>> {
+		match self.events.get(self.next) {
+			Some(Event::Ready(ready, item)) if *ready == id => {
+				let item = item.clone();
+				self.advance();
+				Some(item)
+			}
+			_ => {
+				self.wakers.insert(id, waker.clone());
+				None
+			}
+		}
+	}
+}
+
+// the first line in which the pretty-printed calls differ.
+fn first_difference<T: Debug>(expected: &T, actual: &T) -> String {
+	let expected = format!("{:#?}", expected);
+	let actual = format!("{:#?}", actual);
+	expected.lines().zip(actual.lines()).enumerate()
+		.find(|(_, (expected, actual))| expected != actual)
+		.map(|(line, (expected, actual))| format!(
+			"\nfirst difference in line {}: expected `{}`, got `{}`",
+			line + 1,
+			expected.trim(),
+			actual.trim(),
+		))
+		.unwrap_or_default()
+}
+
+// the recorded result of a call, which must be of the given kind.
+macro_rules! replayed {
+	($env:expr, $call:expr, $result:pat => $value:expr) => {
+		match $env.replay.lock().call($call) {
+			$result => $value,
+			result => panic!("Recorded result {:?} doesn't fit the call", result),
+		}
+	};
+}
+
+/// An environment replaying a trace recorded with `RecordingEnvironment`,
+/// panicking if the voter diverges from it.
+///
+/// The stream and sink of global messages given to the voter must be created
+/// with `global_in` and `global_out`, at the same point as they were wrapped
+/// when recording, and the voter must be polled through `run`.
+pub struct ReplayEnvironment<H, N, S, Id> {
+	replay: SharedReplay<H, N, S, Id>,
+	clock: ReplayClock<H, N, S, Id>,
+}
+
+impl<H, N, S, Id> ReplayEnvironment<H, N, S, Id> where
+	H: Clone + PartialEq + Debug,
+	N: Clone + PartialEq + Debug,
+	S: Clone + PartialEq + Debug,
+	Id: Clone + PartialEq + Debug,
+{
+	/// Replay the given trace.
+	pub fn new(trace: Trace<H, N, S, Id>) -> Self {
+		let replay = Arc::new(Mutex::new(Replay { events: trace, next: 0, wakers: HashMap::new() }));
+		ReplayEnvironment { clock: ReplayClock { replay: replay.clone() }, replay }
+	}
+
+	/// The recorded global messages.
+	pub fn global_in(&self) -> ReplayedGlobalIn<H, N, S, Id> {
+		let id = replayed!(self, Call::GlobalIn, Return::Source(id) => id);
+		ReplayedGlobalIn { id, replay: self.replay.clone() }
+	}
+
+	/// A sink checking the global messages sent against the recorded ones.
+	pub fn global_out(&self) -> ReplayedSink<H, N, S, Id> {
+		ReplayedSink { round: None, replay: self.replay.clone() }
+	}
+
+	/// Poll the voter once for every recorded poll, until the trace has been
+	/// replayed in full.
+	pub fn run<F: Future + Unpin>(&self, voter: &mut F) {
+		let mut cx = Context::from_waker(noop_waker_ref());
+		loop {
+			{
+				let mut replay = self.replay.lock();
+				match replay.events.get(replay.next) {
+					None => return,
+					Some(Event::Poll) => replay.advance(),
+					Some(event) => panic!(
+						"Replay diverged at event {}: expected {:?}, got the end of a poll",
+						replay.next,
+						event,
+					),
+				}
+			}
+
+			if voter.poll_unpin(&mut cx).is_ready() {
+				let replay = self.replay.lock();
+				if let Some(event) = replay.events.get(replay.next) {
+					panic!("Replay diverged at event {}: expected {:?}, got the voter's exit", replay.next, event);
+				}
+				return;
+			}
+		}
+	}
+
+	/// Whether the trace has been replayed in full.
+	pub fn is_done(&self) -> bool {
+		let replay = self.replay.lock();
+		replay.next == replay.events.len()
+	}
+
+	fn source<O>(&self, id: u64, output: fn(Item<H, N, S, Id>) -> Option<O>) -> Replayed<O, H, N, S, Id> {
+		Replayed { id, replay: self.replay.clone(), output }
+	}
+}
+
+fn replayed_timer<H, N, S, Id>(item: Item<H, N, S, Id>) -> Option<Result<(), RecordedError>> {
+	match item {
+		Item::Timer(fired) => Some(fired.map_err(RecordedError)),
+		_ => None,
+	}
+}
+
+impl<H, N, S, Id> Chain<H, N> for ReplayEnvironment<H, N, S, Id> where
+	H: Clone + Eq + Debug,
+	N: BlockNumberOps + Debug,
+	S: Clone + PartialEq + Debug,
+	Id: Clone + PartialEq + Debug,
+{
+	fn ancestry(&self, base: H, block: H) -> Result<Vec<H>, Error> {
+		replayed!(self, Call::Ancestry { base, block }, Return::Ancestry(ancestry) => ancestry)
+	}
+
+	fn best_chain_containing(&self, base: H) -> Option<(H, N)> {
+		replayed!(self, Call::BestChainContaining { base }, Return::Block(best) => best)
+	}
+
+	fn is_equal_or_descendent_of(&self, base: H, block: H) -> bool {
+		replayed!(self, Call::IsEqualOrDescendentOf { base, block }, Return::Bool(is_descendent) => is_descendent)
+	}
+
+	fn last_finalized(&self) -> Option<(H, N)> {
+		replayed!(self, Call::LastFinalized, Return::Block(finalized) => finalized)
+	}
+}
+
+impl<H, N, S, Id> Environment<H, N> for ReplayEnvironment<H, N, S, Id> where
+	H: Clone + Eq + Debug + Send + 'static,
+	N: BlockNumberOps + Debug + Send + 'static,
+	S: Clone + Eq + Debug + Send + 'static,
+	Id: Clone + Hash + Eq + Debug + Send + 'static,
+{
+	type Timer = Replayed<Result<(), RecordedError>, H, N, S, Id>;
+	type Id = Id;
+	type Signature = S;
+	type In = Replayed<Result<SignedMessage<H, N, S, Id>, RecordedError>, H, N, S, Id>;
+	type Out = ReplayedSink<H, N, S, Id>;
+	type Error = RecordedError;
+	type Clock = ReplayClock<H, N, S, Id>;
+
+	fn clock(&self) -> &Self::Clock {
+		&self.clock
+	}
+
+	fn best_chain(&self, base: H) -> Option<BoxFuture<'static, Result<Option<(H, N)>, RecordedError>>> {
+		let id = replayed!(self, Call::BestChain { base }, Return::OptionalSource(id) => id);
+
+		id.map(|id| Box::pin(self.source(id, |item| match item {
+			Item::BestChain(best) => Some(best.map_err(RecordedError)),
+			_ => None,
+		})) as BoxFuture<_>)
+	}
+
+	fn set_id(&self) -> u64 {
+		replayed!(self, Call::SetId, Return::Number(set_id) => set_id)
+	}
+
+	fn round_data(&self, round: u64) -> RoundData<Id, Self::Timer, Self::In, Self::Out> {
+		replayed!(
+			self,
+			Call::RoundData { round },
+			Return::RoundData { voter_id, prevote_timer, precommit_timer, incoming } => RoundData {
+				voter_id,
+				prevote_timer: self.source(prevote_timer, replayed_timer),
+				precommit_timer: self.source(precommit_timer, replayed_timer),
+				incoming: self.source(incoming, |item| match item {
+					Item::Message(message) => Some(message.map_err(RecordedError)),
+					_ => None,
+				}),
+				outgoing: ReplayedSink { round: Some(round), replay: self.replay.clone() },
+			}
+		)
+	}
+
+	fn round_commit_timer(&self) -> Self::Timer {
+		let id = replayed!(self, Call::RoundCommitTimer, Return::Source(id) => id);
+		self.source(id, replayed_timer)
+	}
+
+	fn round_timer(&self, round: u64, phase: Phase) -> Option<Self::Timer> {
+		let id = replayed!(self, Call::RoundTimer { round, phase }, Return::OptionalSource(id) => id);
+		id.map(|id| self.source(id, replayed_timer))
+	}
+
+	fn primary_proposal(
+		&self,
+		round: u64,
+		estimate: PrimaryPropose<H, N>,
+	) -> Result<Option<PrimaryPropose<H, N>>, RecordedError> {
+		replayed!(
+			self,
+			Call::PrimaryProposal { round, estimate },
+			Return::PrimaryProposal(proposal) => proposal.map_err(RecordedError)
+		)
+	}
+
+	fn restrict_vote(
+		&self,
+		round: u64,
+		phase: Phase,
+		base: (H, N),
+		best_target: (H, N),
+		current_target: (H, N),
+	) -> Option<BoxFuture<'static, Result<Option<(H, N)>, RecordedError>>> {
+		let id = replayed!(
+			self,
+			Call::RestrictVote { round, phase, base, best_target, current_target },
+			Return::OptionalSource(id) => id
+		);
+
+		id.map(|id| Box::pin(self.source(id, |item| match item {
+			Item::RestrictedVote(restricted) => Some(restricted.map_err(RecordedError)),
+			_ => None,
+		})) as BoxFuture<_>)
+	}
+
+	fn proposed(&self, round: u64, propose: PrimaryPropose<H, N>) -> Result<(), RecordedError> {
+		replayed!(self, Call::Proposed { round, propose }, Return::Result(result) => result.map_err(RecordedError))
+	}
+
+	fn prevoted(&self, round: u64, prevote: Prevote<H, N>) -> Result<(), RecordedError> {
+		replayed!(self, Call::Prevoted { round, prevote }, Return::Result(result) => result.map_err(RecordedError))
+	}
+
+	fn precommitted(&self, round: u64, precommit: Precommit<H, N>) -> Result<(), RecordedError> {
+		replayed!(
+			self,
+			Call::Precommitted { round, precommit },
+			Return::Result(result) => result.map_err(RecordedError)
+		)
+	}
+
+	fn global_out_error(&self, error: RecordedError) {
+		replayed!(self, Call::GlobalOutError { error: error.0 }, Return::Unit => ())
+	}
+
+	fn commit_not_sent(&self, round: u64, commit: Commit<H, N, S, Id>) {
+		replayed!(self, Call::CommitNotSent { round, commit }, Return::Unit => ())
+	}
+
+	fn finality_stalled(&self, rounds_without_progress: u64, last_finalized: N) {
+		replayed!(self, Call::FinalityStalled { rounds_without_progress, last_finalized }, Return::Unit => ())
+	}
+
+	fn finality_resumed(&self, rounds_without_progress: u64, finalized: N) {
+		replayed!(self, Call::FinalityResumed { rounds_without_progress, finalized }, Return::Unit => ())
+	}
+
+	fn completed(
+		&self,
+		round: u64,
+		state: RoundState<H, N>,
+		base: (H, N),
+		votes: &HistoricalVotes<H, N, S, Id>,
+	) -> Result<(), RecordedError> {
+		replayed!(
+			self,
+			Call::Completed { round, state, base, votes: votes.clone() },
+			Return::Result(result) => result.map_err(RecordedError)
+		)
+	}
+
+	fn block_available(&self, hash: H) -> bool {
+		replayed!(self, Call::BlockAvailable { hash }, Return::Bool(available) => available)
+	}
+
+	fn request_catch_up(&self, round: u64) {
+		replayed!(self, Call::RequestCatchUp { round }, Return::Unit => ())
+	}
+
+	fn finalize_block(&self, hash: H, number: N, round: u64, commit: Commit<H, N, S, Id>) -> Result<(), RecordedError> {
+		replayed!(
+			self,
+			Call::FinalizeBlock { hash, number, round, commit },
+			Return::Result(result) => result.map_err(RecordedError)
+		)
+	}
+
+	fn prevote_equivocation(&self, round: u64, equivocation: Equivocation<Id, Prevote<H, N>, S>) {
+		replayed!(self, Call::PrevoteEquivocation { round, equivocation }, Return::Unit => ())
+	}
+
+	fn precommit_equivocation(&self, round: u64, equivocation: Equivocation<Id, Precommit<H, N>, S>) {
+		replayed!(self, Call::PrecommitEquivocation { round, equivocation }, Return::Unit => ())
+	}
+
+	fn best_chain_issue(&self, round: u64, issue: BestChainIssue<H, N>) {
+		replayed!(self, Call::BestChainIssue { round, issue }, Return::Unit => ())
+	}
+
+	fn too_many_equivocations(&self, event: TooManyEquivocations<Id>) -> Result<(), RecordedError> {
+		replayed!(
+			self,
+			Call::TooManyEquivocations { event },
+			Return::Result(result) => result.map_err(RecordedError)
+		)
+	}
+
+	fn prior_round_state(&self, round: u64, _states: RoundStateStream<H, N>) {
+		replayed!(self, Call::PriorRoundState { round }, Return::Unit => ())
+	}
+
+	fn sign_commit(
+		&self,
+		round: u64,
+		commit: &Commit<H, N, S, Id>,
+	) -> Option<BoxFuture<'static, Result<SignedCommit<H, N, S, Id>, RecordedError>>> {
+		let id = replayed!(
+			self,
+			Call::SignCommit { round, commit: commit.clone() },
+			Return::OptionalSource(id) => id
+		);
+
+		id.map(|id| Box::pin(self.source(id, |item| match item {
+			Item::SignedCommit(signed) => Some(signed.map_err(RecordedError)),
+			_ => None,
+		})) as BoxFuture<_>)
+	}
+
+	fn verify_commit_signature(&self, round: u64, commit: &SignedCommit<H, N, S, Id>) -> bool {
+		replayed!(
+			self,
+			Call::VerifyCommitSignature { round, commit: commit.clone() },
+			Return::Bool(valid) => valid
+		)
+	}
+}
+
+/// A timer, future or stream handed out by a `ReplayEnvironment`, which
+/// yields the recorded items once they're the next recorded event.
+pub struct Replayed<O, H, N, S, Id> {
+	id: u64,
+	replay: SharedReplay<H, N, S, Id>,
+	output: fn(Item<H, N, S, Id>) -> Option<O>,
+}
+
+impl<O, H, N, S, Id> Replayed<O, H, N, S, Id> where
+	H: Clone + PartialEq + Debug,
+	N: Clone + PartialEq + Debug,
+	S: Clone + PartialEq + Debug,
+	Id: Clone + PartialEq + Debug,
+{
+	fn poll_item(&self, cx: &mut Context) -> Poll<Item<H, N, S, Id>> {
+		match self.replay.lock().ready(self.id, cx.waker()) {
+			Some(item) => Poll::Ready(item),
+			None => Poll::Pending,
+		}
+	}
+
+	fn output(&self, item: Item<H, N, S, Id>) -> O {
+		match (self.output)(item.clone()) {
+			Some(output) => output,
+			None => panic!("Recorded item {:?} doesn't fit source {}", item, self.id),
+		}
+	}
+}
+
+impl<O, H, N, S, Id> Future for Replayed<O, H, N, S, Id> where
+	H: Clone + PartialEq + Debug,
+	N: Clone + PartialEq + Debug,
+	S: Clone + PartialEq + Debug,
+	Id: Clone + PartialEq + Debug,
+{
+	type Output = O;
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<O> {
+		let item = futures::ready!(self.poll_item(cx));
+		Poll::Ready(self.output(item))
+	}
+}
+
+impl<O, H, N, S, Id> Stream for Replayed<O, H, N, S, Id> where
+	H: Clone + PartialEq + Debug,
+	N: Clone + PartialEq + Debug,
+	S: Clone + PartialEq + Debug,
+	Id: Clone + PartialEq + Debug,
+{
+	type Item = O;
+
+	fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<O>> {
+		match futures::ready!(self.poll_item(cx)) {
+			Item::End => Poll::Ready(None),
+			item => Poll::Ready(Some(self.output(item))),
+		}
+	}
+}
+
+/// The stream of recorded global messages. The outcomes passed to their
+/// callbacks are checked against the recorded ones.
+pub struct ReplayedGlobalIn<H, N, S, Id> {
+	id: u64,
+	replay: SharedReplay<H, N, S, Id>,
+}
+
+// a callback checking the outcome passed to it against the recorded one.
+fn replayed_callback<O, H, N, S, Id>(
+	replay: &SharedReplay<H, N, S, Id>,
+	call: fn(Outcome) -> Call<H, N, S, Id>,
+) -> Callback<O> where
+	for<'a> Outcome: From<&'a O>,
+	H: Clone + PartialEq + Debug + Send + 'static,
+	N: Clone + PartialEq + Debug + Send + 'static,
+	S: Clone + PartialEq + Debug + Send + 'static,
+	Id: Clone + PartialEq + Debug + Send + 'static,
+{
+	let replay = replay.clone();
+	Callback::Work(Box::new(move |outcome| {
+		replay.lock().call(call(Outcome::from(&outcome)));
+	}))
+}
+
+fn replay_global<H, N, S, Id>(
+	message: GlobalMessage<H, N, S, Id>,
+	replay: &SharedReplay<H, N, S, Id>,
+) -> CommunicationIn<H, N, S, Id> where
+	H: Clone + PartialEq + Debug + Send + 'static,
+	N: Clone + PartialEq + Debug + Send + 'static,
+	S: Clone + PartialEq + Debug + Send + 'static,
+	Id: Clone + PartialEq + Debug + Send + 'static,
+{
+	match message {
+		GlobalMessage::Commit(round, commit) =>
+			CommunicationIn::Commit(round, commit, replayed_callback(replay, Call::CommitOutcome)),
+		GlobalMessage::SignedCommit(round, signed) =>
+			CommunicationIn::SignedCommit(round, signed, replayed_callback(replay, Call::CommitOutcome)),
+		GlobalMessage::CatchUp(catch_up) =>
+			CommunicationIn::CatchUp(catch_up, replayed_callback(replay, Call::CatchUpOutcome)),
+		GlobalMessage::Batch(messages) => CommunicationIn::Batch(
+			messages.into_iter().map(|message| replay_global(message, replay)).collect(),
+		),
+	}
+}
+
+impl<H, N, S, Id> Stream for ReplayedGlobalIn<H, N, S, Id> where
+	H: Clone + PartialEq + Debug + Send + 'static,
+	N: Clone + PartialEq + Debug + Send + 'static,
+	S: Clone + PartialEq + Debug + Send + 'static,
+	Id: Clone + PartialEq + Debug + Send + 'static,
+{
+	type Item = Result<CommunicationIn<H, N, S, Id>, RecordedError>;
+
+	fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+		let item = match self.replay.lock().ready(self.id, cx.waker()) {
+			Some(item) => item,
+			None => return Poll::Pending,
+		};
+
+		match item {
+			Item::End => Poll::Ready(None),
+			Item::Global(Err(e)) => Poll::Ready(Some(Err(RecordedError(e)))),
+			Item::Global(Ok(message)) => Poll::Ready(Some(Ok(replay_global(message, &self.replay)))),
+			item => panic!("Recorded item {:?} doesn't fit the global messages", item),
+		}
+	}
+}
+
+/// A sink checking the messages sent against the recorded ones, either the
+/// outgoing messages of a round or the global ones.
+pub struct ReplayedSink<H, N, S, Id> {
+	round: Option<u64>,
+	replay: SharedReplay<H, N, S, Id>,
+}
+
+impl<H, N, S, Id> ReplayedSink<H, N, S, Id> where
+	H: Clone + PartialEq + Debug,
+	N: Clone + PartialEq + Debug,
+	S: Clone + PartialEq + Debug,
+	Id: Clone + PartialEq + Debug,
+{
+	fn sent(&self, call: Call<H, N, S, Id>) -> Result<(), RecordedError> {
+		replayed!(self, call, Return::Result(result) => result.map_err(RecordedError))
+	}
+}
+
+impl<H, N, S, Id> Sink<Message<H, N>> for ReplayedSink<H, N, S, Id> where
+	H: Clone + PartialEq + Debug,
+	N: Clone + PartialEq + Debug,
+	S: Clone + PartialEq + Debug,
+	Id: Clone + PartialEq + Debug,
+{
+	type Error = RecordedError;
+
+	fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), RecordedError>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn start_send(self: Pin<&mut Self>, message: Message<H, N>) -> Result<(), RecordedError> {
+		let round = self.round.expect("round sinks are only handed out by `round_data`; qed");
+		self.sent(Call::Send { round, message })
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), RecordedError>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), RecordedError>> {
+		Poll::Ready(Ok(()))
+	}
+}
+
+impl<H, N, S, Id> Sink<CommunicationOut<H, N, S, Id>> for ReplayedSink<H, N, S, Id> where
+	H: Clone + PartialEq + Debug,
+	N: Clone + PartialEq + Debug,
+	S: Clone + PartialEq + Debug,
+	Id: Clone + PartialEq + Debug,
+{
+	type Error = RecordedError;
+
+	fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), RecordedError>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn start_send(self: Pin<&mut Self>, message: CommunicationOut<H, N, S, Id>) -> Result<(), RecordedError> {
+		self.sent(Call::SendGlobal(message))
+	}
+
+	fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), RecordedError>> {
+		Poll::Ready(Ok(()))
+	}
+
+	fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), RecordedError>> {
+		Poll::Ready(Ok(()))
+	}
+}
+
+/// The clock of a `ReplayEnvironment`. Time stands still, and timers fire
+/// when they did in the recorded run.
+pub struct ReplayClock<H, N, S, Id> {
+	replay: SharedReplay<H, N, S, Id>,
+}
+
+impl<H, N, S, Id> Clock for ReplayClock<H, N, S, Id> where
+	H: Clone + PartialEq + Debug,
+	N: Clone + PartialEq + Debug,
+	S: Clone + PartialEq + Debug,
+	Id: Clone + PartialEq + Debug,
+{
+	type Instant = Duration;
+	type Timer = Replayed<(), H, N, S, Id>;
+
+	fn now(&self) -> Duration {
+		Duration::default()
+	}
+
+	fn timer_at(&self, _at: Duration) -> Self::Timer {
+		let id = replayed!(self, Call::ClockTimer, Return::Source(id) => id);
+		Replayed {
+			id,
+			replay: self.replay.clone(),
+			output: |item| match item {
+				Item::ClockTimer => Some(()),
+				_ => None,
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::executor::{block_on, ThreadPool};
+	use crate::testing::{chain::GENESIS_HASH, environment::{self, Environment as TestEnvironment, Id}};
+	use crate::voter::Voter;
+	use crate::voter_set::VoterSet;
+
+	#[test]
+	fn replays_a_recorded_voter() {
+		let voters = VoterSet::new((0..3).map(|i| (Id(i), 1))).unwrap();
+		let (network, routing_task) = environment::make_network();
+		let threads_pool = ThreadPool::new().unwrap();
+
+		let make_env = |i| {
+			let env = TestEnvironment::new(network.clone(), Id(i));
+			env.with_chain(|chain| chain.push_blocks(GENESIS_HASH, &["A", "B", "C", "D", "E"]));
+			env
+		};
+
+		for i in 1..3 {
+			let voter = Voter::new(
+				Arc::new(make_env(i)),
+				voters.clone(),
+				network.make_global_comms(),
+				0,
+				RoundState::genesis((GENESIS_HASH, 1)),
+				(GENESIS_HASH, 1),
+			);
+			threads_pool.spawn_ok(voter.map(|v| v.expect("Error voting")));
+		}
+
+		// record the run of the third voter until it finalizes the best block.
+		let env = Arc::new(RecordingEnvironment::new(make_env(0)));
+		let (global_in, global_out) = network.make_global_comms();
+		let global_comms = (env.global_in(global_in), env.global_out(global_out));
+		let finalized = env.inner().finalized_stream();
+		let mut recorded = env.record(Voter::new(
+			env.clone(),
+			voters.clone(),
+			global_comms,
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			(GENESIS_HASH, 1),
+		));
+
+		threads_pool.spawn_ok(routing_task);
+
+		let finalized = finalized
+			.take_while(|&(_, n, _)| future::ready(n < 6))
+			.for_each(|_| future::ready(()));
+		block_on(future::select(&mut recorded, Box::pin(finalized)));
+
+		// replay it with a fresh voter.
+		let trace = env.trace();
+		assert!(trace.iter().any(|event| matches!(event, Event::Call(Call::FinalizeBlock { number: 6, .. }, _))));
+
+		let replay = Arc::new(ReplayEnvironment::new(trace));
+		let global_comms = (replay.global_in(), replay.global_out());
+		let mut voter = Voter::new(
+			replay.clone(),
+			voters,
+			global_comms,
+			0,
+			RoundState::genesis((GENESIS_HASH, 1)),
+			(GENESIS_HASH, 1),
+		);
+
+		replay.run(&mut voter);
+
+		let recorded = recorded.inner();
+		assert!(replay.is_done());
+		assert_eq!(voter.last_finalized_number, recorded.last_finalized_number);
+		assert_eq!(voter.best_round.round_number(), recorded.best_round.round_number());
+		assert_eq!(voter.best_round.round_state(), recorded.best_round.round_state());
+	}
+
+	#[test]
+	fn global_out_errors_are_recorded_and_replayed() {
+		let (network, _routing_task) = environment::make_network();
+		let env = RecordingEnvironment::new(TestEnvironment::new(network, Id(0)));
+
+		env.global_out_error(crate::Error::NotDescendent);
+		assert_eq!(env.inner().take_global_out_errors(), vec![crate::Error::NotDescendent]);
+
+		let trace = env.trace();
+		let error = crate::Error::NotDescendent.to_string();
+		assert_eq!(trace, vec![Event::Call(Call::GlobalOutError { error: error.clone() }, Return::Unit)]);
+
+		let replay = ReplayEnvironment::new(trace);
+		replay.global_out_error(RecordedError(error));
+		assert!(replay.is_done());
+	}
+
+	#[test]
+	fn stall_reports_are_recorded_and_replayed() {
+		use crate::testing::environment::StallReport;
+
+		let (network, _routing_task) = environment::make_network();
+		let env = RecordingEnvironment::new(TestEnvironment::new(network, Id(0)));
+
+		env.finality_stalled(3, 1);
+		env.finality_resumed(4, 2);
+		assert_eq!(env.inner().take_stall_reports(), vec![StallReport::Stalled(3, 1), StallReport::Resumed(4, 2)]);
+
+		let trace = env.trace();
+		assert_eq!(trace, vec![
+			Event::Call(Call::FinalityStalled { rounds_without_progress: 3, last_finalized: 1 }, Return::Unit),
+			Event::Call(Call::FinalityResumed { rounds_without_progress: 4, finalized: 2 }, Return::Unit),
+		]);
+
+		let replay = ReplayEnvironment::new(trace);
+		replay.finality_stalled(3, 1);
+		replay.finality_resumed(4, 2);
+		assert!(replay.is_done());
+	}
+
+	#[test]
+	fn commits_not_sent_are_recorded_and_replayed() {
+		let (network, _routing_task) = environment::make_network();
+		let env = RecordingEnvironment::new(TestEnvironment::new(network, Id(0)));
+
+		let commit = Commit { target_hash: "A", target_number: 2, precommits: Vec::new(), set_id: 0 };
+		env.commit_not_sent(3, commit.clone());
+		assert_eq!(env.inner().take_commits_not_sent().len(), 1);
+
+		let trace = env.trace();
+		assert_eq!(trace, vec![Event::Call(Call::CommitNotSent { round: 3, commit: commit.clone() }, Return::Unit)]);
+
+		let replay = ReplayEnvironment::new(trace);
+		replay.commit_not_sent(3, commit);
+		assert!(replay.is_done());
+	}
+
+	#[test]
+	#[should_panic(expected = "first difference in line 3")]
+	fn divergence_is_pinpointed() {
+		let replay = ReplayEnvironment::<&'static str, u32, u32, u32>::new(vec![
+			Event::Call(Call::Ancestry { base: "A", block: "C" }, Return::Ancestry(Ok(vec!["B"]))),
+		]);
+
+		let _ = replay.ancestry("A", "D");
+	}
+}
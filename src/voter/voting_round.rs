@@ -18,36 +18,74 @@
 use futures::ready;
 use futures::prelude::*;
 use futures::channel::mpsc::UnboundedSender;
+use futures::future::BoxFuture;
 #[cfg(feature = "std")]
 use log::{trace, warn, debug};
 
+use std::collections::HashMap;
 use std::hash::Hash;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
-use crate::round::{Round, State as RoundState};
+use crate::round::{CompletabilityBlockers, Round, State as RoundState};
 use crate::{
-	Commit, Message, Prevote, Precommit, PrimaryPropose, SignedMessage,
-	SignedPrecommit, BlockNumberOps, validate_commit, ImportResult,
+	CatchUp, Commit, Message, Prevote, Precommit, PrimaryPropose, SignedMessage,
+	SignedPrevote, SignedPrecommit, BlockNumberOps, validate_round_commit, ImportResult,
 	HistoricalVotes,
 };
 use crate::voter_set::VoterSet;
-use super::{Environment, Buffered, FinalizedNotification};
+use super::{
+	BestChainIssue, Clock, Environment, Buffered, FinalizedNotification, Metrics, Phase, SignedMessageFor,
+	TooManyEquivocations, VoteImportOutcome,
+};
+
+// how many votes a round keeps pending until their target is available.
+const MAX_PENDING_VOTES: usize = 1024;
 
 /// The state of a voting round.
 pub(super) enum State<T> {
 	Start(T, T),
 	Proposed(T, T),
+	Prevoting(T), // ready to prevote, but no prevote target was found yet.
 	Prevoted(T),
 	Precommitted,
 }
 
+// a lookup of the best chain containing a block, for prevoting.
+enum BestChainLookup<H, N, F> {
+	Pending((H, N), F),
+	Done((H, N), Option<(H, N)>),
+}
+
+type BestChainLookupFor<H, N, E> = BestChainLookup<
+	H,
+	N,
+	BoxFuture<'static, Result<Option<(H, N)>, <E as Environment<H, N>>::Error>>,
+>;
+
+// a pending `Environment::restrict_vote` call for the target of a vote we're
+// about to cast.
+struct VoteRestriction<H, N, F> {
+	phase: Phase,
+	base: (H, N),
+	target: (H, N),
+	restricted: F,
+}
+
+type VoteRestrictionFor<H, N, E> = VoteRestriction<
+	H,
+	N,
+	BoxFuture<'static, Result<Option<(H, N)>, <E as Environment<H, N>>::Error>>,
+>;
+
 impl<T> std::fmt::Debug for State<T> {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
 		match self {
 			State::Start(..) => write!(f, "Start"),
 			State::Proposed(..) => write!(f, "Proposed"),
+			State::Prevoting(_) => write!(f, "Prevoting"),
 			State::Prevoted(_) => write!(f, "Prevoted"),
 			State::Precommitted => write!(f, "Precommitted"),
 		}
@@ -62,14 +100,30 @@ pub(super) struct VotingRound<H, N, E: Environment<H, N>> where
 	env: Arc<E>,
 	voting: Voting,
 	votes: Round<E::Id, H, N, E::Signature>,
+	parent_round: Option<u64>, // the round this one succeeded, if known.
 	incoming: E::In,
 	outgoing: Buffered<E::Out, Message<H, N>>,
 	state: Option<State<E::Timer>>, // state machine driving votes.
+	paced: Option<Vec<Phase>>, // phases signalled so far, if a pacemaker replaces the timers.
+	paused: bool, // whether casting votes is paused, even if voting.
 	bridged_round_state: Option<crate::bridge_state::PriorView<H, N>>, // updates to later round
 	last_round_state: Option<crate::bridge_state::LatterView<H, N>>, // updates from prior round
 	primary_block: Option<(H, N)>, // a block posted by primary as a hint.
 	finalized_sender: UnboundedSender<FinalizedNotification<H, N, E>>,
 	best_finalized: Option<Commit<H, N, E::Signature, E::Id>>,
+	too_many_equivocations: bool, // whether equivocations exceeded the fault tolerance.
+	started: <E::Clock as Clock>::Instant, // when the round was started.
+	best_chain: Option<BestChainLookupFor<H, N, E>>, // lookup of the prevote target.
+	restriction: Option<VoteRestrictionFor<H, N, E>>, // restriction of the next vote's target.
+	// votes whose target isn't available yet, by target. at most one per
+	// voter and phase.
+	pending_votes: HashMap<H, Vec<SignedMessageFor<H, N, E>>>,
+	n_pending_votes: usize,
+	// the state before votes were imported outside of polling, to notify of
+	// changes on the next poll.
+	unnotified_state: Option<RoundState<H, N>>,
+	slow_logged: bool, // whether the round was logged as slow to complete.
+	metrics: Arc<dyn Metrics>,
 }
 
 /// Whether we should vote in the current round (i.e. push votes to the sink.)
@@ -86,18 +140,12 @@ enum Voting {
 impl Voting {
 	/// Whether the voter should cast round votes (prevotes and precommits.)
 	fn is_active(&self) -> bool {
-		match self {
-			Voting::Yes | Voting::Primary => true,
-			_ => false,
-		}
+		matches!(self, Voting::Yes | Voting::Primary)
 	}
 
 	/// Whether the voter is the primary proposer.
 	fn is_primary(&self) -> bool {
-		match self {
-			Voting::Primary => true,
-			_ => false,
-		}
+		matches!(self, Voting::Primary)
 	}
 }
 
@@ -105,7 +153,8 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 	H: Hash + Clone + Eq + Ord + ::std::fmt::Debug,
 	N: Copy + BlockNumberOps + ::std::fmt::Debug,
 {
-	/// Create a new voting round.
+	/// Create a new voting round. If it's started with the state of a prior
+	/// round, it succeeds that round.
 	pub (super) fn new(
 		round_number: u64,
 		voters: VoterSet<E::Id>,
@@ -115,36 +164,63 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 		env: Arc<E>,
 	) -> VotingRound<H, N, E> {
 		let round_data = env.round_data(round_number);
+		let prevote_timer = env.round_timer(round_number, Phase::Prevote)
+			.unwrap_or(round_data.prevote_timer);
+		let precommit_timer = env.round_timer(round_number, Phase::Precommit)
+			.unwrap_or(round_data.precommit_timer);
+
+		if let Some(ref last_round_state) = last_round_state {
+			env.prior_round_state(round_number, last_round_state.watch());
+		}
+
 		let round_params = crate::round::RoundParams {
 			voters,
 			base,
 			round_number,
+			set_id: env.set_id(),
 		};
 
 		let votes = Round::new(round_params);
 
-		let voting = if round_data.voter_id.as_ref() == Some(&votes.primary_voter().0) {
+		let voting = if round_data.voter_id
+			.as_ref()
+			.is_some_and(|id| votes.voters().is_primary(round_number, id))
+		{
 			Voting::Primary
 		} else if round_data.voter_id
 			.as_ref()
-			.map_or(false, |id| votes.voters().contains_key(id))
+			.is_some_and(|id| votes.voters().contains(id))
 		{
 			Voting::Yes
 		} else {
 			Voting::No
 		};
 
+		let parent_round = last_round_state.as_ref().and_then(|_| round_number.checked_sub(1));
+
 		VotingRound {
 			votes,
 			voting,
+			parent_round,
 			incoming: round_data.incoming,
 			outgoing: Buffered::new(round_data.outgoing),
 			state: Some(
-				State::Start(round_data.prevote_timer, round_data.precommit_timer)
+				State::Start(prevote_timer, precommit_timer)
 			),
+			paced: None,
+			paused: false,
 			bridged_round_state: None,
 			primary_block: None,
 			best_finalized: None,
+			too_many_equivocations: false,
+			started: env.clock().now(),
+			best_chain: None,
+			restriction: None,
+			pending_votes: HashMap::new(),
+			n_pending_votes: 0,
+			unnotified_state: None,
+			slow_logged: false,
+			metrics: Arc::new(()),
 			env,
 			last_round_state,
 			finalized_sender,
@@ -160,27 +236,43 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 	) -> VotingRound<H, N, E> {
 
 		let round_data = env.round_data(votes.number());
+		let started = env.clock().now();
 
 		VotingRound {
 			votes,
 			voting: Voting::No,
+			parent_round: None,
 			incoming: round_data.incoming,
 			outgoing: Buffered::new(round_data.outgoing),
 			state: None,
+			paced: None,
+			paused: false,
 			bridged_round_state: None,
 			primary_block: None,
 			env,
 			last_round_state: None,
 			finalized_sender,
 			best_finalized: None,
+			too_many_equivocations: false,
+			started,
+			best_chain: None,
+			restriction: None,
+			pending_votes: HashMap::new(),
+			n_pending_votes: 0,
+			unnotified_state: None,
+			slow_logged: false,
+			metrics: Arc::new(()),
 		}
 	}
 
 	/// Poll the round. When the round is completable and messages have been flushed, it will return `Poll::Ready` but
 	/// can continue to be polled.
 	pub(super) fn poll(&mut self, cx: &mut Context) -> Poll<Result<(), E::Error>> {
+		#[cfg(feature = "tracing")]
+		let _span = tracing::trace_span!(target: "afg", "round", round = self.votes.number()).entered();
+
 		trace!(target: "afg", "Polling round {}, state = {:?}, step = {:?}", self.votes.number(), self.votes.state(), self.state);
-		let pre_state = self.votes.state();
+		let pre_state = self.unnotified_state.take().unwrap_or_else(|| self.votes.state());
 		self.process_incoming(cx)?;
 
 		// we only cast votes when we have access to the previous round state.
@@ -188,7 +280,7 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 		// check whether the voter is lagging behind the current round.
 		let last_round_state = self.last_round_state.as_ref().map(|s| s.get(cx).clone());
 		if let Some(ref last_round_state) = last_round_state {
-			self.primary_propose(last_round_state)?;
+			self.primary_propose(cx, last_round_state)?;
 			self.prevote(cx, last_round_state)?;
 			self.precommit(cx, last_round_state)?;
 		}
@@ -201,7 +293,7 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 		self.notify(pre_state, post_state);
 
 		// early exit if the current round is not completable
-		if !self.votes.completable() {
+		if !self.completable() {
 			return Poll::Pending;
 		}
 
@@ -216,8 +308,7 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 				let finalized_in_last_round = last_round_estimate <= last_round_finalized;
 
 				// or it must be finalized in the current round
-				let finalized_in_current_round = self.finalized().map_or(
-					false,
+				let finalized_in_current_round = self.finalized().is_some_and(
 					|(_, current_round_finalized)| last_round_estimate <= *current_round_finalized,
 				);
 
@@ -246,11 +337,125 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 		self.state.as_ref()
 	}
 
+	/// Replace the sink for outgoing messages of this round. Messages already
+	/// sent to the old sink are flushed before any further message goes out on
+	/// the new one, so none are lost or duplicated.
+	pub(super) fn set_outgoing_sink(&mut self, outgoing: E::Out) {
+		self.outgoing.set_inner(outgoing);
+	}
+
+	/// Report events of this round to the given metrics.
+	pub(super) fn set_metrics(&mut self, metrics: Arc<dyn Metrics>) {
+		self.metrics = metrics;
+	}
+
+	/// Start the phases of this round when a pacemaker signals them, instead
+	/// of when their timers fire.
+	pub(super) fn set_paced(&mut self) {
+		self.paced.get_or_insert_with(Vec::new);
+	}
+
+	/// Note that the pacemaker signalled the given phase. Does nothing unless
+	/// the round is paced.
+	pub(super) fn pace(&mut self, phase: Phase) {
+		if let Some(ref mut paced) = self.paced {
+			if !paced.contains(&phase) {
+				paced.push(phase);
+			}
+		}
+	}
+
+	/// Whether the pacemaker signalled the given phase, or `None` if the round
+	/// isn't paced.
+	pub(super) fn paced(&self, phase: Phase) -> Option<bool> {
+		self.paced.as_ref().map(|paced| paced.contains(&phase))
+	}
+
+	/// Set whether votes for exactly the base of the round are left out of it.
+	pub(super) fn set_strict_base(&mut self, strict_base: bool) {
+		self.votes.set_strict_base(strict_base);
+	}
+
+	/// Set the maximum length of the ancestry fetched for a vote.
+	pub(super) fn set_max_ancestry_len(&mut self, max_ancestry_len: usize) {
+		self.votes.set_max_ancestry_len(max_ancestry_len);
+	}
+
+	/// Stop or resume casting votes and proposing. While paused, the round
+	/// progresses like one we don't vote in.
+	pub(super) fn set_paused(&mut self, paused: bool) {
+		self.paused = paused;
+	}
+
+	// whether we cast prevotes and precommits.
+	fn casting(&self) -> bool {
+		self.voting.is_active() && !self.paused
+	}
+
+	// whether we propose, as the primary.
+	fn proposing(&self) -> bool {
+		self.voting.is_primary() && !self.paused
+	}
+
+	// whether the outgoing sink accepts messages, i.e. all messages pushed
+	// before went out and it's ready for another one. votes are only cast
+	// then, so that they aren't buffered while the network is congested.
+	fn outgoing_ready(&mut self, cx: &mut Context) -> Result<bool, E::Error> {
+		Ok(self.outgoing.poll_ready(cx)?.is_ready())
+	}
+
+	// whether the given phase may start, i.e. the pacemaker signalled it or,
+	// if the round isn't paced, its timer fired.
+	fn phase_reached(&self, phase: Phase, timer: &mut E::Timer, cx: &mut Context) -> Result<bool, E::Error> {
+		if let Some(reached) = self.paced(phase) {
+			return Ok(reached);
+		}
+
+		match Future::poll(Pin::new(timer), cx) {
+			Poll::Ready(Err(e)) => Err(e),
+			Poll::Ready(Ok(())) => Ok(true),
+			Poll::Pending => Ok(false),
+		}
+	}
+
+	/// Get the environment.
+	pub(super) fn env(&self) -> &E {
+		&self.env
+	}
+
+	/// Time elapsed since the round was started.
+	pub(super) fn elapsed(&self) -> Duration {
+		self.env.clock().now() - self.started
+	}
+
+	/// Report why the round isn't completable yet, or `None` if it is.
+	pub(super) fn completability_blockers(&self) -> Option<CompletabilityBlockers<E::Id, H, N>> {
+		self.votes.completability_blockers()
+	}
+
+	/// Log why the round isn't completable yet once it's been running for
+	/// twice the given expected duration, at most once per round.
+	pub(super) fn log_if_slow(&mut self, expected: Duration) {
+		if self.slow_logged || self.elapsed() <= expected * 2 { return }
+
+		if let Some(blockers) = self.completability_blockers() {
+			debug!(target: "afg", "Round {} isn't completable after {:?}: {:?}",
+				self.votes.number(), self.elapsed(), blockers);
+			self.slow_logged = true;
+		}
+	}
+
 	/// Get the round number.
 	pub(super) fn round_number(&self) -> u64 {
 		self.votes.number()
 	}
 
+	/// Get the number of the round this one succeeded. This is `None` for rounds
+	/// completed from a catch-up, which don't follow one of ours.
+	pub(super) fn parent_round(&self) -> Option<u64> {
+		self.parent_round
+	}
+
 	/// Get the round state.
 	pub(super) fn round_state(&self) -> RoundState<H, N> {
 		self.votes.state()
@@ -271,19 +476,52 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 		self.votes.finalized()
 	}
 
+	/// Get the precommits which finalized the best block in this round, see
+	/// `Round::finalization_evidence`.
+	#[allow(clippy::type_complexity)]
+	pub(super) fn finalization_evidence(&self) -> Option<Vec<SignedPrecommit<H, N, E::Signature, E::Id>>> {
+		self.votes.finalization_evidence()
+	}
+
+	/// Whether nothing can change in this round anymore, i.e. it's completable
+	/// and its estimate is finalized. It can then be left before all our votes
+	/// are cast.
+	pub(super) fn settled(&self) -> bool {
+		self.completable() && match (self.votes.estimate(), self.votes.finalized()) {
+			(Some(estimate), Some(finalized)) => estimate == finalized,
+			_ => false,
+		}
+	}
+
+	/// Whether a supermajority precommitted for exactly the finalized block, so
+	/// that waiting for further precommits can't improve the round's commit.
+	pub(super) fn finalized_by_threshold(&self) -> bool {
+		self.votes.finalized().is_some_and(|(hash, number)| {
+			self.votes.precommit_weight_on(hash, *number) >= self.votes.threshold()
+		})
+	}
+
 	/// Check a commit. If it's valid, import all the votes into the round as well.
 	/// Returns the finalized base if it checks out.
 	pub(super) fn check_and_import_from_commit(
 		&mut self,
 		commit: &Commit<H, N, E::Signature, E::Id>
 	) -> Result<Option<(H, N)>, E::Error> {
-		let base = validate_commit(commit, self.voters(), &*self.env)?.ghost;
+		if commit.set_id != self.votes.set_id() {
+			debug!(target: "afg", "Ignoring commit for round {} of set {}, expected set {}",
+				self.round_number(), commit.set_id, self.votes.set_id());
+			return Ok(None);
+		}
+
+		let set_id = self.votes.set_id();
+		let base = validate_round_commit(commit, set_id, self.voters(), &*self.env, self.votes.base())?.ghost;
 		if base.is_none() { return Ok(None) }
 
 		for SignedPrecommit { precommit, signature, id } in commit.precommits.iter().cloned() {
 			let import_result = self.votes.import_precommit(&*self.env, precommit, id, signature)?;
 			if let ImportResult { equivocation: Some(e), .. } = import_result {
 				self.env.precommit_equivocation(self.round_number(), e);
+				self.check_equivocation_weight()?;
 			}
 		}
 
@@ -313,6 +551,28 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 		self.best_finalized.as_ref()
 	}
 
+	/// Assemble a catch-up message with all votes imported in this round.
+	pub(super) fn catch_up(&self) -> CatchUp<H, N, E::Signature, E::Id> {
+		let prevotes = self.votes.prevotes().into_iter()
+			.map(|(id, prevote, signature)| SignedPrevote { prevote, signature, id })
+			.collect();
+
+		let precommits = self.votes.precommits().into_iter()
+			.map(|(id, precommit, signature)| SignedPrecommit { precommit, signature, id })
+			.collect();
+
+		let (base_hash, base_number) = self.votes.base();
+
+		CatchUp {
+			round_number: self.votes.number(),
+			prevotes,
+			precommits,
+			base_hash,
+			base_number,
+			set_id: self.votes.set_id(),
+		}
+	}
+
 	/// Return all votes for the round (prevotes and precommits), sorted by
 	/// imported order and indicating the indices where we voted. At most two
 	/// prevotes and two precommits per voter are present, further equivocations
@@ -324,62 +584,255 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 	fn process_incoming(&mut self, cx: &mut Context) -> Result<(), E::Error> {
 		while let Poll::Ready(Some(incoming)) = Stream::poll_next(Pin::new(&mut self.incoming), cx) {
 			trace!(target: "afg", "Got incoming message");
-			let SignedMessage { message, signature, id } = incoming?;
-			if !self.env.is_equal_or_descendent_of(self.votes.base().0, message.target().0.clone()) {
-				trace!(target: "afg", "Ignoring message targeting {:?} lower than round base {:?}",
-					   message.target(),
-					   self.votes.base(),
-				);
-				continue;
+			self.handle_message(incoming?)?;
+		}
+
+		Ok(())
+	}
+
+	/// Import a message for this round which wasn't received on its incoming
+	/// stream. The round must be polled afterwards to act on it.
+	///
+	/// Votes for blocks which aren't `Environment::block_available` yet are
+	/// kept pending until `block_imported` is called for their target.
+	pub(super) fn handle_message(
+		&mut self,
+		message: SignedMessage<H, N, E::Signature, E::Id>,
+	) -> Result<(), E::Error> {
+		// votes are received repeatedly through gossip, so duplicates are
+		// dropped before any work on the chain.
+		let duplicate = match message.message {
+			Message::Prevote(ref prevote)
+				if self.votes.is_duplicate_prevote(&message.id, prevote, &message.signature) => Some(Phase::Prevote),
+			Message::Precommit(ref precommit)
+				if self.votes.is_duplicate_precommit(&message.id, precommit, &message.signature) => Some(Phase::Precommit),
+			_ => None,
+		};
+		if let Some(phase) = duplicate {
+			self.metrics.on_vote_imported(phase, VoteImportOutcome::Duplicated);
+			return Ok(());
+		}
+
+		if let Some(phase) = vote_phase(&message.message) {
+			if !self.env.block_available(message.message.target().0.clone()) {
+				self.defer_vote(phase, message);
+				return Ok(());
 			}
+		}
 
+		let SignedMessage { message, signature, id } = message;
+		if !self.env.is_equal_or_descendent_of(self.votes.base().0, message.target().0.clone()) {
+			trace!(target: "afg", "Ignoring message targeting {:?} lower than round base {:?}",
+				   message.target(),
+				   self.votes.base(),
+			);
 			match message {
-				Message::Prevote(prevote) => {
-					let import_result = self.votes.import_prevote(&*self.env, prevote, id, signature)?;
-					if let ImportResult { equivocation: Some(e), .. } = import_result {
-						self.env.prevote_equivocation(self.votes.number(), e);
+				Message::Prevote(_) => self.metrics.on_vote_imported(Phase::Prevote, VoteImportOutcome::BelowBase),
+				Message::Precommit(_) => self.metrics.on_vote_imported(Phase::Precommit, VoteImportOutcome::BelowBase),
+				Message::PrimaryPropose(_) => {},
+			}
+			return Ok(());
+		}
+
+		match message {
+			Message::Prevote(prevote) => {
+				afg_event!(trace, [
+					round = self.votes.number(),
+					phase = Phase::Prevote,
+					target_number = prevote.target_number,
+					target_hash = prevote.target_hash,
+					voter = id
+				], "Importing vote");
+
+				let import_result = match self.votes.import_prevote(&*self.env, prevote, id, signature) {
+					Ok(import_result) => import_result,
+					Err(e) => {
+						trace!(target: "afg", "Ignoring prevote which can't be imported: {:?}", e);
+						self.metrics.on_vote_imported(Phase::Prevote, VoteImportOutcome::Malformed);
+						return Ok(());
 					}
+				};
+				if import_result.inconsistent_estimate {
+					warn!(target: "afg", "Estimate of round {} moved against a prevote imported, keeping the prior one",
+						self.votes.number());
 				}
-				Message::Precommit(precommit) => {
-					let import_result = self.votes.import_precommit(&*self.env, precommit, id, signature)?;
-					if let ImportResult { equivocation: Some(e), .. } = import_result {
-						self.env.precommit_equivocation(self.votes.number(), e);
-					}
+				self.metrics.on_vote_imported(Phase::Prevote, VoteImportOutcome::from(&import_result));
+				if let ImportResult { equivocation: Some(e), .. } = import_result {
+					self.env.prevote_equivocation(self.votes.number(), e);
+					self.check_equivocation_weight()?;
 				}
-				Message::PrimaryPropose(primary) => {
-					let primary_id = self.votes.primary_voter().0.clone();
-					if id == primary_id {
-						self.primary_block = Some((primary.target_hash, primary.target_number));
+			}
+			Message::Precommit(precommit) => {
+				afg_event!(trace, [
+					round = self.votes.number(),
+					phase = Phase::Precommit,
+					target_number = precommit.target_number,
+					target_hash = precommit.target_hash,
+					voter = id
+				], "Importing vote");
+
+				let import_result = match self.votes.import_precommit(&*self.env, precommit, id, signature) {
+					Ok(import_result) => import_result,
+					Err(e) => {
+						trace!(target: "afg", "Ignoring precommit which can't be imported: {:?}", e);
+						self.metrics.on_vote_imported(Phase::Precommit, VoteImportOutcome::Malformed);
+						return Ok(());
 					}
+				};
+				if import_result.inconsistent_estimate {
+					warn!(target: "afg", "Estimate of round {} moved against a precommit imported, keeping the prior one",
+						self.votes.number());
 				}
-			};
+				self.metrics.on_vote_imported(Phase::Precommit, VoteImportOutcome::from(&import_result));
+				if let ImportResult { equivocation: Some(e), .. } = import_result {
+					self.env.precommit_equivocation(self.votes.number(), e);
+					self.check_equivocation_weight()?;
+				}
+			}
+			Message::PrimaryPropose(primary) => {
+				if self.votes.voters().is_primary(self.votes.number(), &id) {
+					self.primary_block = Some((primary.target_hash, primary.target_number));
+				}
+			}
+		};
+
+		Ok(())
+	}
+
+	// keep a vote pending until its target is available, unless the voter has
+	// a vote pending in the phase already or too many votes are pending.
+	fn defer_vote(&mut self, phase: Phase, message: SignedMessage<H, N, E::Signature, E::Id>) {
+		if !self.votes.voters().contains(&message.id) {
+			self.metrics.on_vote_imported(phase, VoteImportOutcome::InvalidVoter);
+			return;
+		}
+
+		let already_pending = self.pending_votes.values().flatten().any(|pending| {
+			pending.id == message.id && vote_phase(&pending.message) == Some(phase)
+		});
+
+		if already_pending || self.n_pending_votes >= MAX_PENDING_VOTES {
+			trace!(target: "afg", "Dropping vote for unavailable block {:?} in round {}",
+				message.message.target(), self.votes.number());
+			self.metrics.on_vote_imported(phase, VoteImportOutcome::Unavailable);
+			return;
+		}
+
+		trace!(target: "afg", "Keeping vote for {:?} in round {} pending until the block is available",
+			message.message.target(), self.votes.number());
+		self.metrics.on_vote_imported(phase, VoteImportOutcome::Pending);
+
+		let target = message.message.target().0.clone();
+		self.pending_votes.entry(target).or_default().push(message);
+		self.n_pending_votes += 1;
+	}
+
+	/// Note that the given block was imported, importing the votes pending
+	/// on it. The round must be polled afterwards to act on them, which also
+	/// notifies of any block they finalized.
+	pub(super) fn block_imported(&mut self, hash: &H, number: N) -> Result<(), E::Error> {
+		let pending = match self.pending_votes.remove(hash) {
+			Some(pending) => pending,
+			None => return Ok(()),
+		};
+		self.n_pending_votes -= pending.len();
+
+		if self.unnotified_state.is_none() {
+			self.unnotified_state = Some(self.votes.state());
+		}
+
+		for message in pending {
+			if message.message.target().1 != number {
+				trace!(target: "afg", "Dropping pending vote for {:?}, which was imported as number {:?}",
+					message.message.target(), number);
+				continue;
+			}
+
+			self.handle_message(message)?;
+		}
+
+		Ok(())
+	}
+
+	// whether the round is completable. a round where too many voters have
+	// equivocated is never completable, even though every block might appear
+	// to have supermajority support.
+	fn completable(&self) -> bool {
+		!self.too_many_equivocations && self.votes.completable()
+	}
+
+	// check whether the equivocation weight in either phase now exceeds the
+	// fault tolerance, and notify the environment the first time it does.
+	fn check_equivocation_weight(&mut self) -> Result<(), E::Error> {
+		if self.too_many_equivocations { return Ok(()) }
+
+		let voters = self.votes.voters();
+		let tolerated = voters.total_weight().saturating_sub(voters.threshold());
+		let (prevote, precommit) = self.votes.equivocation_weight();
+
+		if prevote > tolerated || precommit > tolerated {
+			warn!(target: "afg", "Equivocation weight ({}, {}) in round {} exceeds fault tolerance {}",
+				prevote, precommit, self.votes.number(), tolerated);
+
+			self.too_many_equivocations = true;
+			self.env.too_many_equivocations(TooManyEquivocations {
+				round: self.votes.number(),
+				weight: (prevote, precommit),
+				equivocators: self.votes.equivocators(),
+			})?;
 		}
 
 		Ok(())
 	}
 
-	fn primary_propose(&mut self, last_round_state: &RoundState<H, N>) -> Result<(), E::Error> {
+	fn primary_propose(&mut self, cx: &mut Context, last_round_state: &RoundState<H, N>) -> Result<(), E::Error> {
+		if self.proposing() && !self.outgoing_ready(cx)? {
+			return Ok(());
+		}
+
 		match self.state.take() {
 			Some(State::Start(prevote_timer, precommit_timer)) => {
 				let maybe_estimate = last_round_state.estimate.clone();
 
-				match (maybe_estimate, self.voting.is_primary()) {
+				match (maybe_estimate, self.proposing()) {
 					(Some(last_round_estimate), true) => {
 						let maybe_finalized = last_round_state.finalized.clone();
 
 						// Last round estimate has not been finalized.
-						let should_send_primary = maybe_finalized.map_or(true, |f| last_round_estimate.1 > f.1);
+						let should_send_primary = maybe_finalized.is_none_or(|f| last_round_estimate.1 > f.1);
 						if should_send_primary {
-							debug!(target: "afg", "Sending primary block hint for round {}", self.votes.number());
-							let primary = PrimaryPropose {
+							let estimate = PrimaryPropose {
 								target_hash: last_round_estimate.0,
 								target_number: last_round_estimate.1,
 							};
-							self.env.proposed(self.round_number(), primary.clone())?;
-							self.outgoing.push(Message::PrimaryPropose(primary));
-							self.state = Some(State::Proposed(prevote_timer, precommit_timer));
 
-							return Ok(());
+							match self.env.primary_proposal(self.round_number(), estimate.clone())? {
+								Some(primary) => {
+									let primary = if self.is_valid_primary(&primary, &estimate) {
+										primary
+									} else {
+										warn!(target: "afg", "Ignoring primary block hint for round {} \
+											which doesn't build on the last round estimate", self.votes.number());
+										estimate
+									};
+
+									afg_event!(debug, [
+										round = self.votes.number(),
+										target_number = primary.target_number,
+										target_hash = primary.target_hash
+									], "Sending primary block hint";
+										"Sending primary block hint for round {}", self.votes.number());
+									self.env.proposed(self.round_number(), primary.clone())?;
+									self.outgoing.push(Message::PrimaryPropose(primary));
+									self.state = Some(State::Proposed(prevote_timer, precommit_timer));
+
+									return Ok(());
+								},
+								None => {
+									debug!(target: "afg", "Environment suppressed primary block hint \
+										for round {}", self.votes.number());
+								},
+							}
 						} else {
 							debug!(target: "afg", "Last round estimate has been finalized, \
 								not sending primary block hint for round {}", self.votes.number());
@@ -400,73 +853,298 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 		Ok(())
 	}
 
+	// a primary proposal must be for the last round estimate or a descendent.
+	fn is_valid_primary(&self, primary: &PrimaryPropose<H, N>, estimate: &PrimaryPropose<H, N>) -> bool {
+		primary.target_number >= estimate.target_number &&
+			self.env.is_equal_or_descendent_of(estimate.target_hash.clone(), primary.target_hash.clone())
+	}
+
 	fn prevote(&mut self, cx: &mut Context, last_round_state: &RoundState<H, N>) -> Result<(), E::Error> {
-		let state = self.state.take();
+		match self.state.take() {
+			Some(State::Start(prevote_timer, precommit_timer)) => {
+				self.handle_prevote(cx, last_round_state, prevote_timer, precommit_timer, false)?;
+			},
+			Some(State::Proposed(prevote_timer, precommit_timer)) => {
+				self.handle_prevote(cx, last_round_state, prevote_timer, precommit_timer, true)?;
+			},
+			Some(State::Prevoting(precommit_timer)) if !self.casting() => {
+				// paused past the deadline, so we won't prevote anymore.
+				self.state = Some(State::Prevoted(precommit_timer));
+			},
+			Some(State::Prevoting(precommit_timer)) => {
+				// retry looking up a prevote target, without a deadline.
+				let base = self.prevote_base(last_round_state);
+				let cast = match self.poll_best_chain(cx, &base)? {
+					Some(best_chain) if self.outgoing_ready(cx)? => {
+						self.cast_prevote(cx, last_round_state, base, best_chain)?
+					},
+					_ => false,
+				};
 
-		let mut handle_prevote = |mut prevote_timer: E::Timer, precommit_timer: E::Timer, proposed| {
-			let should_prevote = match Future::poll(Pin::new(&mut prevote_timer), cx) {
-				Poll::Ready(Err(e)) => return Err(e),
-				Poll::Ready(Ok(())) => true,
-				Poll::Pending => self.votes.completable(),
-			};
-
-			if should_prevote {
-				if self.voting.is_active() {
-					if let Some(prevote) = self.construct_prevote(last_round_state)? {
-						debug!(target: "afg", "Casting prevote for round {}", self.votes.number());
-						self.env.prevoted(self.round_number(), prevote.clone())?;
-						self.votes.set_prevoted_index();
-						self.outgoing.push(Message::Prevote(prevote));
-					}
+				if cast {
+					self.state = Some(State::Prevoted(precommit_timer));
+				} else {
+					self.state = Some(State::Prevoting(precommit_timer));
 				}
-				self.state = Some(State::Prevoted(precommit_timer));
-			} else if proposed {
-				self.state = Some(State::Proposed(prevote_timer, precommit_timer));
+			},
+			x => { self.state = x; }
+		}
+
+		Ok(())
+	}
+
+	fn handle_prevote(
+		&mut self,
+		cx: &mut Context,
+		last_round_state: &RoundState<H, N>,
+		mut prevote_timer: E::Timer,
+		precommit_timer: E::Timer,
+		proposed: bool,
+	) -> Result<(), E::Error> {
+		// while the sink is congested we only look up the prevote target, and
+		// decide on prevoting once it accepts messages again. the timer isn't
+		// polled meanwhile, so that it's never polled after firing.
+		if self.casting() && !self.outgoing_ready(cx)? {
+			let base = self.prevote_base(last_round_state);
+			self.poll_best_chain(cx, &base)?;
+
+			self.state = Some(if proposed {
+				State::Proposed(prevote_timer, precommit_timer)
 			} else {
-				self.state = Some(State::Start(prevote_timer, precommit_timer));
+				State::Start(prevote_timer, precommit_timer)
+			});
+
+			return Ok(());
+		}
+
+		let timer_fired = self.phase_reached(Phase::Prevote, &mut prevote_timer, cx)?;
+
+		let should_prevote = timer_fired || self.completable();
+
+		let cast = if !self.casting() {
+			should_prevote
+		} else {
+			// look up the prevote target while waiting for the timer, so that
+			// it's ready by the time we prevote.
+			let base = self.prevote_base(last_round_state);
+			match self.poll_best_chain(cx, &base)? {
+				Some(best_chain) if should_prevote => {
+					if !self.cast_prevote(cx, last_round_state, base, best_chain)? {
+						self.state = Some(State::Prevoting(precommit_timer));
+						return Ok(());
+					}
+
+					true
+				},
+				None if timer_fired => {
+					// the lookup is still pending by the deadline, fall back
+					// to the last round estimate.
+					warn!(target: "afg", "Best chain containing {:?} not known in time for prevote in round {}, \
+						prevoting for last round estimate", base, self.votes.number());
+					self.env.best_chain_issue(self.round_number(), BestChainIssue::Unresolved {
+						base,
+					});
+
+					let (target_hash, target_number) = last_round_state.estimate.clone()
+						.expect("Rounds only started when prior round completable; qed");
+					self.push_prevote(Prevote { target_hash, target_number })?;
+
+					true
+				},
+				_ => false,
 			}
+		};
+
+		if cast {
+			self.state = Some(State::Prevoted(precommit_timer));
+		} else if proposed {
+			self.state = Some(State::Proposed(prevote_timer, precommit_timer));
+		} else {
+			self.state = Some(State::Start(prevote_timer, precommit_timer));
+		}
+
+		Ok(())
+	}
 
-			Ok(())
+	// start looking up the best chain containing the given block, unless it's
+	// already being looked up, and poll the lookup. returns its result once
+	// it's done.
+	fn poll_best_chain(&mut self, cx: &mut Context, base: &(H, N)) -> Result<Option<Option<(H, N)>>, E::Error> {
+		let stale = match self.best_chain {
+			Some(BestChainLookup::Pending(ref b, _)) | Some(BestChainLookup::Done(ref b, _)) => b != base,
+			None => true,
 		};
 
-		match state {
-			Some(State::Start(prevote_timer, precommit_timer)) => {
-				handle_prevote(prevote_timer, precommit_timer, false)?;
+		if stale {
+			self.best_chain = Some(match self.env.best_chain(base.0.clone()) {
+				Some(lookup) => BestChainLookup::Pending(base.clone(), lookup),
+				None => BestChainLookup::Done(base.clone(), self.env.best_chain_containing(base.0.clone())),
+			});
+		}
+
+		if let Some(BestChainLookup::Pending(_, ref mut lookup)) = self.best_chain {
+			match Future::poll(Pin::new(lookup), cx) {
+				Poll::Ready(Err(e)) => return Err(e),
+				Poll::Ready(Ok(best_chain)) => {
+					self.best_chain = Some(BestChainLookup::Done(base.clone(), best_chain));
+				},
+				Poll::Pending => {},
+			}
+		}
+
+		match self.best_chain {
+			Some(BestChainLookup::Done(_, ref best_chain)) => Ok(Some(best_chain.clone())),
+			_ => Ok(None),
+		}
+	}
+
+	// cast a prevote given the best chain containing `base`, returning false if
+	// no target for it could be found, in which case a new lookup is started
+	// next time, or while its restriction is pending.
+	fn cast_prevote(
+		&mut self,
+		cx: &mut Context,
+		last_round_state: &RoundState<H, N>,
+		base: (H, N),
+		best_chain: Option<(H, N)>,
+	) -> Result<bool, E::Error> {
+		// the prevote was constructed already if its restriction is pending.
+		let target = match self.restriction {
+			Some(ref restriction) if restriction.phase == Phase::Prevote && restriction.base == base =>
+				restriction.target.clone(),
+			_ => match self.construct_prevote(last_round_state, base.clone(), best_chain.clone()) {
+				Some(prevote) => (prevote.target_hash, prevote.target_number),
+				None => {
+					self.best_chain = None;
+					return Ok(false);
+				},
 			},
-			Some(State::Proposed(prevote_timer, precommit_timer)) => {
-				handle_prevote(prevote_timer, precommit_timer, true)?;
+		};
+
+		let best_chain = best_chain.expect("prevotes are only constructed given the best chain; qed");
+		match self.restrict_vote(cx, Phase::Prevote, base, best_chain, target)? {
+			Some((target_hash, target_number)) => {
+				self.push_prevote(Prevote { target_hash, target_number })?;
+				Ok(true)
 			},
-			x => { self.state = x; }
+			None => Ok(false),
+		}
+	}
+
+	// restrict the target of a vote we're about to cast with
+	// `Environment::restrict_vote`, unless its restriction is pending already.
+	// returns the target to vote for, once the restriction resolved.
+	fn restrict_vote(
+		&mut self,
+		cx: &mut Context,
+		phase: Phase,
+		base: (H, N),
+		best_target: (H, N),
+		target: (H, N),
+	) -> Result<Option<(H, N)>, E::Error> {
+		let pending = self.restriction.as_ref().is_some_and(|restriction| {
+			restriction.phase == phase && restriction.base == base && restriction.target == target
+		});
+
+		if !pending {
+			let round = self.round_number();
+			self.restriction = self.env.restrict_vote(round, phase, base.clone(), best_target, target.clone())
+				.map(|restricted| VoteRestriction { phase, base: base.clone(), target: target.clone(), restricted });
+		}
+
+		let restricted = match self.restriction {
+			Some(ref mut restriction) => match restriction.restricted.poll_unpin(cx) {
+				Poll::Ready(restricted) => restricted?,
+				Poll::Pending => return Ok(None),
+			},
+			None => return Ok(Some(target)),
+		};
+		self.restriction = None;
+
+		let restricted = match restricted {
+			Some(restricted) => restricted,
+			None => return Ok(Some(target)),
+		};
+
+		// a restriction may only hold the vote back, towards the base.
+		let valid = restricted.1 >= base.1 && restricted.1 <= target.1 &&
+			self.env.is_equal_or_descendent_of(base.0.clone(), restricted.0.clone()) &&
+			self.env.is_equal_or_descendent_of(restricted.0.clone(), target.0.clone());
+
+		if valid {
+			debug!(target: "afg", "Restricted {:?} target in round {} from {:?} to {:?}",
+				phase, self.votes.number(), target, restricted);
+			Ok(Some(restricted))
+		} else {
+			warn!(target: "afg", "Ignoring restriction of {:?} target in round {} to {:?}, \
+				which isn't between {:?} and {:?}", phase, self.votes.number(), restricted, base, target);
+			Ok(Some(target))
 		}
+	}
+
+	fn push_prevote(&mut self, prevote: Prevote<H, N>) -> Result<(), E::Error> {
+		afg_event!(debug, [
+			round = self.votes.number(),
+			target_number = prevote.target_number,
+			target_hash = prevote.target_hash
+		], "Casting prevote"; "Casting prevote for round {}", self.votes.number());
+		// recorded before anything else, so that the prevote is in the history
+		// even if the round is dropped before it's sent.
+		self.votes.record_prevote(prevote.clone());
+		self.env.prevoted(self.round_number(), prevote.clone())?;
+		self.outgoing.push(Message::Prevote(prevote));
 
 		Ok(())
 	}
 
 	fn precommit(&mut self, cx: &mut Context, last_round_state: &RoundState<H, N>) -> Result<(), E::Error> {
 		match self.state.take() {
+			Some(State::Prevoted(precommit_timer)) if self.casting() && !self.outgoing_ready(cx)? => {
+				// decide on precommitting once the sink accepts messages again.
+				self.state = Some(State::Prevoted(precommit_timer));
+			},
 			Some(State::Prevoted(mut precommit_timer)) => {
 				let last_round_estimate = last_round_state.estimate.clone()
 					.expect("Rounds only started when prior round completable; qed");
 
-				let should_precommit = {
+				// we decided to precommit already if its restriction is pending.
+				let restricting = self.restriction.as_ref()
+					.is_some_and(|restriction| restriction.phase == Phase::Precommit);
+
+				let should_precommit = restricting || ({
 					// we wait for the last round's estimate to be equal to or
 					// the ancestor of the current round's p-Ghost before precommitting.
-					self.votes.state().prevote_ghost.as_ref().map_or(false, |p_g| {
+					self.votes.state().prevote_ghost.as_ref().is_some_and(|p_g| {
 						p_g == &last_round_estimate ||
 							self.env.is_equal_or_descendent_of(last_round_estimate.0, p_g.0.clone())
 					})
-				} && match Future::poll(Pin::new(&mut precommit_timer), cx) {
-					Poll::Ready(Err(e)) => return Err(e),
-					Poll::Ready(Ok(())) => true,
-					Poll::Pending => self.votes.completable(),
-				};
+				} && (self.phase_reached(Phase::Precommit, &mut precommit_timer, cx)? || self.completable()));
 
 				if should_precommit {
-					if self.voting.is_active() {
-						debug!(target: "afg", "Casting precommit for round {}", self.votes.number());
-						let precommit = self.construct_precommit();
+					if self.casting() {
+						let target = match self.restriction {
+							Some(ref restriction) if restricting => restriction.target.clone(),
+							_ => {
+								let precommit = self.construct_precommit();
+								(precommit.target_hash, precommit.target_number)
+							},
+						};
+
+						let base = self.votes.base();
+						let precommit = match self.restrict_vote(cx, Phase::Precommit, base, target.clone(), target)? {
+							Some((target_hash, target_number)) => Precommit { target_hash, target_number },
+							None => {
+								self.state = Some(State::Prevoted(precommit_timer));
+								return Ok(());
+							},
+						};
+						afg_event!(debug, [
+							round = self.votes.number(),
+							target_number = precommit.target_number,
+							target_hash = precommit.target_hash
+						], "Casting precommit"; "Casting precommit for round {}", self.votes.number());
+						self.votes.record_precommit(precommit.clone());
 						self.env.precommitted(self.round_number(), precommit.clone())?;
-						self.votes.set_precommited_index();
 						self.outgoing.push(Message::Precommit(precommit));
 					}
 					self.state = Some(State::Precommitted);
@@ -480,15 +1158,15 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 		Ok(())
 	}
 
-	// construct a prevote message based on local state.
-	fn construct_prevote(&self, last_round_state: &RoundState<H, N>) -> Result<Option<Prevote<H, N>>, E::Error> {
+	// the block to prevote for the best chain containing, based on local state.
+	fn prevote_base(&self, last_round_state: &RoundState<H, N>) -> (H, N) {
 		let last_round_estimate = last_round_state.estimate.clone()
 			.expect("Rounds only started when prior round completable; qed");
 
-		let find_descendent_of = match self.primary_block {
+		match self.primary_block {
 			None => {
 				// vote for best chain containing prior round-estimate.
-				last_round_estimate.0
+				last_round_estimate.clone()
 			}
 			Some(ref primary_block) => {
 				// we will vote for the best chain containing `p_hash` iff
@@ -500,53 +1178,78 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 
 				// if the blocks are equal, we don't check ancestry.
 				if primary_block == &last_prevote_g {
-					primary_block.0.clone()
+					primary_block.clone()
 				} else if primary_block.1 >= last_prevote_g.1 {
-					last_round_estimate.0
+					last_round_estimate.clone()
 				} else {
 					// from this point onwards, the number of the primary-broadcasted
 					// block is less than the last prevote-GHOST's number.
 					// if the primary block is in the ancestry of p-G we vote for the
 					// best chain containing it.
 					let &(ref p_hash, p_num) = primary_block;
-					match self.env.ancestry(last_round_estimate.0.clone(), last_prevote_g.0) {
+					let (g_hash, g_num) = last_prevote_g;
+					match self.env.ancestry(last_round_estimate.0.clone(), g_hash) {
 						Ok(ancestry) => {
-							let to_sub = p_num + N::one();
-
-							let offset: usize = if last_prevote_g.1 < to_sub {
-								0
-							} else {
-								(last_prevote_g.1 - to_sub).as_()
-							};
+							let offset: usize = p_num.checked_add(&N::one())
+								.and_then(|to_sub| g_num.checked_sub(&to_sub))
+								.map_or(0, |offset| offset.as_());
 
-							if ancestry.get(offset).map_or(false, |b| b == p_hash) {
-								p_hash.clone()
+							if ancestry.get(offset).is_some_and(|b| b == p_hash) {
+								(p_hash.clone(), p_num)
 							} else {
-								last_round_estimate.0
+								last_round_estimate.clone()
 							}
 						}
-						Err(crate::Error::NotDescendent) => last_round_estimate.0,
+						Err(_) => last_round_estimate.clone(),
 					}
 				}
 			}
+		}
+	}
+
+	// construct a prevote message given the best chain containing `base`.
+	fn construct_prevote(
+		&self,
+		last_round_state: &RoundState<H, N>,
+		base: (H, N),
+		best_chain: Option<(H, N)>,
+	) -> Option<Prevote<H, N>> {
+		let last_round_estimate = last_round_state.estimate.clone()
+			.expect("Rounds only started when prior round completable; qed");
+
+		let best_chain = match best_chain {
+			Some(best_chain) => best_chain,
+			None => {
+				// the block might have disappeared in a reorg, or not be
+				// imported yet. skip casting a vote until it's known again.
+				warn!(target: "afg", "Could not cast prevote in round {}: previously known block {:?} \
+					has disappeared, retrying", self.votes.number(), base);
+				self.env.best_chain_issue(self.round_number(), BestChainIssue::Unknown { base });
+				return None;
+			}
 		};
 
-		let best_chain = self.env.best_chain_containing(find_descendent_of.clone());
-		debug_assert!(best_chain.is_some(), "Previously known block {:?} has disappeared from chain", find_descendent_of);
+		// a prevote must be for the last round estimate or a descendent, so
+		// fall back to it if the best chain doesn't build on the block.
+		let builds_on_base = best_chain.1 >= base.1 &&
+			self.env.is_equal_or_descendent_of(base.0.clone(), best_chain.0.clone());
 
-		let t = if let Some(target) = best_chain {
-			target
+		let target = if builds_on_base {
+			best_chain
 		} else {
-			// If this block is considered unknown, something has gone wrong.
-			// log and handle, but skip casting a vote.
-			warn!(target: "afg", "Could not cast prevote: previously known block {:?} has disappeared", find_descendent_of);
-			return Ok(None);
+			warn!(target: "afg", "Best chain {:?} in round {} doesn't contain {:?}, \
+				prevoting for last round estimate", best_chain, self.votes.number(), base);
+			self.env.best_chain_issue(self.round_number(), BestChainIssue::NotDescendent {
+				base,
+				best: best_chain,
+			});
+			last_round_estimate
 		};
 
-		Ok(Some(Prevote {
-			target_hash: t.0,
-			target_number: t.1,
-		}))
+		Some(Prevote {
+			target_hash: target.0,
+			target_number: target.1,
+		})
 	}
 
 	// construct a precommit message based on local state.
@@ -570,24 +1273,76 @@ impl<H, N, E: Environment<H, N>> VotingRound<H, N, E> where
 			b.update(new_state.clone());
 		}
 
-		if last_state.finalized != new_state.finalized && new_state.completable {
+		if last_state.estimate != new_state.estimate {
+			if let Some((ref hash, number)) = new_state.estimate {
+				afg_event!(trace, [
+					round = self.votes.number(),
+					target_number = number,
+					target_hash = hash
+				], "Estimate updated");
+			}
+		}
+
+		if last_state.finalized != new_state.finalized && new_state.completable && !self.too_many_equivocations {
 			// send notification only when the round is completable and we've cast votes.
 			// this is a workaround that ensures when we re-instantiate the voter after
 			// a shutdown, we never re-create the same round with a base that was finalized
 			// in this round or after.
-			if let (&Some(State::Precommitted), Some((ref f_hash, ref f_number))) = (&self.state, new_state.finalized) {
-				let commit = Commit {
-					target_hash: f_hash.clone(),
-					target_number: *f_number,
-					precommits: self.votes.finalizing_precommits(&*self.env)
-						.expect("always returns none if something was finalized; this is checked above; qed")
-						.collect(),
-				};
-				let finalized = (f_hash.clone(), *f_number, self.votes.number(), commit.clone());
-
-				let _ = self.finalized_sender.unbounded_send(finalized);
-				self.best_finalized = Some(commit);
+			if let (&Some(State::Precommitted), Some(finalized)) = (&self.state, new_state.finalized) {
+				self.send_finalized(finalized);
 			}
 		}
 	}
+
+	/// Note that the voter left this round because it's settled, possibly
+	/// before we've cast all our votes. The block finalized in it is signalled
+	/// right away then, since the round won't be re-created as the current one
+	/// after a restart anymore.
+	pub(super) fn left_settled(&mut self) {
+		// otherwise it's been signalled already.
+		if let Some(State::Precommitted) = self.state { return }
+
+		if let Some(finalized) = self.votes.finalized().cloned() {
+			self.send_finalized(finalized);
+		}
+	}
+
+	/// Take the commit for the block finalized in this round, if any, as the
+	/// one to broadcast, without signalling the block. For rounds restored
+	/// after it was signalled already.
+	pub(super) fn restore_finalizing_commit(&mut self) {
+		if let Some(finalized) = self.votes.finalized().cloned() {
+			self.best_finalized = Some(self.commit_for(finalized));
+		}
+	}
+
+	// signal the given block, finalized in this round, along with its commit.
+	fn send_finalized(&mut self, (f_hash, f_number): (H, N)) {
+		let commit = self.commit_for((f_hash.clone(), f_number));
+		let finalized = (f_hash, f_number, self.votes.number(), commit.clone());
+
+		let _ = self.finalized_sender.unbounded_send(finalized);
+		self.best_finalized = Some(commit);
+	}
+
+	// the commit for the given block, finalized in this round.
+	fn commit_for(&mut self, (f_hash, f_number): (H, N)) -> Commit<H, N, E::Signature, E::Id> {
+		Commit {
+			target_hash: f_hash,
+			target_number: f_number,
+			precommits: self.votes.finalizing_precommits(&*self.env)
+				.expect("always returns none if something was finalized; this is checked above; qed")
+				.collect(),
+			set_id: self.votes.set_id(),
+		}
+	}
+}
+
+// the phase of a vote, or `None` for primary proposals.
+fn vote_phase<H, N>(message: &Message<H, N>) -> Option<Phase> {
+	match message {
+		Message::Prevote(_) => Some(Phase::Prevote),
+		Message::Precommit(_) => Some(Phase::Precommit),
+		Message::PrimaryPropose(_) => None,
+	}
 }
@@ -16,84 +16,273 @@
 //!
 //! See docs on `VoterSet` for more information.
 
-use crate::std::{self, collections::HashMap, hash::Hash, vec::Vec};
+use crate::std::{collections::HashMap, hash::Hash, vec::Vec};
 
 use super::threshold;
 
+#[cfg(feature = "derive-codec")]
+use parity_scale_codec::{Compact, Decode, Encode, EncodeLike, Error as CodecError, Input, Output};
+
+#[cfg(feature = "derive-serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Errors that can occur when constructing a `VoterSet`.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+pub enum Error {
+	/// The set has no voters.
+	Empty,
+	/// A voter appears more than once.
+	DuplicateVoter,
+	/// A voter has zero weight.
+	ZeroWeight,
+	/// The total weight of the set doesn't fit in a `u64`.
+	WeightOverflow,
+}
+
+#[cfg(any(feature = "std", feature = "derive-codec", feature = "derive-serde"))]
+impl Error {
+	fn message(&self) -> &'static str {
+		match *self {
+			Error::Empty => "Voter set is empty",
+			Error::DuplicateVoter => "Voter appears more than once in voter set",
+			Error::ZeroWeight => "Voter has zero weight",
+			Error::WeightOverflow => "Total voter weight overflows",
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::fmt::Display for Error {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "{}", self.message())
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
 /// A voter set, with accompanying indices.
+///
+/// A voter set is never empty, has no voters of zero weight, and its total
+/// weight fits in a `u64`.
+///
+/// Voters are kept in canonical order, which is ascending order of their ids.
+/// The canonical index of a voter is its position in that order, so it only
+/// depends on the set of voters and not on the order they were given in.
+/// Bitfields tracking votes and equivocations are indexed by it.
 #[derive(Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "std", derive(Debug))]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
 pub struct VoterSet<Id: Hash + Eq> {
-	weights: HashMap<Id, VoterInfo>,
-	voters: Vec<(Id, u64)>,
+	index: HashMap<Id, usize>,
+	voters: Vec<(Id, VoterInfo)>,
+	total_weight: u64,
 	threshold: u64,
 }
 
+impl<Id: Hash + Eq + Clone + Ord> VoterSet<Id> {
+	/// Create a voter set from voter ids and their weights.
+	///
+	/// Fails if there are no voters, if a voter appears more than once or has
+	/// zero weight, or if the total weight overflows.
+	pub fn new<I: IntoIterator<Item = (Id, u64)>>(voters: I) -> Result<Self, Error> {
+		let mut voters: Vec<_> = voters.into_iter().collect();
+		if voters.is_empty() {
+			return Err(Error::Empty);
+		}
+
+		voters.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+		if voters.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+			return Err(Error::DuplicateVoter);
+		}
+
+		let mut total_weight: u64 = 0;
+		for &(_, weight) in &voters {
+			if weight == 0 {
+				return Err(Error::ZeroWeight);
+			}
+
+			total_weight = total_weight.checked_add(weight).ok_or(Error::WeightOverflow)?;
+		}
+
+		Ok(Self::from_canonical(voters, total_weight))
+	}
+
+	/// Create a voter set from voter ids and their weights, which are trusted
+	/// to be in canonical order already, e.g. because they come from a set
+	/// that was validated before. This skips sorting and checking the voters,
+	/// which matters for large sets.
+	///
+	/// The voters must be sorted by id without duplicates, non-empty, of
+	/// non-zero weight, and their total weight must fit in a `u64`. This is
+	/// only checked with debug assertions enabled.
+	pub fn from_sorted_unchecked(voters: Vec<(Id, u64)>) -> Self {
+		debug_assert!(!voters.is_empty(), "voter set is empty");
+		debug_assert!(
+			voters.windows(2).all(|pair| pair[0].0 < pair[1].0),
+			"voters aren't sorted by id or appear more than once",
+		);
+		debug_assert!(voters.iter().all(|&(_, weight)| weight != 0), "voter has zero weight");
+
+		// overflowing panics with debug assertions enabled.
+		let total_weight = voters.iter().map(|&(_, weight)| weight).sum();
+
+		Self::from_canonical(voters, total_weight)
+	}
+
+	// build the set from valid voters in canonical order.
+	fn from_canonical(voters: Vec<(Id, u64)>, total_weight: u64) -> Self {
+		// a single voter can't tolerate any faults among its own weight: it
+		// must take part in every supermajority on its own.
+		let threshold = if voters.len() == 1 { total_weight } else { threshold(total_weight) };
+
+		let mut index = HashMap::with_capacity(voters.len());
+		let voters = voters.into_iter().enumerate().map(|(canon_idx, (id, weight))| {
+			index.insert(id.clone(), canon_idx);
+			(id, VoterInfo { canon_idx, weight })
+		}).collect();
+
+		VoterSet {
+			index,
+			voters,
+			total_weight,
+			threshold,
+		}
+	}
+}
+
 impl<Id: Hash + Eq> VoterSet<Id> {
 	/// Get the voter info for a voter.
-	pub fn info<'a>(&'a self, id: &Id) -> Option<&'a VoterInfo> {
-		self.weights.get(id)
+	pub fn get(&self, id: &Id) -> Option<&VoterInfo> {
+		self.index.get(id).map(|&idx| &self.voters[idx].1)
 	}
 
-	/// Get the length of the set.
-	pub fn len(&self) -> usize { self.voters.len() }
-
-	/// Whether the set contains the key.
-	pub fn contains_key(&self, id: &Id) -> bool {
-		self.weights.contains_key(id)
+	/// Get the voter at the given canonical index.
+	pub fn nth(&self, idx: usize) -> Option<(&Id, &VoterInfo)> {
+		self.voters.get(idx).map(|(id, info)| (id, info))
 	}
 
-	// Get voter by index.
-	pub fn voter_by_index(&self, idx: usize) -> &(Id, u64) {
-		&self.voters[idx]
+	/// Iterate the voters in canonical order, yielding the canonical index,
+	/// id and weight of each voter.
+	pub fn iter(&self) -> impl Iterator<Item = (usize, &Id, u64)> {
+		self.voters.iter().map(|(id, info)| (info.canon_idx, id, info.weight))
 	}
 
-	/// Get voter info by index.
-	pub fn weight_by_index(&self, idx: usize) -> Option<u64> {
-		self.voters.get(idx).map(|&(_, weight)| weight)
+	/// Get the primary proposer of the given round.
+	///
+	/// Proposers rotate round-robin through the canonical order: the primary of
+	/// round `r` is the voter at index `r mod n`.
+	pub fn primary(&self, round: u64) -> (&Id, &VoterInfo) {
+		self.primary_by(round, PrimarySelection::RoundRobin)
 	}
 
-	/// Get the threshold weight.
-	pub fn threshold(&self) -> u64 { self.threshold }
+	/// Get the primary proposer of the given round under the given selection
+	/// rule.
+	pub fn primary_by(&self, round: u64, selection: PrimarySelection) -> (&Id, &VoterInfo) {
+		let idx = match selection {
+			PrimarySelection::RoundRobin => (round % self.voters.len() as u64) as usize,
+			PrimarySelection::WeightProportional => {
+				// find the voter whose cumulative weight range contains the
+				// round number, modulo the total weight.
+				let mut target = round % self.total_weight;
+				self.voters.iter().position(|(_, info)| {
+					if target < info.weight {
+						true
+					} else {
+						target -= info.weight;
+						false
+					}
+				}).expect("target is below the total weight of all voters; qed")
+			}
+		};
 
-	/// Get the total weight.
-	pub fn total_weight(&self) -> u64 {
-		self.voters.iter().map(|&(_, weight)| weight).sum()
+		self.nth(idx).expect("index is modulo the number of voters; qed")
 	}
 
-	/// Get the voters.
-	pub fn voters(&self) -> &[(Id, u64)] {
-		&self.voters
+	/// Whether the voter is the primary proposer of the given round.
+	pub fn is_primary(&self, round: u64, id: &Id) -> bool {
+		self.primary(round).0 == id
 	}
-}
 
-impl<Id: Hash + Eq + Clone + Ord> std::iter::FromIterator<(Id, u64)> for VoterSet<Id> {
-	fn from_iter<I: IntoIterator<Item = (Id, u64)>>(iterable: I) -> Self {
-		let iter = iterable.into_iter();
-		let (lower, _) = iter.size_hint();
+	/// Whether the set contains the voter.
+	pub fn contains(&self, id: &Id) -> bool {
+		self.index.contains_key(id)
+	}
 
-		let mut voters = Vec::with_capacity(lower);
-		let mut weights = HashMap::with_capacity(lower);
+	/// Get the length of the set.
+	pub fn len(&self) -> usize { self.voters.len() }
 
-		let mut total_weight = 0;
-		for (id, weight) in iter {
-			voters.push((id.clone(), weight));
-			total_weight += weight;
-		}
+	/// Whether the set is empty. This is never the case for a constructed set.
+	pub fn is_empty(&self) -> bool { self.voters.is_empty() }
+
+	/// Get the threshold weight. This is `crate::threshold` of the total
+	/// weight, except for a single voter, where it is the voter's weight.
+	pub fn threshold(&self) -> u64 { self.threshold }
 
-		voters.sort_unstable();
+	/// Get the total weight.
+	pub fn total_weight(&self) -> u64 { self.total_weight }
+
+	/// Get the maximum weight of faulty voters, e.g. equivocators, that the
+	/// set can tolerate. This is `(total_weight - 1) / 3`, except for a single
+	/// voter, where no faults are tolerated. It's always
+	/// `total_weight - threshold`.
+	pub fn faulty_weight(&self) -> u64 { self.total_weight - self.threshold }
+}
 
-		for (idx, (id, weight)) in voters.iter().enumerate() {
-			weights.insert(id.clone(), VoterInfo { canon_idx: idx, weight: *weight });
+// voter sets are encoded as the `(id, weight)` pairs in canonical order and
+// decoded through `VoterSet::new`, so invalid encodings are rejected.
+#[cfg(feature = "derive-codec")]
+impl<Id: Hash + Eq + Encode> Encode for VoterSet<Id> {
+	fn encode_to<T: Output>(&self, dest: &mut T) {
+		Compact(self.voters.len() as u32).encode_to(dest);
+		for (id, info) in &self.voters {
+			id.encode_to(dest);
+			info.weight.encode_to(dest);
 		}
+	}
+}
+
+#[cfg(feature = "derive-codec")]
+impl<Id: Hash + Eq + Encode> EncodeLike for VoterSet<Id> {}
+
+#[cfg(feature = "derive-codec")]
+impl<Id: Hash + Eq + Clone + Ord + Decode> Decode for VoterSet<Id> {
+	fn decode<I: Input>(input: &mut I) -> Result<Self, CodecError> {
+		let voters: Vec<(Id, u64)> = Decode::decode(input)?;
+		VoterSet::new(voters).map_err(|e| e.message().into())
+	}
+}
 
-		let threshold = threshold(total_weight);
-		VoterSet { weights, voters, threshold }
+#[cfg(feature = "derive-serde")]
+impl<Id: Hash + Eq + Serialize> Serialize for VoterSet<Id> {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_seq(self.voters.iter().map(|(id, info)| (id, info.weight)))
 	}
 }
 
+#[cfg(feature = "derive-serde")]
+impl<'de, Id: Hash + Eq + Clone + Ord + Deserialize<'de>> Deserialize<'de> for VoterSet<Id> {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		let voters: Vec<(Id, u64)> = Deserialize::deserialize(deserializer)?;
+		VoterSet::new(voters).map_err(|e| serde::de::Error::custom(e.message()))
+	}
+}
+
+/// How the primary proposer of a round is chosen from a `VoterSet`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
+pub enum PrimarySelection {
+	/// Rotate through the voters in canonical order, one per round. This is
+	/// what the protocol uses.
+	RoundRobin,
+	/// Rotate through the voters with each voter being primary for as many
+	/// consecutive rounds as its weight. Experimental.
+	WeightProportional,
+}
+
+/// A voter's weight and canonical index in a `VoterSet`.
 #[derive(Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "std", derive(Debug))]
+#[cfg_attr(any(feature = "std", test), derive(Debug))]
 pub struct VoterInfo {
 	canon_idx: usize,
 	weight: u64,
@@ -114,46 +303,257 @@ mod tests {
 
 	#[test]
 	fn voters_are_sorted() {
-		let v1: VoterSet<usize> = [
+		let v1 = VoterSet::new(vec![
 			(1, 5),
 			(4, 1),
 			(3, 9),
 			(5, 7),
 			(9, 9),
 			(2, 7),
-		].iter().cloned().collect();
+		]).unwrap();
 
-		let v2: VoterSet<usize> = [
+		let v2 = VoterSet::new(vec![
 			(1, 5),
 			(2, 7),
 			(3, 9),
 			(4, 1),
 			(5, 7),
 			(9, 9),
-		].iter().cloned().collect();
+		]).unwrap();
 
 		assert_eq!(v1, v2);
 	}
 
 	#[test]
-	fn voter_by_index_works() {
-		let v: VoterSet<usize> = [
+	fn lookups_work() {
+		let v = VoterSet::new(vec![
 			(1, 5),
 			(4, 1),
 			(3, 9),
 			(5, 7),
 			(9, 9),
 			(2, 7),
-		].iter().cloned().collect();
+		]).unwrap();
 
 		assert_eq!(v.len(), 6);
 		assert_eq!(v.total_weight(), 38);
 
-		assert_eq!(v.voter_by_index(0), &(1, 5));
-		assert_eq!(v.voter_by_index(1), &(2, 7));
-		assert_eq!(v.voter_by_index(2), &(3, 9));
-		assert_eq!(v.voter_by_index(3), &(4, 1));
-		assert_eq!(v.voter_by_index(4), &(5, 7));
-		assert_eq!(v.voter_by_index(5), &(9, 9));
+		let expected = [(1, 5), (2, 7), (3, 9), (4, 1), (5, 7), (9, 9)];
+		for (idx, &(id, weight)) in expected.iter().enumerate() {
+			let info = VoterInfo { canon_idx: idx, weight };
+
+			assert_eq!(v.nth(idx), Some((&id, &info)));
+			assert_eq!(v.get(&id), Some(&info));
+			assert!(v.contains(&id));
+		}
+
+		assert_eq!(v.nth(6), None);
+		assert_eq!(v.get(&6), None);
+		assert!(!v.contains(&6));
+
+		let ids: Vec<_> = v.iter().map(|(_, id, _)| *id).collect();
+		assert_eq!(ids, vec![1, 2, 3, 4, 5, 9]);
+	}
+
+	#[test]
+	fn canonical_index_is_stable() {
+		let voters = vec![(7, 1), (3, 2), (11, 3), (5, 4), (1, 5)];
+
+		let mut reversed = voters.clone();
+		reversed.reverse();
+
+		let mut rotated = voters.clone();
+		rotated.rotate_left(2);
+
+		let v = VoterSet::new(voters).unwrap();
+		for other in [reversed, rotated] {
+			let other = VoterSet::new(other).unwrap();
+			for (idx, id, weight) in v.iter() {
+				assert_eq!(other.get(id), Some(&VoterInfo { canon_idx: idx, weight }));
+			}
+		}
+
+		let indices: Vec<_> = [1, 3, 5, 7, 11].iter().map(|id| v.get(id).unwrap().canon_idx()).collect();
+		assert_eq!(indices, vec![0, 1, 2, 3, 4]);
+	}
+
+	#[test]
+	fn iter_yields_contiguous_indices_in_canonical_order() {
+		let v = VoterSet::new(vec![(9, 9), (3, 9), (1, 5), (5, 7), (4, 1)]).unwrap();
+
+		let voters: Vec<_> = v.iter().map(|(idx, id, weight)| (idx, *id, weight)).collect();
+		assert_eq!(voters, vec![(0, 1, 5), (1, 3, 9), (2, 4, 1), (3, 5, 7), (4, 9, 9)]);
+
+		for (idx, id, _) in v.iter() {
+			assert_eq!(v.nth(idx).map(|(nth_id, _)| nth_id), Some(id));
+		}
+	}
+
+	#[test]
+	fn single_voter_threshold_is_its_weight() {
+		for &weight in &[1, 2, 4, 5, 100, u64::MAX] {
+			let v = VoterSet::new(crate::std::iter::once((1, weight))).unwrap();
+			assert_eq!(v.total_weight(), weight);
+			assert_eq!(v.threshold(), weight);
+			assert_eq!(v.primary(0).0, &1);
+			assert_eq!(v.primary(u64::MAX).0, &1);
+		}
+
+		// two voters still use the weight-based threshold.
+		let v = VoterSet::new(vec![(1, 4), (2, 1)]).unwrap();
+		assert_eq!(v.threshold(), crate::threshold(5));
+	}
+
+	#[test]
+	fn threshold_is_total_minus_faulty_weight() {
+		let sets: &[&[u64]] = &[
+			&[1, 1],
+			&[1, 1, 1],
+			&[1, 1, 1, 1],
+			&[1; 7],
+			&[1; 10],
+			&[5, 3, 1],
+			&[100, 1, 1, 1],
+			&[u64::MAX / 2, u64::MAX / 2],
+		];
+
+		for weights in sets {
+			let v = VoterSet::new(weights.iter().cloned().enumerate()).unwrap();
+			let total: u64 = weights.iter().sum();
+
+			assert_eq!(v.total_weight(), total);
+			assert_eq!(v.faulty_weight(), (total - 1) / 3);
+			assert_eq!(v.threshold(), v.total_weight() - v.faulty_weight());
+			assert_eq!(v.threshold(), crate::threshold(total));
+		}
+
+		// a single voter tolerates no faults.
+		let v = VoterSet::new(crate::std::iter::once((1, 7))).unwrap();
+		assert_eq!(v.faulty_weight(), 0);
+		assert_eq!(v.threshold(), v.total_weight() - v.faulty_weight());
+	}
+
+	#[test]
+	fn rejects_empty_set() {
+		assert_eq!(VoterSet::<usize>::new(Vec::new()), Err(Error::Empty));
+	}
+
+	#[test]
+	fn rejects_duplicate_voters() {
+		assert_eq!(VoterSet::new(vec![(1, 5), (2, 7), (1, 5)]), Err(Error::DuplicateVoter));
+		assert_eq!(VoterSet::new(vec![(1, 5), (2, 7), (1, 3)]), Err(Error::DuplicateVoter));
+	}
+
+	#[test]
+	fn rejects_zero_weight() {
+		assert_eq!(VoterSet::new(vec![(1, 5), (2, 0)]), Err(Error::ZeroWeight));
+	}
+
+	#[test]
+	fn rejects_weight_overflow() {
+		assert_eq!(
+			VoterSet::new(vec![(1, u64::MAX), (2, u64::MAX / 2), (3, 1)]),
+			Err(Error::WeightOverflow),
+		);
+
+		let v = VoterSet::new(vec![(1, u64::MAX - 1), (2, 1)]).unwrap();
+		assert_eq!(v.total_weight(), u64::MAX);
+		assert_eq!(v.threshold(), threshold(u64::MAX));
+	}
+
+	#[test]
+	fn from_sorted_unchecked_matches_new() {
+		let voters = vec![(1, 5), (2, 7), (3, 9), (4, 1)];
+		assert_eq!(VoterSet::from_sorted_unchecked(voters.clone()), VoterSet::new(voters).unwrap());
+
+		let v = VoterSet::from_sorted_unchecked(vec![(1, 7)]);
+		assert_eq!(v.threshold(), 7);
+	}
+
+	#[test]
+	#[cfg(debug_assertions)]
+	#[should_panic(expected = "voters aren't sorted by id or appear more than once")]
+	fn from_sorted_unchecked_checks_order_in_debug() {
+		VoterSet::from_sorted_unchecked(vec![(1, 5), (3, 9), (2, 7)]);
+	}
+
+	#[test]
+	#[cfg(debug_assertions)]
+	#[should_panic(expected = "voters aren't sorted by id or appear more than once")]
+	fn from_sorted_unchecked_checks_duplicates_in_debug() {
+		VoterSet::from_sorted_unchecked(vec![(1, 5), (2, 7), (2, 7)]);
+	}
+
+	#[test]
+	fn primary_rotation_is_pinned() {
+		let v = VoterSet::new(vec![(30, 1), (10, 3), (20, 2)]).unwrap();
+
+		let primaries: Vec<_> = (0..7).map(|r| *v.primary(r).0).collect();
+		assert_eq!(primaries, vec![10, 20, 30, 10, 20, 30, 10]);
+
+		// large round numbers are reduced without truncation.
+		assert_eq!(*v.primary(u64::MAX).0, 10);
+		assert_eq!(v.primary(4), (&20, &VoterInfo { canon_idx: 1, weight: 2 }));
+
+		assert!(v.is_primary(5, &30));
+		assert!(!v.is_primary(5, &10));
+		assert!(!v.is_primary(5, &40));
+	}
+
+	#[test]
+	fn weight_proportional_primary_rotation_is_pinned() {
+		let v = VoterSet::new(vec![(30, 1), (10, 3), (20, 2)]).unwrap();
+
+		let primaries: Vec<_> = (0..8)
+			.map(|r| *v.primary_by(r, PrimarySelection::WeightProportional).0)
+			.collect();
+		assert_eq!(primaries, vec![10, 10, 10, 20, 20, 30, 10, 10]);
+
+		// u64::MAX % 6 == 3
+		assert_eq!(*v.primary_by(u64::MAX, PrimarySelection::WeightProportional).0, 20);
+	}
+
+	#[cfg(feature = "derive-codec")]
+	#[test]
+	fn codec_round_trip() {
+		let v = VoterSet::new(vec![(4u64, 1), (1, 5), (3, 9)]).unwrap();
+		let encoded = v.encode();
+
+		// the encoding is the canonical list of voters and weights.
+		assert_eq!(encoded, vec![(1u64, 5u64), (3, 9), (4, 1)].encode());
+		assert_eq!(VoterSet::decode(&mut &encoded[..]), Ok(v));
+	}
+
+	#[cfg(feature = "derive-codec")]
+	#[test]
+	fn codec_rejects_invalid_sets() {
+		let duplicates = vec![(1u64, 5u64), (2, 7), (1, 5)].encode();
+		assert!(VoterSet::<u64>::decode(&mut &duplicates[..]).is_err());
+
+		let zero_weight = vec![(1u64, 5u64), (2, 0)].encode();
+		assert!(VoterSet::<u64>::decode(&mut &zero_weight[..]).is_err());
+
+		let empty = Vec::<(u64, u64)>::new().encode();
+		assert!(VoterSet::<u64>::decode(&mut &empty[..]).is_err());
+	}
+
+	#[cfg(feature = "derive-serde")]
+	#[test]
+	fn serde_round_trip() {
+		let v = VoterSet::new(vec![(4u64, 1), (1, 5), (3, 9)]).unwrap();
+		let json = serde_json::to_string(&v).unwrap();
+
+		assert_eq!(json, "[[1,5],[3,9],[4,1]]");
+		assert_eq!(serde_json::from_str::<VoterSet<u64>>(&json).unwrap(), v);
+	}
+
+	#[cfg(feature = "derive-serde")]
+	#[test]
+	fn serde_rejects_invalid_sets() {
+		let err = serde_json::from_str::<VoterSet<u64>>("[[1,5],[2,7],[1,5]]").unwrap_err();
+		assert!(err.to_string().contains("more than once"));
+
+		assert!(serde_json::from_str::<VoterSet<u64>>("[[1,5],[2,0]]").is_err());
+		assert!(serde_json::from_str::<VoterSet<u64>>("[]").is_err());
 	}
 }